@@ -10,3 +10,4 @@
 ./doc/gen_topbase.sh > doc/topbase.md
 ./doc/gen_check.sh > doc/check.md
 ./doc/gen_verify_rf.sh > doc/verify-rf.md
+./doc/gen_reverse_rf.sh > doc/reverse-rf.md
@@ -0,0 +1,7 @@
+#!/usr/bin/env bash
+
+SUBCOMMAND="reverse-rf" envsubst < ./doc/subcommand.template
+
+echo "\`\`\`"
+./target/release/mgt reverse-rf --help
+echo "\`\`\`"
@@ -1,5 +1,10 @@
 use gumdrop::Options;
 use gitfilter::filter::*;
+use gitfilter::commit_rules::{CommitRule, CommitMatcher, CommitAction};
+use gitfilter::mailmap::Mailmap;
+use gitfilter::message_transforms::{MessageTransforms, DEFAULT_ORIGIN_TRAILER};
+use gitfilter::progress::ProgressReporter;
+use gitfilter::submodules::SubmoduleAction;
 use std::path::PathBuf;
 
 #[derive(Debug, Options, Default)]
@@ -20,6 +25,15 @@ pub struct Filter {
     #[options(help = "Name of branch to filter from")]
     pub branch: Option<String>,
 
+    #[options(no_short, help = "write the filtered branch to this branch instead of rewriting it in place")]
+    pub output_branch: Option<String>,
+
+    #[options(no_short, long = "ref", help = "other refs to filter in the same pass, can be a glob, eg: --ref 'refs/heads/release/*'")]
+    pub refs: Vec<String>,
+
+    #[options(no_short, help = "write the other refs under this namespace instead of rewriting them in place, eg: refs/split/")]
+    pub ref_namespace: Option<String>,
+
     #[options(help = "path to filter")]
     pub path: Vec<String>,
 
@@ -31,6 +45,27 @@ pub struct Filter {
 
     #[options(help = "the default is to implicitly exclude everything, by using --default-include you implicitly INCLUDE everything, and can explicitly choose to exclude specific paths")]
     pub default_include: bool,
+
+    #[options(help = "let git-fast-export detect renames and copies, and filter both paths of a rename/copy")]
+    pub detect_renames: bool,
+
+    #[options(help = "what to do with commits that match, syntax: --commit-rule action:kind:value, eg: --commit-rule drop:author:bot")]
+    pub commit_rule: Vec<String>,
+
+    #[options(help = "path to a mailmap file to rewrite the names and emails of authors and committers")]
+    pub mailmap: Option<PathBuf>,
+
+    #[options(help = "regex replacement for commit messages, syntax: --message-replace regex:replacement")]
+    pub message_replace: Vec<String>,
+
+    #[options(help = "add a Mgt-Origin: <original commit id> trailer to every commit message")]
+    pub origin_trailer: bool,
+
+    #[options(no_short, help = "print how many commits were filtered to stderr every 1000 commits")]
+    pub progress: bool,
+
+    #[options(no_short, help = "what to do with submodules: keep, drop, or merge. defaults to keep")]
+    pub submodules: Option<String>,
 }
 
 pub fn get_cli_input() -> Filter {
@@ -62,11 +97,51 @@ fn main() {
     // parse_git_filter_export_via_channel(filter.branch, filter.with_data, empty_cb).unwrap();
 
 
+    let mailmap = filter.mailmap.map(|path| match Mailmap::from_file(&path) {
+        Ok(mailmap) => mailmap,
+        Err(e) => panic!("Invalid --mailmap {}: {:?}", path.display(), e),
+    });
+    let mut message_transforms = MessageTransforms::default();
+    for message_replace in filter.message_replace.iter() {
+        let mut split = message_replace.splitn(2, ':');
+        let regex = split.next().unwrap_or("");
+        let replacement = split.next().unwrap_or("");
+        if let Err(e) = message_transforms.add_replacement(regex, replacement) {
+            panic!("Invalid --message-replace {}: {:?}", message_replace, e);
+        }
+    }
+    if filter.origin_trailer {
+        message_transforms.origin_trailer = Some(DEFAULT_ORIGIN_TRAILER.into());
+    }
+    let submodules = match filter.submodules.as_deref().map(|s| s.parse::<SubmoduleAction>()) {
+        Some(Ok(action)) => action,
+        Some(Err(e)) => panic!("Invalid --submodules: {:?}", e),
+        None => SubmoduleAction::Keep,
+    };
+    let use_message_transforms = !message_transforms.replacements.is_empty() || filter.origin_trailer;
     let filter_opts = FilterOptions {
         stream: stdout(),
         branch: filter.branch,
+        output_branch: filter.output_branch,
+        refs: filter.refs,
+        ref_namespace: filter.ref_namespace,
         default_include: filter.default_include,
         with_blobs: filter.with_data,
+        tags: None,
+        commit_map: None,
+        detect_renames: filter.detect_renames,
+        mailmap,
+        message_transforms: if use_message_transforms { Some(message_transforms) } else { None },
+        incremental: None,
+        progress: if filter.progress {
+            Some(ProgressReporter::new(None, |p| {
+                if p.done || p.commits_processed % 1000 == 0 {
+                    eprintln!("Filtered {} commits ({} kept, {} dropped), {:.0} commits/s",
+                        p.commits_processed, p.commits_kept, p.commits_dropped, p.commits_per_second());
+                }
+            }))
+        } else { None },
+        submodules,
     };
     let mut filter_rules = vec![];
 
@@ -88,6 +163,17 @@ fn main() {
             _ => panic!("Must provide a src:dest when using --path-rename"),
         }
     }
+    for commit_rule in filter.commit_rule.iter() {
+        let mut split = commit_rule.splitn(2, ':');
+        let action = split.next().unwrap_or("").parse::<CommitAction>();
+        let matcher = CommitMatcher::parse(split.next().unwrap_or(""));
+        match (action, matcher) {
+            (Ok(action), Ok(matcher)) => {
+                filter_rules.push(FilterRuleCommit(CommitRule { action, matchers: vec![matcher] }));
+            }
+            (Err(e), _) | (_, Err(e)) => panic!("Invalid --commit-rule {}: {:?}", commit_rule, e),
+        }
+    }
     if filter_rules.len() == 0 {
         panic!("Must provide either a filter include or an exclude");
     }
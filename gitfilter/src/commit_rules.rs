@@ -0,0 +1,217 @@
+use super::export_parser::StructuredCommit;
+use super::export_parser::CommitPersonOwned;
+use super::filter::FilterError;
+use regex::Regex;
+use std::str::FromStr;
+
+/// what to do with a commit that matches a `CommitRule`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CommitAction {
+    /// filter it normally according to the path rules
+    Keep,
+    /// remove the commit, and its changes from the history
+    Drop,
+    /// combine the changes of this commit into its parent.
+    /// the combined commit keeps the author, committer, and
+    /// message of the parent. merge commits, and commits without
+    /// a parent are kept instead because there is nothing to squash them into
+    Squash,
+}
+
+impl FromStr for CommitAction {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ok = match s {
+            "keep" => CommitAction::Keep,
+            "drop" => CommitAction::Drop,
+            "squash" => CommitAction::Squash,
+            _ => return Err(FilterError(format!("'{}' is not a valid commit action. Must be one of: keep, drop, squash", s))),
+        };
+        Ok(ok)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum CommitMatcher {
+    /// matches `Name <email>` of the author, or the committer
+    /// if the commit does not have an author
+    Author(Regex),
+    /// matches `Name <email>` of the committer
+    Committer(Regex),
+    /// matches anywhere in the commit message
+    Message(Regex),
+    /// the author date is before this unix timestamp
+    Before(i64),
+    /// the author date is at or after this unix timestamp
+    After(i64),
+}
+
+impl CommitMatcher {
+    /// parses `<kind>:<value>` where kind is one of
+    /// author, committer, message, before, after.
+    /// author, committer, and message take a regex.
+    /// before and after take a unix timestamp, or a YYYY-MM-DD date in UTC
+    pub fn parse(s: &str) -> Result<CommitMatcher, FilterError> {
+        let (kind, value) = match s.find(':') {
+            Some(i) => (&s[..i], &s[(i + 1)..]),
+            None => return Err(FilterError(format!("Invalid commit matcher '{}'. Must be of the form <kind>:<value>", s))),
+        };
+        let make_regex = |value: &str| {
+            Regex::new(value)
+                .map_err(|e| FilterError(format!("Invalid regex '{}' in commit matcher: {}", value, e)))
+        };
+        let matcher = match kind {
+            "author" => CommitMatcher::Author(make_regex(value)?),
+            "committer" => CommitMatcher::Committer(make_regex(value)?),
+            "message" => CommitMatcher::Message(make_regex(value)?),
+            "before" => CommitMatcher::Before(parse_date(value)?),
+            "after" => CommitMatcher::After(parse_date(value)?),
+            _ => return Err(FilterError(format!("Invalid commit matcher kind '{}'. Must be one of: author, committer, message, before, after", kind))),
+        };
+        Ok(matcher)
+    }
+
+    pub fn is_match(&self, commit: &StructuredCommit) -> bool {
+        let author = commit.get_author().unwrap_or(&commit.committer);
+        match self {
+            CommitMatcher::Author(regex) => regex.is_match(&person_string(author)),
+            CommitMatcher::Committer(regex) => regex.is_match(&person_string(&commit.committer)),
+            CommitMatcher::Message(regex) => regex.is_match(&commit.commit_message),
+            CommitMatcher::Before(time) => match person_time(author) {
+                Some(t) => t < *time,
+                None => false,
+            },
+            CommitMatcher::After(time) => match person_time(author) {
+                Some(t) => t >= *time,
+                None => false,
+            },
+        }
+    }
+}
+
+/// a commit matches a rule if it matches every one of its matchers
+#[derive(Clone, Debug)]
+pub struct CommitRule {
+    pub action: CommitAction,
+    pub matchers: Vec<CommitMatcher>,
+}
+
+impl CommitRule {
+    pub fn is_match(&self, commit: &StructuredCommit) -> bool {
+        self.matchers.iter().all(|m| m.is_match(commit))
+    }
+}
+
+pub fn person_string(person: &CommitPersonOwned) -> String {
+    match person.name {
+        Some(ref name) => format!("{} <{}>", name, person.email),
+        None => format!("<{}>", person.email),
+    }
+}
+
+/// the timestr is `<unix timestamp> <timezone offset>`
+pub fn person_time(person: &CommitPersonOwned) -> Option<i64> {
+    person.timestr.split_whitespace().next()?.parse().ok()
+}
+
+/// parses either a unix timestamp, or a YYYY-MM-DD date
+/// which is treated as midnight UTC
+pub fn parse_date(s: &str) -> Result<i64, FilterError> {
+    if let Ok(timestamp) = s.parse::<i64>() {
+        return Ok(timestamp);
+    }
+    let err = || FilterError(format!("Invalid date '{}'. Must be a unix timestamp or YYYY-MM-DD", s));
+    let parts: Vec<&str> = s.split('-').collect();
+    let (year, month, day) = match parts[..] {
+        [y, m, d] if y.len() == 4 && m.len() == 2 && d.len() == 2 => (
+            y.parse::<i64>().map_err(|_| err())?,
+            m.parse::<i64>().map_err(|_| err())?,
+            d.parse::<i64>().map_err(|_| err())?,
+        ),
+        _ => return Err(err()),
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(err());
+    }
+    Ok(days_from_civil(year, month, day) * 86400)
+}
+
+/// number of days since 1970-01-01 of the given date
+/// in the proleptic gregorian calendar
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::export_parser::AuthorPerson;
+
+    fn make_commit(author: &str, email: &str, time: &str, message: &str) -> StructuredCommit {
+        StructuredCommit {
+            committer: CommitPersonOwned {
+                name: Some("committer".into()),
+                email: "committer@email.com".into(),
+                timestr: "0 +0000".into(),
+            },
+            author: AuthorPerson::Author(CommitPersonOwned {
+                name: Some(author.into()),
+                email: email.into(),
+                timestr: time.into(),
+            }),
+            commit_message: message.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_date_works() {
+        assert_eq!(parse_date("0").unwrap(), 0);
+        assert_eq!(parse_date("1970-01-02").unwrap(), 86400);
+        assert_eq!(parse_date("2021-03-01").unwrap(), 1614556800);
+        assert!(parse_date("2021-3-1").is_err());
+        assert!(parse_date("yesterday").is_err());
+    }
+
+    #[test]
+    fn commit_matchers_work() {
+        let commit = make_commit("dependabot", "bot@github.com", "1614556800 -0800", "bump deps [skip-export]\n");
+        assert!(CommitMatcher::parse("author:^dependabot ").unwrap().is_match(&commit));
+        assert!(CommitMatcher::parse("author:<bot@github.com>").unwrap().is_match(&commit));
+        assert!(!CommitMatcher::parse("committer:bot").unwrap().is_match(&commit));
+        assert!(CommitMatcher::parse("message:\\[skip-export\\]").unwrap().is_match(&commit));
+        assert!(CommitMatcher::parse("before:2021-03-02").unwrap().is_match(&commit));
+        assert!(!CommitMatcher::parse("before:2021-03-01").unwrap().is_match(&commit));
+        assert!(CommitMatcher::parse("after:2021-03-01").unwrap().is_match(&commit));
+        assert!(CommitMatcher::parse("date:2021-03-01").is_err());
+        assert!(CommitMatcher::parse("author").is_err());
+    }
+
+    #[test]
+    fn commit_rule_needs_every_matcher() {
+        let commit = make_commit("dependabot", "bot@github.com", "1614556800 -0800", "bump deps\n");
+        let rule = CommitRule {
+            action: CommitAction::Drop,
+            matchers: vec![
+                CommitMatcher::parse("author:dependabot").unwrap(),
+                CommitMatcher::parse("message:skip-export").unwrap(),
+            ],
+        };
+        assert!(!rule.is_match(&commit));
+        let rule = CommitRule {
+            action: CommitAction::Drop,
+            matchers: vec![
+                CommitMatcher::parse("author:dependabot").unwrap(),
+                CommitMatcher::parse("message:bump").unwrap(),
+            ],
+        };
+        assert!(rule.is_match(&commit));
+    }
+}
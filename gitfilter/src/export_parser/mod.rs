@@ -43,13 +43,15 @@ impl PartialOrd for WaitObj {
 }
 
 pub fn parse_git_filter_export<O, E: Display + From<io::Error>, P: AsRef<Path>>(
-    export_branch: Option<String>,
+    export_refs: Vec<String>,
     with_blobs: bool,
+    detect_renames: bool,
+    marks: Option<MarksFiles>,
     location: Option<P>,
     cb: impl FnMut(StructuredExportObject) -> Result<O, E>,
 ) -> io::Result<()> {
     let mut cb = cb;
-    parse_git_filter_export_with_callback(export_branch, with_blobs, location, |unparsed| {
+    parse_git_filter_export_with_callback(export_refs, with_blobs, detect_renames, marks, location, |unparsed| {
         match parse_into_structured_object(unparsed) {
             Ok(parsed) => cb(parsed),
             Err(e) => Err(E::from(e)),
@@ -71,9 +73,11 @@ pub fn parse_git_filter_export<O, E: Display + From<io::Error>, P: AsRef<Path>>(
 /// `parse_git_filter_export` because it will still read via
 /// a channel, rather than be blocking
 pub fn parse_git_filter_export_via_channel<O, E: Display, P: AsRef<Path>>(
-    export_branch: Option<String>,
+    export_refs: Vec<String>,
     with_blobs: bool,
+    detect_renames: bool,
     n_parsing_threads: Option<usize>,
+    marks: Option<MarksFiles>,
     location: Option<P>,
     cb: impl FnMut(StructuredExportObject) -> Result<O, E>,
 ) -> io::Result<()> {
@@ -133,7 +137,7 @@ pub fn parse_git_filter_export_via_channel<O, E: Display, P: AsRef<Path>>(
     // will then pass the PARSED message back to our main thread
     let thread_handle = thread::spawn(move || {
         let mut counter = 0;
-        parse_git_filter_export_with_callback(export_branch, with_blobs, location, |x| {
+        parse_git_filter_export_with_callback(export_refs, with_blobs, detect_renames, marks, location, |x| {
             let thread_index = counter % n_parsing_threads as usize;
             let (parse_tx, _) = &spawned_threads[thread_index];
             let res = parse_tx.send((counter, x));
@@ -195,10 +199,14 @@ pub fn parse_git_filter_export_via_channel<O, E: Display, P: AsRef<Path>>(
 
 pub fn write_person_info(write_data: &mut Vec<u8>, person: &CommitPersonOwned, is_author: bool) {
     if is_author {
-        write_data.extend(b"author ");
+        write_person_line(write_data, person, b"author ");
     } else {
-        write_data.extend(b"committer ");
+        write_person_line(write_data, person, b"committer ");
     }
+}
+
+pub fn write_person_line(write_data: &mut Vec<u8>, person: &CommitPersonOwned, keyword: &[u8]) {
+    write_data.extend(keyword);
     if let Some(name) = &person.name {
         write_data.extend(name.as_bytes());
         write_data.push(b' ');
@@ -221,6 +229,9 @@ pub fn write_to_stream<W: Write>(stream: W, obj: StructuredExportObject) -> io::
     if obj.has_feature_done {
         write_data.extend(b"feature done\n");
     }
+    if obj.has_checkpoint {
+        write_data.extend(b"checkpoint\n\n");
+    }
     if let Some(reset_ref) = &obj.has_reset {
         write_data.extend(b"reset ");
         write_data.extend(reset_ref.as_bytes());
@@ -277,6 +288,16 @@ pub fn write_to_stream<W: Write>(stream: W, obj: StructuredExportObject) -> io::
                 write_data.push(b'\n');
             }
         }
+        for oid in commit_obj.merge_oids {
+            if first_merge {
+                first_merge = false;
+                write_data.extend(b"from ");
+            } else {
+                write_data.extend(b"merge ");
+            }
+            write_data.extend(oid.as_bytes());
+            write_data.push(b'\n');
+        }
         for fileop in commit_obj.fileops {
             match fileop {
                 FileOpsOwned::FileModify(mode, dataref, path) => {
@@ -329,6 +350,28 @@ pub fn write_to_stream<W: Write>(stream: W, obj: StructuredExportObject) -> io::
         write_data.push(b'\n');
         write_data.extend(blob_obj.data);
         write_data.push(b'\n');
+    } else if let StructuredObjectType::Tag(tag_obj) = obj.object_type {
+        write_data.extend(b"tag ");
+        write_data.extend(tag_obj.name.as_bytes());
+        write_data.push(b'\n');
+        if let Some(mark) = tag_obj.mark {
+            write_data.extend(b"mark :");
+            write_data.extend(mark.to_string().as_bytes());
+            write_data.push(b'\n');
+        }
+        write_data.extend(b"from :");
+        write_data.extend(tag_obj.from.to_string().as_bytes());
+        write_data.push(b'\n');
+        write_data.extend(b"original-oid ");
+        write_data.extend(tag_obj.original_oid.as_bytes());
+        write_data.push(b'\n');
+        write_person_line(&mut write_data, &tag_obj.tagger, b"tagger ");
+        write_data.extend(b"data ");
+        let msg_as_bytes = tag_obj.tag_message.as_bytes();
+        write_data.extend(msg_as_bytes.len().to_string().as_bytes());
+        write_data.push(b'\n');
+        write_data.extend(msg_as_bytes);
+        write_data.push(b'\n');
     }
 
     stream.write_all(&write_data)?;
@@ -348,7 +391,7 @@ mod tests {
     fn using_multiple_parsing_threads_keeps_order_the_same() {
         let mut expected_count = 1;
         parse_git_filter_export_via_channel(
-            None, false, Some(4), NO_LOCATION, |obj| {
+            vec![], false, false, Some(4), None, NO_LOCATION, |obj| {
                 if let StructuredObjectType::Commit(commit_obj) = obj.object_type {
                     let mark_str = format!(":{}", commit_obj.mark);
                     let expected_mark_str = format!(":{}", expected_count);
@@ -368,7 +411,7 @@ mod tests {
     fn using_blobs_and_multiple_parsing_threads_keeps_order_the_same() {
         let mut expected_count = 1;
         parse_git_filter_export_via_channel(
-            None, true, Some(4), NO_LOCATION, |obj| {
+            vec![], true, false, Some(4), None, NO_LOCATION, |obj| {
                 if let StructuredObjectType::Commit(commit_obj) = obj.object_type {
                     let mark_str = format!(":{}", commit_obj.mark);
                     let expected_mark_str = format!(":{}", expected_count);
@@ -389,7 +432,7 @@ mod tests {
     #[test]
     fn test1() {
         let now = std::time::Instant::now();
-        parse_git_filter_export_via_channel(None, false, Some(1), NO_LOCATION,
+        parse_git_filter_export_via_channel(vec![], false, false, Some(1), None, NO_LOCATION,
             |_| { if 1 == 1 { Ok(()) } else { Err("a") } }).unwrap();
         eprintln!("total time {:?}", now.elapsed());
     }
@@ -397,7 +440,7 @@ mod tests {
     #[test]
     fn works_with_blobs() {
         let now = std::time::Instant::now();
-        parse_git_filter_export_via_channel(None, true, Some(1), NO_LOCATION,
+        parse_git_filter_export_via_channel(vec![], true, false, Some(1), None, NO_LOCATION,
             |_| { if 1 == 1 { Ok(()) } else { Err("a") } }).unwrap();
         eprintln!("total time {:?}", now.elapsed());
     }
@@ -20,6 +20,10 @@ pub fn get_regex_authorline(text: &str) -> Option<Captures> {
     regex_capture!(text, r"^(?:author|committer) (.*?) ?<(.*?)> (.*?)$")
 }
 
+pub fn get_regex_taggerline(text: &str) -> Option<Captures<'_>> {
+    regex_capture!(text, r"^tagger (.*?) ?<(.*?)> (.*?)$")
+}
+
 pub fn get_regex_filemodifyline(text: &str) -> Option<Captures> {
     regex_capture!(text, r"^M ([0-9]*) (.*?) (.*?)$")
 }
@@ -29,11 +33,11 @@ pub fn get_regex_filedeleteline(text: &str) -> Option<Captures> {
 }
 
 pub fn get_regex_filecopyline(text: &str) -> Option<Captures> {
-    regex_capture!(text, r"^C (.*?) (.*?)$")
+    regex_capture!(text, r#"^C ("(?:[^"\\]|\\.)*"|\S*) (.*)$"#)
 }
 
 pub fn get_regex_filerenameline(text: &str) -> Option<Captures> {
-    regex_capture!(text, r"^R (.*?) (.*?)$")
+    regex_capture!(text, r#"^R ("(?:[^"\\]|\\.)*"|\S*) (.*)$"#)
 }
 
 pub fn get_regex_notemodifyline(text: &str) -> Option<Captures> {
@@ -47,7 +51,7 @@ pub fn owned_string_option(orig: Option<&str>) -> Option<String> {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct StructuredCommit {
     pub commit_ref: String,
     pub mark: usize,
@@ -67,6 +71,9 @@ pub struct StructuredCommit {
     // 1 = regular commit
     // 2 or more = merge commit
     pub merges: Vec<usize>,
+    /// parents that are not part of the export stream, by their commit
+    /// id. they come after the `merges`, eg: merged in submodule commits
+    pub merge_oids: Vec<String>,
     pub fileops: Vec<FileOpsOwned>,
 }
 
@@ -80,17 +87,33 @@ impl StructuredCommit {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct StructuredBlob {
     pub mark: usize,
     pub original_oid: String,
     pub data: Vec<u8>,
 }
 
-#[derive(Debug)]
+/// an annotated tag. fast-export writes these
+/// after all of the commits
+#[derive(Debug, Default, Clone)]
+pub struct StructuredTag {
+    /// the name of the tag, without the refs/tags/ prefix
+    pub name: String,
+    /// only set if fast-export was run with --mark-tags
+    pub mark: Option<usize>,
+    /// the mark of the commit this tag points to
+    pub from: usize,
+    pub original_oid: String,
+    pub tagger: CommitPersonOwned,
+    pub tag_message: String,
+}
+
+#[derive(Debug, Clone)]
 pub enum StructuredObjectType {
     Blob(StructuredBlob),
     Commit(StructuredCommit),
+    Tag(StructuredTag),
     NoType,
 }
 
@@ -100,7 +123,7 @@ impl Default for StructuredObjectType {
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct StructuredExportObject {
     pub has_reset: Option<String>,
     pub has_reset_from: Option<String>,
@@ -108,6 +131,9 @@ pub struct StructuredExportObject {
     // there are other features but we dont implement them,
     // if we see the keyword 'feature', we assume its "feature done"
     pub has_feature_done: bool,
+    /// a checkpoint before this object. a checkpoint at the end of
+    /// the stream is an object of its own, with no type
+    pub has_checkpoint: bool,
 
     pub data_size: String,
 
@@ -119,6 +145,7 @@ pub enum BeforeDataParserMode {
     Reset,
     Commit,
     Blob,
+    Tag,
 }
 use BeforeDataParserMode::*;
 
@@ -138,20 +165,23 @@ pub enum NextWordType {
     Data,
     From,
     Merge,
+    TagName,
+    TagFrom,
 }
 use NextWordType::*;
 use crate::{ioerre, ioerr};
 
 /// here we diverge from git-fast-import spec a bit.
 /// the fast-import spec has several commands, but we only handle
-/// two of them: commit and blob.
-/// we dont handle tags because we ignore tags. resets are part
-/// of the before_data_object so we dont treat it as a seperate object,
-/// same goes for feature done. we ignore progress, checkpoint and alias, and the rest
+/// three of them: commit, blob and tag.
+/// resets are part of the before_data_object so we dont treat
+/// it as a seperate object, same goes for feature done and checkpoint.
+/// we ignore progress and alias, and the rest
 #[derive(Debug)]
 pub enum ObjectType<'a> {
     Commit(CommitObject<'a>),
     Blob(BlobObject<'a>),
+    Tag(TagObject<'a>),
 }
 
 impl<'a> Default for ObjectType<'a> {
@@ -167,7 +197,7 @@ pub struct CommitPerson<'a> {
     pub timestr: &'a str,
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct CommitPersonOwned {
     pub name: Option<String>,
     pub email: String,
@@ -192,7 +222,7 @@ impl<'a> PartialEq for CommitPerson<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum AuthorPerson {
     NoAuthor,
     SameAsCommitPerson,
@@ -210,6 +240,15 @@ pub struct BlobObject<'a> {
     oid: &'a str,
 }
 
+#[derive(Default, Debug)]
+pub struct TagObject<'a> {
+    name: &'a str,
+    mark: Option<usize>,
+    from: &'a str,
+    oid: &'a str,
+    tagger: CommitPerson<'a>,
+}
+
 #[derive(Default, Debug)]
 pub struct CommitObject<'a> {
     refname: &'a str,
@@ -230,6 +269,7 @@ pub struct BeforeDataObject<'a> {
     // there are other features but we dont implement them,
     // if we see the keyword 'feature', we assume its "feature done"
     has_feature_done: bool,
+    has_checkpoint: bool,
 
     object: ObjectType<'a>,
 
@@ -245,7 +285,7 @@ pub enum FileOps<'a> {
     FileDeleteAll,
     NoteModify(&'a str, &'a str),
 }
-#[derive(Debug, PartialEq, Hash)]
+#[derive(Debug, PartialEq, Hash, Clone)]
 pub enum FileOpsOwned {
     FileModify(String, String, String),
     FileDelete(String),
@@ -314,6 +354,13 @@ pub fn set_object_property<'a>(
         } else if let Mark = next_word_type {
             blob_obj.mark = parse_mark_to_usize(value);
         }
+    } else if let ObjectType::Tag(tag_obj) = &mut object.object {
+        match next_word_type {
+            Oid => tag_obj.oid = value,
+            Mark => tag_obj.mark = Some(parse_mark_to_usize(value)),
+            TagFrom => tag_obj.from = value,
+            _ => {},
+        }
     }
 }
 
@@ -330,7 +377,12 @@ pub fn parse_next_word<'a>(
     let next_word = word_split.next()
         .ok_or(ioerr!("Failed to parse: insufficient input"))?;
     match next_word_type {
-        Oid | Mark => set_object_property(next_word, object, next_word_type),
+        Oid | Mark | TagFrom => set_object_property(next_word, object, next_word_type),
+        TagName => {
+            let tag_obj = TagObject { name: next_word, ..Default::default() };
+            object.object = ObjectType::Tag(tag_obj);
+            *parse_mode = BeforeDataParserMode::Tag;
+        },
         CommitRef => {
             let mut commit_obj = CommitObject::default();
             commit_obj.refname = next_word;
@@ -424,6 +476,22 @@ pub fn parse_author_or_committer_line<'a>(
     Ok(())
 }
 
+pub fn parse_tagger_line<'a>(
+    line: &'a str,
+    object: &mut BeforeDataObject<'a>,
+) -> io::Result<()> {
+    let [name, email, timestr] = get_n_captures::<3>(
+        get_regex_taggerline, line).ok_or(ioerr!("Failed to parse tagger line: {}", line))?;
+    if let ObjectType::Tag(tag_obj) = &mut object.object {
+        tag_obj.tagger = CommitPerson {
+            name: if name.is_empty() { None } else { Some(name) },
+            email,
+            timestr,
+        };
+    }
+    Ok(())
+}
+
 pub fn parse_filemodify_line<'a>(
     line: &'a str,
     object: &mut AfterDataObject<'a>,
@@ -500,9 +568,11 @@ pub fn parse_before_data_line<'a>(
 
     match parse_mode {
         // in the initial state we are looking for one of several words
-        // feature, reset, commit, or blob
+        // feature, checkpoint, reset, commit, blob, or tag
         BeforeDataParserMode::Initial => match first_word {
             "feature" => object.has_feature_done = true,
+            "checkpoint" => object.has_checkpoint = true,
+            "tag" => parse_next_word(&mut word_split, object, TagName, parse_mode)?,
             "reset" => parse_next_word(&mut word_split, object, ResetLine, parse_mode)?,
             "commit" => parse_next_word(&mut word_split, object, CommitRef, parse_mode)?,
             "blob" => {
@@ -541,7 +611,16 @@ pub fn parse_before_data_line<'a>(
             "original-oid" => parse_next_word(&mut word_split, object, Oid, parse_mode)?,
             "data" => parse_next_word(&mut word_split, object, Data, parse_mode)?,
             _ => return ioerre!("Unknown blob parsing?\n{}", line),
-        }
+        },
+
+        Tag => match first_word {
+            "mark" => parse_next_word(&mut word_split, object, Mark, parse_mode)?,
+            "from" => parse_next_word(&mut word_split, object, TagFrom, parse_mode)?,
+            "original-oid" => parse_next_word(&mut word_split, object, Oid, parse_mode)?,
+            "tagger" => parse_tagger_line(line, object)?,
+            "data" => parse_next_word(&mut word_split, object, Data, parse_mode)?,
+            _ => return ioerre!("Unknown tag parsing?\n{}", line),
+        },
     }
 
     Ok(())
@@ -667,11 +746,19 @@ pub fn parse_into_structured_object(
 
     let mut output_object = StructuredExportObject::default();
     output_object.has_feature_done = before_data_obj.has_feature_done;
+    output_object.has_checkpoint = before_data_obj.has_checkpoint;
     output_object.has_reset = owned_string_option(before_data_obj.has_reset);
     output_object.has_reset_from = owned_string_option(before_data_obj.has_reset_from);
     output_object.data_size = before_data_obj.data.into();
 
     let object_type = match &before_data_obj.object {
+        // a reset at the end of the stream that
+        // points a ref to a commit that was already exported
+        // or a checkpoint at the end of the stream
+        ObjectType::Commit(commit_obj) if commit_obj.refname.is_empty()
+            && (before_data_obj.has_reset.is_some() || before_data_obj.has_checkpoint) => {
+            StructuredObjectType::NoType
+        }
         ObjectType::Commit(commit_obj) => {
             let author_type = match &commit_obj.author {
                 None => AuthorPerson::NoAuthor,
@@ -693,6 +780,7 @@ pub fn parse_into_structured_object(
                 author: author_type,
                 commit_message: String::from_utf8_lossy(&unparsed.data).into(),
                 merges,
+                merge_oids: vec![],
                 fileops: after_data_obj.fileops.iter().map(|x| x.into()).collect(),
             };
             StructuredObjectType::Commit(structured_commit)
@@ -705,6 +793,17 @@ pub fn parse_into_structured_object(
             };
             StructuredObjectType::Blob(structured_blob)
         }
+        ObjectType::Tag(tag_obj) => {
+            let structured_tag = StructuredTag {
+                name: tag_obj.name.into(),
+                mark: tag_obj.mark,
+                from: parse_mark_to_usize(tag_obj.from),
+                original_oid: tag_obj.oid.into(),
+                tagger: (&tag_obj.tagger).into(),
+                tag_message: String::from_utf8_lossy(&unparsed.data).into(),
+            };
+            StructuredObjectType::Tag(structured_tag)
+        }
     };
 
     output_object.object_type = object_type;
@@ -745,6 +844,47 @@ data 12"#;
         assert_eq!(obj.author.unwrap().timestr, "1548162866 -0800");
     }
 
+    #[test]
+    fn tags_and_checkpoints_are_parsed() {
+        let unparsed = UnparsedFastExportObject {
+            before_data_str: "tag v1.0\nfrom :3\noriginal-oid abc\ntagger T T <t@t> 1 +0000\ndata 7\n".into(),
+            data: b"release".to_vec(),
+            after_data_str: String::new(),
+        };
+        let obj = parse_into_structured_object(unparsed).unwrap();
+        let tag = if let StructuredObjectType::Tag(t) = obj.object_type {
+            t
+        } else { panic!("expected tag object") };
+        assert_eq!(tag.name, "v1.0");
+        assert_eq!(tag.from, 3);
+        assert_eq!(tag.mark, None);
+        assert_eq!(tag.original_oid, "abc");
+        assert_eq!(tag.tagger.name, Some("T T".into()));
+        assert_eq!(tag.tag_message, "release");
+
+        let unparsed = UnparsedFastExportObject {
+            before_data_str: "checkpoint\n".into(),
+            data: vec![],
+            after_data_str: String::new(),
+        };
+        let obj = parse_into_structured_object(unparsed).unwrap();
+        assert!(obj.has_checkpoint);
+        assert!(matches!(obj.object_type, StructuredObjectType::NoType));
+    }
+
+    #[test]
+    fn regex_rename_capture_works_with_quoted_paths() {
+        let captures = get_regex_filerenameline("R a.txt b c.txt").unwrap();
+        assert_eq!(captures.get(1).unwrap().as_str(), "a.txt");
+        assert_eq!(captures.get(2).unwrap().as_str(), "b c.txt");
+        let captures = get_regex_filerenameline(r#"R "a b.txt" "c \"d\".txt""#).unwrap();
+        assert_eq!(captures.get(1).unwrap().as_str(), "\"a b.txt\"");
+        assert_eq!(captures.get(2).unwrap().as_str(), r#""c \"d\".txt""#);
+        let captures = get_regex_filecopyline(r#"C "a b.txt" c.txt"#).unwrap();
+        assert_eq!(captures.get(1).unwrap().as_str(), "\"a b.txt\"");
+        assert_eq!(captures.get(2).unwrap().as_str(), "c.txt");
+    }
+
     #[test]
     fn regex_author_capture_works() {
         let sample1 = "author Bryan Bryan <bb@email.com> 1548162866 -0800";
@@ -1,5 +1,5 @@
 use std::io::{BufReader, Error, ErrorKind, BufRead, Read, self};
-use std::{path::Path, process::Stdio, fmt::Display};
+use std::{path::{Path, PathBuf}, process::Stdio, fmt::Display};
 use crate::{ioerr, ioerre};
 
 pub enum ParseState {
@@ -24,6 +24,38 @@ pub fn make_expected_progress_string(progress_num: u32) -> String {
     s
 }
 
+/// true if the line starts a new command. used to find the end of
+/// tags, because unlike commits and blobs, fast-export
+/// does not write a progress line after a tag
+pub fn starts_new_command(line: &str) -> bool {
+    line.starts_with("tag ") || line.starts_with("reset ")
+        || line.starts_with("commit ") || line == "blob"
+        || line == "checkpoint" || line == "done"
+}
+
+fn send_object<O, E: Display>(
+    cb: &mut impl FnMut(UnparsedFastExportObject) -> Result<O, E>,
+    unparsed_obj: UnparsedFastExportObject,
+) -> io::Result<()> {
+    if let Err(e) = cb(unparsed_obj) {
+        return ioerre!("Error from callback:\n{}", e);
+    }
+    Ok(())
+}
+
+/// each of the trailing commands is an object of its own
+fn send_trailing_commands<O, E: Display>(
+    cb: &mut impl FnMut(UnparsedFastExportObject) -> Result<O, E>,
+    remaining: &str,
+) -> io::Result<()> {
+    for command in split_trailing_commands(remaining) {
+        send_object(cb, UnparsedFastExportObject {
+            before_data_str: command, data: vec![], after_data_str: String::new(),
+        })?;
+    }
+    Ok(())
+}
+
 pub fn parse_from_stream<R: BufRead, O, E: Display>(
     bufreader: &mut R,
     cb: impl FnMut(UnparsedFastExportObject) -> Result<O, E>,
@@ -45,6 +77,13 @@ pub fn parse_from_stream<R: BufRead, O, E: Display>(
                 if num_read == 0 { break; }
                 line_vec.pop(); // remove trailing slash
                 let line = String::from_utf8_lossy(&line_vec[..]);
+                if line.starts_with("tag ") {
+                    // fast-export writes the tags after all of the commits,
+                    // possibly after resets of refs whose tip was already exported.
+                    // those resets are not part of the tag
+                    send_trailing_commands(&mut cb, &before_data_str)?;
+                    before_data_str.clear();
+                }
                 if line.starts_with("data ") {
                     let data_size_index = 5; // data + space is 5 chars
                     let data_size = line.get(data_size_index..).ok_or(ioerr!("Failed to parse data line"))?;
@@ -69,21 +108,28 @@ pub fn parse_from_stream<R: BufRead, O, E: Display>(
                 if num_read == 0 { break; }
                 line_vec.pop(); // remove trailing slash
                 let line = unsafe { String::from_utf8_unchecked(line_vec) };
-                if line.starts_with(&expected_progress_string) {
-                    expected_object += 1;
-                    expected_progress_string = make_expected_progress_string(expected_object);
+                let is_progress = line.starts_with(&expected_progress_string);
+                let is_end_of_tag = !is_progress && starts_new_command(&line);
+                if is_progress || is_end_of_tag {
+                    if is_progress {
+                        expected_object += 1;
+                        expected_progress_string = make_expected_progress_string(expected_object);
+                    }
 
                     let unparsed_obj = UnparsedFastExportObject {
                         before_data_str, data: data_vec, after_data_str
                     };
-                    if let Err(e) = cb(unparsed_obj) {
-                        return ioerre!("Error from callback:\n{}", e);
-                    }
+                    send_object(&mut cb, unparsed_obj)?;
 
                     before_data_str = String::new();
                     data_vec = vec![];
                     after_data_str = String::new();
                     parse_state = ParseState::BeforeData;
+                    if is_end_of_tag {
+                        // this line is the start of the next object
+                        before_data_str.push_str(&line);
+                        before_data_str.push('\n');
+                    }
                 } else {
                     after_data_str.push_str(&line);
                     after_data_str.push('\n');
@@ -92,8 +138,66 @@ pub fn parse_from_stream<R: BufRead, O, E: Display>(
         }
     }
 
+    // a tag at the very end of a stream that does not end with `done`
+    if let ParseState::AfterData = parse_state {
+        let unparsed_obj = UnparsedFastExportObject {
+            before_data_str, data: data_vec, after_data_str
+        };
+        send_object(&mut cb, unparsed_obj)?;
+        return Ok(());
+    }
 
-    Ok(())
+    // fast-export writes a reset for every ref whose tip was
+    // already exported under a different ref name at the very end
+    // of the stream. they dont have a data section, so
+    // each of them is passed to the callback as its own object
+    send_trailing_commands(&mut cb, &before_data_str)
+}
+
+/// splits whatever is left in the stream after the last
+/// object into `reset <ref>\nfrom <commit-ish>\n` and `checkpoint\n` strings.
+/// anything else (eg: the final `done`) is ignored
+pub fn split_trailing_commands(remaining: &str) -> Vec<String> {
+    let mut out: Vec<String> = vec![];
+    for line in remaining.lines() {
+        if line.starts_with("reset ") || line == "checkpoint" {
+            out.push(format!("{}\n", line));
+        } else if line.starts_with("from ") {
+            if let Some(last) = out.last_mut() {
+                last.push_str(line);
+                last.push('\n');
+            }
+        }
+    }
+    out
+}
+
+/// turns the refs to export into arguments for git fast-export.
+/// a ref can be a branch name, a full ref name, or a glob.
+/// globs that dont start with `refs/` match branch names,
+/// eg: `release/*` is the same as `refs/heads/release/*`.
+/// exports master if no refs are given
+pub fn make_export_ref_args(export_refs: &[String]) -> Vec<String> {
+    if export_refs.is_empty() {
+        return vec!["master".into()];
+    }
+    export_refs.iter().map(|r| {
+        let is_glob = r.contains(['*', '?', '[']);
+        match (is_glob, r.starts_with("refs/")) {
+            (false, _) => r.clone(),
+            (true, true) => format!("--glob={}", r),
+            (true, false) => format!("--branches={}", r),
+        }
+    }).collect()
+}
+
+/// marks files that git fast-export reads from and writes to.
+/// commits that have a mark in the `import` file are not exported
+/// again, and commits that are exported reference them by that mark
+#[derive(Debug, Clone)]
+pub struct MarksFiles {
+    pub import: PathBuf,
+    pub export: PathBuf,
 }
 
 /// This 'parser' will only parse the data section
@@ -103,23 +207,41 @@ pub fn parse_from_stream<R: BufRead, O, E: Display>(
 /// sections.
 /// optionally specify a path to the
 /// git repo if you are not currently in it.
+/// see `make_export_ref_args` for what the export refs can be.
 pub fn parse_git_filter_export_with_callback<O, E: Display, P: AsRef<Path>>(
-    export_branch: Option<String>,
+    export_refs: Vec<String>,
     with_blobs: bool,
+    detect_renames: bool,
+    marks: Option<MarksFiles>,
     repo_location: Option<P>,
     cb: impl FnMut(UnparsedFastExportObject) -> Result<O, E>,
 ) -> io::Result<()> {
     // let now = Instant::now();
-    let export_branch = export_branch.unwrap_or("master".into());
+    let export_ref_args = make_export_ref_args(&export_refs);
     let mut fast_export_command = vec!["git", "fast-export", "--show-original-ids",
         "--signed-tags=strip", "--tag-of-filtered-object=drop",
         "--fake-missing-tagger","--reference-excluded-parents",
-        "--reencode=yes", "--use-done-feature", &export_branch,
-        "--progress", "1"
+        "--reencode=yes", "--use-done-feature",
     ];
+    fast_export_command.extend(export_ref_args.iter().map(|s| s.as_str()));
+    let marks_args = match &marks {
+        Some(m) => vec![
+            format!("--import-marks-if-exists={}", m.import.display()),
+            format!("--export-marks={}", m.export.display()),
+        ],
+        None => vec![],
+    };
+    fast_export_command.extend(marks_args.iter().map(|s| s.as_str()));
+    fast_export_command.extend(&["--progress", "1"]);
     if !with_blobs {
         fast_export_command.push("--no-data");
     }
+    if detect_renames {
+        // -M and -C make fast-export output R and C
+        // file operations instead of a delete and a modify
+        fast_export_command.push("-M");
+        fast_export_command.push("-C");
+    }
 
     let mut child = exechelper::spawn_with_env_ex2(
         &fast_export_command, &[], &[], repo_location,
@@ -140,3 +262,52 @@ pub fn parse_git_filter_export_with_callback<O, E: Display, P: AsRef<Path>>(
     child.wait()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn trailing_resets_are_split_into_objects() {
+        let remaining = "\nreset refs/heads/release/1.0\nfrom :3\n\nreset refs/heads/old\nfrom :1\n\ncheckpoint\n\ndone\n";
+        assert_eq!(split_trailing_commands(remaining), vec![
+            "reset refs/heads/release/1.0\nfrom :3\n".to_string(),
+            "reset refs/heads/old\nfrom :1\n".into(),
+            "checkpoint\n".into(),
+        ]);
+        assert!(split_trailing_commands("done\n").is_empty());
+    }
+
+    #[test]
+    fn tags_are_objects_even_without_a_progress_line() {
+        let stream = "commit refs/heads/master\nmark :1\ndata 3\nabc\nM 100644 :9 a.txt\n\nprogress 1 objects\n\
+            reset refs/heads/old\nfrom :1\n\n\
+            tag v1\nfrom :1\ntagger a <a@a> 0 +0000\ndata 4\nv1.0\n\
+            tag v2\nfrom :1\ntagger a <a@a> 0 +0000\ndata 4\nv2.0\n\
+            reset refs/heads/other\nfrom :1\n\ndone\n";
+        let mut objects = vec![];
+        parse_from_stream(&mut stream.as_bytes(), |obj| -> Result<(), String> {
+            objects.push((obj.before_data_str, String::from_utf8(obj.data).unwrap()));
+            Ok(())
+        }).unwrap();
+        let first_lines: Vec<_> = objects.iter()
+            .map(|(before, data)| (before.lines().next().unwrap().to_string(), data.as_str()))
+            .collect();
+        assert_eq!(first_lines, vec![
+            ("commit refs/heads/master".to_string(), "abc"),
+            ("reset refs/heads/old".into(), ""),
+            ("tag v1".into(), "v1.0"),
+            ("tag v2".into(), "v2.0"),
+            ("reset refs/heads/other".into(), ""),
+        ]);
+    }
+
+    #[test]
+    fn export_ref_args_work() {
+        assert_eq!(make_export_ref_args(&[]), vec!["master"]);
+        let refs = vec!["main".to_string(), "refs/heads/release/*".into(), "hotfix/*".into()];
+        assert_eq!(make_export_ref_args(&refs), vec![
+            "main", "--glob=refs/heads/release/*", "--branches=hotfix/*",
+        ]);
+    }
+}
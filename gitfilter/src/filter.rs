@@ -1,24 +1,57 @@
 use super::export_parser;
+use crate::{ioerr, ioerre};
 use export_parser::{StructuredExportObject, StructuredCommit};
 use export_parser::FileOpsOwned;
 use super::filter_state::FilterState;
 use super::filter_state::MAPS_TO_EMPTY;
 use super::filter_state::UNKNOWN_MAP;
+use super::tags::{self, TagOptions};
+use super::commit_rules::{CommitRule, CommitAction};
+use super::mailmap::Mailmap;
+use super::message_transforms::MessageTransforms;
+use super::incremental::{self, IncrementalPaths};
+use super::progress::ProgressReporter;
+use super::submodules::{self, SubmoduleAction, GITLINK_MODE, GITMODULES, TREE_MODE};
+use super::export_parser::MarksFiles;
 use std::io::Write;
-use std::process::Stdio;
+use std::process::{Child, ChildStdin, Stdio};
 use std::{path::{PathBuf, Path}, io};
+pub use regex::Regex;
 
 #[derive(Clone, Debug)]
 pub enum FilterRule {
     FilterRulePathInclude(String),
     FilterRulePathExclude(String),
     FilterRulePathRename(String, String),
+
+    FilterRuleGlobInclude(GlobPattern),
+    /// unlike the path exclude, a glob exclude is always definitive:
+    /// if it matches, the file is excluded regardless of the order of the rules
+    FilterRuleGlobExclude(GlobPattern),
+    /// replaces the directory prefix of the glob
+    /// (everything before the first wildcard) with the given path
+    FilterRuleGlobRename(GlobPattern, String),
+
+    FilterRuleRegexInclude(Regex),
+    /// unlike the path exclude, a regex exclude is always definitive:
+    /// if it matches, the file is excluded regardless of the order of the rules
+    FilterRuleRegexExclude(Regex),
+    /// replaces the matched portion of the path with the
+    /// given replacement string, which can reference
+    /// capture groups, eg: $1, ${name}
+    FilterRuleRegexRename(Regex, String),
+
+    /// decides what to do with an entire commit based on its
+    /// author, committer, date, or message. commit rules are checked
+    /// in order, and the first one that matches is used.
+    /// commits that dont match any commit rule are kept
+    FilterRuleCommit(CommitRule),
 }
 pub use FilterRule::*;
 
 pub type FilterRules = Vec<FilterRule>;
 #[derive(Debug)]
-pub struct FilterError(String);
+pub struct FilterError(pub(crate) String);
 
 impl From<FilterError> for io::Error {
     fn from(orig: FilterError) -> Self {
@@ -26,6 +59,170 @@ impl From<FilterError> for io::Error {
     }
 }
 
+/// a glob pattern that matches an entire path, or
+/// any directory that is a parent of that path, eg:
+/// `src/*/docs` matches `src/a/docs/readme.md`.
+/// `*` and `?` do not match a `/`, `**` matches any number
+/// of directories, and `[...]` is a character class
+#[derive(Clone, Debug)]
+pub struct GlobPattern {
+    pub glob: String,
+    /// the part of the glob up to and including the last /
+    /// before the first wildcard character
+    pub prefix: String,
+    regex: Regex,
+}
+
+impl GlobPattern {
+    pub fn new(glob: &str) -> Result<GlobPattern, FilterError> {
+        let regex_str = glob_to_regex_string(glob);
+        let regex = Regex::new(&regex_str)
+            .map_err(|e| FilterError(format!("Invalid glob pattern '{}': {}", glob, e)))?;
+        let first_wildcard = glob.find(|c| c == '*' || c == '?' || c == '[')
+            .unwrap_or(glob.len());
+        let prefix = match glob[..first_wildcard].rfind('/') {
+            Some(i) => glob[..(i + 1)].to_string(),
+            None => "".to_string(),
+        };
+        Ok(GlobPattern {
+            glob: glob.to_string(),
+            prefix,
+            regex,
+        })
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        self.regex.is_match(path)
+    }
+
+    /// replace the prefix of the glob in this path with dest.
+    /// this assumes that path is a match of this glob.
+    /// the prefix is always a directory, so dest is treated as one too
+    pub fn rename(&self, path: &str, dest: &str) -> String {
+        if dest.is_empty() || dest.ends_with('/') {
+            path.replacen(&self.prefix, dest, 1)
+        } else {
+            path.replacen(&self.prefix, &format!("{}/", dest), 1)
+        }
+    }
+}
+
+pub fn glob_to_regex_string(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut out = String::from("^");
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '*' => {
+                if chars.get(i + 1) == Some(&'*') {
+                    if chars.get(i + 2) == Some(&'/') {
+                        // **/ matches zero or more directories
+                        out.push_str("(?:.*/)?");
+                        i += 3;
+                    } else {
+                        out.push_str(".*");
+                        i += 2;
+                    }
+                    continue;
+                }
+                out.push_str("[^/]*");
+            }
+            '?' => out.push_str("[^/]"),
+            '[' => {
+                // find the end of the character class. a ] right
+                // after the [ or [! is part of the class
+                let mut end = i + 1;
+                if chars.get(end) == Some(&'!') { end += 1; }
+                if chars.get(end) == Some(&']') { end += 1; }
+                while end < chars.len() && chars[end] != ']' {
+                    end += 1;
+                }
+                if end >= chars.len() {
+                    // no closing bracket, so treat it literally
+                    out.push_str("\\[");
+                } else {
+                    out.push('[');
+                    let mut j = i + 1;
+                    if chars[j] == '!' {
+                        out.push('^');
+                        j += 1;
+                    }
+                    while j < end {
+                        match chars[j] {
+                            '\\' | '[' | ']' | '&' | '~' | '^' => {
+                                out.push('\\');
+                                out.push(chars[j]);
+                            }
+                            other => out.push(other),
+                        }
+                        j += 1;
+                    }
+                    out.push(']');
+                    i = end;
+                }
+            }
+            _ => {
+                let mut buf = [0; 4];
+                out.push_str(&regex::escape(c.encode_utf8(&mut buf)));
+            }
+        }
+        i += 1;
+    }
+    // a glob can also match a directory that contains this path
+    out.push_str("(?:/.*)?$");
+    out
+}
+
+/// the inverse of a regex rename cannot generally be known, but
+/// for checking if a path could have been produced by a regex rename,
+/// we can make a regex out of the replacement string where
+/// every capture group reference matches anything.
+pub fn regex_from_replacement(replacement: &str) -> Result<Regex, FilterError> {
+    let chars: Vec<char> = replacement.chars().collect();
+    let mut out = String::from("^");
+    let mut literal = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '$' {
+            literal.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        if chars.get(i + 1) == Some(&'$') {
+            literal.push('$');
+            i += 2;
+            continue;
+        }
+        // figure out where the group reference ends
+        let mut end = i + 1;
+        if chars.get(end) == Some(&'{') {
+            while end < chars.len() && chars[end] != '}' {
+                end += 1;
+            }
+            end += 1;
+        } else {
+            while end < chars.len() && (chars[end].is_alphanumeric() || chars[end] == '_') {
+                end += 1;
+            }
+        }
+        if end == i + 1 {
+            // a lone $ is just a literal
+            literal.push('$');
+            i += 1;
+            continue;
+        }
+        out.push_str(&regex::escape(&literal));
+        literal.clear();
+        out.push_str(".*");
+        i = end;
+    }
+    out.push_str(&regex::escape(&literal));
+    out.push_str("(?:/.*)?$");
+    Regex::new(&out)
+        .map_err(|e| FilterError(format!("Invalid replacement '{}': {}", replacement, e)))
+}
+
 /// how to use this filtered commit
 #[derive(Debug)]
 pub enum FilterResponse {
@@ -36,6 +233,10 @@ pub enum FilterResponse {
     UseAsIs,
 
     UseAsReset(FilterAsReset),
+
+    /// use it, but it does not have a parent anymore,
+    /// so the branch has to be reset before it
+    UseAsRoot,
 }
 
 #[derive(Debug)]
@@ -75,8 +276,57 @@ pub struct FilterOptions<T: Write> {
     pub stream: T,
     /// defaults to master
     pub branch: Option<String>,
+    /// opt-in: if provided, the filtered branch is written to this
+    /// branch instead of rewriting `branch` in place
+    pub output_branch: Option<String>,
+    /// opt-in: other refs to filter in the same pass as `branch`.
+    /// each can be a branch name, a full ref name, or a glob such
+    /// as `refs/heads/release/*`. every ref shares the same marks,
+    /// so commits that are in several refs are only filtered once
+    pub refs: Vec<String>,
+    /// opt-in: if provided, every filtered ref other than `branch`
+    /// is written under this namespace instead of being rewritten
+    /// in place, eg: with a namespace of `refs/split/`,
+    /// `refs/heads/release/1.0` is written to `refs/split/release/1.0`
+    pub ref_namespace: Option<String>,
     pub default_include: bool,
     pub with_blobs: bool,
+    /// opt-in: if provided, tags that point to
+    /// commits that are kept are recreated to point
+    /// to the filtered commits
+    pub tags: Option<TagOptions>,
+    /// opt-in: if provided, after filtering a file is written
+    /// to this path where every line is
+    /// `<original commit id> <filtered commit id>`
+    /// for every commit that was kept.
+    /// only used by `filter_with_rules_direct_ex` since it needs
+    /// the marks exported by git fast-import
+    pub commit_map: Option<PathBuf>,
+    /// opt-in: if true, git fast-export detects renames and copies
+    /// and the filter rules get applied to both paths of the
+    /// rename/copy instead of seeing them as a delete and a modify
+    pub detect_renames: bool,
+    /// opt-in: if provided, the names and emails of the
+    /// authors and committers of every kept commit are rewritten
+    pub mailmap: Option<Mailmap>,
+    /// opt-in: if provided, the message of every
+    /// kept commit is rewritten before it is written out
+    pub message_transforms: Option<MessageTransforms>,
+    /// opt-in: if provided, the state of the filter is saved to
+    /// this directory, and the next filter with the same directory
+    /// only filters the commits that were added since, on top
+    /// of what was already filtered. a relative directory is relative
+    /// to the location of the repository. `filter_with_rules_direct_ex`
+    /// replaces the previous state after git fast-import succeeds. other callers
+    /// have to call `incremental::commit_state` themselves once the filtered
+    /// commits were imported
+    pub incremental: Option<PathBuf>,
+    /// opt-in: if provided, it is told about every
+    /// commit that was filtered, and when the filter is done
+    pub progress: Option<ProgressReporter>,
+    /// what to do with submodules that are kept by the filter rules.
+    /// defaults to leaving them untouched. see `SubmoduleAction`
+    pub submodules: SubmoduleAction,
     // TODO:
     // pub num_threads: Option<usize>,
 }
@@ -86,8 +336,19 @@ impl<T: Write> From<T> for FilterOptions<T> {
         FilterOptions {
             stream: orig,
             branch: None,
+            output_branch: None,
+            refs: vec![],
+            ref_namespace: None,
             default_include: false,
             with_blobs: false,
+            tags: None,
+            commit_map: None,
+            detect_renames: false,
+            mailmap: None,
+            message_transforms: None,
+            incremental: None,
+            progress: None,
+            submodules: SubmoduleAction::Untouched,
         }
     }
 }
@@ -136,6 +397,39 @@ pub fn should_use_file(
                     should_keep = true;
                 }
             }
+            FilterRuleGlobInclude(glob) => {
+                if glob.is_match(check_path) {
+                    should_keep = true;
+                }
+            }
+            FilterRuleGlobExclude(glob) => {
+                if glob.is_match(check_path) {
+                    return false;
+                }
+            }
+            FilterRuleGlobRename(glob, dest) => {
+                if glob.is_match(check_path) {
+                    replace = Some(glob.rename(check_path, dest));
+                    should_keep = true;
+                }
+            }
+            FilterRuleRegexInclude(regex) => {
+                if regex.is_match(check_path) {
+                    should_keep = true;
+                }
+            }
+            FilterRuleRegexExclude(regex) => {
+                if regex.is_match(check_path) {
+                    return false;
+                }
+            }
+            FilterRuleRegexRename(regex, replacement) => {
+                if regex.is_match(check_path) {
+                    replace = Some(regex.replace(check_path, replacement.as_str()).into_owned());
+                    should_keep = true;
+                }
+            }
+            FilterRuleCommit(_) => {}
         }
     }
 
@@ -154,50 +448,347 @@ pub fn should_use_file(
     // but for our pattern matching above, it would be easier if it didnt have spaces
     // so after we filter, if we still want to keep this, and it still has
     // spaces, then we have to readd quotes to the ends of the path
-    if should_keep && re_add_quotes && path.contains(' ') {
+    if should_keep && re_add_quotes && !path.starts_with('"') && path.contains(' ') {
         *path = format!("\"{}\"", path);
     }
 
     should_keep
 }
 
+/// finds the mode and blob id of the file that was renamed or copied
+/// from `src` to `dest` by the commit with the given original id. this is
+/// needed when a file is renamed or copied from a path that
+/// we filter out into a path that we keep: the source does not
+/// exist in the filtered history, so we have to write out the blob.
+/// the source cannot simply be read from the first parent: a merge can copy
+/// from its other parents, and the source can be changed by the same commit
+/// before it is copied. what the copy wrote is what `dest` has in the
+/// commit itself, so that is used, unless the commit removed `dest` again.
+/// the paths are as fast-export writes them, ie: they can be quoted
+pub fn get_copied_blob(
+    location: Option<&PathBuf>,
+    original_oid: &str,
+    src: &str,
+    dest: &str,
+) -> Result<(String, String), FilterError> {
+    let dest = unquote_path(dest);
+    if let Some(found) = get_blob_at(location, original_oid, &dest)? {
+        return Ok(found);
+    }
+    let src = unquote_path(src);
+    let parent = format!("{}^", original_oid);
+    match get_blob_at(location, &parent, &src)? {
+        Some(found) => Ok(found),
+        None => Err(FilterError(format!("Failed to find {} before commit {}", src, original_oid))),
+    }
+}
+
+/// the mode and blob id of the file at `path` in the tree of `committish`
+fn get_blob_at(
+    location: Option<&PathBuf>,
+    committish: &str,
+    path: &str,
+) -> Result<Option<(String, String)>, FilterError> {
+    // the path is not a pattern, even if it has a * in it
+    let args = ["git", "--literal-pathspecs", "ls-tree", committish, "--", path];
+    let output = tags::run_git(&args, location)
+        .map_err(|e| FilterError(format!("Failed to find {} in {}: {}", path, committish, e)))?;
+    // <mode> SP <type> SP <object> TAB <file>
+    let mut split = output.split([' ', '\t']);
+    match (split.next(), split.next(), split.next()) {
+        (Some(mode), Some("blob"), Some(blob)) => Ok(Some((mode.to_string(), blob.to_string()))),
+        _ => Ok(None),
+    }
+}
+
+/// fast-export quotes paths that have special characters the same
+/// way as git's core.quotePath, ie: `"a \"b\"\303\251"`. returns the actual path
+pub fn unquote_path(path: &str) -> String {
+    let inner = match path.strip_prefix('"').and_then(|p| p.strip_suffix('"')) {
+        Some(inner) => inner,
+        None => return path.to_string(),
+    };
+    let mut bytes = vec![];
+    let mut chars = inner.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some(escaped) => escaped,
+            None => break,
+        };
+        let byte = match escaped {
+            'a' => 0x07,
+            'b' => 0x08,
+            'f' => 0x0c,
+            'n' => b'\n',
+            'r' => b'\r',
+            't' => b'\t',
+            'v' => 0x0b,
+            '0'..='7' => {
+                // up to 3 octal digits are one byte of the path
+                let mut value = escaped.to_digit(8).unwrap_or(0);
+                for _ in 0..2 {
+                    match chars.peek().and_then(|d| d.to_digit(8)) {
+                        Some(digit) => {
+                            value = value * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                value as u8
+            }
+            other => other as u8,
+        };
+        bytes.push(byte);
+    }
+    String::from_utf8_lossy(&bytes).into()
+}
+
+/// every file that the commit with the given original id
+/// started out with, ie: the files of its first parent. paths that
+/// contain spaces are quoted the same way that fast-export quotes them
+pub fn list_files_before_commit(
+    location: Option<&PathBuf>,
+    original_oid: &str,
+    has_parent: bool,
+) -> Result<Vec<String>, FilterError> {
+    if !has_parent {
+        return Ok(vec![]);
+    }
+    let parent = format!("{}^", original_oid);
+    let args = ["git", "ls-tree", "-r", "-z", "--name-only", parent.as_str()];
+    let output = tags::run_git(&args, location)
+        .map_err(|e| FilterError(format!("Failed to list the files before commit {}: {}", original_oid, e)))?;
+    Ok(output.split('\0').filter(|p| !p.is_empty()).map(|p| {
+        if p.contains(' ') { format!("\"{}\"", p) } else { p.to_string() }
+    }).collect())
+}
+
+/// notes refs are filtered differently: the paths of the files
+/// in a notes tree are the ids of the commits that the notes are for,
+/// so they are rewritten to point to the filtered commits instead
+pub fn is_notes_ref(ref_name: &str) -> bool {
+    ref_name.starts_with("refs/notes/")
+}
+
+/// the id of the commit that a file in a notes tree is a note for.
+/// the path can be split into directories, eg: `40/3997ae...`
+pub fn get_noted_oid(path: &str) -> Option<String> {
+    let oid: String = path.split('/').collect();
+    let is_oid = (oid.len() == 40 || oid.len() == 64)
+        && oid.chars().all(|c| c.is_ascii_hexdigit());
+    if is_oid { Some(oid) } else { None }
+}
+
+/// turns the file operations of a commit of a notes ref
+/// into note operations, ie: `M <mode> <blob> <oid>` is the same as
+/// `N <blob> <oid>`, and `D <oid>` is the same as `N 0000... <oid>`.
+/// a deleteall removes every note that the parent had.
+/// files that are not notes are left alone
+pub fn convert_note_fileops(
+    filter_state: &FilterState,
+    commit: &mut StructuredCommit,
+) -> Result<(), FilterError> {
+    let delete_note = |oid: String| {
+        FileOpsOwned::NoteModify("0".repeat(oid.len()), oid)
+    };
+    let mut newfileops = vec![];
+    for op in commit.fileops.drain(..) {
+        match op {
+            FileOpsOwned::FileModify(mode, dataref, path) => match get_noted_oid(&path) {
+                Some(oid) => newfileops.push(FileOpsOwned::NoteModify(dataref, oid)),
+                None => newfileops.push(FileOpsOwned::FileModify(mode, dataref, path)),
+            },
+            FileOpsOwned::FileDelete(path) => match get_noted_oid(&path) {
+                Some(oid) => newfileops.push(delete_note(oid)),
+                None => newfileops.push(FileOpsOwned::FileDelete(path)),
+            },
+            FileOpsOwned::FileDeleteAll => {
+                let paths = list_files_before_commit(
+                    filter_state.repo_location.as_ref(), &commit.original_oid, !commit.merges.is_empty())?;
+                for path in paths {
+                    match get_noted_oid(&path) {
+                        Some(oid) => newfileops.push(delete_note(oid)),
+                        None => newfileops.push(FileOpsOwned::FileDelete(path)),
+                    }
+                }
+            }
+            op => newfileops.push(op),
+        }
+    }
+    commit.fileops = newfileops;
+    Ok(())
+}
+
+/// the mark of the commit that a note is for, only if
+/// that commit was kept. the commit can be
+/// given as a mark, or as its original id
+pub fn get_noted_mark(filter_state: &FilterState, commitish: &str) -> Option<usize> {
+    if commitish.starts_with(':') {
+        let mark = export_parser::parse_mark_to_usize(commitish);
+        match filter_state.get_mapped_mark(mark) {
+            Some(m) if *m == mark => Some(mark),
+            _ => None,
+        }
+    } else {
+        filter_state.get_kept_mark(commitish)
+    }
+}
+
+/// true if the filtered parent of a commit has a file at this path
+pub fn parent_has_file(filter_state: &FilterState, merges: &[usize], path: &str) -> bool {
+    match merges.first().and_then(|m| filter_state.get_mapped_mark(*m)) {
+        Some(parent) => filter_state.has_file(*parent, path),
+        None => false,
+    }
+}
+
+/// true if `.gitmodules` is rewritten by `filter_gitmodules`
+/// instead of being filtered like any other file
+pub fn should_rewrite_gitmodules(filter_state: &FilterState, path: &str) -> bool {
+    path == GITMODULES && filter_state.submodules != SubmoduleAction::Untouched
+}
+
+/// a `.gitmodules` that the filter rules keep at `path` only lists the submodules
+/// that are still gitlinks after filtering, at their filtered paths.
+/// if none are left, it is deleted
+pub fn filter_gitmodules(
+    default_include: bool,
+    filter_state: &FilterState,
+    merges: &[usize],
+    filter_rules: &FilterRules,
+    mode: String,
+    dataref: String,
+    path: String,
+) -> Result<Option<FileOpsOwned>, FilterError> {
+    let location = filter_state.repo_location.as_ref();
+    let contents = submodules::read_blob(location, &dataref)?;
+    let rewritten = submodules::rewrite_gitmodules(
+        &contents, filter_state.submodules, filter_rules, default_include);
+    let op = match rewritten {
+        Some(rewritten) if rewritten == contents => {
+            Some(FileOpsOwned::FileModify(mode, dataref, path))
+        }
+        Some(rewritten) => {
+            let blob = submodules::write_blob(location, &rewritten)?;
+            Some(FileOpsOwned::FileModify(mode, blob, path))
+        }
+        None if parent_has_file(filter_state, merges, &path) => {
+            Some(FileOpsOwned::FileDelete(path))
+        }
+        None => None,
+    };
+    Ok(op)
+}
+
 pub fn apply_filter_rules_to_fileops(
     default_include: bool,
-    _filter_state: &mut FilterState,
+    filter_state: &mut FilterState,
     commit: &mut StructuredCommit,
     filter_rules: &FilterRules,
-) -> Vec<FileOpsOwned> {
+) -> Result<Vec<FileOpsOwned>, FilterError> {
     let mut newfileops = vec![];
     for op in commit.fileops.drain(..) {
+        let is_rename = matches!(op, FileOpsOwned::FileRename(_, _));
         match op {
-            // TODO: not sure if need to handle these?
-            // by not doing anything here, we are explicitly
-            // removing them, but file copy
-            // is a hard one, not sure what to do for that one
-            // is removing that ok?
-            FileOpsOwned::FileCopy(_, _) => {}
-            FileOpsOwned::FileDeleteAll => {}
-            FileOpsOwned::NoteModify(_, _) => {}
-
-            // renames are tricky too, but I think
-            // we want to drop them unless
-            // we are renaming something WITHIN the
-            // include path. ie: both src and dest
-            // must contain the path we want to include.
-            // otherwise, if we want to include B, and
-            // the rename is from A -> B, then why would we include
-            // something about A here since we are filtering it out?
-            FileOpsOwned::FileRename(_, _) => {
-                // TODO:
-                // if src.starts_with(include_path) && dest.starts_with(include_path) {
-                //     newfileops.push(FileOpsOwned::FileRename(src, dest));
-                // }
+            // a deleteall removes every file of the parent,
+            // but the filtered parent only has the files that we keep
+            FileOpsOwned::FileDeleteAll => {
+                let paths = list_files_before_commit(
+                    filter_state.repo_location.as_ref(), &commit.original_oid, !commit.merges.is_empty())?;
+                for mut path in paths {
+                    // a rewritten .gitmodules might have been deleted already
+                    let is_gitmodules = should_rewrite_gitmodules(filter_state, &path);
+                    let keep = should_use_file(&mut path, filter_rules, default_include)
+                        && (!is_gitmodules || parent_has_file(filter_state, &commit.merges, &path));
+                    if keep {
+                        newfileops.push(FileOpsOwned::FileDelete(path));
+                    }
+                }
+            }
+            FileOpsOwned::FileModify(mode, dataref, mut path)
+                if should_rewrite_gitmodules(filter_state, &path) && !dataref.starts_with(':') =>
+            {
+                if !should_use_file(&mut path, filter_rules, default_include) {
+                    continue;
+                }
+                if let Some(op) = filter_gitmodules(
+                    default_include, filter_state, &commit.merges, filter_rules, mode, dataref, path)? {
+                    newfileops.push(op);
+                }
+            }
+            FileOpsOwned::FileDelete(mut path) if should_rewrite_gitmodules(filter_state, &path) => {
+                if should_use_file(&mut path, filter_rules, default_include)
+                    && parent_has_file(filter_state, &commit.merges, &path)
+                {
+                    newfileops.push(FileOpsOwned::FileDelete(path));
+                }
+            }
+            // notes for commits that were filtered out are dropped
+            FileOpsOwned::NoteModify(dataref, commitish) => {
+                if let Some(mark) = get_noted_mark(filter_state, &commitish) {
+                    newfileops.push(FileOpsOwned::NoteModify(dataref, format!(":{}", mark)));
+                }
+            }
+
+            // renames and copies have 2 paths, and each
+            // can either be kept or filtered out:
+            // - both kept: keep it as a rename/copy of the filtered paths
+            // - only the source kept: the file was renamed out of the
+            //   paths we keep, so its a delete. a copy can be dropped since
+            //   the source still exists
+            // - only the destination kept: the file comes from a
+            //   path we filter out, so we write it as a modify with its blob
+            FileOpsOwned::FileRename(mut src, mut dest) |
+            FileOpsOwned::FileCopy(mut src, mut dest) => {
+                let src_path = src.clone();
+                let dest_path = dest.clone();
+                let use_src = should_use_file(&mut src, filter_rules, default_include);
+                let use_dest = should_use_file(&mut dest, filter_rules, default_include);
+                match (use_src, use_dest) {
+                    (true, true) => if is_rename {
+                        newfileops.push(FileOpsOwned::FileRename(src, dest));
+                    } else {
+                        newfileops.push(FileOpsOwned::FileCopy(src, dest));
+                    },
+                    (true, false) => if is_rename {
+                        newfileops.push(FileOpsOwned::FileDelete(src));
+                    },
+                    (false, true) => {
+                        let (mode, blob) = get_copied_blob(
+                            filter_state.repo_location.as_ref(), &commit.original_oid, &src_path, &dest_path)?;
+                        newfileops.push(FileOpsOwned::FileModify(mode, blob, dest));
+                    }
+                    (false, false) => {}
+                }
             }
 
             // easiest cases. if it exists, keep it
             FileOpsOwned::FileModify(mode, dataref, mut path) => {
-                if should_use_file(&mut path, filter_rules, default_include) {
+                if !should_use_file(&mut path, filter_rules, default_include) {
+                    continue;
+                }
+                if mode != GITLINK_MODE {
                     newfileops.push(FileOpsOwned::FileModify(mode, dataref, path));
+                    continue;
+                }
+                match filter_state.submodules {
+                    SubmoduleAction::Untouched |
+                    SubmoduleAction::Keep => newfileops.push(FileOpsOwned::FileModify(mode, dataref, path)),
+                    SubmoduleAction::Drop => {}
+                    // the gitlink becomes a directory with the files of the submodule
+                    // commit, and that commit becomes another parent of this commit
+                    SubmoduleAction::Merge => {
+                        let tree = submodules::get_submodule_tree(
+                            filter_state.repo_location.as_ref(), &dataref, &path)?;
+                        newfileops.push(FileOpsOwned::FileModify(TREE_MODE.into(), tree, path));
+                        commit.merge_oids.push(dataref);
+                    }
                 }
             }
             FileOpsOwned::FileDelete(mut path) => {
@@ -207,7 +798,7 @@ pub fn apply_filter_rules_to_fileops(
             }
         }
     }
-    newfileops
+    Ok(newfileops)
 }
 
 pub fn get_mapped_mark(
@@ -468,14 +1059,98 @@ pub fn perform_filter2_for_merge_commit(
     Ok(FilterResponse::UseAsIs)
 }
 
+/// returns the action of the first commit rule that matches
+/// this commit, or Keep if none match
+pub fn get_commit_action(
+    commit: &StructuredCommit,
+    filter_rules: &FilterRules,
+) -> CommitAction {
+    for rule in filter_rules {
+        if let FilterRuleCommit(commit_rule) = rule {
+            if commit_rule.is_match(commit) {
+                return commit_rule.action;
+            }
+        }
+    }
+    CommitAction::Keep
+}
+
+/// the commit is dropped entirely, so anything that points
+/// to it should point to its (first) parent instead
+pub fn perform_filter2_drop_commit(
+    filter_state: &mut FilterState,
+    commit: &mut StructuredCommit,
+) -> Result<FilterResponse, FilterError> {
+    let resolved = resolve_merges(filter_state, &commit.merges)?;
+    let maps_to = match resolved.first() {
+        Some(parent) => *parent,
+        None => MAPS_TO_EMPTY,
+    };
+    filter_state.set_mark_map(commit.mark, maps_to);
+    Ok(FilterResponse::DontUse)
+}
+
+/// we expect the commit.fileops to have already
+/// applied the filter rules.
+/// the parent of this commit was already written, so to squash
+/// into it, we write a new commit that replaces the parent: it has the parent's
+/// parents and metadata, and the changes of both commits.
+/// the original parent keeps its mark, so its other children still point to it.
+/// we only squash into a parent that does not have a kept child yet,
+/// otherwise this commit is kept as it is
+pub fn perform_filter2_squash_into_parent(
+    filter_state: &mut FilterState,
+    commit: &mut StructuredCommit,
+    parent: usize,
+) -> Result<FilterResponse, FilterError> {
+    let resolved_parent = get_mapped_mark(filter_state, parent)?;
+    // nothing to squash into, or nothing to squash
+    if resolved_parent == MAPS_TO_EMPTY
+        || commit.fileops.is_empty()
+        || parent_has_same_contents(filter_state, resolved_parent, &commit.fileops)?
+    {
+        return perform_filter2_for_regular_commit(filter_state, commit, parent);
+    }
+
+    // the parent is only in the kept commits until one of its children is
+    // kept, and an incremental filter does not have the commits of the
+    // previous filter. either way, we cant replace the parent anymore
+    let base = match filter_state.kept_commits.remove(&resolved_parent) {
+        Some(base) => base,
+        None => return perform_filter2_for_regular_commit(filter_state, commit, parent),
+    };
+    let mut fileops = base.fileops;
+    fileops.append(&mut commit.fileops);
+    commit.merges = base.merges;
+    commit.author = base.author;
+    commit.committer = base.committer;
+    commit.commit_message = base.commit_message;
+
+    filter_state.set_mark_map(commit.mark, commit.mark);
+    filter_state.update_graph(commit.mark, &commit.merges);
+    // the parent's contents already include the parent's changes
+    filter_state.using_commit_with_contents(commit.mark, &[resolved_parent], &fileops);
+    commit.fileops = fileops;
+
+    if commit.merges.is_empty() {
+        return Ok(FilterResponse::UseAsRoot);
+    }
+    Ok(FilterResponse::UseAsIs)
+}
+
 pub fn perform_filter2(
     default_include: bool,
     filter_state: &mut FilterState,
     commit: &mut StructuredCommit,
     filter_rules: &FilterRules,
 ) -> Result<FilterResponse, FilterError> {
+    let action = get_commit_action(commit, filter_rules);
+    if action == CommitAction::Drop {
+        return perform_filter2_drop_commit(filter_state, commit);
+    }
+
     let newfileops = apply_filter_rules_to_fileops(
-        default_include, filter_state, commit, filter_rules);
+        default_include, filter_state, commit, filter_rules)?;
     commit.fileops = newfileops;
 
     let resp = match commit.merges.len() {
@@ -486,12 +1161,91 @@ pub fn perform_filter2(
         // regular commit with 1 parent
         1 => {
             let parent = commit.merges[0];
-            perform_filter2_for_regular_commit(filter_state, commit, parent)
+            if action == CommitAction::Squash {
+                perform_filter2_squash_into_parent(filter_state, commit, parent)
+            } else {
+                perform_filter2_for_regular_commit(filter_state, commit, parent)
+            }
         },
         // two or more parents: merge commit
         _ => perform_filter2_for_merge_commit(filter_state, commit)
     };
-    resp
+    let resp = resp?;
+    // we might need to squash future commits into this one. the commit is
+    // written to the stream after this, so we need our own copy of it.
+    // only commits without kept children can be squashed into, so
+    // the parents of this commit are not needed anymore
+    if filter_state.store_kept_commits && resp.is_used() {
+        for parent in commit.merges.iter() {
+            filter_state.kept_commits.remove(parent);
+        }
+        filter_state.kept_commits.insert(commit.mark, commit.clone());
+    }
+    Ok(resp)
+}
+
+impl<T: Write> FilterOptions<T> {
+    /// the refs to pass to git fast-export: `branch` first,
+    /// and then the other `refs`
+    pub fn get_export_refs(&self) -> Vec<String> {
+        let mut export_refs = vec![self.branch.clone().unwrap_or_else(|| "master".into())];
+        export_refs.extend(self.refs.iter().cloned());
+        export_refs
+    }
+
+    /// describes everything that changes which commits are
+    /// kept and how they are rewritten. an incremental filter can only resume
+    /// from a previous filter that had the exact same options
+    pub fn get_incremental_options(&self, filter_rules: &FilterRules) -> String {
+        format!(
+            "{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{} {} {}\n",
+            self.get_export_refs(), self.ref_namespace, filter_rules,
+            self.mailmap, self.message_transforms, self.submodules,
+            self.default_include, self.with_blobs, self.detect_renames,
+        )
+    }
+}
+
+/// returns the ref that a filtered ref is written to.
+/// the branch is rewritten in place, unless an output branch
+/// is provided. other refs are written under the namespace if one is provided:
+/// `refs/heads/<name>` becomes `<namespace><name>`, and
+/// any other `refs/<name>` also becomes `<namespace><name>`.
+/// except for tags and notes, which git only uses under `refs/tags/`
+/// and `refs/notes/`, so the namespace goes after that instead, eg:
+/// `refs/notes/commits` becomes `refs/notes/<namespace without refs/>commits`
+pub fn get_output_ref(
+    ref_name: &str,
+    branch: &str,
+    output_branch: Option<&str>,
+    ref_namespace: Option<&str>,
+) -> String {
+    let short_name = ref_name.strip_prefix("refs/heads/");
+    if ref_name == branch || short_name == Some(branch) {
+        return match output_branch {
+            Some(o) if o.starts_with("refs/") => o.to_string(),
+            Some(o) => format!("refs/heads/{}", o),
+            None => ref_name.to_string(),
+        };
+    }
+    let namespace = match ref_namespace {
+        Some(n) => n,
+        None => return ref_name.to_string(),
+    };
+    for prefix in ["refs/tags/", "refs/notes/"].iter() {
+        if let Some(name) = ref_name.strip_prefix(prefix) {
+            let namespace = namespace.strip_prefix("refs/").unwrap_or(namespace).trim_end_matches('/');
+            return format!("{}{}/{}", prefix, namespace, name);
+        }
+    }
+    let short_name = short_name
+        .or_else(|| ref_name.strip_prefix("refs/"))
+        .unwrap_or(ref_name);
+    if namespace.ends_with('/') {
+        format!("{}{}", namespace, short_name)
+    } else {
+        format!("{}/{}", namespace, short_name)
+    }
 }
 
 pub fn filter_with_rules<P: AsRef<Path>, T: Write>(
@@ -499,19 +1253,182 @@ pub fn filter_with_rules<P: AsRef<Path>, T: Write>(
     filter_rules: FilterRules,
     location: Option<P>,
 ) -> io::Result<()> {
-    // eprintln!("Filter rules: {:#?}", filter_rules);
-    let mut filter_state = FilterState::default();
-    let default_include = filter_options.default_include;
-    let cb = |obj: &mut StructuredExportObject| -> io::Result<bool> {
+    filter_with_rules_ex(filter_options, filter_rules, location)?;
+    Ok(())
+}
+
+/// filters one export stream with one set of rules, and writes the
+/// result to its stream. every object of the export stream is given
+/// to `write_object` in order, and then `finish` writes everything
+/// that can only be written after all of the commits
+pub struct FilterPipeline<T: Write> {
+    pub stream: T,
+    pub filter_state: FilterState,
+    filter_rules: FilterRules,
+    default_include: bool,
+    tag_options: Option<TagOptions>,
+    mailmap: Option<Mailmap>,
+    message_transforms: Option<MessageTransforms>,
+    track_original_oids: bool,
+    branch: String,
+    output_branch: Option<String>,
+    ref_namespace: Option<String>,
+    filtering_several_refs: bool,
+    track_ref_tips: bool,
+    incremental: Option<(IncrementalPaths, String)>,
+    progress: Option<ProgressReporter>,
+    /// the commits of notes refs, see `write_object`
+    deferred_notes: Vec<StructuredExportObject>,
+    location: Option<PathBuf>,
+    /// the arguments of the export stream that this pipeline expects
+    pub export_refs: Vec<String>,
+    pub with_blobs: bool,
+    pub detect_renames: bool,
+    pub marks: Option<MarksFiles>,
+}
+
+impl<T: Write> FilterPipeline<T> {
+    pub fn new<P: AsRef<Path>>(
+        filter_options: FilterOptions<T>,
+        filter_rules: FilterRules,
+        location: Option<P>,
+    ) -> io::Result<FilterPipeline<T>> {
+        let mut filter_options = filter_options;
+        let location: Option<PathBuf> = location.map(|l| l.as_ref().to_path_buf());
+        let incremental = filter_options.incremental.as_ref().map(|dir| (
+            IncrementalPaths::new(location.as_ref(), dir),
+            filter_options.get_incremental_options(&filter_rules),
+        ));
+        let mut filter_state = match &incremental {
+            Some((paths, options)) => incremental::load_state(paths, options)?.unwrap_or_default(),
+            None => FilterState::default(),
+        };
+        filter_state.store_kept_commits = filter_rules.iter().any(|rule| {
+            matches!(rule, FilterRuleCommit(r) if r.action == CommitAction::Squash)
+        });
+        filter_state.repo_location = location.clone();
+        filter_state.submodules = filter_options.submodules;
+        let filtering_several_refs = !filter_options.refs.is_empty();
+        // when filtering several refs, the tip of a ref might get filtered
+        // out, or be a commit that was exported under a different ref.
+        // so we remember the original mark of the tip of every ref,
+        // and reset every ref to what its tip was mapped to at the end.
+        // when filtering incrementally, a ref might not have any new commits,
+        // so we do the same to point it at the previously filtered commit
+        let track_ref_tips = filtering_several_refs || incremental.is_some();
+        if let Some((paths, _)) = &incremental {
+            // no need to export what was already filtered
+            filter_options.refs.extend(incremental::get_excluded_tips(paths, &filter_state)?);
+            filter_state.ref_tips.clear();
+        }
+        let marks = incremental.as_ref().map(|(paths, _)| paths.export_marks_files());
+        // notes can be for any commit, and are found by the original id of the commit
+        let exports_notes = filter_options.refs.iter()
+            .any(|r| is_notes_ref(r) || r.starts_with("refs/*"));
+        Ok(FilterPipeline {
+            export_refs: filter_options.get_export_refs(),
+            stream: filter_options.stream,
+            filter_state,
+            filter_rules,
+            default_include: filter_options.default_include,
+            track_original_oids: filter_options.tags.is_some() || filter_options.commit_map.is_some() || exports_notes,
+            tag_options: filter_options.tags,
+            mailmap: filter_options.mailmap,
+            message_transforms: filter_options.message_transforms,
+            branch: filter_options.branch.unwrap_or_else(|| "master".into()),
+            output_branch: filter_options.output_branch,
+            ref_namespace: filter_options.ref_namespace,
+            filtering_several_refs,
+            track_ref_tips,
+            incremental,
+            progress: filter_options.progress,
+            deferred_notes: vec![],
+            location,
+            with_blobs: filter_options.with_blobs,
+            detect_renames: filter_options.detect_renames,
+            marks,
+        })
+    }
+
+    fn get_output_ref(&self, ref_name: &str) -> String {
+        get_output_ref(ref_name, &self.branch, self.output_branch.as_deref(), self.ref_namespace.as_deref())
+    }
+
+    /// filters the object in place. returns true if
+    /// the object should be written to the stream
+    pub fn filter_object(&mut self, obj: &mut StructuredExportObject) -> io::Result<bool> {
+        if let Some(reset_ref) = &obj.has_reset {
+            obj.has_reset = Some(self.get_output_ref(reset_ref));
+        }
+        let filter_state = &mut self.filter_state;
         // TODO: filter on blobs as well:
         match &mut obj.object_type {
             export_parser::StructuredObjectType::Blob(_) => Ok(true),
+            // a ref that points to a commit that was already exported
+            export_parser::StructuredObjectType::NoType => {
+                let from_mark = obj.has_reset_from.as_ref()
+                    .and_then(|from| from.strip_prefix(':'))
+                    .and_then(|mark| mark.parse::<usize>().ok());
+                match (&obj.has_reset, from_mark, self.track_ref_tips) {
+                    (Some(reset_ref), Some(from_mark), true) => {
+                        filter_state.ref_tips.insert(reset_ref.clone(), from_mark);
+                        Ok(false)
+                    }
+                    _ => Ok(true),
+                }
+            }
+            // tags of commits that were filtered out
+            // point to the commit they were mapped to
+            export_parser::StructuredObjectType::Tag(ref mut t) => {
+                let mapped = match filter_state.get_mapped_mark(t.from) {
+                    Some(&m) if m != MAPS_TO_EMPTY && m != UNKNOWN_MAP => m,
+                    _ => return Ok(false),
+                };
+                t.from = mapped;
+                if let Some(mailmap) = &self.mailmap {
+                    mailmap.apply(&mut t.tagger);
+                }
+                let output_ref = get_output_ref(
+                    &format!("refs/tags/{}", t.name), &self.branch, None, self.ref_namespace.as_deref());
+                if let Some(name) = output_ref.strip_prefix("refs/tags/") {
+                    t.name = name.to_string();
+                }
+                Ok(true)
+            }
             export_parser::StructuredObjectType::Commit(ref mut c) => {
-                let resp = perform_filter2(default_include, &mut filter_state, c, &filter_rules)?;
+                if is_notes_ref(&c.commit_ref) {
+                    convert_note_fileops(filter_state, c)?;
+                }
+                c.commit_ref = get_output_ref(
+                    &c.commit_ref, &self.branch, self.output_branch.as_deref(), self.ref_namespace.as_deref());
+                if self.track_ref_tips {
+                    filter_state.ref_tips.insert(c.commit_ref.clone(), c.mark);
+                }
+                if self.track_original_oids {
+                    filter_state.original_oid_map.insert(c.original_oid.clone(), c.mark);
+                }
+                let resp = perform_filter2(self.default_include, filter_state, c, &self.filter_rules)?;
                 if !filter_state.have_used_a_commit && resp.is_used() {
                     filter_state.have_used_a_commit = true;
                 }
                 let is_used = resp.is_used();
+                if let (Some(mailmap), true) = (&self.mailmap, is_used) {
+                    if let export_parser::AuthorPerson::Author(ref mut author) = c.author {
+                        mailmap.apply(author);
+                    }
+                    mailmap.apply(&mut c.committer);
+                }
+                if let (Some(message_transforms), true) = (&self.message_transforms, is_used) {
+                    message_transforms.apply(c);
+                }
+                // without a parent of its own, the first merged in submodule commit
+                // becomes the 'from', but we dont want to start out with its files
+                if c.merges.is_empty() && !c.merge_oids.is_empty() {
+                    c.fileops.insert(0, FileOpsOwned::FileDeleteAll);
+                }
+                if let FilterResponse::UseAsRoot = resp {
+                    obj.has_reset = Some(c.commit_ref.clone());
+                }
                 if let Some(reset) = resp.is_a_reset() {
                     match reset {
                         FilterAsReset::AsReset(resetref) => {
@@ -524,12 +1441,192 @@ pub fn filter_with_rules<P: AsRef<Path>, T: Write>(
                     }
                     obj.object_type = export_parser::StructuredObjectType::NoType;
                 }
+                if let Some(progress) = &mut self.progress {
+                    progress.commit_processed(is_used);
+                }
                 Ok(is_used)
             },
-            _ => Ok(true),
         }
+    }
+
+    /// filters the object and writes it to the stream. the commits of
+    /// notes refs are written after every other commit, because the notes
+    /// can be for commits that come after them in the export stream
+    pub fn write_object(&mut self, obj: StructuredExportObject) -> io::Result<()> {
+        if let export_parser::StructuredObjectType::Commit(c) = &obj.object_type {
+            if is_notes_ref(&c.commit_ref) {
+                // features have to be at the start of the stream
+                let mut obj = obj;
+                if obj.has_feature_done {
+                    obj.has_feature_done = false;
+                    self.stream.write_all(b"feature done\n")?;
+                }
+                self.deferred_notes.push(obj);
+                return Ok(());
+            }
+        }
+        self.write_filtered_object(obj)
+    }
+
+    fn write_filtered_object(&mut self, obj: StructuredExportObject) -> io::Result<()> {
+        let mut obj = obj;
+        if self.filter_object(&mut obj)? {
+            export_parser::write_to_stream(&mut self.stream, obj)?;
+        }
+        Ok(())
+    }
+
+    /// writes the refs, and tags, and the final `done` to the stream.
+    /// if the filter options have a `commit_map`, then this returns the
+    /// original commit id and the mark of every commit that was kept
+    pub fn finish(mut self) -> io::Result<(T, Vec<(String, usize)>)> {
+        for obj in std::mem::take(&mut self.deferred_notes) {
+            self.write_filtered_object(obj)?;
+        }
+        let mut stream = self.stream;
+        let filter_state = self.filter_state;
+        let mut progress = self.progress;
+        for (ref_name, tip_mark) in filter_state.ref_tips.iter() {
+            let mapped = match filter_state.get_mapped_mark(*tip_mark) {
+                Some(&m) if m != MAPS_TO_EMPTY && m != UNKNOWN_MAP => m,
+                // every commit of this ref was filtered out
+                _ => continue,
+            };
+            let obj = StructuredExportObject {
+                has_reset: Some(ref_name.clone()),
+                has_reset_from: Some(format!(":{}", mapped)),
+                object_type: export_parser::StructuredObjectType::NoType,
+                ..Default::default()
+            };
+            export_parser::write_to_stream(&mut stream, obj)?;
+        }
+
+        // tags are created after all of the commits
+        // so that the marks they point to already exist
+        if let Some(tag_options) = self.tag_options {
+            let merged_into = if self.filtering_several_refs { None } else { Some(self.branch.as_str()) };
+            let tag_objects = tags::make_tag_objects(
+                &tag_options, merged_into, self.location.as_ref(), self.mailmap.as_ref(),
+                |oid| filter_state.get_kept_mark(oid),
+            )?;
+            for obj in tag_objects {
+                export_parser::write_to_stream(&mut stream, obj)?;
+            }
+        }
+
+        stream.write_all(b"done\n")?;
+        if let Some(progress) = &mut progress {
+            progress.finish();
+        }
+
+        if let Some((paths, options)) = &self.incremental {
+            incremental::save_state(paths, options, &filter_state)?;
+        }
+
+        let mut kept = vec![];
+        if self.track_original_oids {
+            for oid in filter_state.original_oid_map.keys() {
+                if let Some(mark) = filter_state.get_kept_mark(oid) {
+                    kept.push((oid.clone(), mark));
+                }
+            }
+        }
+        Ok((stream, kept))
+    }
+}
+
+/// like `filter_with_rules`, but if the filter options
+/// have a `commit_map`, then this returns the
+/// original commit id and the mark of every commit that was kept
+pub fn filter_with_rules_ex<P: AsRef<Path>, T: Write>(
+    filter_options: FilterOptions<T>,
+    filter_rules: FilterRules,
+    location: Option<P>,
+) -> io::Result<Vec<(String, usize)>> {
+    // eprintln!("Filter rules: {:#?}", filter_rules);
+    let mut pipeline = FilterPipeline::new(filter_options, filter_rules, location)?;
+    export_parser::parse_git_filter_export_via_channel(
+        pipeline.export_refs.clone(), pipeline.with_blobs, pipeline.detect_renames,
+        None, pipeline.marks.clone(), pipeline.location.clone(),
+        |obj| pipeline.write_object(obj),
+    )?;
+    let (_, kept) = pipeline.finish()?;
+    Ok(kept)
+}
+
+/// like `filter_with_rules_ex`, but filters several sets of options and
+/// rules while only reading the history once: every object of the export
+/// stream is given to every filter, and each filter writes to its own stream.
+/// every filter has to export the same refs, so use `output_branch` to
+/// write each filter to a different branch. returns what `filter_with_rules_ex`
+/// returns for each filter, in the same order.
+/// incremental filters are not supported because they export different commits
+pub fn filter_with_rules_multiplexed_ex<P: AsRef<Path>, T: Write>(
+    targets: Vec<(FilterOptions<T>, FilterRules)>,
+    location: Option<P>,
+) -> io::Result<Vec<Vec<(String, usize)>>> {
+    let location: Option<PathBuf> = location.map(|l| l.as_ref().to_path_buf());
+    let mut pipelines = vec![];
+    for (filter_options, filter_rules) in targets {
+        if filter_options.incremental.is_some() {
+            return ioerre!("Cannot filter incrementally when filtering several rules in one pass");
+        }
+        pipelines.push(FilterPipeline::new(filter_options, filter_rules, location.as_ref())?);
+    }
+    let first = match pipelines.first() {
+        Some(p) => p,
+        None => return Ok(vec![]),
     };
-    filter_with_cb(filter_options, location, cb)
+    let (export_refs, with_blobs, detect_renames) = (first.export_refs.clone(), first.with_blobs, first.detect_renames);
+    for pipeline in pipelines.iter() {
+        if pipeline.export_refs != export_refs
+            || pipeline.with_blobs != with_blobs
+            || pipeline.detect_renames != detect_renames
+        {
+            return ioerre!("Every filter has to export the same refs with the same options to be filtered in one pass. Expected {:?}, but found {:?}", export_refs, pipeline.export_refs);
+        }
+    }
+
+    export_parser::parse_git_filter_export_via_channel(
+        export_refs, with_blobs, detect_renames, None, None, location,
+        |obj| -> io::Result<()> {
+            if let Some((last, rest)) = pipelines.split_last_mut() {
+                for pipeline in rest {
+                    pipeline.write_object(obj.clone())?;
+                }
+                last.write_object(obj)?;
+            }
+            Ok(())
+        },
+    )?;
+
+    let mut out = vec![];
+    for pipeline in pipelines {
+        let (_, kept) = pipeline.finish()?;
+        out.push(kept);
+    }
+    Ok(out)
+}
+
+/// given the contents of a marks file exported by git fast-import
+/// (`:<mark> <oid>` per line) and the kept commits of a filter,
+/// returns the contents of a commit map file:
+/// `<original oid> <filtered oid>` per line.
+pub fn make_commit_map_contents(
+    marks_file: &str,
+    kept: &[(String, usize)],
+) -> String {
+    let mark_to_oid = incremental::parse_marks_file(marks_file);
+    let mut out = String::new();
+    for (original, mark) in kept {
+        if let Some(oid) = mark_to_oid.get(mark) {
+            out.push_str(original);
+            out.push(' ');
+            out.push_str(oid);
+            out.push('\n');
+        }
+    }
+    out
 }
 
 // temporary function to test out filtering
@@ -538,11 +1635,28 @@ pub fn filter_with_cb<P: AsRef<Path>, T: Write, F: Into<FilterOptions<T>>>(
     location: Option<P>,
     cb: impl FnMut(&mut StructuredExportObject) -> io::Result<bool>,
 ) -> io::Result<()> {
+    let mut stream = filter_with_cb_ex(options, location, cb)?;
+    stream.write_all(b"done\n")?;
+
+    Ok(())
+}
+
+/// like `filter_with_cb`, but does not write the final
+/// `done` to the stream. instead the stream is returned
+/// so that more objects can be written to it
+pub fn filter_with_cb_ex<P: AsRef<Path>, T: Write, F: Into<FilterOptions<T>>>(
+    options: F,
+    location: Option<P>,
+    cb: impl FnMut(&mut StructuredExportObject) -> io::Result<bool>,
+) -> io::Result<T> {
     let options: FilterOptions<T> = options.into();
+    let export_refs = options.get_export_refs();
+    let marks = options.incremental.as_ref()
+        .map(|dir| IncrementalPaths::new(location.as_ref(), dir).export_marks_files());
     let mut stream = options.stream;
     let mut cb = cb;
     export_parser::parse_git_filter_export_via_channel(
-        options.branch, options.with_blobs, None, location,
+        export_refs, options.with_blobs, options.detect_renames, None, marks, location,
         |mut obj| {
             let succeeded = cb(&mut obj)?;
             if succeeded {
@@ -552,9 +1666,134 @@ pub fn filter_with_cb<P: AsRef<Path>, T: Write, F: Into<FilterOptions<T>>>(
         }
     )?;
 
-    stream.write_all(b"done\n")?;
+    Ok(stream)
+}
 
-    Ok(())
+impl<T: Write> FilterOptions<T> {
+    /// returns the same options, but writing to a different stream
+    pub fn with_stream<S: Write>(self, stream: S) -> FilterOptions<S> {
+        FilterOptions {
+            stream,
+            branch: self.branch,
+            output_branch: self.output_branch,
+            refs: self.refs,
+            ref_namespace: self.ref_namespace,
+            default_include: self.default_include,
+            with_blobs: self.with_blobs,
+            tags: self.tags,
+            commit_map: self.commit_map,
+            detect_renames: self.detect_renames,
+            mailmap: self.mailmap,
+            message_transforms: self.message_transforms,
+            incremental: self.incremental,
+            progress: self.progress,
+            submodules: self.submodules,
+        }
+    }
+}
+
+/// a git fast-import process that the filtered
+/// objects of one filter are written to
+pub struct FastImport {
+    pub child: Child,
+    commit_map: Option<PathBuf>,
+    marks_file: Option<PathBuf>,
+    incremental_paths: Option<IncrementalPaths>,
+    location: Option<PathBuf>,
+}
+
+impl FastImport {
+    pub fn spawn<T: Write, P: AsRef<Path>>(
+        filter_options: &FilterOptions<T>,
+        location: Option<P>,
+    ) -> io::Result<FastImport> {
+        let location: Option<PathBuf> = location.map(|l| l.as_ref().to_path_buf());
+        let mut exe_and_args = vec![
+            "git".to_string(), "-c".into(), "core.ignorecase=false".into(), "fast-import".into(),
+            "--date-format=raw-permissive".into(), "--force".into(), "--quiet".into(),
+        ];
+        // when filtering incrementally, fast-import needs the marks
+        // of the previously filtered commits, and it keeps
+        // its marks for the next filter
+        let incremental_paths = filter_options.incremental.as_ref()
+            .map(|dir| IncrementalPaths::new(location.as_ref(), dir));
+        let import_marks = incremental_paths.as_ref().map(|p| p.import_marks_files());
+        let marks_file = match &import_marks {
+            Some(marks) => Some(marks.export.clone()),
+            None => filter_options.commit_map.as_ref().map(|p| {
+                let mut marks = p.clone().into_os_string();
+                marks.push(".marks");
+                PathBuf::from(marks)
+            }),
+        };
+        if let Some(marks) = &import_marks {
+            exe_and_args.push(format!("--import-marks-if-exists={}", marks.import.display()));
+            if let Some(parent) = marks.export.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        if let Some(marks) = &marks_file {
+            exe_and_args.push(format!("--export-marks={}", marks.display()));
+        }
+        let exe_and_args: Vec<&str> = exe_and_args.iter().map(|s| s.as_str()).collect();
+        let child = exechelper::spawn_with_env_ex2(
+            &exe_and_args,
+            &[], &[],
+            location.clone(),
+            Some(Stdio::piped()),
+            Some(Stdio::null()),
+            Some(Stdio::null())
+        )?;
+        Ok(FastImport {
+            child,
+            commit_map: filter_options.commit_map.clone(),
+            marks_file,
+            incremental_paths,
+            location,
+        })
+    }
+
+    pub fn take_stdin(&mut self) -> io::Result<ChildStdin> {
+        self.child.stdin.take().ok_or_else(|| ioerr!("Failed to take stdin of git fast-import"))
+    }
+
+    /// waits for fast-import to finish importing what the filter wrote to it.
+    /// `filtered` is the result of the filter. if both succeeded, the commit
+    /// map is written, and the incremental state replaces the previous one
+    pub fn finish(self, filtered: io::Result<Vec<(String, usize)>>) -> io::Result<()> {
+        let mut child = self.child;
+        // if the filter failed, its stream was already
+        // dropped, so fast-import will see the end of its input
+        let waited = child.wait();
+        let kept = match (filtered, waited) {
+            (Ok(kept), Ok(status)) if status.success() => kept,
+            (_, Err(e)) => return Err(e),
+            (Err(e), _) => return Err(e),
+            // the marks that fast-import wrote, if any, do not
+            // match what was imported, so we cannot use them
+            (Ok(_), Ok(status)) => return ioerre!("git fast-import failed with {}", status),
+        };
+
+        if let (Some(commit_map), Some(marks)) = (self.commit_map, &self.marks_file) {
+            // the marks are relative to the location
+            // that fast-import ran in:
+            let marks_path = match (&self.location, marks.is_relative()) {
+                (Some(l), true) => l.join(marks),
+                _ => marks.clone(),
+            };
+            let marks_contents = std::fs::read_to_string(&marks_path)?;
+            if self.incremental_paths.is_none() {
+                let _ = std::fs::remove_file(&marks_path);
+            }
+            let contents = make_commit_map_contents(&marks_contents, &kept);
+            std::fs::write(commit_map, contents)?;
+        }
+
+        if let Some(paths) = &self.incremental_paths {
+            incremental::commit_state(paths)?;
+        }
+        Ok(())
+    }
 }
 
 pub fn filter_with_rules_direct_ex<P: AsRef<Path>, T: Write>(
@@ -562,41 +1801,42 @@ pub fn filter_with_rules_direct_ex<P: AsRef<Path>, T: Write>(
     filter_rules: FilterRules,
     location: Option<P>,
 ) -> io::Result<()> {
-    let exe_and_args = [
-        "git", "-c", "core.ignorecase=false", "fast-import", "--date-format=raw-permissive", "--force", "--quiet"
-    ];
-    let location_clone = match location {
-        Some(ref l) => Some(l.as_ref().to_owned()),
-        None => None,
-    };
-    let mut gitimport_handle = exechelper::spawn_with_env_ex2(
-        &exe_and_args,
-        &[], &[],
-        location_clone,
-        Some(Stdio::piped()),
-        Some(Stdio::null()),
-        Some(Stdio::null())
-    )?;
-
-    let gitimport_stdin = gitimport_handle.stdin.as_mut().ok_or_else(|| std::io::ErrorKind::InvalidInput)?;
-    let overwritten_options = FilterOptions {
-        stream: gitimport_stdin,
-        branch: filter_options.branch,
-        default_include: filter_options.default_include,
-        with_blobs: filter_options.with_blobs,
-    };
+    let mut fast_import = FastImport::spawn(&filter_options, location.as_ref())?;
+    let gitimport_stdin = fast_import.take_stdin()?;
+    let overwritten_options = filter_options.with_stream(gitimport_stdin);
+    let res = filter_with_rules_ex(overwritten_options, filter_rules, location);
+    fast_import.finish(res)
+}
 
-    let res = filter_with_rules(overwritten_options, filter_rules, location);
-    let res2 = gitimport_handle.wait();
-    if res.is_ok() && res2.is_ok() {
-        return Ok(());
+/// like `filter_with_rules_direct_ex`, but filters several sets of
+/// options and rules in one pass over the history, see `filter_with_rules_multiplexed_ex`.
+/// every filter is piped into its own git fast-import
+pub fn filter_with_rules_multiplexed_direct_ex<P: AsRef<Path>, T: Write>(
+    targets: Vec<(FilterOptions<T>, FilterRules)>,
+    location: Option<P>,
+) -> io::Result<()> {
+    let mut fast_imports = vec![];
+    let mut overwritten_targets = vec![];
+    for (filter_options, filter_rules) in targets {
+        let mut fast_import = FastImport::spawn(&filter_options, location.as_ref())?;
+        let gitimport_stdin = fast_import.take_stdin()?;
+        overwritten_targets.push((filter_options.with_stream(gitimport_stdin), filter_rules));
+        fast_imports.push(fast_import);
     }
-
-    if let Err(e) = res2 {
-        Err(e)
-    } else {
-        res
+    let all_kept = match filter_with_rules_multiplexed_ex(overwritten_targets, location) {
+        Ok(all_kept) => all_kept,
+        Err(e) => {
+            // every fast-import still has to exit before we return
+            for fast_import in fast_imports {
+                let _ = fast_import.finish(Err(ioerr!("{}", e)));
+            }
+            return Err(e);
+        }
+    };
+    for (fast_import, kept) in fast_imports.into_iter().zip(all_kept) {
+        fast_import.finish(Ok(kept))?;
     }
+    Ok(())
 }
 
 /// filter from your given rules and options, and pipe directly
@@ -640,6 +1880,38 @@ mod test {
         }).unwrap();
     }
 
+    #[test]
+    fn multiplexed_filters_are_the_same_as_separate_filters() {
+        let make_options = |writer: Vec<u8>, default_include: bool, output_branch: &str| {
+            let mut options: FilterOptions<Vec<u8>> = writer.into();
+            options.default_include = default_include;
+            options.output_branch = Some(output_branch.into());
+            options
+        };
+        let toml_rules = vec![FilterRulePathInclude("Cargo.toml".into())];
+        let mut expected = vec![];
+        for (default_include, output_branch, rules) in [(true, "everything", vec![]), (false, "toml", toml_rules.clone())] {
+            let mut pipeline = FilterPipeline::new(
+                make_options(vec![], default_include, output_branch), rules, NO_LOCATION).unwrap();
+            export_parser::parse_git_filter_export_via_channel(
+                pipeline.export_refs.clone(), false, false, Some(1), None, NO_LOCATION,
+                |obj| pipeline.write_object(obj),
+            ).unwrap();
+            expected.push(pipeline.finish().unwrap().0);
+        }
+
+        let mut everything = vec![];
+        let mut toml = vec![];
+        let targets = vec![
+            (make_options(vec![], true, "everything").with_stream(&mut everything), vec![]),
+            (make_options(vec![], false, "toml").with_stream(&mut toml), toml_rules),
+        ];
+        filter_with_rules_multiplexed_ex(targets, NO_LOCATION).unwrap();
+        assert_eq!(String::from_utf8_lossy(&everything), String::from_utf8_lossy(&expected[0]));
+        assert_eq!(String::from_utf8_lossy(&toml), String::from_utf8_lossy(&expected[1]));
+        assert!(String::from_utf8_lossy(&everything).contains("commit refs/heads/everything\n"));
+    }
+
     // commenting this test case because
     // 1. it's not that important, we don't use this feature actually
     // 2. CI pipeline cannot run the git fast export properly because
@@ -697,7 +1969,7 @@ mod test {
             &mut filter_state,
             &mut commit,
             &filter_rules
-        );
+        ).unwrap();
 
         let expected = FileOpsOwned::FileModify(
             "".into(), "".into(), "b.txt".into(),
@@ -722,7 +1994,7 @@ mod test {
             &mut filter_state,
             &mut commit,
             &filter_rules
-        );
+        ).unwrap();
 
         let expected1 = FileOpsOwned::FileModify(
             "".into(), "".into(), "a.txt".into(),
@@ -750,7 +2022,7 @@ mod test {
             &mut filter_state,
             &mut commit,
             &filter_rules
-        );
+        ).unwrap();
 
         let expected1 = FileOpsOwned::FileModify(
             "".into(), "".into(), "nospace/a.txt".into(),
@@ -778,7 +2050,7 @@ mod test {
             &mut filter_state,
             &mut commit,
             &filter_rules
-        );
+        ).unwrap();
 
         let expected1 = FileOpsOwned::FileModify(
             "".into(), "".into(), "\"with space/a.txt\"".into(),
@@ -823,7 +2095,7 @@ mod test {
             &mut filter_state,
             &mut commit,
             &filter_rules
-        );
+        ).unwrap();
 
         let expected = FileOpsOwned::FileModify(
             "".into(), "".into(), "src/a/".into(),
@@ -847,7 +2119,7 @@ mod test {
             &mut filter_state,
             &mut commit,
             &filter_rules
-        );
+        ).unwrap();
 
         let expected1 = FileOpsOwned::FileModify(
             "".into(), "".into(), "src/a/".into(),
@@ -876,7 +2148,7 @@ mod test {
             &mut filter_state,
             &mut commit,
             &filter_rules
-        );
+        ).unwrap();
 
         let expected = vec![FileOpsOwned::FileModify(
             "".into(), "".into(), "src/a/".into(),
@@ -906,7 +2178,7 @@ mod test {
             &mut filter_state,
             &mut commit,
             &filter_rules
-        );
+        ).unwrap();
 
         let expected = vec![
             "a.txt", "a.q", "xyz/hello.txt"
@@ -943,7 +2215,7 @@ mod test {
             &mut filter_state,
             &mut commit,
             &filter_rules
-        );
+        ).unwrap();
 
         let expected = vec![
             "a.txt", "a.q", "xyz/hello.txt"
@@ -956,4 +2228,446 @@ mod test {
         }
         assert_eq!(new_fileops, expected_fileops);
     }
+
+    #[test]
+    fn glob_patterns_match_correctly() {
+        let glob = GlobPattern::new("**/*.md").unwrap();
+        assert!(glob.is_match("readme.md"));
+        assert!(glob.is_match("docs/a/readme.md"));
+        assert!(!glob.is_match("readme.md.txt"));
+        assert_eq!(glob.prefix, "");
+
+        let glob = GlobPattern::new("src/**/test_*.rs").unwrap();
+        assert!(glob.is_match("src/test_a.rs"));
+        assert!(glob.is_match("src/x/y/test_a.rs"));
+        assert!(!glob.is_match("src/x/y/a_test.rs"));
+        assert!(!glob.is_match("lib/src/test_a.rs"));
+        assert_eq!(glob.prefix, "src/");
+
+        // a glob that matches a directory matches everything in it
+        let glob = GlobPattern::new("src/*/docs").unwrap();
+        assert!(glob.is_match("src/a/docs/readme.md"));
+        assert!(!glob.is_match("src/a/b/docs/readme.md"));
+        assert_eq!(glob.rename("src/a/docs/readme.md", "lib"), "lib/a/docs/readme.md");
+        assert_eq!(glob.rename("src/a/docs/readme.md", "lib/"), "lib/a/docs/readme.md");
+        assert_eq!(glob.rename("src/a/docs/readme.md", ""), "a/docs/readme.md");
+
+        let glob = GlobPattern::new("file[0-9].[!c]").unwrap();
+        assert!(glob.is_match("file1.h"));
+        assert!(!glob.is_match("file1.c"));
+        assert!(!glob.is_match("filea.h"));
+    }
+
+    #[test]
+    fn filter_rules_handle_globs_and_regex() {
+        let mut filter_state = FilterState::default();
+        let mut commit = current_commit_state(&[
+            "src/lib.rs",
+            "src/a/BUILD",
+            "src/a/test_a.rs",
+            "docs/readme.md",
+            "docs/internal/secret.md",
+        ]);
+        let filter_rules = vec![
+            FilterRule::FilterRuleGlobRename(GlobPattern::new("src/**/BUILD").unwrap(), "build/".into()),
+            FilterRule::FilterRuleGlobInclude(GlobPattern::new("src/**/*.rs").unwrap()),
+            FilterRule::FilterRuleRegexRename(Regex::new(r"^docs/(.*)\.md$").unwrap(), "md/$1.markdown".into()),
+            FilterRule::FilterRuleGlobExclude(GlobPattern::new("**/test_*.rs").unwrap()),
+            FilterRule::FilterRuleRegexExclude(Regex::new("internal/").unwrap()),
+        ];
+        let new_fileops = apply_filter_rules_to_fileops(
+            false,
+            &mut filter_state,
+            &mut commit,
+            &filter_rules
+        ).unwrap();
+
+        let expected = vec![
+            "src/lib.rs", "build/a/BUILD", "md/readme.markdown",
+        ];
+        let mut expected_fileops = vec![];
+        for path in expected {
+            expected_fileops.push(
+                FileOpsOwned::FileModify("".into(), "".into(), path.to_string())
+            );
+        }
+        assert_eq!(new_fileops, expected_fileops);
+    }
+
+    #[test]
+    fn filter_rules_apply_to_both_paths_of_renames_and_copies() {
+        let mut commit = StructuredCommit {
+            fileops: vec![
+                // rename within the kept paths
+                FileOpsOwned::FileRename("src/a.txt".into(), "src/b.txt".into()),
+                // rename out of the kept paths
+                FileOpsOwned::FileRename("src/c.txt".into(), "other/c.txt".into()),
+                // copy within the kept paths
+                FileOpsOwned::FileCopy("src/d.txt".into(), "src/e.txt".into()),
+                // copy out of the kept paths
+                FileOpsOwned::FileCopy("src/f.txt".into(), "other/f.txt".into()),
+                // not kept at all
+                FileOpsOwned::FileRename("other/g.txt".into(), "other/h.txt".into()),
+            ],
+            ..Default::default()
+        };
+        let mut filter_state = FilterState::default();
+        let filter_rule = FilterRule::FilterRulePathRename("src/".into(), "lib/".into());
+        let filter_rules = vec![filter_rule];
+
+        let new_fileops = apply_filter_rules_to_fileops(
+            false,
+            &mut filter_state,
+            &mut commit,
+            &filter_rules
+        ).unwrap();
+
+        let expected = vec![
+            FileOpsOwned::FileRename("lib/a.txt".into(), "lib/b.txt".into()),
+            FileOpsOwned::FileDelete("lib/c.txt".into()),
+            FileOpsOwned::FileCopy("lib/d.txt".into(), "lib/e.txt".into()),
+        ];
+        assert_eq!(new_fileops, expected);
+    }
+
+    #[test]
+    fn get_output_ref_works() {
+        let namespace = Some("refs/split/");
+        assert_eq!(get_output_ref("refs/heads/release/1.0", "master", None, None), "refs/heads/release/1.0");
+        assert_eq!(get_output_ref("refs/heads/release/1.0", "master", None, namespace), "refs/split/release/1.0");
+        assert_eq!(get_output_ref("refs/remotes/origin/x", "master", None, Some("refs/split")), "refs/split/remotes/origin/x");
+        // the branch is rewritten in place unless there is an output branch
+        assert_eq!(get_output_ref("refs/heads/master", "master", None, namespace), "refs/heads/master");
+        assert_eq!(get_output_ref("refs/heads/master", "refs/heads/master", None, namespace), "refs/heads/master");
+        assert_eq!(get_output_ref("refs/heads/master", "master", Some("lib"), namespace), "refs/heads/lib");
+        assert_eq!(get_output_ref("refs/heads/master", "master", Some("refs/split/lib"), None), "refs/split/lib");
+        // tags and notes stay where git looks for them
+        assert_eq!(get_output_ref("refs/tags/v1", "master", None, namespace), "refs/tags/split/v1");
+        assert_eq!(get_output_ref("refs/notes/commits", "master", None, Some("refs/split-out/lib")), "refs/notes/split-out/lib/commits");
+    }
+
+    #[test]
+    fn commit_rules_can_drop_and_squash_commits() {
+        use crate::commit_rules::CommitMatcher;
+        use export_parser::{AuthorPerson, CommitPersonOwned};
+        let make_commit = |mark: usize, merges: Vec<usize>, author: &str, file: &str| {
+            StructuredCommit {
+                mark,
+                merges,
+                author: AuthorPerson::Author(CommitPersonOwned {
+                    name: Some(author.into()),
+                    email: "email".into(),
+                    timestr: "0 +0000".into(),
+                }),
+                commit_message: format!("commit {}\n", mark),
+                fileops: vec![FileOpsOwned::FileModify("100644".into(), mark.to_string(), file.into())],
+                ..Default::default()
+            }
+        };
+        let filter_rules = vec![
+            FilterRule::FilterRulePathInclude("src/".into()),
+            FilterRule::FilterRuleCommit(CommitRule {
+                action: CommitAction::Squash,
+                matchers: vec![CommitMatcher::parse("author:^bot").unwrap()],
+            }),
+            FilterRule::FilterRuleCommit(CommitRule {
+                action: CommitAction::Drop,
+                matchers: vec![CommitMatcher::parse("author:^bo").unwrap()],
+            }),
+        ];
+        let mut filter_state = FilterState {
+            store_kept_commits: true,
+            ..Default::default()
+        };
+
+        let mut commit1 = make_commit(1, vec![], "human", "src/a.txt");
+        let resp = perform_filter2(false, &mut filter_state, &mut commit1, &filter_rules).unwrap();
+        assert!(matches!(resp, FilterResponse::UseAsIs));
+
+        // squashed into commit 1, which does not have a parent
+        let mut commit2 = make_commit(2, vec![1], "bot", "src/b.txt");
+        let resp = perform_filter2(false, &mut filter_state, &mut commit2, &filter_rules).unwrap();
+        assert!(matches!(resp, FilterResponse::UseAsRoot));
+        assert!(commit2.merges.is_empty());
+        assert_eq!(commit2.commit_message, "commit 1\n");
+        assert_eq!(commit2.fileops.len(), 2);
+
+        // the first commit rule that matches is used, so this is dropped
+        let mut commit3 = make_commit(3, vec![2], "bob", "src/c.txt");
+        let resp = perform_filter2(false, &mut filter_state, &mut commit3, &filter_rules).unwrap();
+        assert!(!resp.is_used());
+
+        // our parent was dropped, so we point to what it pointed to
+        let mut commit4 = make_commit(4, vec![3], "human", "src/d.txt");
+        let resp = perform_filter2(false, &mut filter_state, &mut commit4, &filter_rules).unwrap();
+        assert!(matches!(resp, FilterResponse::UseAsIs));
+        assert_eq!(commit4.merges, vec![2]);
+
+        // the other children of commit 1 keep it as their parent,
+        // because they do not have the changes of commit 2
+        let mut commit5 = make_commit(5, vec![1], "human", "src/e.txt");
+        let resp = perform_filter2(false, &mut filter_state, &mut commit5, &filter_rules).unwrap();
+        assert!(matches!(resp, FilterResponse::UseAsIs));
+        assert_eq!(commit5.merges, vec![1]);
+        assert!(!filter_state.kept_commits.contains_key(&1));
+
+        // commit 2 already has kept children, so it cannot be replaced anymore
+        let mut commit6 = make_commit(6, vec![2], "bot", "src/f.txt");
+        let resp = perform_filter2(false, &mut filter_state, &mut commit6, &filter_rules).unwrap();
+        assert!(matches!(resp, FilterResponse::UseAsIs));
+        assert_eq!(commit6.merges, vec![2]);
+        assert_eq!(commit6.commit_message, "commit 6\n");
+        assert_eq!(commit6.fileops.len(), 1);
+    }
+
+    #[test]
+    fn quoted_paths_are_not_quoted_twice() {
+        let filter_rules = vec![FilterRule::FilterRulePathInclude("src/".into())];
+        let mut path = "\"src/b b.txt\"".to_string();
+        assert!(should_use_file(&mut path, &filter_rules, false));
+        assert_eq!(path, "\"src/b b.txt\"");
+
+        let filter_rules = vec![FilterRule::FilterRulePathRename("src/".into(), "lib/".into())];
+        let mut path = "\"src/b b.txt\"".to_string();
+        assert!(should_use_file(&mut path, &filter_rules, false));
+        assert_eq!(path, "\"lib/b b.txt\"");
+    }
+
+    #[test]
+    fn renames_into_kept_paths_need_the_original_commit() {
+        let mut commit = StructuredCommit {
+            fileops: vec![
+                FileOpsOwned::FileRename("other/a.txt".into(), "src/a.txt".into()),
+            ],
+            ..Default::default()
+        };
+        let mut filter_state = FilterState::default();
+        let filter_rules = vec![FilterRule::FilterRulePathInclude("src/".into())];
+
+        // there is no commit to find the blob in
+        let res = apply_filter_rules_to_fileops(
+            false,
+            &mut filter_state,
+            &mut commit,
+            &filter_rules
+        );
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn notes_are_rewritten_to_the_kept_commits() {
+        let kept_oid = "a".repeat(40);
+        let dropped_oid = "b".repeat(40);
+        let mut filter_state = FilterState::default();
+        filter_state.set_mark_map(1, 1);
+        filter_state.set_mark_map(2, 1);
+        filter_state.original_oid_map.insert(kept_oid.clone(), 1);
+        filter_state.original_oid_map.insert(dropped_oid.clone(), 2);
+        let mut commit = StructuredCommit {
+            commit_ref: "refs/notes/commits".into(),
+            fileops: vec![
+                FileOpsOwned::FileModify("100644".into(), "note1".into(), format!("aa/{}", &kept_oid[2..])),
+                FileOpsOwned::FileModify("100644".into(), "note2".into(), dropped_oid.clone()),
+                FileOpsOwned::FileDelete(kept_oid.clone()),
+                FileOpsOwned::NoteModify("note3".into(), ":1".into()),
+                FileOpsOwned::NoteModify("note4".into(), ":2".into()),
+            ],
+            ..Default::default()
+        };
+        convert_note_fileops(&filter_state, &mut commit).unwrap();
+        let newfileops = apply_filter_rules_to_fileops(
+            false, &mut filter_state, &mut commit, &vec![]).unwrap();
+        assert_eq!(newfileops, vec![
+            FileOpsOwned::NoteModify("note1".into(), ":1".into()),
+            FileOpsOwned::NoteModify("0".repeat(40), ":1".into()),
+            FileOpsOwned::NoteModify("note3".into(), ":1".into()),
+        ]);
+    }
+
+    #[test]
+    fn gitlinks_follow_the_submodule_action() {
+        let make_commit = || StructuredCommit {
+            fileops: vec![
+                FileOpsOwned::FileModify(GITLINK_MODE.into(), "a".repeat(40), "lib/sub".into()),
+                FileOpsOwned::FileModify("100644".into(), "blob".into(), "lib/a.txt".into()),
+            ],
+            ..Default::default()
+        };
+        let filter_rules = vec![FilterRulePathRename("lib/".into(), "vendor/".into())];
+        let mut filter_state = FilterState::default();
+        let newfileops = apply_filter_rules_to_fileops(
+            false, &mut filter_state, &mut make_commit(), &filter_rules).unwrap();
+        assert_eq!(newfileops, vec![
+            FileOpsOwned::FileModify(GITLINK_MODE.into(), "a".repeat(40), "vendor/sub".into()),
+            FileOpsOwned::FileModify("100644".into(), "blob".into(), "vendor/a.txt".into()),
+        ]);
+
+        filter_state.submodules = SubmoduleAction::Drop;
+        let newfileops = apply_filter_rules_to_fileops(
+            false, &mut filter_state, &mut make_commit(), &filter_rules).unwrap();
+        assert_eq!(newfileops, vec![
+            FileOpsOwned::FileModify("100644".into(), "blob".into(), "vendor/a.txt".into()),
+        ]);
+    }
+
+    #[test]
+    fn gitmodules_follows_the_path_rules() {
+        let make_commit = || StructuredCommit {
+            fileops: vec![
+                FileOpsOwned::FileModify("100644".into(), "blob".into(), GITMODULES.into()),
+                FileOpsOwned::FileDelete(GITMODULES.into()),
+            ],
+            ..Default::default()
+        };
+        let excluded = vec![FilterRulePathInclude("lib/".into())];
+        let included = vec![FilterRulePathInclude(GITMODULES.into())];
+        // by default .gitmodules is like any other file
+        let mut filter_state = FilterState::default();
+        let newfileops = apply_filter_rules_to_fileops(
+            false, &mut filter_state, &mut make_commit(), &excluded).unwrap();
+        assert!(newfileops.is_empty());
+        let newfileops = apply_filter_rules_to_fileops(
+            false, &mut filter_state, &mut make_commit(), &included).unwrap();
+        assert_eq!(newfileops, make_commit().fileops);
+
+        // it is only rewritten if the rules keep it
+        for action in [SubmoduleAction::Keep, SubmoduleAction::Drop, SubmoduleAction::Merge] {
+            filter_state.submodules = action;
+            let newfileops = apply_filter_rules_to_fileops(
+                false, &mut filter_state, &mut make_commit(), &excluded).unwrap();
+            assert!(newfileops.is_empty());
+        }
+    }
+
+    #[test]
+    fn deleteall_only_deletes_the_kept_files() {
+        let dir = std::env::temp_dir().join(format!("gitfilter-deleteall-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib/a.txt"), "a").unwrap();
+        std::fs::write(dir.join("lib/b c.txt"), "b").unwrap();
+        std::fs::write(dir.join("other.txt"), "c").unwrap();
+        let run = |args: &[&str]| tags::run_git(args, Some(&dir)).unwrap();
+        run(&["git", "init", "-q"]);
+        run(&["git", "add", "."]);
+        run(&["git", "-c", "user.name=a", "-c", "user.email=a@a", "commit", "-q", "-m", "one"]);
+        run(&["git", "-c", "user.name=a", "-c", "user.email=a@a", "commit", "-q", "--allow-empty", "-m", "two"]);
+        let head = run(&["git", "rev-parse", "HEAD"]);
+
+        let mut filter_state = FilterState { repo_location: Some(dir.clone()), ..Default::default() };
+        let mut commit = StructuredCommit {
+            original_oid: head.trim().into(),
+            merges: vec![1],
+            fileops: vec![
+                FileOpsOwned::FileDeleteAll,
+                FileOpsOwned::FileModify("100644".into(), "x".into(), "lib/a.txt".into()),
+            ],
+            ..Default::default()
+        };
+        let filter_rules = vec![FilterRulePathRename("lib/".into(), "".into())];
+        let newfileops = apply_filter_rules_to_fileops(
+            false, &mut filter_state, &mut commit, &filter_rules).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(newfileops, vec![
+            FileOpsOwned::FileDelete("a.txt".into()),
+            FileOpsOwned::FileDelete("\"b c.txt\"".into()),
+            FileOpsOwned::FileModify("100644".into(), "x".into(), "a.txt".into()),
+        ]);
+    }
+
+    #[test]
+    fn unquote_path_works() {
+        assert_eq!(unquote_path("lib/a.txt"), "lib/a.txt");
+        assert_eq!(unquote_path("\"lib/a b.txt\""), "lib/a b.txt");
+        assert_eq!(unquote_path("\"a \\\"b\\\"\\303\\251\\\\c\\td\""), "a \"b\"\u{e9}\\c\td");
+    }
+
+    #[test]
+    fn copied_blobs_are_found_in_merges_and_quoted_paths() {
+        let dir = std::env::temp_dir().join(format!("gitfilter-copied-blob-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("other")).unwrap();
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        let run = |args: &[&str]| tags::run_git(args, Some(&dir)).unwrap();
+        let commit = |message: &str| run(&["git", "commit", "-q", "--allow-empty", "-m", message]);
+        run(&["git", "init", "-q"]);
+        run(&["git", "config", "user.name", "a"]);
+        run(&["git", "config", "user.email", "a@a"]);
+        commit("base");
+        let main = run(&["git", "rev-parse", "--abbrev-ref", "HEAD"]);
+
+        // a file that only exists in the second parent
+        // of a merge is copied by the merge
+        run(&["git", "checkout", "-q", "-b", "side"]);
+        std::fs::write(dir.join("other/y.txt"), "y").unwrap();
+        run(&["git", "add", "."]);
+        commit("side");
+        run(&["git", "checkout", "-q", main.trim()]);
+        std::fs::write(dir.join("main.txt"), "main").unwrap();
+        run(&["git", "add", "."]);
+        commit("main");
+        run(&["git", "merge", "-q", "--no-ff", "--no-commit", "side"]);
+        std::fs::copy(dir.join("other/y.txt"), dir.join("lib/y.txt")).unwrap();
+        run(&["git", "add", "."]);
+        commit("merge");
+        let merge = run(&["git", "rev-parse", "HEAD"]);
+        let expected = run(&["git", "rev-parse", "HEAD:lib/y.txt"]);
+        let merge_blob = get_copied_blob(Some(&dir), merge.trim(), "other/y.txt", "lib/y.txt");
+
+        // a quoted path is renamed, and the renamed file is renamed again
+        std::fs::write(dir.join("other/\u{e9} \"x\".txt"), "x").unwrap();
+        run(&["git", "add", "."]);
+        commit("quoted");
+        run(&["git", "mv", "other/\u{e9} \"x\".txt", "lib/\u{e9} \"x\".txt"]);
+        run(&["git", "mv", "lib/\u{e9} \"x\".txt", "lib/x.txt"]);
+        commit("rename");
+        let rename = run(&["git", "rev-parse", "HEAD"]);
+        let expected_quoted = run(&["git", "rev-parse", "HEAD:lib/x.txt"]);
+        let quoted_blob = get_copied_blob(Some(&dir), rename.trim(),
+            "\"other/\\303\\251 \\\"x\\\".txt\"", "\"lib/\\303\\251 \\\"x\\\".txt\"");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(merge_blob.unwrap(), ("100644".to_string(), expected.trim().to_string()));
+        assert_eq!(quoted_blob.unwrap(), ("100644".to_string(), expected_quoted.trim().to_string()));
+    }
+
+    #[test]
+    fn regex_from_replacement_works() {
+        let regex = regex_from_replacement("md/$1.markdown").unwrap();
+        assert!(regex.is_match("md/readme.markdown"));
+        assert!(regex.is_match("md/a/b.markdown"));
+        assert!(!regex.is_match("docs/readme.md"));
+        let regex = regex_from_replacement("${name}/x$$").unwrap();
+        assert!(regex.is_match("abc/x$"));
+        assert!(!regex.is_match("abc/x"));
+    }
+
+    #[test]
+    fn failed_fast_import_does_not_write_the_commit_map() {
+        let dir = std::env::temp_dir().join(format!("gitfilter-fast-import-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        tags::run_git(&["git", "init", "-q"], Some(&dir)).unwrap();
+        let commit_map = dir.join("commit_map");
+        let mut options: FilterOptions<Vec<u8>> = vec![].into();
+        options.commit_map = Some(commit_map.clone());
+        let mut fast_import = FastImport::spawn(&options, Some(&dir)).unwrap();
+        let mut stdin = fast_import.take_stdin().unwrap();
+        stdin.write_all(b"this is not a fast-import command\n").unwrap();
+        drop(stdin);
+        let res = fast_import.finish(Ok(vec![("orig1".to_string(), 1)]));
+        let commit_map_exists = commit_map.exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(res.unwrap_err().to_string().contains("git fast-import failed"));
+        assert!(!commit_map_exists);
+    }
+
+    #[test]
+    fn make_commit_map_contents_works() {
+        let marks = ":1 aaa\n:2 bbb\n:4 ddd\n";
+        let kept = vec![
+            ("orig1".to_string(), 1),
+            ("orig3".to_string(), 3),
+            ("orig4".to_string(), 4),
+        ];
+        let contents = make_commit_map_contents(marks, &kept);
+        assert_eq!(contents, "orig1 aaa\norig4 ddd\n");
+    }
 }
@@ -1,17 +1,45 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, BTreeMap};
+use std::path::PathBuf;
+use std::io::{self, BufRead, Write};
 use crate::export_parser::FileOpsOwned;
+use crate::export_parser::StructuredCommit;
+use crate::submodules::SubmoduleAction;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
+use crate::{ioerr, ioerre};
 
 pub const MAPS_TO_EMPTY: usize = 0;
 pub const UNKNOWN_MAP: usize = usize::MAX;
 
+/// the first line of a saved filter state. if the
+/// format changes, the version has to change too
+pub const FILTER_STATE_HEADER: &str = "gitfilter-state 1";
+
 #[derive(Debug, Default)]
 pub struct FilterState {
     pub have_used_a_commit: bool,
     pub graph: Vec<Vec<usize>>,
     pub mark_map: Vec<usize>,
     pub contents_hash_map: HashMap<usize, HashMap<u64, u64>>,
+    /// only filled in when filtering with tags
+    pub original_oid_map: HashMap<String, usize>,
+    /// where the repository being filtered is. None
+    /// means the current directory
+    pub repo_location: Option<PathBuf>,
+    /// only true when filtering with rules that squash
+    /// commits, because then we need to know what the commit
+    /// we squash into looks like
+    pub store_kept_commits: bool,
+    pub kept_commits: HashMap<usize, StructuredCommit>,
+    /// the mark of the tip of every ref that was filtered, before
+    /// it was mapped. only tracked when filtering several refs,
+    /// or when filtering incrementally
+    pub ref_tips: BTreeMap<String, usize>,
+    /// true if this state was loaded from a previous filter.
+    /// the commits of the previous filter are not in `kept_commits`
+    pub resumed: bool,
+    /// what to do with the submodules that are kept
+    pub submodules: SubmoduleAction,
 }
 
 fn calculate_hash<T: Hash>(t: &T) -> u64 {
@@ -60,6 +88,20 @@ impl FilterState {
         self.contents_hash_map.insert(mark, parents_merged_map);
     }
 
+    /// true if the contents of the commit with
+    /// this mark have a file at this path
+    pub fn has_file(&self, mark: usize, path: &str) -> bool {
+        let deleted = calculate_hash(&FileOpsOwned::FileDelete(path.into()));
+        let contents = match self.contents_hash_map.get(&mark) {
+            Some(c) => c,
+            None => return false,
+        };
+        match contents.get(&calculate_hash(&path)) {
+            Some(hash) => *hash != deleted,
+            None => false,
+        }
+    }
+
     pub fn contents_are_same_as(&self, parent: usize, contents: &Vec<FileOpsOwned>) -> Option<bool> {
         match self.contents_hash_map.get(&parent) {
             Some(parent_hash_map) => {
@@ -140,10 +182,120 @@ impl FilterState {
         self.graph[mark] = our_ancestry_table;
     }
 
+    /// writes everything that is needed to resume filtering
+    /// after the last commit that this state has seen:
+    /// the mark map, the ancestry graph, the contents hashes,
+    /// and the ref tips. the kept commits are not saved.
+    pub fn write_to<W: Write>(&self, out: W) -> io::Result<()> {
+        let mut out = io::BufWriter::new(out);
+        writeln!(out, "{}", FILTER_STATE_HEADER)?;
+        writeln!(out, "have_used_a_commit {}", self.have_used_a_commit)?;
+        write!(out, "mark_map")?;
+        for mapped in self.mark_map.iter() {
+            write!(out, " {}", mapped)?;
+        }
+        writeln!(out)?;
+        writeln!(out, "graph_len {}", self.graph.len())?;
+        for (mark, ancestors) in self.graph.iter().enumerate() {
+            if ancestors.is_empty() { continue; }
+            write!(out, "graph {}", mark)?;
+            for ancestor in ancestors {
+                write!(out, " {}", ancestor)?;
+            }
+            writeln!(out)?;
+        }
+        for (mark, hashes) in self.contents_hash_map.iter() {
+            write!(out, "contents {}", mark)?;
+            for (key, value) in hashes {
+                write!(out, " {}:{}", key, value)?;
+            }
+            writeln!(out)?;
+        }
+        for (ref_name, mark) in self.ref_tips.iter() {
+            writeln!(out, "ref_tip {} {}", mark, ref_name)?;
+        }
+        out.flush()
+    }
+
+    /// reads a state that was written by `write_to`.
+    /// the returned state is marked as `resumed`
+    pub fn read_from<R: BufRead>(input: R) -> io::Result<FilterState> {
+        let mut state = FilterState { resumed: true, ..Default::default() };
+        let mut lines = input.lines();
+        match lines.next() {
+            Some(Ok(header)) if header == FILTER_STATE_HEADER => (),
+            _ => return ioerre!("Invalid filter state. Expected it to start with '{}'", FILTER_STATE_HEADER),
+        }
+        let parse_num = |s: &str, line: &str| -> io::Result<usize> {
+            s.parse().map_err(|_| ioerr!("Invalid filter state line: {}", line))
+        };
+        for line in lines {
+            let line = line?;
+            let mut words = line.split(' ');
+            match words.next().unwrap_or("") {
+                "" => (),
+                "have_used_a_commit" => state.have_used_a_commit = words.next() == Some("true"),
+                "mark_map" => {
+                    for word in words {
+                        state.mark_map.push(parse_num(word, &line)?);
+                    }
+                }
+                "graph_len" => {
+                    let len = parse_num(words.next().unwrap_or(""), &line)?;
+                    state.graph = vec![vec![]; len];
+                }
+                "graph" => {
+                    let mark = parse_num(words.next().unwrap_or(""), &line)?;
+                    let mut ancestors = vec![];
+                    for word in words {
+                        ancestors.push(parse_num(word, &line)?);
+                    }
+                    state.extend_ancestry_graph_until(mark);
+                    state.graph[mark] = ancestors;
+                }
+                "contents" => {
+                    let mark = parse_num(words.next().unwrap_or(""), &line)?;
+                    let mut hashes = HashMap::new();
+                    for word in words {
+                        let (key, value) = match word.split_once(':') {
+                            Some((k, v)) => (k.parse::<u64>(), v.parse::<u64>()),
+                            None => return ioerre!("Invalid filter state line: {}", line),
+                        };
+                        match (key, value) {
+                            (Ok(k), Ok(v)) => { hashes.insert(k, v); }
+                            _ => return ioerre!("Invalid filter state line: {}", line),
+                        }
+                    }
+                    state.contents_hash_map.insert(mark, hashes);
+                }
+                "ref_tip" => {
+                    let mark = parse_num(words.next().unwrap_or(""), &line)?;
+                    let ref_name = match words.next() {
+                        Some(r) if !r.is_empty() => r.to_string(),
+                        _ => return ioerre!("Invalid filter state line: {}", line),
+                    };
+                    state.ref_tips.insert(ref_name, mark);
+                }
+                _ => return ioerre!("Invalid filter state line: {}", line),
+            }
+        }
+        Ok(state)
+    }
+
     pub fn get_mapped_mark(&self, mark: usize) -> Option<&usize> {
         self.mark_map.get(mark)
     }
 
+    /// returns the mark of the commit with this original oid
+    /// only if that commit was kept, ie: it was not filtered out.
+    pub fn get_kept_mark(&self, original_oid: &str) -> Option<usize> {
+        let mark = *self.original_oid_map.get(original_oid)?;
+        match self.get_mapped_mark(mark) {
+            Some(m) if *m == mark => Some(mark),
+            _ => None,
+        }
+    }
+
     pub fn is_ancestor(&self, mark: usize, parent: usize) -> bool {
         if mark == parent { return true }
 
@@ -170,3 +322,39 @@ impl FilterState {
         mark_exists_in_a_parent
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn filter_state_can_be_saved_and_loaded() {
+        let mut state = FilterState::default();
+        state.set_mark_map(1, 1);
+        state.set_mark_map(2, 1);
+        state.set_mark_map(3, 3);
+        state.set_mark_map(4, MAPS_TO_EMPTY);
+        state.update_graph(1, &[]);
+        state.update_graph(3, &[1]);
+        state.using_commit_with_contents(1, &[], &vec![FileOpsOwned::FileDelete("a.txt".into())]);
+        state.using_commit_with_contents(3, &[1], &vec![FileOpsOwned::FileDelete("b.txt".into())]);
+        state.have_used_a_commit = true;
+        state.ref_tips.insert("refs/heads/master".into(), 4);
+
+        let mut saved = vec![];
+        state.write_to(&mut saved).unwrap();
+        let loaded = FilterState::read_from(&saved[..]).unwrap();
+        assert!(loaded.resumed);
+        assert!(loaded.have_used_a_commit);
+        assert_eq!(loaded.mark_map, state.mark_map);
+        assert_eq!(loaded.graph, state.graph);
+        assert_eq!(loaded.contents_hash_map, state.contents_hash_map);
+        assert_eq!(loaded.ref_tips, state.ref_tips);
+        assert!(loaded.is_ancestor(1, 3));
+        assert_eq!(loaded.contents_are_same_as(3, &vec![FileOpsOwned::FileDelete("a.txt".into())]), Some(true));
+
+        assert!(FilterState::read_from(&b"not a state\n"[..]).is_err());
+        let bad = format!("{}\ngraph x\n", FILTER_STATE_HEADER);
+        assert!(FilterState::read_from(bad.as_bytes()).is_err());
+    }
+}
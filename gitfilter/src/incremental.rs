@@ -0,0 +1,183 @@
+// incremental filtering keeps everything that is needed to
+// continue a filter in a directory: the marks that git fast-export
+// gave to the original commits, the marks that git fast-import gave
+// to the filtered commits, and the `FilterState` of the filter.
+// the next filter imports those marks so fast-export only exports
+// the commits that were added since, and those commits are filtered
+// on top of what was filtered before.
+// every file is first written with a `.new` extension, and only
+// replaces the previous file once the whole filter succeeded,
+// so a filter that fails halfway does not corrupt the state.
+
+use super::export_parser::MarksFiles;
+use super::filter_state::FilterState;
+use crate::ioerr;
+use std::collections::HashMap;
+use std::io::{self, BufReader};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+pub const EXPORT_MARKS_FILE: &str = "export-marks";
+pub const IMPORT_MARKS_FILE: &str = "import-marks";
+pub const FILTER_STATE_FILE: &str = "filter-state";
+pub const OPTIONS_FILE: &str = "options";
+pub const NEW_EXTENSION: &str = "new";
+
+#[derive(Debug, Clone)]
+pub struct IncrementalPaths {
+    pub dir: PathBuf,
+}
+
+impl IncrementalPaths {
+    /// a relative directory is relative to the
+    /// location of the repository being filtered. the directory
+    /// is made absolute because git runs in that location
+    pub fn new<P: AsRef<Path>>(location: Option<P>, dir: &Path) -> IncrementalPaths {
+        let dir = match location {
+            Some(l) if dir.is_relative() => l.as_ref().join(dir),
+            _ => dir.to_path_buf(),
+        };
+        let dir = match (dir.is_relative(), std::env::current_dir()) {
+            (true, Ok(cwd)) => cwd.join(dir),
+            _ => dir,
+        };
+        IncrementalPaths { dir }
+    }
+
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    pub fn new_file(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", name, NEW_EXTENSION))
+    }
+
+    /// the marks of the original commits. fast-export reads
+    /// the ones from the previous filter, and writes all of them
+    /// to the new file
+    pub fn export_marks_files(&self) -> MarksFiles {
+        MarksFiles {
+            import: self.file(EXPORT_MARKS_FILE),
+            export: self.new_file(EXPORT_MARKS_FILE),
+        }
+    }
+
+    /// the marks of the filtered commits, same as `export_marks_files`
+    /// but for fast-import
+    pub fn import_marks_files(&self) -> MarksFiles {
+        MarksFiles {
+            import: self.file(IMPORT_MARKS_FILE),
+            export: self.new_file(IMPORT_MARKS_FILE),
+        }
+    }
+}
+
+/// returns the state of the previous filter, or None if there was no
+/// previous filter. `options` describes everything that changes how commits
+/// are filtered (the rules, the mailmap, etc.). if the options are different
+/// than the previous filter's options, the previous state is discarded
+/// so that everything gets filtered again with the new options.
+pub fn load_state(paths: &IncrementalPaths, options: &str) -> io::Result<Option<FilterState>> {
+    let previous_options = std::fs::read_to_string(paths.file(OPTIONS_FILE)).ok();
+    let state_path = paths.file(FILTER_STATE_FILE);
+    if previous_options.as_deref() != Some(options) || !state_path.exists() {
+        for name in [EXPORT_MARKS_FILE, IMPORT_MARKS_FILE, FILTER_STATE_FILE, OPTIONS_FILE].iter() {
+            let path = paths.file(name);
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        return Ok(None);
+    }
+    let file = File::open(&state_path)
+        .map_err(|e| ioerr!("Failed to open {}: {}", state_path.display(), e))?;
+    let state = FilterState::read_from(BufReader::new(file))
+        .map_err(|e| ioerr!("Failed to load {}: {}", state_path.display(), e))?;
+    Ok(Some(state))
+}
+
+/// writes the state and options of this filter next to the previous
+/// ones. they only replace the previous ones in `commit_state`
+pub fn save_state(paths: &IncrementalPaths, options: &str, state: &FilterState) -> io::Result<()> {
+    std::fs::create_dir_all(&paths.dir)?;
+    let file = File::create(paths.new_file(FILTER_STATE_FILE))?;
+    state.write_to(file)?;
+    std::fs::write(paths.new_file(OPTIONS_FILE), options)?;
+    Ok(())
+}
+
+/// call this after the filtered commits were successfully imported
+/// by git fast-import. replaces the previous state with the new state
+pub fn commit_state(paths: &IncrementalPaths) -> io::Result<()> {
+    for name in [EXPORT_MARKS_FILE, IMPORT_MARKS_FILE, FILTER_STATE_FILE, OPTIONS_FILE].iter() {
+        let new_path = paths.new_file(name);
+        if new_path.exists() {
+            std::fs::rename(&new_path, paths.file(name))?;
+        }
+    }
+    Ok(())
+}
+
+/// parses a marks file (`:<mark> <oid>` per line) into mark -> oid
+pub fn parse_marks_file(contents: &str) -> HashMap<usize, &str> {
+    let mut out = HashMap::new();
+    for line in contents.lines() {
+        let mut split = line.splitn(2, ' ');
+        let mark = split.next().unwrap_or("").trim_start_matches(':');
+        let oid = split.next().unwrap_or("").trim();
+        if let Ok(mark) = mark.parse::<usize>() {
+            out.insert(mark, oid);
+        }
+    }
+    out
+}
+
+/// returns `^<oid>` for the original tip of every ref of the
+/// previous filter, so that fast-export does not have to walk
+/// the history that was already filtered
+pub fn get_excluded_tips(paths: &IncrementalPaths, state: &FilterState) -> io::Result<Vec<String>> {
+    let marks_path = paths.file(EXPORT_MARKS_FILE);
+    if !marks_path.exists() {
+        return Ok(vec![]);
+    }
+    let contents = std::fs::read_to_string(&marks_path)?;
+    let marks = parse_marks_file(&contents);
+    let mut out: Vec<String> = state.ref_tips.values()
+        .filter_map(|mark| marks.get(mark))
+        .map(|oid| format!("^{}", oid))
+        .collect();
+    out.sort();
+    out.dedup();
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn excluded_tips_come_from_the_export_marks() {
+        let dir = std::env::temp_dir().join(format!("gitfilter-incremental-test-{}", std::process::id()));
+        let paths = IncrementalPaths::new(None::<PathBuf>, &dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(paths.file(EXPORT_MARKS_FILE), ":1 aaa\n:2 bbb\n:3 ccc\n").unwrap();
+        let mut state = FilterState::default();
+        state.ref_tips.insert("refs/heads/master".into(), 3);
+        state.ref_tips.insert("refs/heads/other".into(), 3);
+        state.ref_tips.insert("refs/heads/gone".into(), 9);
+        assert_eq!(get_excluded_tips(&paths, &state).unwrap(), vec!["^ccc"]);
+
+        // the state is only used after it was committed,
+        // and only if the options are the same
+        state.set_mark_map(3, 3);
+        save_state(&paths, "rules", &state).unwrap();
+        assert!(load_state(&paths, "rules").unwrap().is_none());
+        save_state(&paths, "rules", &state).unwrap();
+        commit_state(&paths).unwrap();
+        let loaded = load_state(&paths, "rules").unwrap().unwrap();
+        assert_eq!(loaded.mark_map, state.mark_map);
+        assert!(load_state(&paths, "other rules").unwrap().is_none());
+        assert!(!paths.file(FILTER_STATE_FILE).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
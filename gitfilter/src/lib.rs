@@ -2,6 +2,13 @@
 pub mod export_parser;
 pub mod filter;
 pub mod filter_state;
+pub mod tags;
+pub mod commit_rules;
+pub mod mailmap;
+pub mod message_transforms;
+pub mod incremental;
+pub mod progress;
+pub mod submodules;
 
 
 /// used to make a simple io error with a string formatted message
@@ -0,0 +1,206 @@
+use super::export_parser::CommitPersonOwned;
+use super::filter::FilterError;
+use std::path::Path;
+
+/// one line of a mailmap. a commit identity is matched
+/// by its email, and optionally also by its name.
+/// the proper name and proper email replace the
+/// ones of the commit, if they are provided
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MailmapEntry {
+    pub proper_name: Option<String>,
+    pub proper_email: Option<String>,
+    pub commit_name: Option<String>,
+    pub commit_email: String,
+}
+
+impl MailmapEntry {
+    /// like git, names and emails are compared case insensitively
+    pub fn is_match(&self, name: Option<&str>, email: &str) -> bool {
+        if !self.commit_email.eq_ignore_ascii_case(email) {
+            return false;
+        }
+        match (&self.commit_name, name) {
+            (None, _) => true,
+            (Some(commit_name), Some(name)) => commit_name.eq_ignore_ascii_case(name),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// rewrites the names and emails of authors and committers
+/// using the same format as git's `.mailmap`:
+/// ```text
+/// Proper Name <commit@email>
+/// <proper@email> <commit@email>
+/// Proper Name <proper@email> <commit@email>
+/// Proper Name <proper@email> Commit Name <commit@email>
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mailmap {
+    pub entries: Vec<MailmapEntry>,
+}
+
+/// parses the first `Name <email>` of the string.
+/// the name is optional. returns the name, email, and the
+/// rest of the string after the email
+pub fn parse_name_email(s: &str) -> Option<(Option<String>, String, &str)> {
+    let open = s.find('<')?;
+    let close = open + s[open..].find('>')?;
+    let name = s[..open].trim();
+    let name = if name.is_empty() { None } else { Some(name.to_string()) };
+    let email = s[(open + 1)..close].trim().to_string();
+    Some((name, email, &s[(close + 1)..]))
+}
+
+impl Mailmap {
+    pub fn parse(contents: &str) -> Result<Mailmap, FilterError> {
+        let mut mailmap = Mailmap::default();
+        for line in contents.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let (first_name, first_email, rest) = match parse_name_email(trimmed) {
+                Some(parsed) => parsed,
+                None => return Err(FilterError(format!("Invalid mailmap line '{}'", line))),
+            };
+            let rest = rest.trim();
+            let entry = if rest.is_empty() || rest.starts_with('#') {
+                MailmapEntry {
+                    proper_name: first_name,
+                    proper_email: None,
+                    commit_name: None,
+                    commit_email: first_email,
+                }
+            } else {
+                let (commit_name, commit_email, _) = match parse_name_email(rest) {
+                    Some(parsed) => parsed,
+                    None => return Err(FilterError(format!("Invalid mailmap line '{}'", line))),
+                };
+                MailmapEntry {
+                    proper_name: first_name,
+                    proper_email: Some(first_email),
+                    commit_name,
+                    commit_email,
+                }
+            };
+            mailmap.entries.push(entry);
+        }
+        Ok(mailmap)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Mailmap, FilterError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| FilterError(format!("Failed to read mailmap {}: {}", path.display(), e)))?;
+        Mailmap::parse(&contents)
+    }
+
+    /// adds a mapping of a commit identity: `Name <email>` or `<email>`
+    /// to a proper identity: `Name <email>`, `<email>`, or just `Name`
+    pub fn add(&mut self, commit_identity: &str, proper_identity: &str) -> Result<(), FilterError> {
+        let (commit_name, commit_email) = match parse_name_email(commit_identity) {
+            Some((name, email, rest)) if rest.trim().is_empty() => (name, email),
+            _ => return Err(FilterError(format!("Invalid identity '{}'. Must be of the form 'Name <email>' or '<email>'", commit_identity))),
+        };
+        let (proper_name, proper_email) = match parse_name_email(proper_identity) {
+            Some((name, email, rest)) if rest.trim().is_empty() => (name, Some(email)),
+            Some(_) => return Err(FilterError(format!("Invalid identity '{}'. Must be of the form 'Name <email>', '<email>' or 'Name'", proper_identity))),
+            None => {
+                let name = proper_identity.trim();
+                if name.is_empty() {
+                    return Err(FilterError(format!("Invalid identity for '{}'. The name cannot be empty", commit_identity)));
+                }
+                (Some(name.to_string()), None)
+            }
+        };
+        self.entries.push(MailmapEntry { proper_name, proper_email, commit_name, commit_email });
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// an entry that matches the name and email is preferred over
+    /// an entry that only matches the email. if several entries
+    /// match equally, the last one wins
+    pub fn lookup(&self, name: Option<&str>, email: &str) -> Option<&MailmapEntry> {
+        let mut matching = self.entries.iter().rev().filter(|e| e.is_match(name, email));
+        let first = matching.next()?;
+        if first.commit_name.is_some() {
+            return Some(first);
+        }
+        Some(matching.find(|e| e.commit_name.is_some()).unwrap_or(first))
+    }
+
+    /// returns the name and email that this identity maps to
+    pub fn map_identity(&self, name: Option<&str>, email: &str) -> (Option<String>, String) {
+        match self.lookup(name, email) {
+            None => (name.map(|n| n.to_string()), email.to_string()),
+            Some(entry) => (
+                entry.proper_name.clone().or_else(|| name.map(|n| n.to_string())),
+                entry.proper_email.clone().unwrap_or_else(|| email.to_string()),
+            ),
+        }
+    }
+
+    /// returns true if the person was changed
+    pub fn apply(&self, person: &mut CommitPersonOwned) -> bool {
+        let (name, email) = self.map_identity(person.name.as_deref(), &person.email);
+        if name == person.name && email == person.email {
+            return false;
+        }
+        person.name = name;
+        person.email = email;
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mailmap_parses_every_git_format() {
+        let contents = "# comment\n\
+            Proper Name <commit@a.com>\n\
+            <proper@b.com> <commit@b.com>\n\
+            \n\
+            Proper Name <proper@c.com> <commit@c.com> # trailing comment\n\
+            Proper Name <proper@d.com> Commit Name <commit@d.com>\n";
+        let mailmap = Mailmap::parse(contents).unwrap();
+        assert_eq!(mailmap.entries.len(), 4);
+        assert_eq!(mailmap.map_identity(Some("x"), "Commit@A.com"), (Some("Proper Name".into()), "Commit@A.com".into()));
+        assert_eq!(mailmap.map_identity(Some("x"), "commit@b.com"), (Some("x".into()), "proper@b.com".into()));
+        assert_eq!(mailmap.map_identity(None, "commit@c.com"), (Some("Proper Name".into()), "proper@c.com".into()));
+        assert_eq!(mailmap.map_identity(Some("commit name"), "commit@d.com"), (Some("Proper Name".into()), "proper@d.com".into()));
+        // the name has to match too
+        assert_eq!(mailmap.map_identity(Some("other"), "commit@d.com"), (Some("other".into()), "commit@d.com".into()));
+        assert!(Mailmap::parse("no email here").is_err());
+    }
+
+    #[test]
+    fn mailmap_prefers_entries_with_names() {
+        let mut mailmap = Mailmap::default();
+        mailmap.add("Bob <bob@corp.internal>", "Bob Smith <bob@example.com>").unwrap();
+        mailmap.add("<bob@corp.internal>", "<team@example.com>").unwrap();
+        mailmap.add("<alice@corp.internal>", "Alice").unwrap();
+        assert!(mailmap.add("bob@corp.internal", "Bob").is_err());
+        assert!(mailmap.add("<bob@corp.internal>", "Bob <bob@example.com> extra").is_err());
+
+        let mut person = CommitPersonOwned {
+            name: Some("Bob".into()),
+            email: "bob@corp.internal".into(),
+            timestr: "0 +0000".into(),
+        };
+        assert!(mailmap.apply(&mut person));
+        assert_eq!(person.name.as_deref(), Some("Bob Smith"));
+        assert_eq!(person.email, "bob@example.com");
+        assert!(!mailmap.apply(&mut person));
+
+        assert_eq!(mailmap.map_identity(Some("Robert"), "bob@corp.internal"), (Some("Robert".into()), "team@example.com".into()));
+        assert_eq!(mailmap.map_identity(Some("a"), "alice@corp.internal"), (Some("Alice".into()), "alice@corp.internal".into()));
+    }
+}
@@ -0,0 +1,144 @@
+use super::export_parser::StructuredCommit;
+use super::filter::FilterError;
+use regex::Regex;
+
+/// the default key of the trailer that records which
+/// commit a filtered commit was made from
+pub const DEFAULT_ORIGIN_TRAILER: &str = "Mgt-Origin";
+
+/// changes to make to the message of every commit that is kept
+#[derive(Clone, Debug, Default)]
+pub struct MessageTransforms {
+    /// applied in order. the replacement can reference
+    /// capture groups, eg: $1, ${name}
+    pub replacements: Vec<(Regex, String)>,
+    /// if provided, a trailer of `<key>: <original commit id>`
+    /// is added to the message after the replacements
+    pub origin_trailer: Option<String>,
+}
+
+impl MessageTransforms {
+    pub fn add_replacement(&mut self, regex: &str, replacement: &str) -> Result<(), FilterError> {
+        let regex = Regex::new(regex)
+            .map_err(|e| FilterError(format!("Invalid message regex '{}': {}", regex, e)))?;
+        self.replacements.push((regex, replacement.to_string()));
+        Ok(())
+    }
+
+    pub fn apply(&self, commit: &mut StructuredCommit) {
+        for (regex, replacement) in &self.replacements {
+            if let std::borrow::Cow::Owned(replaced) = regex.replace_all(&commit.commit_message, replacement.as_str()) {
+                commit.commit_message = replaced;
+            }
+        }
+        if let Some(key) = &self.origin_trailer {
+            commit.commit_message = set_trailer(&commit.commit_message, key, &commit.original_oid);
+        }
+    }
+}
+
+/// a line is a trailer if it looks like `Key: value`
+/// where the key has no whitespace
+pub fn is_trailer_line(line: &str) -> bool {
+    match line.find(": ") {
+        Some(i) => i > 0 && !line[..i].contains(char::is_whitespace),
+        None => false,
+    }
+}
+
+/// returns the byte index of where the trailer block starts,
+/// ie: the last paragraph of the message if every line
+/// of it is a trailer. the subject line is never a trailer block
+pub fn find_trailer_block(message: &str) -> Option<usize> {
+    let trimmed = message.trim_end();
+    let start = trimmed.rfind("\n\n")? + 2;
+    let block = &trimmed[start..];
+    if !block.is_empty() && block.lines().all(is_trailer_line) {
+        Some(start)
+    } else {
+        None
+    }
+}
+
+/// adds `<key>: <value>` to the trailer block of the message,
+/// replacing any trailers that have the same key
+pub fn set_trailer(message: &str, key: &str, value: &str) -> String {
+    let trailer = format!("{}: {}", key, value);
+    let prefix = format!("{}: ", key);
+    let trimmed = message.trim_end();
+    let mut out = match find_trailer_block(trimmed) {
+        Some(start) => {
+            let mut out = trimmed[..start].to_string();
+            for line in trimmed[start..].lines() {
+                if !line.starts_with(&prefix) {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+            }
+            out
+        }
+        None if trimmed.is_empty() => String::new(),
+        None => format!("{}\n\n", trimmed),
+    };
+    out.push_str(&trailer);
+    out.push('\n');
+    out
+}
+
+/// returns the values of every trailer with this key in the message
+pub fn get_trailer_values<'a>(message: &'a str, key: &str) -> Vec<&'a str> {
+    let trimmed = message.trim_end();
+    let start = match find_trailer_block(trimmed) {
+        Some(start) => start,
+        None => return vec![],
+    };
+    let prefix = format!("{}: ", key);
+    trimmed[start..].lines()
+        .filter_map(|line| line.strip_prefix(prefix.as_str()))
+        .map(|value| value.trim())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_trailer_works() {
+        assert_eq!(set_trailer("subject\n", "Mgt-Origin", "abc"), "subject\n\nMgt-Origin: abc\n");
+        assert_eq!(
+            set_trailer("subject\n\nbody: not a trailer\nbecause of this line\n", "Mgt-Origin", "abc"),
+            "subject\n\nbody: not a trailer\nbecause of this line\n\nMgt-Origin: abc\n",
+        );
+        assert_eq!(
+            set_trailer("subject\n\nbody\n\nSigned-off-by: me\nMgt-Origin: old\n", "Mgt-Origin", "abc"),
+            "subject\n\nbody\n\nSigned-off-by: me\nMgt-Origin: abc\n",
+        );
+        // the subject is never a trailer
+        assert_eq!(set_trailer("fix: a bug", "Mgt-Origin", "abc"), "fix: a bug\n\nMgt-Origin: abc\n");
+    }
+
+    #[test]
+    fn get_trailer_values_works() {
+        let message = "subject\n\nMgt-Origin: in body\nbody\n\nMgt-Origin: abc\nOther: x\n";
+        assert_eq!(get_trailer_values(message, "Mgt-Origin"), vec!["abc"]);
+        assert!(get_trailer_values("Mgt-Origin: abc\n", "Mgt-Origin").is_empty());
+    }
+
+    #[test]
+    fn message_transforms_apply_replacements_then_trailer() {
+        let mut transforms = MessageTransforms::default();
+        transforms.add_replacement(r"\s*\(?JIRA-[0-9]+\)?", "").unwrap();
+        transforms.add_replacement(r"corp\.internal/(\w+)", "example.com/$1").unwrap();
+        transforms.origin_trailer = Some(DEFAULT_ORIGIN_TRAILER.into());
+        assert!(transforms.add_replacement("(", "").is_err());
+
+        let mut commit = StructuredCommit {
+            commit_message: "fix the thing (JIRA-123)\n\nsee corp.internal/docs\n".into(),
+            original_oid: "abc123".into(),
+            ..Default::default()
+        };
+        transforms.apply(&mut commit);
+        assert_eq!(commit.commit_message, "fix the thing\n\nsee example.com/docs\n\nMgt-Origin: abc123\n");
+    }
+}
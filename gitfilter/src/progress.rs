@@ -0,0 +1,135 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// how far along a filter is. given to the
+/// callback of a `ProgressReporter`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Progress {
+    pub commits_processed: usize,
+    /// commits that were written to the output
+    pub commits_kept: usize,
+    /// commits that were filtered out, or squashed into their parent
+    pub commits_dropped: usize,
+    /// how many commits are expected to be processed, if known
+    pub total_commits: Option<usize>,
+    pub elapsed: Duration,
+    /// true for the last report, after every commit was processed
+    pub done: bool,
+}
+
+impl Progress {
+    pub fn commits_per_second(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs <= 0.0 {
+            return 0.0;
+        }
+        self.commits_processed as f64 / secs
+    }
+
+    /// how long until every commit is processed at the current throughput.
+    /// None if the total is not known, or nothing was processed yet
+    pub fn eta(&self) -> Option<Duration> {
+        let total = self.total_commits?;
+        let per_second = self.commits_per_second();
+        if per_second <= 0.0 {
+            return None;
+        }
+        let remaining = total.saturating_sub(self.commits_processed);
+        Some(Duration::from_secs_f64(remaining as f64 / per_second))
+    }
+}
+
+/// counts the commits of a filter, and calls the callback
+/// after every commit, and once more when the filter is done.
+/// the callback is called often, so it should decide
+/// for itself how often it actually shows anything
+pub struct ProgressReporter {
+    start: Instant,
+    progress: Progress,
+    callback: Box<dyn FnMut(&Progress)>,
+}
+
+impl fmt::Debug for ProgressReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressReporter")
+            .field("progress", &self.progress)
+            .finish()
+    }
+}
+
+impl ProgressReporter {
+    pub fn new(
+        total_commits: Option<usize>,
+        callback: impl FnMut(&Progress) + 'static,
+    ) -> ProgressReporter {
+        ProgressReporter {
+            start: Instant::now(),
+            progress: Progress { total_commits, ..Default::default() },
+            callback: Box::new(callback),
+        }
+    }
+
+    pub fn commit_processed(&mut self, kept: bool) {
+        self.progress.commits_processed += 1;
+        if kept {
+            self.progress.commits_kept += 1;
+        } else {
+            self.progress.commits_dropped += 1;
+        }
+        self.report();
+    }
+
+    pub fn finish(&mut self) {
+        self.progress.done = true;
+        self.report();
+    }
+
+    fn report(&mut self) {
+        self.progress.elapsed = self.start.elapsed();
+        (self.callback)(&self.progress);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    #[test]
+    fn eta_comes_from_the_throughput() {
+        let mut progress = Progress {
+            commits_processed: 100,
+            total_commits: Some(400),
+            elapsed: Duration::from_secs(10),
+            ..Default::default()
+        };
+        assert_eq!(progress.commits_per_second(), 10.0);
+        assert_eq!(progress.eta(), Some(Duration::from_secs(30)));
+        progress.commits_processed = 500;
+        assert_eq!(progress.eta(), Some(Duration::from_secs(0)));
+        progress.total_commits = None;
+        assert_eq!(progress.eta(), None);
+    }
+
+    #[test]
+    fn reporter_counts_kept_and_dropped_commits() {
+        let reports = Rc::new(RefCell::new(vec![]));
+        let reports_clone = reports.clone();
+        let mut reporter = ProgressReporter::new(Some(3), move |p| {
+            reports_clone.borrow_mut().push(p.clone());
+        });
+        reporter.commit_processed(true);
+        reporter.commit_processed(false);
+        reporter.commit_processed(true);
+        reporter.finish();
+        let reports = reports.borrow();
+        assert_eq!(reports.len(), 4);
+        let last = reports.last().unwrap();
+        assert_eq!(last.commits_processed, 3);
+        assert_eq!(last.commits_kept, 2);
+        assert_eq!(last.commits_dropped, 1);
+        assert!(last.done);
+        assert!(!reports[2].done);
+    }
+}
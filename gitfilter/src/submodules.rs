@@ -0,0 +1,180 @@
+use super::filter::{FilterError, FilterRules, should_use_file};
+use super::tags;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::str::FromStr;
+
+/// the mode of a submodule, ie: a gitlink. the data
+/// of a gitlink is the id of a commit of the submodule
+pub const GITLINK_MODE: &str = "160000";
+/// the mode that fast-import uses to replace a
+/// whole directory with an existing tree
+pub const TREE_MODE: &str = "040000";
+/// where git finds the path and url of every submodule
+pub const GITMODULES: &str = ".gitmodules";
+
+/// what to do with the submodules of the history being filtered.
+/// unless they are untouched, `.gitmodules` only lists the submodules
+/// that are still gitlinks, at their filtered paths
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SubmoduleAction {
+    /// gitlinks and `.gitmodules` are filtered like any other file
+    #[default]
+    Untouched,
+    /// keep the gitlink at its filtered path
+    Keep,
+    /// remove the gitlink from the history
+    Drop,
+    /// replace the gitlink with the files of the submodule commit that
+    /// it points to, and merge that commit in, so that the history of the
+    /// submodule becomes part of the filtered history. the commits of the
+    /// submodule have to exist in the repository being filtered
+    Merge,
+}
+
+impl FromStr for SubmoduleAction {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ok = match s {
+            "keep" => SubmoduleAction::Keep,
+            "drop" => SubmoduleAction::Drop,
+            "merge" => SubmoduleAction::Merge,
+            _ => return Err(FilterError(format!("'{}' is not a valid submodule action. Must be one of: keep, drop, merge", s))),
+        };
+        Ok(ok)
+    }
+}
+
+/// the path of a `path = <path>` line of a `.gitmodules` file
+fn get_path_value(line: &str) -> Option<&str> {
+    let mut split = line.splitn(2, '=');
+    match (split.next(), split.next()) {
+        (Some(key), Some(value)) if key.trim() == "path" => Some(value.trim().trim_matches('"')),
+        _ => None,
+    }
+}
+
+/// rewrites the contents of a `.gitmodules` file so that it only has the
+/// submodules that are kept by the filter rules, at their filtered paths.
+/// if the submodules are not kept as gitlinks, none of them are kept.
+/// returns None if no submodules are left
+pub fn rewrite_gitmodules(
+    contents: &str,
+    action: SubmoduleAction,
+    filter_rules: &FilterRules,
+    default_include: bool,
+) -> Option<String> {
+    // every section, starting with its header. lines
+    // before the first header are in a section without a header
+    let mut sections: Vec<Vec<String>> = vec![vec![]];
+    for line in contents.lines() {
+        if line.trim_start().starts_with('[') {
+            sections.push(vec![]);
+        }
+        if let Some(section) = sections.last_mut() {
+            section.push(line.to_string());
+        }
+    }
+
+    let mut out = vec![];
+    let mut num_submodules = 0;
+    for mut section in sections {
+        let is_submodule = section.first()
+            .map(|header| header.trim_start().starts_with("[submodule"))
+            .unwrap_or(false);
+        if is_submodule {
+            if action != SubmoduleAction::Keep {
+                continue;
+            }
+            let path_line = section.iter().position(|line| get_path_value(line).is_some());
+            if let Some(i) = path_line {
+                let line = &section[i];
+                let mut path = get_path_value(line).unwrap_or_default().to_string();
+                if !should_use_file(&mut path, filter_rules, default_include) {
+                    continue;
+                }
+                let indent: String = line.chars().take_while(|c| c.is_whitespace()).collect();
+                section[i] = format!("{}path = {}", indent, path.trim_matches('"'));
+            }
+            num_submodules += 1;
+        }
+        out.extend(section);
+    }
+
+    if num_submodules == 0 {
+        return None;
+    }
+    let mut out = out.join("\n");
+    out.push('\n');
+    Some(out)
+}
+
+/// the contents of the blob with the given id
+pub fn read_blob(location: Option<&PathBuf>, oid: &str) -> Result<String, FilterError> {
+    tags::run_git(&["git", "cat-file", "blob", oid], location)
+        .map_err(|e| FilterError(format!("Failed to read blob {}: {}", oid, e)))
+}
+
+/// writes the contents as a new blob into the
+/// repository, and returns the id of the blob
+pub fn write_blob(location: Option<&PathBuf>, contents: &str) -> Result<String, FilterError> {
+    let args = ["git", "hash-object", "-w", "--stdin"];
+    let err = |e: std::io::Error| FilterError(format!("Failed to write blob: {}", e));
+    let mut child = exechelper::spawn_with_env_ex2(
+        &args, &[], &[], location,
+        Some(Stdio::piped()), Some(Stdio::piped()), Some(Stdio::piped()),
+    ).map_err(err)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(contents.as_bytes()).map_err(err)?;
+    }
+    let output = child.wait_with_output().map_err(err)?;
+    if !output.status.success() {
+        return Err(FilterError(format!("Failed to write blob: {}", String::from_utf8_lossy(&output.stderr))));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// the id of the tree of the submodule commit
+/// that the gitlink at `path` points to
+pub fn get_submodule_tree(
+    location: Option<&PathBuf>,
+    commit_oid: &str,
+    path: &str,
+) -> Result<String, FilterError> {
+    let tree = format!("{}^{{tree}}", commit_oid);
+    let output = tags::run_git(&["git", "rev-parse", "--verify", "-q", tree.as_str()], location)
+        .map_err(|_| FilterError(format!(
+            "Failed to find commit {} of the submodule at {}. To merge in a submodule, its history has to be fetched into this repository",
+            commit_oid, path,
+        )))?;
+    Ok(output.trim().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::filter::FilterRule;
+
+    const GITMODULES_CONTENTS: &str = "[submodule \"lib/a\"]\n\tpath = lib/a\n\turl = ../a\n[submodule \"other\"]\n\tpath = other\n\turl = ../other\n";
+
+    #[test]
+    fn gitmodules_only_has_the_kept_submodules() {
+        let filter_rules = vec![FilterRule::FilterRulePathRename("lib/".into(), "vendor/".into())];
+        let contents = rewrite_gitmodules(
+            GITMODULES_CONTENTS, SubmoduleAction::Keep, &filter_rules, false).unwrap();
+        assert_eq!(contents, "[submodule \"lib/a\"]\n\tpath = vendor/a\n\turl = ../a\n");
+
+        let filter_rules = vec![FilterRule::FilterRulePathInclude("src/".into())];
+        assert_eq!(rewrite_gitmodules(GITMODULES_CONTENTS, SubmoduleAction::Keep, &filter_rules, false), None);
+        assert_eq!(rewrite_gitmodules(GITMODULES_CONTENTS, SubmoduleAction::Drop, &vec![], true), None);
+    }
+
+    #[test]
+    fn submodule_actions_can_be_parsed() {
+        assert_eq!("keep".parse::<SubmoduleAction>().unwrap(), SubmoduleAction::Keep);
+        assert_eq!("merge".parse::<SubmoduleAction>().unwrap(), SubmoduleAction::Merge);
+        assert!("flatten".parse::<SubmoduleAction>().is_err());
+    }
+}
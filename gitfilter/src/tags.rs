@@ -0,0 +1,387 @@
+use std::io;
+use std::path::Path;
+use std::process::Stdio;
+use crate::export_parser::{StructuredExportObject, StructuredObjectType, StructuredTag};
+use crate::export_parser::{CommitPersonOwned, get_regex_taggerline, get_n_captures};
+use crate::filter::FilterError;
+use crate::mailmap::Mailmap;
+use crate::{ioerr, ioerre};
+
+/// this is what git fast-export --fake-missing-tagger
+/// uses for tags that dont have a tagger
+pub const FAKE_TAGGER_NAME: &str = "Unspecified Tagger";
+pub const FAKE_TAGGER_EMAIL: &str = "unspecified-tagger";
+pub const FAKE_TAGGER_TIME: &str = "0 +0000";
+
+/// signatures are stripped from the tag message
+/// just like fast-export --signed-tags=strip
+pub const SIGNATURE_HEADERS: [&str; 3] = [
+    "-----BEGIN PGP SIGNATURE-----",
+    "-----BEGIN SSH SIGNATURE-----",
+    "-----BEGIN SIGNED MESSAGE-----",
+];
+
+/// maps the name of a tag to a new name. the pattern
+/// can contain a single `*` that matches any part of the name,
+/// and the `*` in the replacement is replaced with what it matched,
+/// eg: `v*` -> `mylib-v*` renames `v1.0` to `mylib-v1.0`.
+/// if the pattern has no `*`, it must match the entire tag name
+#[derive(Clone, Debug)]
+pub struct TagMapping {
+    pub pattern: String,
+    pub replacement: String,
+}
+
+impl TagMapping {
+    pub fn new(pattern: &str, replacement: &str) -> Result<TagMapping, FilterError> {
+        if pattern.matches('*').count() > 1 {
+            let err_str = format!("Invalid tag pattern '{}': only a single * is allowed", pattern);
+            return Err(FilterError(err_str));
+        }
+        if replacement.matches('*').count() > 1 {
+            let err_str = format!("Invalid tag replacement '{}': only a single * is allowed", replacement);
+            return Err(FilterError(err_str));
+        }
+        if replacement.contains('*') && !pattern.contains('*') {
+            let err_str = format!("Invalid tag replacement '{}': the pattern '{}' does not have a *", replacement, pattern);
+            return Err(FilterError(err_str));
+        }
+        if replacement.is_empty() {
+            let err_str = format!("Invalid tag replacement for '{}': cannot be empty", pattern);
+            return Err(FilterError(err_str));
+        }
+        Ok(TagMapping {
+            pattern: pattern.into(),
+            replacement: replacement.into(),
+        })
+    }
+
+    /// returns the new name of this tag, or None
+    /// if the tag does not match this mapping
+    pub fn rename(&self, tag: &str) -> Option<String> {
+        let star_index = match self.pattern.find('*') {
+            Some(i) => i,
+            None => {
+                return if tag == self.pattern {
+                    Some(self.replacement.clone())
+                } else { None };
+            }
+        };
+        let before = &self.pattern[..star_index];
+        let after = &self.pattern[(star_index + 1)..];
+        if tag.len() < before.len() + after.len() {
+            return None;
+        }
+        if !tag.starts_with(before) || !tag.ends_with(after) {
+            return None;
+        }
+        let matched = &tag[before.len()..(tag.len() - after.len())];
+        Some(self.replacement.replacen('*', matched, 1))
+    }
+}
+
+/// tag options are opt-in. If you provide them to the
+/// filter, then after filtering the branch, every tag
+/// in the source namespace that points to a commit
+/// of the filtered branch is recreated to point to the filtered commit.
+#[derive(Clone, Debug, Default)]
+pub struct TagOptions {
+    /// where to look for tags, eg: refs/tags/
+    pub source: String,
+    /// the first mapping that matches a tag name is used.
+    /// tags that dont match any mapping are not recreated
+    pub mappings: Vec<TagMapping>,
+}
+
+impl TagOptions {
+    pub fn rename(&self, tag: &str) -> Option<String> {
+        self.mappings.iter().find_map(|m| m.rename(tag))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TagInfo {
+    /// the name of the tag without the source namespace
+    pub name: String,
+    /// the commit that this tag points to
+    pub commit_oid: String,
+    /// the oid of the tag object. None if this is a lightweight tag
+    pub tag_oid: Option<String>,
+}
+
+pub fn run_git<P: AsRef<Path>>(
+    args: &[&str],
+    location: Option<P>,
+) -> io::Result<String> {
+    let child = exechelper::spawn_with_env_ex2(
+        args, &[], &[], location,
+        Some(Stdio::null()), Some(Stdio::piped()), Some(Stdio::piped()),
+    )?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return ioerre!("Failed to run {}:\n{}", args.join(" "), String::from_utf8_lossy(&output.stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into())
+}
+
+/// parses the output of:
+/// `git for-each-ref --format=%(objecttype) %(objectname) %(*objecttype) %(*objectname) %(refname)`
+/// tags that point to something other than a commit are ignored
+pub fn parse_tag_refs(output: &str, source: &str) -> Vec<TagInfo> {
+    let mut out = vec![];
+    for line in output.lines() {
+        let mut split = line.splitn(5, ' ');
+        let (objtype, objname, peeled_type, peeled_name, refname) = match (
+            split.next(), split.next(), split.next(), split.next(), split.next()
+        ) {
+            (Some(a), Some(b), Some(c), Some(d), Some(e)) => (a, b, c, d, e),
+            _ => continue,
+        };
+        let name = match refname.strip_prefix(source) {
+            Some(n) => n.trim_start_matches('/'),
+            None => continue,
+        };
+        let info = match (objtype, peeled_type) {
+            ("commit", _) => TagInfo {
+                name: name.into(),
+                commit_oid: objname.into(),
+                tag_oid: None,
+            },
+            ("tag", "commit") => TagInfo {
+                name: name.into(),
+                commit_oid: peeled_name.into(),
+                tag_oid: Some(objname.into()),
+            },
+            _ => continue,
+        };
+        out.push(info);
+    }
+    out
+}
+
+/// get all of the tags in the source namespace
+/// that point to a commit of the given branch.
+/// if no branch is given, every tag in the source namespace is returned
+pub fn get_tags_merged_into<P: AsRef<Path>>(
+    branch: Option<&str>,
+    source: &str,
+    location: Option<P>,
+) -> io::Result<Vec<TagInfo>> {
+    let source = source.trim_end_matches('/');
+    let mut args = vec![
+        "git".to_string(), "for-each-ref".into(),
+        "--format=%(objecttype) %(objectname) %(*objecttype) %(*objectname) %(refname)".into(),
+    ];
+    if let Some(branch) = branch {
+        args.push(format!("--merged={}", branch));
+    }
+    args.push(source.into());
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    let output = run_git(&args, location)?;
+    Ok(parse_tag_refs(&output, source))
+}
+
+/// parses the output of `git cat-file tag <oid>`
+/// into its tagger, and its message. the tagger is None
+/// if the tag does not have one. any signature is removed
+/// from the message.
+pub fn parse_tag_object(contents: &str) -> io::Result<(Option<CommitPersonOwned>, String)> {
+    let (header, message) = match contents.find("\n\n") {
+        Some(i) => (&contents[..i], &contents[(i + 2)..]),
+        None => (contents, ""),
+    };
+
+    let mut tagger = None;
+    for line in header.lines() {
+        if line.starts_with("tagger ") {
+            let [name, email, timestr] = get_n_captures::<3>(get_regex_taggerline, line)
+                .ok_or(ioerr!("Failed to parse tagger line: {}", line))?;
+            tagger = Some(CommitPersonOwned {
+                name: if name.is_empty() { None } else { Some(name.into()) },
+                email: email.into(),
+                timestr: timestr.into(),
+            });
+        }
+    }
+
+    let mut message_end = message.len();
+    for sig_header in SIGNATURE_HEADERS.iter() {
+        if message.starts_with(sig_header) {
+            message_end = 0;
+            break;
+        }
+        let sig_line = format!("\n{}", sig_header);
+        if let Some(i) = message.find(&sig_line) {
+            message_end = message_end.min(i + 1);
+        }
+    }
+
+    Ok((tagger, message[..message_end].into()))
+}
+
+pub fn fake_tagger() -> CommitPersonOwned {
+    CommitPersonOwned {
+        name: Some(FAKE_TAGGER_NAME.into()),
+        email: FAKE_TAGGER_EMAIL.into(),
+        timestr: FAKE_TAGGER_TIME.into(),
+    }
+}
+
+/// creates the objects that recreate the tags of the branch
+/// in the fast-import stream. `get_mark` should return the mark of the
+/// commit with the given original oid, or None if that commit
+/// was filtered out. a tag whose new name already exists is replaced, so that
+/// tags that were rewritten before follow the commits they were rewritten from,
+/// but a tag is never replaced by its own rewrite, ie: `tags = true` in split-out.
+/// when several refs were filtered, pass None for the branch, and
+/// `get_mark` decides which tags point to filtered commits.
+/// the mailmap is applied to the tagger of annotated tags
+/// just like it is for the tags of the fast-export stream.
+pub fn make_tag_objects<P: AsRef<Path>>(
+    tag_options: &TagOptions,
+    branch: Option<&str>,
+    location: Option<P>,
+    mailmap: Option<&Mailmap>,
+    get_mark: impl Fn(&str) -> Option<usize>,
+) -> io::Result<Vec<StructuredExportObject>> {
+    let location = location.as_ref().map(|l| l.as_ref());
+    let tags = get_tags_merged_into(branch, &tag_options.source, location)?;
+    let mut out = vec![];
+    for tag in tags {
+        let new_name = match tag_options.rename(&tag.name) {
+            Some(n) => n,
+            None => continue,
+        };
+        if format!("{}{}", tag_options.source, tag.name) == format!("refs/tags/{}", new_name) {
+            continue;
+        }
+        let mark = match get_mark(&tag.commit_oid) {
+            Some(m) => m,
+            None => continue,
+        };
+
+        let mut obj = StructuredExportObject::default();
+        match tag.tag_oid {
+            None => {
+                obj.has_reset = Some(format!("refs/tags/{}", new_name));
+                obj.has_reset_from = Some(format!(":{}", mark));
+                obj.object_type = StructuredObjectType::NoType;
+            }
+            Some(tag_oid) => {
+                let contents = run_git(&["git", "cat-file", "tag", &tag_oid], location)?;
+                let (mut tagger, tag_message) = parse_tag_object(&contents)?;
+                if let (Some(mailmap), Some(tagger)) = (mailmap, &mut tagger) {
+                    mailmap.apply(tagger);
+                }
+                obj.object_type = StructuredObjectType::Tag(StructuredTag {
+                    name: new_name,
+                    mark: None,
+                    from: mark,
+                    original_oid: tag_oid,
+                    tagger: tagger.unwrap_or_else(fake_tagger),
+                    tag_message,
+                });
+            }
+        }
+        out.push(obj);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tag_mappings_rename_correctly() {
+        let prefix = TagMapping::new("*", "mylib-*").unwrap();
+        assert_eq!(prefix.rename("v1.0"), Some("mylib-v1.0".into()));
+
+        let mapping = TagMapping::new("v*", "mylib-v*").unwrap();
+        assert_eq!(mapping.rename("v1.0"), Some("mylib-v1.0".into()));
+        assert_eq!(mapping.rename("release-1.0"), None);
+
+        let suffix = TagMapping::new("*-lib", "lib/*").unwrap();
+        assert_eq!(suffix.rename("1.0-lib"), Some("lib/1.0".into()));
+        assert_eq!(suffix.rename("1.0-app"), None);
+
+        let exact = TagMapping::new("stable", "mylib-stable").unwrap();
+        assert_eq!(exact.rename("stable"), Some("mylib-stable".into()));
+        assert_eq!(exact.rename("stable2"), None);
+
+        assert!(TagMapping::new("v*.*", "x").is_err());
+        assert!(TagMapping::new("v1", "x-*").is_err());
+
+        let options = TagOptions {
+            source: "refs/tags/".into(),
+            mappings: vec![
+                TagMapping::new("v*", "mylib-v*").unwrap(),
+                TagMapping::new("*", "other-*").unwrap(),
+            ],
+        };
+        assert_eq!(options.rename("v2"), Some("mylib-v2".into()));
+        assert_eq!(options.rename("x"), Some("other-x".into()));
+    }
+
+    #[test]
+    fn tag_refs_parse_correctly() {
+        let output = "commit aaa   refs/tags/v1\n\
+            tag bbb commit ccc refs/tags/v2\n\
+            tag ddd tree eee refs/tags/tree-tag\n\
+            tag fff commit ggg refs/mgt/tags/v3\n";
+        let tags = parse_tag_refs(output, "refs/tags");
+        assert_eq!(tags, vec![
+            TagInfo { name: "v1".into(), commit_oid: "aaa".into(), tag_oid: None },
+            TagInfo { name: "v2".into(), commit_oid: "ccc".into(), tag_oid: Some("bbb".into()) },
+        ]);
+    }
+
+    #[test]
+    fn tag_objects_parse_correctly() {
+        let contents = "object aaa\ntype commit\ntag v2\n\
+            tagger A Person <a@a.com> 1548162866 -0800\n\n\
+            release two\n\nmore info\n\
+            -----BEGIN PGP SIGNATURE-----\nabc\n-----END PGP SIGNATURE-----\n";
+        let (tagger, message) = parse_tag_object(contents).unwrap();
+        let tagger = tagger.unwrap();
+        assert_eq!(tagger.name, Some("A Person".into()));
+        assert_eq!(tagger.email, "a@a.com");
+        assert_eq!(tagger.timestr, "1548162866 -0800");
+        assert_eq!(message, "release two\n\nmore info\n");
+
+        let contents = "object aaa\ntype commit\ntag v3\n\nno tagger\n";
+        let (tagger, message) = parse_tag_object(contents).unwrap();
+        assert!(tagger.is_none());
+        assert_eq!(message, "no tagger\n");
+    }
+
+    #[test]
+    fn annotated_tag_taggers_are_mailmapped() {
+        let dir = std::env::temp_dir().join(format!("gitfilter-tag-mailmap-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let run = |args: &[&str]| run_git(args, Some(&dir)).unwrap();
+        let identity = ["-c", "user.name=Old Name", "-c", "user.email=old@a.com"];
+        run(&["git", "init", "-q"]);
+        run(&[&["git"][..], &identity, &["commit", "-q", "--allow-empty", "-m", "one"]].concat());
+        run(&[&["git"][..], &identity, &["tag", "-a", "v1", "-m", "release one"]].concat());
+
+        let options = TagOptions {
+            source: "refs/tags/".into(),
+            mappings: vec![TagMapping::new("*", "mylib-*").unwrap()],
+        };
+        let mailmap = Mailmap::parse("Proper Name <proper@a.com> <old@a.com>").unwrap();
+        let objects = make_tag_objects(&options, None, Some(&dir), Some(&mailmap), |_| Some(1));
+        std::fs::remove_dir_all(&dir).unwrap();
+        let objects = objects.unwrap();
+        assert_eq!(objects.len(), 1);
+        match &objects[0].object_type {
+            StructuredObjectType::Tag(tag) => {
+                assert_eq!(tag.name, "mylib-v1");
+                assert_eq!(tag.tagger.name, Some("Proper Name".into()));
+                assert_eq!(tag.tagger.email, "proper@a.com");
+                assert_eq!(tag.tag_message, "release one\n");
+            }
+            _ => panic!("expected an annotated tag"),
+        }
+    }
+}
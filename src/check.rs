@@ -1,21 +1,34 @@
 use std::path::PathBuf;
+use std::io;
 
 use super::git_helpers3;
 use super::git_helpers3::Oid;
 use super::exec_helpers;
-use super::repo_file::RepoFile;
+use super::repo_file::{RepoFile, Remote};
 use super::die;
+use super::ioerr;
 use super::topbase;
 use super::repo_file;
 use super::cli::MgtCommandCheck;
 use super::core::get_all_repo_files;
-use git_helpers3::{RawBlobSummary, CommitWithBlobs};
+use super::verify;
+use super::workspace;
+use super::output::{self, OutputFormat};
+use super::topbase::SuccessfulTopbaseResult;
+use super::sync::SyncType;
+use gitfilter::filter::FilterRules;
+use git_helpers3::{RawBlobSummary, Commit};
+use serde_json::{json, Value};
 
 pub struct Checker<'a> {
     upstream_branch: String,
     current_branch: String,
     current_is_remote: bool,
     repo_file: &'a RepoFile,
+    // only used for output:
+    upstream_name: String,
+    current_name: String,
+    remote_name: String,
 }
 
 impl<'a> Checker<'a> {
@@ -26,8 +39,10 @@ impl<'a> Checker<'a> {
         local_branch: Option<S>,
         remote_branch: Option<S>,
         repo_file: &'a RepoFile,
-    ) -> Checker {
-        create_checker(current_is_remote, local_branch, remote_branch, repo_file)
+        remote: &Remote,
+        should_print: bool,
+    ) -> Checker<'a> {
+        create_checker(current_is_remote, local_branch, remote_branch, repo_file, remote, should_print)
     }
 
     pub fn check_for_updates(
@@ -35,14 +50,14 @@ impl<'a> Checker<'a> {
         repo_file_path: Option<&str>,
         should_clean_fetch_head: bool,
         should_summarize: bool,
-    ) {
+    ) -> io::Result<()> {
         let (commits_to_take, commit_summaries) = check_for_updates(
             self.repo_file,
             &self.upstream_branch,
             &self.current_branch,
             self.current_is_remote,
             should_summarize
-        );
+        )?;
 
         if should_summarize {
             let command_to_take = match repo_file_path {
@@ -67,6 +82,46 @@ impl<'a> Checker<'a> {
             // TODO: clean fetch head...
             // hard to do because of gits auto gc?
         }
+        Ok(())
+    }
+
+    /// like `check_for_updates` but instead of printing a summary,
+    /// return everything we found as a json object
+    pub fn check_for_updates_json(
+        &self,
+        repo_file_path: &str,
+    ) -> io::Result<Value> {
+        // we need the commits of both sides to know the sync type
+        let should_rewind = true;
+        let topbase_result = find_updates(
+            self.repo_file,
+            &self.upstream_branch,
+            &self.current_branch,
+            self.current_is_remote,
+            should_rewind,
+        )?;
+        let (commits_to_take, sync_type) = match topbase_result {
+            // the remote branch is always the A branch, see `find_updates`.
+            // like sync, merge commits don't count towards being ahead
+            Some(ref res) => (
+                get_commits_to_take(res, self.current_is_remote),
+                SyncType::from_ahead(
+                    res.top_right_commits.iter().any(|c| ! c.is_merge),
+                    res.top_commits.iter().any(|c| ! c.is_merge),
+                ),
+            ),
+            None => (&[][..], SyncType::Disjoint),
+        };
+        let mut out = json!({
+            "repo_file": repo_file_path,
+            "remote": self.remote_name,
+            "current": self.current_name,
+            "upstream": self.upstream_name,
+            "sync_type": sync_type.name(),
+            "commits_to_take": output::commits_to_json(commits_to_take),
+        });
+        output::extend_json_object(&mut out, output::topbase_result_to_json(topbase_result.as_ref()));
+        Ok(out)
     }
 }
 
@@ -91,54 +146,40 @@ pub fn summarize_updates(
     }
 }
 
+/// the filter rules of a repo file in both directions.
+/// local is what split-out would use, remote is what split-in would use.
+pub struct RepoFileFilterRules {
+    pub local: FilterRules,
+    pub remote: FilterRules,
+}
+
+impl RepoFileFilterRules {
+    pub fn new(repo_file: &RepoFile) -> io::Result<RepoFileFilterRules> {
+        let mut local_ops = verify::get_vec_of_file_ops_with_order(repo_file, true);
+        let mut remote_ops = verify::get_vec_of_file_ops_with_order(repo_file, false);
+        Ok(RepoFileFilterRules {
+            local: verify::make_filter_rules_for_matching(&mut local_ops)?,
+            remote: verify::make_filter_rules_for_matching(&mut remote_ops)?,
+        })
+    }
+}
+
 // evaluate the include/exclude rules of the repo file
-// to see if the blob path is relevant to these rules
+// to see if the blob path is relevant to these rules.
+// this uses the same logic as gitfilter so that
+// check and sync agree with what would actually get filtered
 pub fn blob_path_applies_to_repo_file(
     blob_path: &String,
-    repo_file: &RepoFile,
+    repo_file_rules: &RepoFileFilterRules,
     is_remote: bool,
 ) -> bool {
-    let mut blob_matches_include = false;
-    let empty_vec = vec![];
-    let include_vec = match &repo_file.include {
-        Some(v) => v,
-        None => &empty_vec,
-    };
-    let include_as_vec = match &repo_file.include_as {
-        Some(v) => v,
-        None => &empty_vec,
-    };
-    let exclude_vec = match &repo_file.exclude {
-        Some(v) => v,
-        None => &empty_vec,
+    let filter_rules = if is_remote {
+        &repo_file_rules.remote
+    } else {
+        &repo_file_rules.local
     };
-
-    let skip_by = if is_remote { 1 } else { 0 };
-    let mut paths_to_include = include_vec.clone();
-    for p in include_as_vec.iter().skip(skip_by).step_by(2) {
-        paths_to_include.push(p.clone());
-    }
-    // paths_to_include
-    // try to see if it matches any of the include/include_as
-    for i in paths_to_include.iter() {
-        // remember a single empty space means take anything here
-        if i == " " || blob_path.starts_with(i) {
-            blob_matches_include = true;
-            break;
-        }
-    }
-
-    // if it matches include, make sure it doesnt match the exclude
-    if blob_matches_include {
-        for e in exclude_vec {
-            if blob_path.starts_with(e) {
-                return false;
-            }
-        }
-        return true;
-    }
-
-    false
+    let mut path = blob_path.clone();
+    gitfilter::filter::should_use_file(&mut path, filter_rules, false)
 }
 
 fn get_formatted_remote_or_branch_str(branch_and_remote: &str, is_remote: bool) -> String {
@@ -192,13 +233,8 @@ pub fn _clean_fetch(path_to_repo_root: &PathBuf) -> std::io::Result<bool> {
     Ok(true)
 }
 
-pub fn run_check(cmd: &mut MgtCommandCheck) {
-    // remote is true by default, unless --local
-    // was specified
-    if !cmd.local {
-        cmd.remote = true;
-    }
-
+/// the <repo-file>, or every repo file in it if it is a directory
+pub fn get_files_to_check(cmd: &MgtCommandCheck) -> Vec<String> {
     let repo_file_path = if cmd.repo_file.len() < 1 {
         die!("Must provide repo file path");
     } else {
@@ -206,7 +242,7 @@ pub fn run_check(cmd: &mut MgtCommandCheck) {
     };
 
     let repo_file_pathbuf: PathBuf = repo_file_path.clone().into();
-    let files_to_check = if repo_file_pathbuf.is_file() {
+    if repo_file_pathbuf.is_file() {
         vec![repo_file_path.to_string()]
     } else {
         // iterate over that folder and find all repo files
@@ -219,47 +255,118 @@ pub fn run_check(cmd: &mut MgtCommandCheck) {
             Ok(files) => files,
             Err(e) => die!("Failed to read repo file directory: {}", e),
         }
+    }
+}
+
+pub fn run_check(cmd: &mut MgtCommandCheck) {
+    // remote is true by default, unless --local
+    // was specified
+    if !cmd.local {
+        cmd.remote = true;
+    }
+
+    let files_to_check = if cmd.group.is_some() || cmd.all_subrepos {
+        if !cmd.repo_file.is_empty() {
+            die!("Cannot use --group or --all-subrepos with a repo file path");
+        }
+        let subrepo_files = workspace::get_current_workspace().and_then(|workspace| {
+            let subrepos = workspace.get_subrepos(cmd.group.as_deref())?;
+            Ok(subrepos.iter().map(|subrepo| subrepo.repo_file.to_string_lossy().to_string()).collect())
+        });
+        match subrepo_files {
+            Ok(files) => files,
+            Err(e) => die!("{}", e),
+        }
+    } else {
+        get_files_to_check(cmd)
     };
 
-    for file in files_to_check {
-        println!("---\nChecking {}", file);
+    let is_json = cmd.format.unwrap_or_default() == OutputFormat::Json;
+    let mut json_out = vec![];
+    let mut had_error = false;
+    // every repo file is compared to the same local branch,
+    // so its history is only walked once
+    git_helpers3::with_shared_blob_log(|| for file in files_to_check {
+        if ! is_json {
+            println!("---\nChecking {}", file);
+        }
         let repo_file = repo_file::parse_repo_file_from_toml_path(&file);
         let current_is_remote = cmd.remote;
-        let checker = Checker::create(
-            current_is_remote,
-            cmd.local_branch.clone(),
-            cmd.remote_branch.clone(),
-            &repo_file
-        );
-        checker.check_for_updates(
-            Some(&file),
-            true,
-            true
-        );
+        // updates can only be taken from remotes that can be pulled
+        // from, and only given to remotes that can be pushed to
+        let remotes: Vec<Remote> = repo_file::get_remotes(&repo_file).into_iter()
+            .filter(|remote| if current_is_remote { remote.pull } else { remote.push })
+            .collect();
+        if remotes.is_empty() {
+            match repo_file.remotes {
+                None => die!("repo file missing remote_repo"),
+                Some(_) => die!("repo file has no remote that can be {}", if current_is_remote { "pulled from" } else { "pushed to" }),
+            }
+        }
+        for remote in &remotes {
+            let checker = Checker::create(
+                current_is_remote,
+                cmd.local_branch.clone(),
+                cmd.remote_branch.clone(),
+                &repo_file,
+                remote,
+                ! is_json,
+            );
+            if is_json {
+                match checker.check_for_updates_json(&file) {
+                    Ok(out) => json_out.push(out),
+                    Err(e) => {
+                        had_error = true;
+                        json_out.push(json!({
+                            "repo_file": file,
+                            "remote": checker.remote_name,
+                            "error": e.to_string(),
+                        }));
+                    }
+                }
+                continue;
+            }
+            if let Err(e) = checker.check_for_updates(Some(&file), true, true) {
+                had_error = true;
+                eprintln!("Failed to check {}:\n{}", file, e);
+            }
+        }
+    });
+
+    if is_json {
+        output::print_json(&Value::Array(json_out));
+    }
+    if had_error {
+        std::process::exit(1);
     }
 }
 
 /// create the checker struct that is setup and ready
-/// to run the check operation
-pub fn create_checker<S: ToString>(
+/// to run the check operation. if should_print, the branches
+/// being checked are printed before fetching, so the user
+/// can see what failed to fetch
+pub fn create_checker<'a, S: ToString>(
     current_is_remote: bool,
     local_branch: Option<S>,
     remote_branch: Option<S>,
-    repo_file: &RepoFile,
-) -> Checker {
+    repo_file: &'a RepoFile,
+    remote: &Remote,
+    should_print: bool,
+) -> Checker<'a> {
     // 'current' is NOT the branch we are currently on
     // but rather its the branch that potentially
     // has the most recent updates
     let upstream_is_remote = ! current_is_remote;
+    let remote_name = remote.name.clone();
 
     let current = get_current_branch_name(
-        repo_file,
+        remote,
         current_is_remote,
         &local_branch,
         &remote_branch,
     );
     let upstream = get_upstream_branch_name(
-        repo_file,
+        remote,
         current_is_remote,
         &local_branch,
         &remote_branch,
@@ -268,8 +375,16 @@ pub fn create_checker<S: ToString>(
     // whichever is the remote one will be in the format of <uri>?<ref>
     // so we need to know which to be able to split by :
     // checking if upstream should get updates from current
-    println!("Current: {}", get_formatted_remote_or_branch_str(&current, current_is_remote));
-    println!("Upstream: {}", get_formatted_remote_or_branch_str(&upstream, upstream_is_remote));
+    let current_name = get_formatted_remote_or_branch_str(&current, current_is_remote);
+    let upstream_name = get_formatted_remote_or_branch_str(&upstream, upstream_is_remote);
+    if should_print {
+        // only show which remote it is when there can be more than one
+        if repo_file.remotes.is_some() {
+            println!("Remote: {}", remote_name);
+        }
+        println!("Current: {}", current_name);
+        println!("Upstream: {}", upstream_name);
+    }
 
     // probably want to have two modes eventually:
     // default is to fetch entire remote branch and then run the git diff-tree, and rev-list
@@ -294,17 +409,20 @@ pub fn create_checker<S: ToString>(
         false => current,
     };
 
-    Checker { upstream_branch, current_branch, current_is_remote, repo_file }
+    Checker {
+        upstream_branch, current_branch, current_is_remote, repo_file,
+        upstream_name, current_name, remote_name,
+    }
 }
 
 fn get_current_branch_name<S: ToString>(
-    repo_file: &RepoFile,
+    remote: &Remote,
     current_is_remote: bool,
     local_branch: &Option<S>,
     remote_branch: &Option<S>,
 ) -> String {
     if current_is_remote {
-        get_remote_branch2(repo_file, remote_branch)
+        get_remote_branch2(remote, remote_branch)
     } else {
         match local_branch {
             Some(ref s) => s.to_string(),
@@ -317,26 +435,23 @@ fn get_current_branch_name<S: ToString>(
 /// except it should do the opposite based
 /// on the current_is_remote flag
 fn get_upstream_branch_name<S: ToString>(
-    repo_file: &RepoFile,
+    remote: &Remote,
     current_is_remote: bool,
     local_branch: &Option<S>,
     remote_branch: &Option<S>,
 ) -> String {
-    get_current_branch_name(repo_file, !current_is_remote, local_branch, remote_branch)
+    get_current_branch_name(remote, !current_is_remote, local_branch, remote_branch)
 }
 
 fn get_remote_branch2<S: ToString>(
-    repo_file: &RepoFile,
+    remote: &Remote,
     remote_branch: &Option<S>,
 ) -> String {
-    let remote_repo = match repo_file.remote_repo {
-        Some(ref s) => s,
-        None => die!("repo file missing remote_repo"),
-    };
+    let remote_repo = &remote.url;
     // check if user provided a --remote <branch>
     let remote_branch = match remote_branch {
         Some(ref s) => s.to_string(),
-        None => match repo_file.remote_branch {
+        None => match remote.branch {
             Some(ref s) => s.to_string(),
             None => "HEAD".to_string(),
         }
@@ -355,21 +470,64 @@ fn check_for_updates(
     current_branch: &str,
     current_is_remote: bool,
     should_summarize: bool,
-) -> (Vec<Oid>, Vec<String>) {
-    // we need to enable rewind mode if our current branch
-    // is on the right.
-    let mut should_rewind = false;
+) -> io::Result<(Vec<Oid>, Vec<String>)> {
+    let mut out_ids = vec![];
+    let mut out_str = vec![];
+    // we only need the commits of the current branch, which are on the
+    // right if current is local. See `get_commits_to_take`
+    let should_rewind = ! current_is_remote;
+    // TODO: failure to find a fork point returns (vec![], vec![])
+    // which we interpret as an "up-to-date" case above, which is not correct
+    let successful_topbase = match find_updates(repo_file, upstream_branch, current_branch, current_is_remote, should_rewind)? {
+        Some(t) => t,
+        None => return Ok((out_ids, out_str)),
+    };
+
+    for out_commit in get_commits_to_take(&successful_topbase, current_is_remote) {
+        if should_summarize {
+            out_ids.push(out_commit.id.clone());
+            out_str.push(out_commit.summary.clone());
+        }
+    }
+
+    Ok((out_ids, out_str))
+}
+
+/// the remote branch is always the A branch, so if current is local,
+/// the commits that upstream wants are on the 'right' side
+/// (ie: current branch is the B branch, and we always care about
+/// upstream getting updates from current). so we take the
+/// top_right_commits instead of the top_commits in that case.
+/// See `find_updates`.
+fn get_commits_to_take(
+    successful_topbase: &SuccessfulTopbaseResult<Commit>,
+    current_is_remote: bool,
+) -> &[Commit] {
+    if ! current_is_remote {
+        &successful_topbase.top_right_commits
+    } else {
+        &successful_topbase.top_commits
+    }
+}
+
+/// run the topbase between the remote branch (A) and the
+/// local branch (B). if should_rewind, the commits of B above the
+/// fork point are found as well. returns None if we failed to find a fork point
+fn find_updates(
+    repo_file: &RepoFile,
+    upstream_branch: &str,
+    current_branch: &str,
+    current_is_remote: bool,
+    should_rewind: bool,
+) -> io::Result<Option<SuccessfulTopbaseResult<Commit>>> {
     let (a_branch, b_branch) = if current_is_remote {
         (current_branch, upstream_branch)
     } else {
-        should_rewind = true;
         (upstream_branch, current_branch)
     };
 
     let hashing_mode = topbase::BlobHashingMode::WithoutPath;
     let traverse_at_a_time = 500;
-    let mut out_ids = vec![];
-    let mut out_str = vec![];
 
     // check all blob paths to make sure they apply
     // to our repo file:
@@ -377,40 +535,19 @@ fn check_for_updates(
     // we check if the path of this blob is relevant to the repo
     // and we have to consider if its a local or remote commit,
     // hence the b == current_branch or b == upstream_branch
+    let repo_file_rules = match RepoFileFilterRules::new(repo_file) {
+        Ok(rules) => rules,
+        Err(e) => die!("Failed to make filter rules from repo file:\n{}", e),
+    };
     let should_use_blob_cb = |c: &mut RawBlobSummary, b: &str| {
         let this_is_a_remote_blob = if current_is_remote {
             b == current_branch
         } else {
             b == upstream_branch
         };
-        blob_path_applies_to_repo_file(&c.path_dest, repo_file, this_is_a_remote_blob)
+        blob_path_applies_to_repo_file(&c.path_dest, &repo_file_rules, this_is_a_remote_blob)
     };
-    let successful_topbase = match topbase::find_a_b_difference2::<CommitWithBlobs, _>(
+    topbase::find_a_b_difference2::<Commit, _>(
         a_branch, b_branch, Some(traverse_at_a_time), hashing_mode, should_rewind, Some(should_use_blob_cb))
-    {
-        Ok(s) => if let Some(t) = s { t } else { return (out_ids, out_str) },
-        Err(_) => return (out_ids, out_str),
-    };
-    // TODO: failure to find a fork point returns (vec![], vec![])
-    // which we interpret as an "up-to-date" case above, which is not correct
-
-    // if we should rewind, that means we expect the commits that upstream
-    // wants are on the 'right' side (ie: current branch is the B branch,
-    // and we always care about upstream getting updates from current).
-    // so we iterate the top_right_commits instead of the top_commits
-    // in that case:
-    let iter_commits = if should_rewind {
-        successful_topbase.top_right_commits.iter()
-    } else {
-        successful_topbase.top_commits.iter()
-    };
-
-    for out_commit in iter_commits {
-        if should_summarize {
-            out_ids.push(out_commit.commit.id.clone());
-            out_str.push(out_commit.commit.summary.clone());
-        }
-    }
-
-    (out_ids, out_str)
+        .map_err(|e| ioerr!("Failed to find the updates between {} and {}: {}", upstream_branch, current_branch, e))
 }
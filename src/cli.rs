@@ -8,9 +8,12 @@ use super::split_out::run_split_out_as;
 use super::split_in::run_split_in;
 use super::split_in::run_split_in_as;
 use super::verify::run_verify;
+use super::reverse::run_reverse;
 use super::topbase::run_topbase;
 use super::topbase::ABTraversalMode;
 use super::sync::run_sync;
+use super::sync::{SyncPolicy, MergePolicy};
+use super::output::OutputFormat;
 use std::path::PathBuf;
 
 #[derive(Debug, Options)]
@@ -24,6 +27,8 @@ pub struct MgtCommandCheck {
     pub recursive: bool,
     #[options(help = "check if the remote has commits not present in this local branch. This is the default")]
     pub remote: bool,
+    #[options(no_short, help = "check the repo file of every subrepo in the workspace file (mgt.toml) instead of a <repo-file>")]
+    pub all_subrepos: bool,
     #[options(short = "h")]
     pub help: bool,
 
@@ -32,6 +37,10 @@ pub struct MgtCommandCheck {
     pub local_branch: Option<String>,
     #[options(short = "b", meta = "BRANCH-NAME", help = "check updates to/from a specific remote branch instead of what's in the repo file")]
     pub remote_branch: Option<String>,
+    #[options(meta = "FORMAT", help = "Valid formats are [text, json]. json outputs a list with the sync type, fork point and commits of each repo file, or the error if it could not be checked. default is text")]
+    pub format: Option<OutputFormat>,
+    #[options(no_short, meta = "GROUP", help = "check the repo files of the subrepos in this group of the workspace file (mgt.toml) instead of a <repo-file>")]
+    pub group: Option<String>,
 
     // positional arg: repo_file
     // (its a vec to appease gumdrop cli parser
@@ -45,12 +54,15 @@ pub struct MgtCommandDifflog {
     #[options(free)]
     pub branches: Vec<String>,
 
-    #[options(short = "m", help = "(fullbase not implemented yet) Valid modes are [topbase, rewind, fullbase]. default is rewind")]
+    #[options(short = "m", help = "Valid modes are [topbase, rewind, fullbase]. fullbase shows every fork point of the two branches instead of only the most recent one. default is rewind")]
     pub traversal_mode: Option<ABTraversalMode>,
 
     #[options(short = "w", help = "Force specify a width to display the log. default is to use whole terminal")]
     pub term_width: Option<usize>,
 
+    #[options(meta = "FORMAT", help = "Valid formats are [text, json]. json outputs the fork point and the commits on each side instead of the log. default is text")]
+    pub format: Option<OutputFormat>,
+
     #[options(short = "h")]
     pub help: bool,
 }
@@ -95,6 +107,12 @@ pub struct MgtCommandSplit {
     #[options(long = "as", help = "path relative to root of the local repository that will contain the entire repository being split")]
     pub as_subdir: Option<String>,
 
+    #[options(no_short, help = "split-out only. resume from the last time this output branch was split out, and only filter the commits that were added since. the output branch is reset and then the newly filtered commits are appended to the previously filtered commits")]
+    pub incremental: bool,
+
+    #[options(no_short, help = "split-out only. do not push the output branch to the [remotes] of the repo file")]
+    pub no_push: bool,
+
     // for program use, not by user
     #[options(skip)]
     pub direction: Option<Direction>,
@@ -128,9 +146,24 @@ pub struct MgtCommandVerify {
 
     #[options(help = "provide a list of files to verify from stdin, one file per line. By default we get this list of files for you via:\ngit ls-tree -r HEAD --name-only --full-tree\n You can achieve the default behavior by doing:\n git ls-tree -r HEAD --name-only --full-tree | mgt verify-rf --stdin <PATH/TO/REPOFILE>")]
     pub stdin: bool,
+
+    #[options(no_short, help = "rewrite a version 1 repo file in place as a version 2 repo file, with its include, include_as and exclude as [[rule]]s in the order they were applied. Comments are not kept")]
+    pub migrate: bool,
 }
 
 #[derive(Debug, Options)]
+pub struct MgtCommandReverse {
+    #[options(short = "h")]
+    pub help: bool,
+
+    #[options(free, help = "path to your repo file")]
+    pub repo_file: Vec<String>,
+
+    #[options(help = "provide a list of files to check the reversed repo file with from stdin, one file per line. By default every file of HEAD is used, ie: git ls-tree -r HEAD --name-only --full-tree")]
+    pub stdin: bool,
+}
+
+#[derive(Debug, Clone, Options)]
 pub struct MgtCommandSync {
     #[options(short = "h")]
     pub help: bool,
@@ -138,6 +171,12 @@ pub struct MgtCommandSync {
     #[options(free, help = "path to repo file(s) or a folder container repo files")]
     pub repo_files: Vec<PathBuf>,
 
+    #[options(no_short, help = "sync every subrepo in the workspace file (mgt.toml) instead of the given repo files")]
+    pub all_subrepos: bool,
+
+    #[options(no_short, meta = "GROUP", help = "sync the subrepos in this group of the workspace file (mgt.toml) instead of the given repo files")]
+    pub group: Option<String>,
+
     #[options(help = "when iterating the sync of multiple repo files, if a single one fails, do not sync the rest")]
     pub fail_fast: bool,
 
@@ -149,6 +188,24 @@ pub struct MgtCommandSync {
 
     #[options(help = "only show the summary of the potential sync, but do not perform any filter, pull, or push operations")]
     pub summary_only: bool,
+
+    #[options(meta = "FORMAT", help = "Valid formats are [text, json]. json can only be used with --summary-only, and outputs a list with the sync type, fork point, and commits of each repo file. default is text")]
+    pub format: Option<OutputFormat>,
+
+    #[options(help = "never ask any interactive questions. Instead, the --on-* and --merge options decide what to do. Exits with 0 if everything is up to date, 10 if local is ahead, 11 if remote is ahead, 12 if they diverged, 13 if they are disjoint, and 1 on error")]
+    pub non_interactive: bool,
+
+    #[options(meta = "POLICY", help = "what to do with --non-interactive when the remote is ahead. Valid policies are [pull, skip, fail]. default is the sync policy of the subrepo in mgt.toml, or skip")]
+    pub on_remote_ahead: Option<SyncPolicy>,
+
+    #[options(meta = "POLICY", help = "what to do with --non-interactive when the local is ahead. Valid policies are [push, skip, fail]. default is the sync policy of the subrepo in mgt.toml, or skip")]
+    pub on_local_ahead: Option<SyncPolicy>,
+
+    #[options(meta = "POLICY", help = "what to do with --non-interactive when local and remote have diverged. Valid policies are [rebase, skip, fail]. rebase does a pull --rebase, then push. default is the sync policy of the subrepo in mgt.toml, or skip")]
+    pub on_diverged: Option<SyncPolicy>,
+
+    #[options(meta = "POLICY", help = "whether to merge pulled changes into your branch with --non-interactive. Valid policies are [always, never]. never leaves the pulled changes on a branch for you to review. default is the merge policy of the subrepo in mgt.toml, or never")]
+    pub merge: Option<MergePolicy>,
 }
 
 #[derive(Debug, Options)]
@@ -184,6 +241,12 @@ pub enum MgtSubcommands {
 
     #[options(help = "alias for verify-repo-file")]
     VerifyRf(MgtCommandVerify),
+
+    #[options(help = "print the repo file that splits the split out repository back in, with every path where it came from")]
+    ReverseRepoFile(MgtCommandReverse),
+
+    #[options(help = "alias for reverse-repo-file")]
+    ReverseRf(MgtCommandReverse),
 }
 
 pub fn get_version_str() -> String {
@@ -215,17 +278,17 @@ pub fn print_usage<A: AsRef<impl Options>>(
         let desc = "create a new branch with this repository's history rewritten according to the --as <subdirectory>";
         (p_desc, desc, Some(vec!["input-branch", "gen-repo-file", "num-commits", "--rebase", "--topbase"]))
     } else if cmd_name.contains("split-out") {
-        let p_desc = Some(repo_file_desc);
+        let p_desc = Some("    <repo-file>...    path to file that contains instructions of how to split a repository.\n                      with several repo files, each is split out into a branch named after it\n                      while only reading the history once");
         let desc = "create a new branch with this repository's history rewritten according to the repo file rules";
         (p_desc, desc, Some(vec!["input-branch", "gen-repo-file", "--as", "num-commits"]))
     } else if cmd_name.contains("split-in-as") {
         let p_desc = Some("    <git-repo-uri>    a valid git url of the repository to split in");
         let desc = "fetch and rewrite a remote repository's history onto a new branch and into the --as <subdirectory>";
-        (p_desc, desc, Some(vec!["input-branch"]))
+        (p_desc, desc, Some(vec!["input-branch", "--incremental"]))
     } else if cmd_name.contains("split-in") {
         let p_desc = Some(repo_file_desc);
         let desc = "fetch and rewrite a remote repository's history onto a new branch according to the repo file rules";
-        (p_desc, desc, Some(vec!["gen-repo-file", "--as"]))
+        (p_desc, desc, Some(vec!["gen-repo-file", "--as", "--incremental"]))
     } else if cmd_name.contains("topbase") {
         let p_desc = Some("    <base>    the branch to rebase onto.\n    [top]     the branch that will be rebased. defaults to current branch");
         let desc = "rebase top branch onto bottom branch but stop the rebase after the first shared commit";
@@ -315,6 +378,9 @@ impl AsRef<MgtCommandSplit> for MgtCommandSplit {
 impl AsRef<MgtCommandVerify> for MgtCommandVerify {
     fn as_ref(&self) -> &MgtCommandVerify { self }
 }
+impl AsRef<MgtCommandReverse> for MgtCommandReverse {
+    fn as_ref(&self) -> &MgtCommandReverse { self }
+}
 impl AsRef<MgtCommandSync> for MgtCommandSync {
     fn as_ref(&self) -> &MgtCommandSync { self }
 }
@@ -387,6 +453,8 @@ pub fn get_cli_input() -> Mgt {
                 let subcommand_name = cli.command_name().unwrap();
                 let usage_line = if subcommand_name == "split-out-as" {
                     Some("[FLAGS] --as <subdirectory> --output-branch <branch-name>")
+                } else if subcommand_name == "split-out" {
+                    Some("[FLAGS] [OPTIONS] <repo_file>...")
                 } else if subcommand_name == "split-in-as" {
                     Some("[FLAGS] [OPTIONS] <git-repo-uri> --as <subdirectory>")
                 } else { None };
@@ -403,6 +471,13 @@ pub fn get_cli_input() -> Mgt {
                     true
                 } else { false }
             }
+            MgtSubcommands::ReverseRepoFile(r) |
+            MgtSubcommands::ReverseRf(r) => {
+                if cli.help || r.help {
+                    print_usage(&r, Some("mgt reverse-rf"), None);
+                    true
+                } else { false }
+            }
             MgtSubcommands::Sync(s) => {
                 if cli.help || s.help {
                     print_usage(&s, Some("mgt sync"), None);
@@ -500,6 +575,10 @@ pub fn validate_input_and_run(mgt_opts: Mgt) {
             MgtSubcommands::VerifyRf(ref mut cmd) => {
                 run_verify(cmd);
             }
+            MgtSubcommands::ReverseRepoFile(ref mut cmd) |
+            MgtSubcommands::ReverseRf(ref mut cmd) => {
+                run_reverse(cmd);
+            }
             MgtSubcommands::Sync(ref mut cmd) => {
                 run_sync(cmd);
             }
@@ -0,0 +1,254 @@
+// a persistent record of which commits in one history
+// correspond to which commits in another history.
+// every time mgt filters a branch (split-out, split-in, sync)
+// or rebases filtered commits (sync), the original commit ids
+// and their new commit ids are appended to a file in the .git directory.
+// finding the fork point between a monorepo and a subrepo
+// can then be done by looking up commits in this map
+// instead of comparing blobs of every commit.
+
+use std::io;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+
+use super::ioerr;
+use super::git_helpers3;
+use gitfilter::message_transforms::get_trailer_values;
+
+pub const COMMIT_MAP_DIR: &str = "mgt";
+pub const COMMIT_MAP_FILE: &str = "commit-map";
+
+/// every line of the commit map file is a pair of
+/// commit ids: `<commit id> <commit id>` which means
+/// those two commits are the same commit in different histories.
+/// the relation is symmetric and transitive, eg: if a monorepo
+/// commit A was filtered into B, and then B was rebased into C,
+/// then A and C correspond to each other.
+#[derive(Debug, Default)]
+pub struct CommitMap {
+    pub edges: HashMap<String, Vec<String>>,
+}
+
+impl CommitMap {
+    pub fn parse(contents: &str) -> CommitMap {
+        let mut out = CommitMap::default();
+        for line in contents.lines() {
+            let mut split = line.split_whitespace();
+            let (a, b) = match (split.next(), split.next()) {
+                (Some(a), Some(b)) => (a, b),
+                _ => continue,
+            };
+            out.add(a, b);
+        }
+        out
+    }
+
+    /// returns false if the two commits were already
+    /// known to correspond to each other directly
+    pub fn add(&mut self, a: &str, b: &str) -> bool {
+        if a == b || self.has_edge(a, b) {
+            return false;
+        }
+        self.edges.entry(a.to_string()).or_default().push(b.to_string());
+        self.edges.entry(b.to_string()).or_default().push(a.to_string());
+        true
+    }
+
+    pub fn has_edge(&self, a: &str, b: &str) -> bool {
+        self.edges.get(a).map(|others| others.iter().any(|o| o == b)).unwrap_or(false)
+    }
+
+    pub fn contains(&self, commit_id: &str) -> bool {
+        self.edges.contains_key(commit_id)
+    }
+
+    /// returns every commit id that corresponds
+    /// to this commit id, not including itself
+    pub fn counterparts(&self, commit_id: &str) -> HashSet<&str> {
+        let mut seen = HashSet::new();
+        let mut stack = vec![commit_id];
+        while let Some(id) = stack.pop() {
+            if let Some(others) = self.edges.get(id) {
+                for other in others {
+                    if seen.insert(other.as_str()) {
+                        stack.push(other.as_str());
+                    }
+                }
+            }
+        }
+        seen.remove(commit_id);
+        seen
+    }
+}
+
+pub fn get_commit_map_path() -> io::Result<PathBuf> {
+    let git_dir = git_helpers3::get_git_dir()
+        .map_err(|e| ioerr!("Failed to find the .git directory because\n{}", e))?;
+    Ok(PathBuf::from(git_dir).join(COMMIT_MAP_DIR).join(COMMIT_MAP_FILE))
+}
+
+/// returns false if we have not recorded anything yet
+pub fn commit_map_exists() -> bool {
+    match get_commit_map_path().and_then(std::fs::metadata) {
+        Ok(metadata) => metadata.len() > 0,
+        Err(_) => false,
+    }
+}
+
+/// returns an empty map if we have not recorded anything yet
+pub fn load_commit_map() -> io::Result<CommitMap> {
+    let path = get_commit_map_path()?;
+    if ! path.exists() {
+        return Ok(CommitMap::default());
+    }
+    let contents = std::fs::read_to_string(&path)?;
+    Ok(CommitMap::parse(&contents))
+}
+
+/// returns the lines of both commit map contents, without the pairs that
+/// are in it more than once, in either order, and without invalid lines.
+/// returns None if that is the same as the existing contents
+pub fn merge_commit_map_contents(existing: &str, contents: &str) -> Option<String> {
+    let mut map = CommitMap::default();
+    let mut out = String::new();
+    for line in existing.lines().chain(contents.lines()) {
+        let mut split = line.split_whitespace();
+        if let (Some(a), Some(b)) = (split.next(), split.next()) {
+            if map.add(a, b) {
+                out.push_str(&format!("{} {}\n", a, b));
+            }
+        }
+    }
+    if out == existing { None } else { Some(out) }
+}
+
+/// adds `contents` which is already in the format of the commit map
+/// file to the commit map. pairs that the map already has are not added
+/// again, and the duplicates of older versions of mgt are removed
+pub fn append_to_commit_map(contents: &str) -> io::Result<()> {
+    if contents.is_empty() {
+        return Ok(());
+    }
+    let path = get_commit_map_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let existing = if path.exists() {
+        std::fs::read_to_string(&path)?
+    } else { String::new() };
+    let merged = match merge_commit_map_contents(&existing, contents) {
+        Some(merged) => merged,
+        None => return Ok(()),
+    };
+    // write the whole map next to it first, so that
+    // failing to write it does not lose the map
+    let mut tmp_path = path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    let mut file = OpenOptions::new().create(true).write(true).truncate(true).open(&tmp_path)?;
+    file.write_all(merged.as_bytes())?;
+    drop(file);
+    std::fs::rename(&tmp_path, &path)
+}
+
+pub fn record_pairs(pairs: &[(String, String)]) -> io::Result<()> {
+    let mut contents = String::new();
+    for (a, b) in pairs {
+        contents.push_str(&format!("{} {}\n", a, b));
+    }
+    append_to_commit_map(&contents)
+}
+
+/// returns the commit ids that are picked in an
+/// interactive rebase string, in the order they will be picked
+pub fn get_picked_commits(interactive_rebase_str: &str) -> Vec<String> {
+    interactive_rebase_str.lines().filter_map(|line| {
+        let mut split = line.split_whitespace();
+        match (split.next(), split.next()) {
+            (Some("pick"), Some(id)) => Some(id.to_string()),
+            _ => None,
+        }
+    }).collect()
+}
+
+/// after successfully rebasing the picked commits of `top_name`
+/// onto `onto`, the new commits are exactly `onto..top_name`.
+/// we record them as corresponding to the commits that were picked.
+/// if the number of commits does not match, eg: because
+/// a commit became empty and was dropped during the rebase, then
+/// we cannot know which commit is which, so we dont record anything.
+pub fn record_rebased_commits(
+    onto: &str,
+    top_name: &str,
+    interactive_rebase_str: &str,
+) -> io::Result<()> {
+    let picked = get_picked_commits(interactive_rebase_str);
+    let rebased = git_helpers3::get_commit_hashes(top_name, Some(onto), true)
+        .map_err(|e| ioerr!("Failed to get commits of {} because\n{}", top_name, e))?;
+    if picked.len() != rebased.len() {
+        return Ok(());
+    }
+    let pairs: Vec<(String, String)> = picked.into_iter().zip(rebased).collect();
+    record_pairs(&pairs)
+}
+
+/// looks for trailers of `<trailer>: <commit id>` in the history
+/// of the committish, and records each commit as corresponding
+/// to the commit id in its trailer. pairs that are already
+/// in the commit map are not recorded again.
+/// returns the number of pairs that were recorded
+pub fn record_origin_trailers(committish: &str, trailer: &str) -> io::Result<usize> {
+    let commit_map = load_commit_map()?;
+    let messages = git_helpers3::get_commit_messages(committish)
+        .map_err(|e| ioerr!("Failed to get commit messages of {} because\n{}", committish, e))?;
+    let mut pairs = vec![];
+    for (id, message) in messages.iter() {
+        let origin = match get_trailer_values(message, trailer).last() {
+            Some(origin) => origin.to_string(),
+            None => continue,
+        };
+        let already_recorded = commit_map.edges.get(id)
+            .map(|others| others.contains(&origin))
+            .unwrap_or(false);
+        if !already_recorded {
+            pairs.push((origin, id.clone()));
+        }
+    }
+    record_pairs(&pairs)?;
+    Ok(pairs.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn commit_map_counterparts_are_transitive() {
+        let map = CommitMap::parse("aaa bbb\nccc ddd\nbbb eee\n\n");
+        let mut counterparts: Vec<&str> = map.counterparts("aaa").into_iter().collect();
+        counterparts.sort();
+        assert_eq!(counterparts, vec!["bbb", "eee"]);
+        let mut counterparts: Vec<&str> = map.counterparts("eee").into_iter().collect();
+        counterparts.sort();
+        assert_eq!(counterparts, vec!["aaa", "bbb"]);
+        let counterparts: Vec<&str> = map.counterparts("ddd").into_iter().collect();
+        assert_eq!(counterparts, vec!["ccc"]);
+        assert!(map.counterparts("fff").is_empty());
+    }
+
+    #[test]
+    fn commit_map_contents_are_deduplicated() {
+        let existing = "aaa bbb\nbbb aaa\nccc ddd\naaa bbb\n";
+        assert_eq!(merge_commit_map_contents(existing, "eee fff\nddd ccc\n").unwrap(),
+            "aaa bbb\nccc ddd\neee fff\n");
+        assert_eq!(merge_commit_map_contents("aaa bbb\n", "bbb aaa\naaa aaa\n"), None);
+        assert_eq!(merge_commit_map_contents("", "aaa bbb").unwrap(), "aaa bbb\n");
+    }
+
+    #[test]
+    fn get_picked_commits_works() {
+        let rebase_str = "pick aaa first commit\n\npick bbb second pick commit\n";
+        assert_eq!(get_picked_commits(rebase_str), vec!["aaa", "bbb"]);
+    }
+}
@@ -2,19 +2,27 @@ use std::env;
 use die::die;
 use std::path::PathBuf;
 use std::path::{Path, MAIN_SEPARATOR};
-use std::io::{self, sink};
+use std::io::{self, sink, Sink};
 
 use git_url_parse::GitUrl;
 use gitfilter::filter::FilterOptions;
 use gitfilter::filter::FilterRules;
+use gitfilter::tags::TagOptions;
+use gitfilter::mailmap::Mailmap;
+use gitfilter::message_transforms::MessageTransforms;
+use gitfilter::submodules::SubmoduleAction;
 
 use super::exec_helpers;
 use super::git_helpers3;
+use super::commit_map;
+use super::progress;
 use super::repo_file::RepoFile;
+use super::repo_file;
 use super::ioerre;
 use super::ioerr;
 
 pub const VALID_REPO_FILE_EXTENSION: &str = "rf";
+pub const INCREMENTAL_DIR: &str = "incremental";
 
 pub fn get_current_ref() -> Option<String> {
     match git_helpers3::get_current_ref() {
@@ -52,29 +60,140 @@ pub fn go_to_repo_root() {
     }
 }
 
+/// the opt-in parts of a filter that come from the repo file.
+/// everything is off by default
+#[derive(Debug, Default)]
+pub struct FilterExtras {
+    pub tag_options: Option<TagOptions>,
+    pub mailmap: Option<Mailmap>,
+    pub message_transforms: Option<MessageTransforms>,
+    /// other refs to filter in the same pass as the
+    /// output branch, and the namespace to write them to
+    pub other_refs: Option<(Vec<String>, String)>,
+    /// if provided, the filter resumes from the state
+    /// saved in this directory by the previous filter
+    pub incremental: Option<PathBuf>,
+    /// what to do with the submodules that are kept
+    pub submodules: SubmoduleAction,
+}
+
+/// where the state of incremental filters of this output branch
+/// is kept: `.git/mgt/incremental/<output branch>`
+pub fn get_incremental_state_dir(output_branch: &str) -> io::Result<PathBuf> {
+    let git_dir = git_helpers3::get_git_dir()
+        .map_err(|e| ioerr!("Failed to find the .git directory because\n{}", e))?;
+    Ok(PathBuf::from(git_dir).join(commit_map::COMMIT_MAP_DIR).join(INCREMENTAL_DIR).join(output_branch))
+}
+
+impl FilterExtras {
+    pub fn print(&self) {
+        if let Some((ref refs, ref namespace)) = self.other_refs {
+            println!("Also filtering refs {:?} into {}", refs, namespace);
+        }
+        if let Some(ref tag_options) = self.tag_options {
+            println!("Running with tag options:\n{:#?}", tag_options);
+        }
+        if let Some(ref mailmap) = self.mailmap {
+            println!("Running with mailmap:\n{:#?}", mailmap);
+        }
+        if let Some(ref message_transforms) = self.message_transforms {
+            println!("Running with message transforms:\n{:#?}", message_transforms);
+        }
+        if let Some(ref incremental) = self.incremental {
+            println!("Resuming from the filter state in {}", incremental.display());
+        }
+        if self.submodules != SubmoduleAction::Untouched {
+            println!("Running with submodule action: {:?}", self.submodules);
+        }
+    }
+
+    /// the options to filter `branch` into `output_branch`,
+    /// or in place if there is no output branch
+    pub fn into_filter_options(
+        self,
+        branch: String,
+        output_branch: Option<String>,
+        filtered_map_path: PathBuf,
+    ) -> FilterOptions<Sink> {
+        let other_refs = self.other_refs;
+        FilterOptions {
+            stream: sink(),
+            branch: Some(branch),
+            output_branch,
+            refs: other_refs.as_ref().map(|(refs, _)| refs.clone()).unwrap_or_default(),
+            ref_namespace: other_refs.map(|(_, namespace)| namespace),
+            default_include: false,
+            with_blobs: false,
+            tags: self.tag_options,
+            commit_map: Some(filtered_map_path),
+            detect_renames: false,
+            mailmap: self.mailmap,
+            message_transforms: self.message_transforms,
+            incremental: self.incremental,
+            progress: None,
+            submodules: self.submodules,
+        }
+    }
+}
+
+/// where a filter writes the commits it kept before they are appended
+/// to the commit map. `index` distinguishes filters that run at the same time
+pub fn get_filtered_map_path(index: Option<usize>) -> io::Result<PathBuf> {
+    let commit_map_path = commit_map::get_commit_map_path()?;
+    let mut filtered_map_path = commit_map_path.into_os_string();
+    filtered_map_path.push(".filtered");
+    if let Some(index) = index {
+        filtered_map_path.push(format!(".{}", index));
+    }
+    let filtered_map_path = PathBuf::from(filtered_map_path);
+    if let Some(parent) = filtered_map_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    Ok(filtered_map_path)
+}
+
+/// failing to record the commit map is not fatal, we
+/// can always fall back to comparing blobs
+pub fn record_filtered_map(filtered_map_path: &Path) {
+    let recorded = std::fs::read_to_string(filtered_map_path)
+        .and_then(|filtered_map| commit_map::append_to_commit_map(&filtered_map));
+    let _ = std::fs::remove_file(filtered_map_path);
+    if let Err(e) = recorded {
+        eprintln!("Warning: failed to record the commit map after filtering: {}", e);
+    }
+}
+
 pub fn perform_gitfilter_res(
     filter_rules: FilterRules,
     output_branch: String,
+    extras: FilterExtras,
     dry_run: bool,
     verbose: bool,
 ) -> io::Result<()> {
-    let filter_options = FilterOptions {
-        stream: sink(),
-        branch: Some(output_branch),
-        default_include: false,
-        with_blobs: false,
-    };
-
     if dry_run || verbose {
         println!("Running with filter rules:\n{:#?}", filter_rules);
+        extras.print();
     }
+
     if dry_run { return Ok(()); }
 
+    // we record which commits got filtered into which
+    // so that we can find fork points later without
+    // having to compare blobs
+    let filtered_map_path = get_filtered_map_path(None)?;
+    // the total is only the number of commits of the branch
+    // when filtering every commit of the branch, and nothing else
+    let count_commits = extras.other_refs.is_none() && extras.incremental.is_none();
+    let progress = progress::make_progress_reporter(&output_branch, count_commits);
+    let mut filter_options = extras.into_filter_options(output_branch, None, filtered_map_path.clone());
+    filter_options.progress = Some(progress);
+
     let res = gitfilter::filter::filter_with_rules_direct(
         filter_options, filter_rules);
     if let Err(e) = res {
         return ioerre!("Failed to perform gitfilter: {}", e);
     }
+    record_filtered_map(&filtered_map_path);
 
     // remember, at the end of gitfilter, we have to revert the files that
     // are currently staged:
@@ -84,13 +203,70 @@ pub fn perform_gitfilter_res(
     Ok(())
 }
 
+/// one of the filters of `perform_gitfilter_multiplexed_res`
+#[derive(Debug)]
+pub struct FilterTarget {
+    pub filter_rules: FilterRules,
+    pub output_branch: String,
+    pub extras: FilterExtras,
+}
+
+/// filters the input branch into the output branch of every target
+/// while only reading the history of the input branch once.
+/// unlike `perform_gitfilter_res`, the input branch is left alone
+pub fn perform_gitfilter_multiplexed_res(
+    input_branch: String,
+    targets: Vec<FilterTarget>,
+    dry_run: bool,
+    verbose: bool,
+) -> io::Result<()> {
+    if dry_run || verbose {
+        for target in targets.iter() {
+            println!("Filtering {} into {} with filter rules:\n{:#?}", input_branch, target.output_branch, target.filter_rules);
+            target.extras.print();
+        }
+    }
+
+    if dry_run { return Ok(()); }
+
+    let mut filtered_map_paths = vec![];
+    let mut filter_targets = vec![];
+    for (index, target) in targets.into_iter().enumerate() {
+        let filtered_map_path = get_filtered_map_path(Some(index))?;
+        let count_commits = target.extras.other_refs.is_none();
+        let mut filter_options = target.extras.into_filter_options(
+            input_branch.clone(), Some(target.output_branch), filtered_map_path.clone());
+        // every target sees the same commits, so
+        // the progress of the first one is shown
+        if index == 0 {
+            filter_options.progress = Some(progress::make_progress_reporter(&input_branch, count_commits));
+        }
+        filtered_map_paths.push(filtered_map_path);
+        filter_targets.push((filter_options, target.filter_rules));
+    }
+
+    let no_location: Option<PathBuf> = None;
+    let res = gitfilter::filter::filter_with_rules_multiplexed_direct_ex(
+        filter_targets, no_location);
+    if let Err(e) = res {
+        return ioerre!("Failed to perform gitfilter: {}", e);
+    }
+    for filtered_map_path in filtered_map_paths.iter() {
+        record_filtered_map(filtered_map_path);
+    }
+    Ok(())
+}
+
 pub fn perform_gitfilter(
     filter_rules: FilterRules,
     output_branch: String,
+    extras: FilterExtras,
     dry_run: bool,
     verbose: bool,
 ) {
-    if let Err(e) = perform_gitfilter_res(filter_rules, output_branch, dry_run, verbose) {
+    if let Err(e) = perform_gitfilter_res(
+        filter_rules, output_branch, extras, dry_run, verbose
+    ) {
         die!("{}", e);
     }
 }
@@ -171,6 +347,29 @@ pub fn rebase(
 }
 
 /// panic if all dependencies are not met
+/// merging in a submodule needs its commits to be in this
+/// repository. this fetches every branch of every submodule
+/// that is checked out, without creating any refs for them.
+/// submodules that are not checked out have to be fetched by the user
+pub fn fetch_submodule_histories(dry_run: bool) {
+    let paths = match git_helpers3::get_submodule_paths() {
+        Ok(paths) => paths,
+        Err(e) => die!("Failed to read the submodules from .gitmodules:\n{}", e),
+    };
+    let log_p = if dry_run { "   # " } else { "" };
+    for path in paths {
+        if !Path::new(&path).join(".git").exists() {
+            eprintln!("Warning: submodule {} is not checked out, so its history was not fetched", path);
+            continue;
+        }
+        println!("{}Fetching the history of submodule {}", log_p, path);
+        if dry_run { continue; }
+        if let Err(e) = git_helpers3::fetch_all_branches(&path) {
+            die!("Failed to fetch the history of submodule {}:\n{}", path, e);
+        }
+    }
+}
+
 pub fn verify_dependencies() {
     if ! exec_helpers::executed_successfully(&["git", "--version"]) {
         die!("Failed to run. Missing dependency 'git'");
@@ -237,6 +436,34 @@ pub fn make_and_checkout_output_branch(
     }
 }
 
+/// like `make_and_checkout_output_branch`, but an output branch
+/// that already exists is reset to the current HEAD instead of failing.
+/// this is used by incremental filters, which append to the
+/// previously filtered commits rather than to the existing output branch
+pub fn reset_and_checkout_output_branch(
+    output_branch: &Option<String>,
+    dry_run: bool,
+    verbose: bool,
+) {
+    let output_branch_name = match output_branch {
+        Some(s) => s,
+        None => die!("Must provide an output branch"),
+    };
+
+    if dry_run {
+        println!("git checkout -B {}", output_branch_name);
+        return;
+    }
+
+    if let Err(e) = git_helpers3::reset_and_checkout_branch(output_branch_name.as_str()) {
+        die!("Failed to checkout branch {}: {}", output_branch_name, e);
+    }
+
+    if verbose {
+        println!("reset and checked out branch {}", output_branch_name);
+    }
+}
+
 pub fn make_and_checkout_orphan_branch_res(
     orphan_branch: &str,
     dry_run: bool,
@@ -285,7 +512,7 @@ pub fn populate_empty_branch_with_remote_commits_res(
     num_commits: Option<u32>,
     dry_run: bool,
 ) -> io::Result<()> {
-    let remote_repo = repo_file.remote_repo.clone();
+    let remote_repo = repo_file::get_primary_remote(repo_file).map(|remote| remote.url);
     let log_p = if dry_run { "   # " } else { "" };
 
     match (dry_run, input_branch) {
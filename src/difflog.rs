@@ -4,9 +4,11 @@ use terminal_size::{Width, terminal_size};
 
 use super::topbase::ABTraversalMode;
 use super::cli::MgtCommandDifflog;
-use super::topbase::find_a_b_difference2;
+use super::topbase::{find_a_b_difference2, find_a_b_fullbase};
 use super::git_helpers3::Commit;
 use crate::topbase::{NopCB, BlobHashingMode};
+use super::output::{self, OutputFormat};
+use serde_json::json;
 
 pub fn format_right_string(
     commit: &str,
@@ -152,6 +154,43 @@ pub fn format_title(
     format!("{}{}{}\n{}", left_str,seperator, right_str, bottom_seperator)
 }
 
+/// shows every fork point of the two branches, with the
+/// commits that each branch has between those fork points
+pub fn run_fullbase(
+    cmd: &MgtCommandDifflog,
+    term_width: usize,
+) -> io::Result<()> {
+    let branch_left = &cmd.branches[0];
+    let branch_right = &cmd.branches[1];
+    // TODO: make this a cli option
+    let hashing_mode = BlobHashingMode::Full;
+    let segments = find_a_b_fullbase::<Commit, NopCB>(
+        branch_left, branch_right, hashing_mode, None)?;
+    if cmd.format.unwrap_or_default() == OutputFormat::Json {
+        let mut out = json!({
+            "left": branch_left,
+            "right": branch_right,
+        });
+        output::extend_json_object(&mut out, output::fullbase_result_to_json(&segments));
+        output::print_json(&out);
+        return Ok(());
+    }
+    if segments.is_empty() {
+        println!("Failed to find a fork point");
+        return Ok(());
+    }
+
+    println!("{}", format_title(branch_left, branch_right, term_width));
+    for segment in segments {
+        print!("{}", format_group_string(&segment.left_commits, &segment.right_commits, term_width));
+        if let Some((left_fork, right_fork)) = segment.fork_point {
+            print!("{}", format_fork_point(&vec![left_fork], &vec![right_fork], term_width));
+        }
+    }
+
+    Ok(())
+}
+
 pub fn run_actual(cmd: &mut MgtCommandDifflog) -> io::Result<()> {
     let branch_left = &cmd.branches[0];
     let branch_right = &cmd.branches[1];
@@ -167,6 +206,10 @@ pub fn run_actual(cmd: &mut MgtCommandDifflog) -> io::Result<()> {
         }
     };
 
+    if let Some(ABTraversalMode::Fullbase) = cmd.traversal_mode {
+        return run_fullbase(cmd, term_width);
+    }
+
     let should_rewind = if let Some(mode) = cmd.traversal_mode {
         match mode {
             ABTraversalMode::TopbaseRewind => true,
@@ -180,8 +223,17 @@ pub fn run_actual(cmd: &mut MgtCommandDifflog) -> io::Result<()> {
     let hashing_mode = BlobHashingMode::Full;
     // TODO: make this a cli option
     let traverse_at_a_time = 500;
-    let topbase_res = find_a_b_difference2::<_, NopCB>(
+    let topbase_res = find_a_b_difference2::<Commit, NopCB>(
         branch_left, branch_right, Some(traverse_at_a_time), hashing_mode, should_rewind, None)?;
+    if cmd.format.unwrap_or_default() == OutputFormat::Json {
+        let mut out = json!({
+            "left": branch_left,
+            "right": branch_right,
+        });
+        output::extend_json_object(&mut out, output::topbase_result_to_json(topbase_res.as_ref()));
+        output::print_json(&out);
+        return Ok(());
+    }
     let successful_topbase = match topbase_res {
         Some(s) => s,
         None => {
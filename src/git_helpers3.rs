@@ -2,10 +2,30 @@
 /// with git via the CLI instead of libgit2
 
 use super::exec_helpers;
-use std::{io::{self, BufReader}, io::BufRead, process::Stdio};
+use std::{io::{self, BufReader}, io::BufRead, process::Stdio, collections::HashSet};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 use crate::{ioerre, ioerr};
 pub use crate::blob_log_parser::*;
 
+type BlobLogCache = HashMap<(String, Option<usize>), Rc<Vec<CommitWithBlobs>>>;
+
+thread_local! {
+    /// only set while running `with_shared_blob_log`
+    static BLOB_LOG_CACHE: RefCell<Option<BlobLogCache>> = const { RefCell::new(None) };
+}
+
+/// runs `f` such that every `iterate_blob_log` of the same commit and the same
+/// number of commits only reads the git log once. a log without a number
+/// of commits is not shared, because its callers usually stop early. commands that
+/// look at many repo files, ie: check and sync, use this to walk the
+/// history of the local branch once, instead of once for every repo file
+pub fn with_shared_blob_log<R>(f: impl FnOnce() -> R) -> R {
+    BLOB_LOG_CACHE.with(|cache| *cache.borrow_mut() = Some(HashMap::new()));
+    let out = f();
+    BLOB_LOG_CACHE.with(|cache| *cache.borrow_mut() = None);
+    out
+}
+
 #[derive(Debug, Clone)]
 pub struct Oid {
     pub hash: String,
@@ -48,6 +68,52 @@ pub fn iterate_blob_log<T>(
     callback: T,
 ) -> io::Result<()>
     where T: FnMut(CommitWithBlobs) -> bool,
+{
+    let is_shared = BLOB_LOG_CACHE.with(|cache| cache.borrow().is_some());
+    if ! is_shared || num_commits.is_none() {
+        return iterate_blob_log_uncached(committish, num_commits, callback);
+    }
+
+    // names like HEAD and FETCH_HEAD can point somewhere else
+    // the next time, so the log is shared by the commit it starts at
+    let commit_id = get_commit_id(committish)
+        .map_err(|e| ioerr!("Failed to find commit {}: {}", committish, e))?;
+    let key = (commit_id, num_commits);
+    let cached = BLOB_LOG_CACHE.with(|cache| {
+        cache.borrow().as_ref().and_then(|c| c.get(&key).cloned())
+    });
+    let commits = match cached {
+        Some(commits) => commits,
+        None => {
+            let mut commits = vec![];
+            iterate_blob_log_uncached(&key.0, num_commits, |c| {
+                commits.push(c);
+                false
+            })?;
+            let commits = Rc::new(commits);
+            BLOB_LOG_CACHE.with(|cache| {
+                if let Some(c) = cache.borrow_mut().as_mut() {
+                    c.insert(key, commits.clone());
+                }
+            });
+            commits
+        }
+    };
+    let mut callback = callback;
+    for c in commits.iter() {
+        if callback(c.clone()) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn iterate_blob_log_uncached<T>(
+    committish: &str,
+    num_commits: Option<usize>,
+    callback: T,
+) -> io::Result<()>
+    where T: FnMut(CommitWithBlobs) -> bool,
 {
     let mut exec_args = vec![
         "git", "--no-pager", "log", "--no-color", "--raw",
@@ -211,6 +277,20 @@ pub fn checkout_branch(
     }
 }
 
+/// like `checkout_branch` with `make_new`, but if the
+/// branch already exists, it is reset to the current HEAD
+pub fn reset_and_checkout_branch(
+    branch_name: &str,
+) -> Result<(), String> {
+    let exec_args = [
+        "git", "checkout", "-B", branch_name,
+    ];
+    match exec_helpers::executed_with_error(&exec_args) {
+        None => Ok(()),
+        Some(e) => Err(e),
+    }
+}
+
 pub fn get_current_ref() -> Result<String, String> {
     let exec_args = [
         "git", "rev-parse", "--abbrev-ref", "HEAD"
@@ -383,6 +463,74 @@ pub fn get_repo_root() -> Result<String, String> {
     }
 }
 
+/// returns the full hash of the commit that the committish points to
+pub fn get_commit_id(committish: &str) -> Result<String, String> {
+    let commit = format!("{}^{{commit}}", committish);
+    let exec_args = [
+        "git", "rev-parse", "--verify", "--quiet", commit.as_str(),
+    ];
+    match exec_helpers::execute(&exec_args) {
+        Ok(out) => {
+            if out.status == 0 {
+                Ok(out.stdout.trim_end().into())
+            } else {
+                Err(format!("{} is not a commit", committish))
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// returns the absolute path of the .git directory
+/// of the repository we are currently in
+pub fn get_git_dir() -> Result<String, String> {
+    let exec_args = [
+        "git", "rev-parse", "--absolute-git-dir",
+    ];
+    match exec_helpers::execute(&exec_args) {
+        Ok(out) => {
+            if out.status == 0 {
+                Ok(out.stdout.trim_end().into())
+            } else {
+                Err(out.stderr)
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// returns the full hash of every commit reachable
+/// from the given committish, most recent first.
+/// the `exclude` committish, if given, and its ancestors are left out
+pub fn get_commit_hashes(
+    committish: &str,
+    exclude: Option<&str>,
+    reverse: bool,
+) -> Result<Vec<String>, String> {
+    let exclude_str;
+    let mut exec_args = vec![
+        "git", "rev-list", committish,
+    ];
+    if let Some(exclude) = exclude {
+        exclude_str = format!("^{}", exclude);
+        exec_args.push(exclude_str.as_str());
+    }
+    if reverse {
+        exec_args.push("--reverse");
+    }
+    exec_args.push("--");
+    match exec_helpers::execute(&exec_args) {
+        Ok(out) => {
+            if out.status == 0 {
+                Ok(out.stdout.lines().map(|l| l.to_string()).collect())
+            } else {
+                Err(out.stderr)
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
 pub fn fetch_branch(remote: &str, branch: &str) -> Result<(), String> {
     let args = [
         "git", "fetch",
@@ -403,6 +551,109 @@ pub fn fetch_branch(remote: &str, branch: &str) -> Result<(), String> {
     Ok(())
 }
 
+pub fn push_branch(remote: &str, local_branch: &str, remote_branch: &str) -> Result<(), String> {
+    let refspec = format!("{}:{}", local_branch, remote_branch);
+    let args = [
+        "git", "push",
+        remote, refspec.as_str(),
+    ];
+    match exec_helpers::executed_with_error(&args) {
+        None => Ok(()),
+        Some(e) => Err(e),
+    }
+}
+
+/// fetches every branch of the remote without creating any
+/// refs for them, ie: the objects are only reachable from FETCH_HEAD
+pub fn fetch_all_branches(remote: &str) -> Result<(), String> {
+    let heads = match exec_helpers::execute(&["git", "ls-remote", "--heads", remote]) {
+        Err(e) => return Err(e.to_string()),
+        Ok(o) => match o.status {
+            0 => o.stdout,
+            _ => return Err(o.stderr),
+        },
+    };
+    let branches: Vec<&str> = heads.lines().filter_map(|line| line.split('\t').nth(1)).collect();
+    if branches.is_empty() {
+        return Ok(());
+    }
+    let mut args = vec!["git", "fetch", "--no-tags", "-q", remote];
+    args.extend(branches);
+    match exec_helpers::executed_with_error(&args) {
+        None => Ok(()),
+        Some(e) => Err(e),
+    }
+}
+
+/// the path of every submodule in the .gitmodules
+/// of the current directory, if there is one
+pub fn get_submodule_paths() -> Result<Vec<String>, String> {
+    if !std::path::Path::new(".gitmodules").exists() {
+        return Ok(vec![]);
+    }
+    let args = [
+        "git", "config", "-f", ".gitmodules",
+        "--get-regexp", r"^submodule\..*\.path$",
+    ];
+    match exec_helpers::execute(&args) {
+        Err(e) => Err(e.to_string()),
+        // an exit code of 1 means nothing matched
+        Ok(o) => match o.status {
+            0 | 1 => Ok(o.stdout.lines()
+                .filter_map(|line| line.split_once(' '))
+                .map(|(_, path)| path.to_string())
+                .collect()),
+            _ => Err(o.stderr),
+        },
+    }
+}
+
+/// fetch all of the tags of the remote into the given
+/// namespace, eg: refs/tags/v1 -> <namespace>v1 so that
+/// they dont conflict with our own tags
+pub fn fetch_tags(
+    remote: &str,
+    namespace: &str,
+    num_commits: Option<u32>,
+) -> Result<(), String> {
+    let refspec = format!("+refs/tags/*:{}*", namespace);
+    let mut args = vec![
+        "git", "fetch",
+        remote, refspec.as_str(),
+        "--no-tags",
+    ];
+    let depth_string;
+    if let Some(n) = num_commits {
+        depth_string = format!("--depth={}", n);
+        args.push(depth_string.as_str());
+    }
+
+    match exec_helpers::executed_with_error(&args) {
+        None => Ok(()),
+        Some(e) => Err(e),
+    }
+}
+
+/// delete every ref in the given namespace
+pub fn delete_refs(namespace: &str) -> Result<(), String> {
+    let args = [
+        "git", "for-each-ref", "--format=%(refname)", namespace,
+    ];
+    let refs = match exec_helpers::execute(&args) {
+        Err(e) => return Err(e.to_string()),
+        Ok(o) => match o.status {
+            0 => o.stdout,
+            _ => return Err(o.stderr),
+        },
+    };
+    for refname in refs.lines() {
+        if let Some(e) = exec_helpers::executed_with_error(&["git", "update-ref", "-d", refname]) {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
 pub fn get_all_files_in_repo() -> Result<String, String> {
     let exec_args = [
         "git", "ls-tree", "-r", "HEAD", "--name-only", "--full-tree"
@@ -419,6 +670,51 @@ pub fn get_all_files_in_repo() -> Result<String, String> {
     }
 }
 
+/// returns every unique (name, email) of the authors
+/// and committers in the history of HEAD
+pub fn get_all_identities() -> Result<Vec<(String, String)>, String> {
+    let exec_args = [
+        "git", "log", "--format=%an%x00%ae%n%cn%x00%ce", "HEAD",
+    ];
+    let out = exec_helpers::execute(&exec_args).map_err(|e| e.to_string())?;
+    if out.status != 0 {
+        return Err(out.stderr);
+    }
+    let mut identities = vec![];
+    let mut seen = HashSet::new();
+    for line in out.stdout.lines() {
+        let mut split = line.splitn(2, '\0');
+        let (name, email) = match (split.next(), split.next()) {
+            (Some(name), Some(email)) => (name, email),
+            _ => continue,
+        };
+        if seen.insert(line) {
+            identities.push((name.to_string(), email.to_string()));
+        }
+    }
+    Ok(identities)
+}
+
+/// returns the commit id and full commit message
+/// of every commit in the history of the committish
+pub fn get_commit_messages(committish: &str) -> Result<Vec<(String, String)>, String> {
+    let exec_args = [
+        "git", "log", "-z", "--format=%H%n%B", committish,
+    ];
+    let out = exec_helpers::execute(&exec_args).map_err(|e| e.to_string())?;
+    if out.status != 0 {
+        return Err(out.stderr);
+    }
+    let messages = out.stdout.split('\0').filter_map(|entry| {
+        let mut split = entry.splitn(2, '\n');
+        match (split.next(), split.next()) {
+            (Some(id), Some(message)) if !id.is_empty() => Some((id.to_string(), message.to_string())),
+            _ => None,
+        }
+    }).collect();
+    Ok(messages)
+}
+
 pub fn reset_stage() -> Result<String, String> {
     // git reset --hard
     let exec_args = [
@@ -483,6 +779,22 @@ pub fn rebase_interactively_with_commits(
     Ok(())
 }
 
+pub fn rebase_abort() -> Result<(), String> {
+    let exec_args = [
+        "git", "rebase", "--abort"
+    ];
+    match exec_helpers::execute(&exec_args) {
+        Ok(out) => {
+            if out.status == 0 {
+                Ok(())
+            } else {
+                Err(out.stderr)
+            }
+        }
+        Err(e) => Err(e.to_string())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -510,4 +822,25 @@ mod test {
         // a git repository with more than 1 commit...
         assert!(data.len() > 1);
     }
+
+    #[test]
+    #[cfg_attr(not(feature = "gittests"), ignore)]
+    fn shared_blob_log_is_the_same_as_the_git_log() {
+        let get_ids = |n: usize| {
+            let mut ids = vec![];
+            iterate_blob_log("HEAD", Some(n), |c| {
+                ids.push(c.commit.id.hash);
+                ids.len() == 2
+            }).unwrap();
+            ids
+        };
+        let expected = get_ids(3);
+        let shared = with_shared_blob_log(|| {
+            let first = get_ids(3);
+            assert!(BLOB_LOG_CACHE.with(|cache| cache.borrow().as_ref().unwrap().len() == 1));
+            (first, get_ids(3))
+        });
+        assert_eq!(shared, (expected.clone(), expected));
+        assert!(BLOB_LOG_CACHE.with(|cache| cache.borrow().is_none()));
+    }
 }
@@ -15,6 +15,12 @@ mod core;
 mod sync;
 mod difflog;
 mod blob_log_parser;
+mod output;
+mod commit_map;
+mod progress;
+mod validate;
+mod workspace;
+mod reverse;
 
 fn main() {
     let mgt = cli::get_cli_input();
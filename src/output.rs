@@ -0,0 +1,111 @@
+// helpers for commands that can print their results
+// in a machine readable format instead of for a human
+// in a terminal. These should only contain data, and never
+// any formatting decisions like truncating summaries.
+
+use std::str::FromStr;
+use serde_json::{json, Value};
+
+use super::git_helpers3::Commit;
+use super::topbase::{SuccessfulTopbaseResult, FullbaseSegment};
+use super::git_helpers3::CommitWithBlobs;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ok = match s {
+            "text" => OutputFormat::Text,
+            "json" => OutputFormat::Json,
+            _ => return Err(format!("{} is not a valid output format", s)),
+        };
+        Ok(ok)
+    }
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Text
+    }
+}
+
+pub fn commit_to_json(commit: &Commit) -> Value {
+    json!({
+        "oid": commit.id.long(),
+        "summary": commit.summary,
+        "is_merge": commit.is_merge,
+    })
+}
+
+pub fn commits_to_json<T: AsRef<Commit>>(commits: &[T]) -> Value {
+    let commits: Vec<Value> = commits.iter()
+        .map(|c| commit_to_json(c.as_ref())).collect();
+    Value::Array(commits)
+}
+
+/// the fork point is a pair of the A commit, and the B commit.
+/// top_right_commits is only filled in if the topbase was done with rewind.
+/// if the topbase failed to find a fork point then
+/// the fork point is null, and there are no commits.
+pub fn topbase_result_to_json<T: From<CommitWithBlobs> + AsRef<Commit>>(
+    topbase_result: Option<&SuccessfulTopbaseResult<T>>,
+) -> Value {
+    match topbase_result {
+        None => json!({
+            "fork_point": null,
+            "top_commits": [],
+            "top_right_commits": [],
+        }),
+        Some(res) => json!({
+            "fork_point": [
+                commit_to_json(res.fork_point.0.as_ref()),
+                commit_to_json(res.fork_point.1.as_ref()),
+            ],
+            "top_commits": commits_to_json(&res.top_commits),
+            "top_right_commits": commits_to_json(&res.top_right_commits),
+        }),
+    }
+}
+
+/// every segment has the commits of each branch above its
+/// fork point. the last segment has a null fork point if there are
+/// commits below the oldest fork point.
+pub fn fullbase_result_to_json<T: From<CommitWithBlobs> + AsRef<Commit>>(
+    segments: &[FullbaseSegment<T>],
+) -> Value {
+    let segments: Vec<Value> = segments.iter().map(|segment| {
+        let fork_point = match segment.fork_point {
+            Some((ref a, ref b)) => json!([commit_to_json(a.as_ref()), commit_to_json(b.as_ref())]),
+            None => Value::Null,
+        };
+        json!({
+            "fork_point": fork_point,
+            "left_commits": commits_to_json(&segment.left_commits),
+            "right_commits": commits_to_json(&segment.right_commits),
+        })
+    }).collect();
+    json!({ "segments": segments })
+}
+
+/// add all of the keys of `extra` into `obj`.
+/// both are expected to be json objects
+pub fn extend_json_object(obj: &mut Value, extra: Value) {
+    if let (Some(obj), Value::Object(extra)) = (obj.as_object_mut(), extra) {
+        obj.extend(extra);
+    }
+}
+
+pub fn print_json(value: &Value) {
+    match serde_json::to_string_pretty(value) {
+        Ok(s) => println!("{}", s),
+        // serializing a Value can only fail if
+        // it has non-string map keys, which json! never makes
+        Err(e) => eprintln!("Failed to serialize output as json: {}", e),
+    }
+}
@@ -0,0 +1,202 @@
+use std::io::{self, Write};
+use std::time::Duration;
+use terminal_size::{Width, Height};
+use gitfilter::progress::{Progress, ProgressReporter};
+
+use super::git_helpers3;
+
+/// nothing is shown for filters that are done before this
+pub const PROGRESS_DELAY: Duration = Duration::from_secs(1);
+/// how often the progress bar is redrawn on a terminal
+pub const PROGRESS_BAR_INTERVAL: Duration = Duration::from_millis(100);
+/// how often a progress line is printed when not on a terminal
+pub const PROGRESS_LINE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// formats a duration as `1h02m`, `3m05s`, or `11s`
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, (secs % 3600) / 60, secs % 60);
+    if hours > 0 {
+        format!("{}h{:02}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m{:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+fn format_counts(progress: &Progress) -> String {
+    let processed = match progress.total_commits {
+        Some(total) => format!("{}/{}", progress.commits_processed, total),
+        None => progress.commits_processed.to_string(),
+    };
+    let mut out = format!(
+        "{} commits ({} kept, {} dropped), {:.0} commits/s",
+        processed, progress.commits_kept,
+        progress.commits_dropped, progress.commits_per_second(),
+    );
+    if progress.done {
+        out.push_str(&format!(", took {}", format_duration(progress.elapsed)));
+    } else if let Some(eta) = progress.eta() {
+        out.push_str(&format!(", ETA {}", format_duration(eta)));
+    }
+    out
+}
+
+pub fn format_progress_line(progress: &Progress) -> String {
+    format!("Filtered {}", format_counts(progress))
+}
+
+/// a bar of how many of the total commits were processed, followed by the
+/// counts. the whole line fits in `width` characters.
+/// without a total, only the counts are shown
+pub fn format_progress_bar(progress: &Progress, width: usize) -> String {
+    let counts = format_counts(progress);
+    let total = match progress.total_commits {
+        Some(total) if total > 0 => total,
+        // padded so that it covers the previous line
+        _ => return format!("{:width$}", counts, width = width).chars().take(width).collect(),
+    };
+    // the bar takes whatever is left after the counts, but is
+    // never smaller than 10 characters. `[`, `]` and a space take 3
+    let bar_width = width.saturating_sub(counts.len() + 3).max(10);
+    let filled = (progress.commits_processed.min(total) * bar_width) / total;
+    let mut bar = "=".repeat(filled);
+    if filled < bar_width {
+        bar.push('>');
+        bar.push_str(&" ".repeat(bar_width - filled - 1));
+    }
+    let line = format!("[{}] {}", bar, counts);
+    line.chars().take(width).collect()
+}
+
+/// shows the progress of a filter on stderr. a progress
+/// bar that is redrawn in place when on a terminal, otherwise
+/// a line every `PROGRESS_LINE_INTERVAL`.
+/// filters that are done within `PROGRESS_DELAY` do not show anything
+#[derive(Debug, Default)]
+pub struct ProgressRenderer {
+    /// the terminal width, or None if not on a terminal
+    pub width: Option<usize>,
+    last_shown: Option<Duration>,
+}
+
+/// the size of the terminal that stderr is on, because
+/// that is where the progress is shown, and not stdout
+#[cfg(unix)]
+fn stderr_terminal_size() -> Option<(Width, Height)> {
+    use std::os::unix::io::AsRawFd;
+    terminal_size::terminal_size_using_fd(io::stderr().as_raw_fd())
+}
+
+#[cfg(windows)]
+fn stderr_terminal_size() -> Option<(Width, Height)> {
+    use std::os::windows::io::AsRawHandle;
+    terminal_size::terminal_size_using_handle(io::stderr().as_raw_handle())
+}
+
+impl ProgressRenderer {
+    pub fn new() -> ProgressRenderer {
+        let width = stderr_terminal_size().map(|(Width(w), _)| w as usize);
+        ProgressRenderer { width, last_shown: None }
+    }
+
+    /// returns what should be written to stderr for this progress, if anything
+    pub fn render(&mut self, progress: &Progress) -> Option<String> {
+        if progress.done {
+            // only finish what was started
+            self.last_shown?;
+            return match self.width {
+                Some(width) => Some(format!("\r{}\n", format_progress_bar(progress, width))),
+                None => Some(format!("{}\n", format_progress_line(progress))),
+            };
+        }
+        let interval = match self.width {
+            Some(_) => PROGRESS_BAR_INTERVAL,
+            None => PROGRESS_LINE_INTERVAL,
+        };
+        let should_show = match self.last_shown {
+            None => progress.elapsed >= PROGRESS_DELAY,
+            Some(last) => progress.elapsed >= last + interval,
+        };
+        if !should_show {
+            return None;
+        }
+        self.last_shown = Some(progress.elapsed);
+        match self.width {
+            Some(width) => Some(format!("\r{}", format_progress_bar(progress, width))),
+            None => Some(format!("{}\n", format_progress_line(progress))),
+        }
+    }
+}
+
+/// a progress reporter for filtering `branch` that renders to stderr.
+/// if `count_commits` is false, or the commits can't be counted, the
+/// total is unknown, and no ETA is shown
+pub fn make_progress_reporter(branch: &str, count_commits: bool) -> ProgressReporter {
+    let total_commits = if count_commits {
+        git_helpers3::get_number_of_commits_in_ref(branch).ok()
+    } else {
+        None
+    };
+    let mut renderer = ProgressRenderer::new();
+    ProgressReporter::new(total_commits, move |progress| {
+        if let Some(out) = renderer.render(progress) {
+            let mut stderr = io::stderr();
+            let _ = stderr.write_all(out.as_bytes());
+            let _ = stderr.flush();
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn progress_at(secs: u64, processed: usize) -> Progress {
+        Progress {
+            commits_processed: processed,
+            commits_kept: processed / 2,
+            commits_dropped: processed - processed / 2,
+            total_commits: Some(100),
+            elapsed: Duration::from_secs(secs),
+            done: false,
+        }
+    }
+
+    #[test]
+    fn durations_are_formatted_compactly() {
+        assert_eq!(format_duration(Duration::from_secs(11)), "11s");
+        assert_eq!(format_duration(Duration::from_secs(185)), "3m05s");
+        assert_eq!(format_duration(Duration::from_secs(3720)), "1h02m");
+    }
+
+    #[test]
+    fn progress_bar_fits_the_width() {
+        let progress = progress_at(10, 50);
+        assert_eq!(format_progress_line(&progress),
+            "Filtered 50/100 commits (25 kept, 25 dropped), 5 commits/s, ETA 10s");
+        let bar = format_progress_bar(&progress, 80);
+        assert_eq!(bar.len(), 80);
+        assert!(bar.starts_with("[=========>         ] 50/100 commits"));
+        assert_eq!(format_progress_bar(&progress, 20), "[=====>    ] 50/100 ");
+    }
+
+    #[test]
+    fn renderer_only_shows_slow_filters() {
+        let mut renderer = ProgressRenderer { width: None, last_shown: None };
+        assert_eq!(renderer.render(&progress_at(0, 10)), None);
+        let mut done = progress_at(0, 20);
+        done.done = true;
+        assert_eq!(renderer.render(&done), None);
+
+        let mut renderer = ProgressRenderer { width: None, last_shown: None };
+        assert!(renderer.render(&progress_at(1, 10)).is_some());
+        assert_eq!(renderer.render(&progress_at(3, 20)), None);
+        assert!(renderer.render(&progress_at(6, 30)).is_some());
+        let mut done = progress_at(7, 100);
+        done.done = true;
+        assert_eq!(renderer.render(&done).unwrap(),
+            "Filtered 100/100 commits (50 kept, 50 dropped), 14 commits/s, took 7s\n");
+    }
+}
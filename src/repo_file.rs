@@ -1,4 +1,4 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::io;
@@ -6,6 +6,318 @@ use toml::Value;
 use super::die;
 use super::ioerre;
 use super::ioerr;
+use super::validate;
+use super::workspace;
+use gitfilter::filter::{GlobPattern, Regex};
+use gitfilter::tags::{TagMapping, TagOptions};
+use gitfilter::commit_rules::{CommitAction, CommitMatcher, CommitRule};
+use gitfilter::mailmap::Mailmap;
+use gitfilter::message_transforms::{MessageTransforms, DEFAULT_ORIGIN_TRAILER};
+use gitfilter::submodules::SubmoduleAction;
+use std::str::FromStr;
+
+/// the newest version of the repo file format.
+/// repo files without a `version` are version 1
+pub const LATEST_REPO_FILE_VERSION: i64 = 2;
+
+/// paths in a repo file that start with this are glob patterns
+pub const GLOB_PATTERN_PREFIX: &str = "glob:";
+/// paths in a repo file that start with this are regular expressions
+pub const REGEX_PATTERN_PREFIX: &str = "regex:";
+
+/// the keys that can be at the top level of a version 1 repo file
+pub const TOP_LEVEL_KEYS: &[&str] = &[
+    "repo", "filter", "include", "include_as", "exclude",
+    "tags", "commits", "mailmap", "message", "refs", "submodules", "remotes", "extends", "vars",
+];
+/// the keys that choose which paths are kept. in a version 1 repo file
+/// they can be at the top level, or in one of the `PATH_TABLES`
+pub const PATH_KEYS: &[&str] = &["include", "include_as", "exclude"];
+pub const PATH_TABLES: &[&str] = &["repo", "filter"];
+
+/// by default, a path in a repo file matches anything that
+/// starts with that path. A path can also be a glob pattern
+/// if it starts with `glob:`, or a regular expression if it
+/// starts with `regex:`
+#[derive(Debug, PartialEq)]
+pub enum PathPattern<'a> {
+    Prefix(&'a str),
+    Glob(&'a str),
+    Regex(&'a str),
+}
+
+impl<'a> PathPattern<'a> {
+    pub fn is_prefix(&self) -> bool {
+        matches!(self, PathPattern::Prefix(_))
+    }
+}
+
+pub fn parse_path_pattern(path: &str) -> PathPattern<'_> {
+    if let Some(glob) = path.strip_prefix(GLOB_PATTERN_PREFIX) {
+        PathPattern::Glob(glob)
+    } else if let Some(regex) = path.strip_prefix(REGEX_PATTERN_PREFIX) {
+        PathPattern::Regex(regex)
+    } else {
+        PathPattern::Prefix(path)
+    }
+}
+
+/// one of the `[[rule]]`s of a version 2 repo file. the rules
+/// are applied in the order they are written, so a rule
+/// overrides what the rules before it decided for a path
+#[derive(Debug, PartialEq, Clone)]
+pub enum PathRule {
+    Include(String),
+    Exclude(String),
+    /// keeps everything that matches the source,
+    /// and renames it to the destination
+    Rename(String, String),
+}
+
+/// the name of the remote that is set by `[repo] remote` and `branch`
+pub const DEFAULT_REMOTE_NAME: &str = "origin";
+
+/// a repository that the split out branch is kept in sync with.
+/// every `[remotes.<name>]` table of the repo file is a remote
+#[derive(Debug, PartialEq, Clone)]
+pub struct Remote {
+    pub name: String,
+    pub url: String,
+    /// the branch to pull from and push to.
+    /// if None, the remote's HEAD is used
+    pub branch: Option<String>,
+    /// false if mgt should never pull from this remote
+    pub pull: bool,
+    /// false if mgt should never push to this remote
+    pub push: bool,
+}
+
+/// every remote of the repo file. the `[repo]` remote is first,
+/// and it can be pulled from and pushed to
+pub fn get_remotes(repofile: &RepoFile) -> Vec<Remote> {
+    let mut remotes = vec![];
+    if let Some(url) = &repofile.remote_repo {
+        remotes.push(Remote {
+            name: DEFAULT_REMOTE_NAME.into(),
+            url: url.clone(),
+            branch: repofile.remote_branch.clone(),
+            pull: true,
+            push: true,
+        });
+    }
+    remotes.extend(repofile.remotes.iter().flatten().cloned());
+    remotes
+}
+
+/// the remote to pull from when only one remote can be used,
+/// ie: when rebasing onto the remote, or fetching its tags
+pub fn get_primary_remote(repofile: &RepoFile) -> Option<Remote> {
+    get_remotes(repofile).into_iter().find(|remote| remote.pull)
+}
+
+pub fn validate_remotes(repofile: &RepoFile) -> io::Result<()> {
+    for remote in repofile.remotes.iter().flatten() {
+        if remote.url.is_empty() {
+            return ioerre!("Remote '{}' must have a url", remote.name);
+        }
+        if remote.name == DEFAULT_REMOTE_NAME && repofile.remote_repo.is_some() {
+            return ioerre!("Remote '{}' is already the remote of [repo]. Use a different name", DEFAULT_REMOTE_NAME);
+        }
+    }
+    Ok(())
+}
+
+/// true if the repo file keeps any paths. nothing is
+/// kept by default, so a repo file needs at least one
+pub fn has_includes(repofile: &RepoFile) -> bool {
+    let has_rule_includes = repofile.rules.as_ref().map(|rules| {
+        rules.iter().any(|rule| !matches!(rule, PathRule::Exclude(_)))
+    }).unwrap_or(false);
+    has_rule_includes || repofile.include.is_some() || repofile.include_as.is_some()
+}
+
+/// make sure every glob/regex in the repo file is valid.
+/// the destination of an include_as cannot be a pattern.
+pub fn validate_path_patterns(repofile: &RepoFile) -> io::Result<()> {
+    let empty_vec = vec![];
+    let include = repofile.include.as_ref().unwrap_or(&empty_vec);
+    let exclude = repofile.exclude.as_ref().unwrap_or(&empty_vec);
+    let include_as = repofile.include_as.as_ref().unwrap_or(&empty_vec);
+    let mut sources: Vec<&String> = include.iter().chain(exclude.iter()).chain(include_as.iter().step_by(2)).collect();
+    let mut destinations: Vec<&String> = include_as.iter().skip(1).step_by(2).collect();
+    for rule in repofile.rules.iter().flatten() {
+        match rule {
+            PathRule::Include(path) | PathRule::Exclude(path) => sources.push(path),
+            PathRule::Rename(src, dest) => {
+                sources.push(src);
+                destinations.push(dest);
+            }
+        }
+    }
+    for path in sources {
+        match parse_path_pattern(path) {
+            PathPattern::Prefix(_) => (),
+            PathPattern::Glob(glob) => {
+                GlobPattern::new(glob)?;
+            }
+            PathPattern::Regex(regex) => {
+                if let Err(e) = Regex::new(regex) {
+                    return ioerre!("Invalid regex pattern '{}': {}", regex, e);
+                }
+            }
+        }
+    }
+    for path in destinations {
+        if ! parse_path_pattern(path).is_prefix() {
+            return ioerre!("The destination of an include_as cannot be a glob or regex pattern: '{}'", path);
+        }
+    }
+    Ok(())
+}
+
+/// like include_as, the tags are pairs of
+/// local tag name = remote tag name. split out maps
+/// local names to remote names, and split in is the reverse.
+/// returns an error if any of the mappings are invalid
+pub fn get_tag_mappings(repofile: &RepoFile, reverse: bool) -> io::Result<Vec<TagMapping>> {
+    let mut mappings = vec![];
+    if let Some(tags) = &repofile.tags {
+        for pair in tags.chunks(2) {
+            if let [local, remote] = pair {
+                let mapping = if reverse {
+                    TagMapping::new(remote, local)?
+                } else {
+                    TagMapping::new(local, remote)?
+                };
+                mappings.push(mapping);
+            }
+        }
+    }
+    Ok(mappings)
+}
+
+/// returns None if the repo file did not opt in to tags.
+/// the source is the namespace to read the original tags from
+pub fn get_tag_options(
+    repofile: &RepoFile,
+    source: &str,
+    reverse: bool,
+) -> io::Result<Option<TagOptions>> {
+    if repofile.tags.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(TagOptions {
+        source: source.into(),
+        mappings: get_tag_mappings(repofile, reverse)?,
+    }))
+}
+
+/// the commits section is pairs of action, and a matcher
+/// of the form `<kind>:<value>`. the rules are in the
+/// order they appear in the repo file, and the first rule
+/// that matches a commit decides what happens to it.
+/// returns an error if any of the rules are invalid
+pub fn get_commit_rules(repofile: &RepoFile) -> io::Result<Vec<CommitRule>> {
+    let mut rules = vec![];
+    if let Some(commits) = &repofile.commits {
+        for pair in commits.chunks(2) {
+            if let [action, matcher] = pair {
+                rules.push(CommitRule {
+                    action: CommitAction::from_str(action)?,
+                    matchers: vec![CommitMatcher::parse(matcher)?],
+                });
+            }
+        }
+    }
+    Ok(rules)
+}
+
+/// the mailmap entries of the repo file are pairs of a
+/// commit identity and the identity it should be rewritten to.
+/// the entries of the repo file are added after the entries
+/// of the mailmap file, so they take precedence.
+/// returns None if the repo file does not have a mailmap
+pub fn get_mailmap(repofile: &RepoFile) -> io::Result<Option<Mailmap>> {
+    if repofile.mailmap.is_none() && repofile.mailmap_file.is_none() {
+        return Ok(None);
+    }
+    let mut mailmap = match &repofile.mailmap_file {
+        Some(path) => Mailmap::from_file(path)?,
+        None => Mailmap::default(),
+    };
+    add_mailmap_entries(repofile, &mut mailmap)?;
+    Ok(Some(mailmap))
+}
+
+pub fn add_mailmap_entries(repofile: &RepoFile, mailmap: &mut Mailmap) -> io::Result<()> {
+    if let Some(entries) = &repofile.mailmap {
+        for pair in entries.chunks(2) {
+            if let [commit_identity, proper_identity] = pair {
+                mailmap.add(commit_identity, proper_identity)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// returns None if the repo file does not change commit messages.
+/// returns an error if any of the regexes are invalid
+pub fn get_message_transforms(repofile: &RepoFile) -> io::Result<Option<MessageTransforms>> {
+    if repofile.message_replace.is_none() && repofile.origin_trailer.is_none() {
+        return Ok(None);
+    }
+    let mut transforms = MessageTransforms::default();
+    if let Some(replacements) = &repofile.message_replace {
+        for pair in replacements.chunks(2) {
+            if let [regex, replacement] = pair {
+                transforms.add_replacement(regex, replacement)?;
+            }
+        }
+    }
+    transforms.origin_trailer = repofile.origin_trailer.clone();
+    Ok(Some(transforms))
+}
+
+/// the default namespace that the other refs of a split out
+/// are written to. the output branch is appended to it
+pub const DEFAULT_SPLIT_OUT_REF_NAMESPACE: &str = "refs/split-out/";
+
+/// returns the refs to split out in the same pass as
+/// the output branch, and the namespace to write them to.
+/// by default they are written to `refs/split-out/<output branch>/`.
+/// returns None if the repo file does not have any other refs
+pub fn get_split_out_refs(repofile: &RepoFile, output_branch: &str) -> Option<(Vec<String>, String)> {
+    let refs = repofile.refs.clone()?;
+    let namespace = match &repofile.ref_namespace {
+        Some(namespace) => namespace.clone(),
+        None => format!("{}{}/", DEFAULT_SPLIT_OUT_REF_NAMESPACE, output_branch),
+    };
+    Some((refs, namespace))
+}
+
+pub fn validate_refs(repofile: &RepoFile) -> io::Result<()> {
+    let empty_vec = vec![];
+    for ref_name in repofile.refs.as_ref().unwrap_or(&empty_vec) {
+        if ref_name.is_empty() || ref_name.contains(char::is_whitespace) {
+            return ioerre!("Invalid ref '{}'. A ref cannot be empty, or contain whitespace", ref_name);
+        }
+    }
+    if let Some(namespace) = &repofile.ref_namespace {
+        if ! namespace.starts_with("refs/") || namespace.contains(char::is_whitespace) {
+            return ioerre!("Invalid ref namespace '{}'. It must start with refs/ and cannot contain whitespace", namespace);
+        }
+    }
+    Ok(())
+}
+
+/// what to do with the submodules when splitting.
+/// returns an error if the action is invalid
+pub fn get_submodule_action(repofile: &RepoFile) -> io::Result<SubmoduleAction> {
+    match &repofile.submodules {
+        Some(action) => Ok(SubmoduleAction::from_str(action)?),
+        None => Ok(SubmoduleAction::Untouched),
+    }
+}
 
 #[derive(Debug, PartialEq, Default)]
 pub struct RepoFile {
@@ -15,6 +327,39 @@ pub struct RepoFile {
     pub include_as: Option<Vec<String>>,
     pub include: Option<Vec<String>>,
     pub exclude: Option<Vec<String>>,
+    /// pairs of tag name patterns and what to rename them to.
+    /// tags are only carried across a split if this is provided
+    pub tags: Option<Vec<String>>,
+    /// pairs of commit action (keep, drop, squash)
+    /// and the commit matcher it applies to
+    pub commits: Option<Vec<String>>,
+    /// pairs of commit identity and the identity
+    /// to rewrite it to when splitting out
+    pub mailmap: Option<Vec<String>>,
+    /// path to a file in the format of git's .mailmap,
+    /// relative to the root of the repository
+    pub mailmap_file: Option<String>,
+    /// pairs of regex and what to replace it with
+    /// in the commit messages when splitting out
+    pub message_replace: Option<Vec<String>>,
+    /// the key of the trailer that records the original
+    /// commit id of commits that are split out
+    pub origin_trailer: Option<String>,
+    /// other refs, or globs of refs to split out
+    /// in the same pass as the output branch
+    pub refs: Option<Vec<String>>,
+    /// where the other refs are written to when splitting out
+    pub ref_namespace: Option<String>,
+    /// what to do with submodules when splitting: keep, drop, or merge
+    pub submodules: Option<String>,
+    /// the `[[rule]]`s of a version 2 repo file, in the order they are
+    /// written. version 1 repo files use include, include_as and exclude instead
+    pub rules: Option<Vec<PathRule>>,
+    /// the `[remotes.<name>]` of the repo file, in the order they are written
+    pub remotes: Option<Vec<Remote>>,
+    /// every repo file that this repo file extends, directly or not.
+    /// they are already merged into this repo file
+    pub extends: Option<Vec<String>>,
 }
 
 impl RepoFile {
@@ -51,9 +396,233 @@ pub fn line_is_break(line: &String) -> bool {
     true
 }
 
+/// every problem with the repo file, and the repo files it extends
+/// is reported before it is parsed. warnings are printed
+/// to stderr, and errors are returned
 pub fn parse_repo_file_from_toml(filename: &str) -> io::Result<RepoFile> {
+    let mut extended = vec![];
+    let (version, mut toml_table) = read_repo_file_toml_table(filename, &mut vec![], &mut extended)?;
+    let workspace_vars = workspace::get_workspace_vars(Path::new(filename))?;
+    expand_repo_file_vars(&mut toml_table, &workspace_vars)?;
+    let mut repo_file = parse_repo_file_from_toml_table(version, &toml_table)?;
+    if !extended.is_empty() {
+        repo_file.extends = Some(extended);
+    }
+    Ok(repo_file)
+}
+
+/// reads the toml of a repo file, and merges in every repo file that it
+/// `extends`, relative to the directory of the repo file. `chain` is the
+/// repo files that are being read, to find cycles, and every repo file
+/// that is extended is added to `extended`
+fn read_repo_file_toml_table(
+    filename: &str,
+    chain: &mut Vec<(PathBuf, String)>,
+    extended: &mut Vec<String>,
+) -> io::Result<(i64, toml::map::Map<String, Value>)> {
     let lines = read_file_into_lines(filename)?;
-    parse_repo_file_from_toml_lines(lines)
+    let canonical_path = std::fs::canonicalize(filename)?;
+    if chain.iter().any(|(path, _)| *path == canonical_path) {
+        let names: Vec<&str> = chain.iter().map(|(_, name)| name.as_str()).chain([filename]).collect();
+        return ioerre!("Repo file {} extends itself: {}", filename, names.join(" -> "));
+    }
+    let diagnostics = validate::validate_repo_file_lines(filename, &lines);
+    validate::report_diagnostics(&diagnostics)?;
+    let version = get_repo_file_version(&lines)?;
+    let mut toml_table = get_repo_file_toml_table(&lines)?;
+    let bases = match toml_table.remove("extends") {
+        Some(extends) => toml_value_to_vec(&extends),
+        None => return Ok((version, toml_table)),
+    };
+
+    chain.push((canonical_path, filename.to_string()));
+    let dir = Path::new(filename).parent().unwrap_or_else(|| Path::new(""));
+    let mut merged = toml::map::Map::new();
+    for base in bases {
+        let base_path = dir.join(&base);
+        let base_path = base_path.to_string_lossy();
+        let (base_version, base_table) = read_repo_file_toml_table(&base_path, chain, extended)?;
+        if base_version != version {
+            return ioerre!("{} cannot extend {}, because it is version {}, and {} is version {}",
+                filename, base_path, version, base_path, base_version);
+        }
+        merge_repo_file_tables(&mut merged, base_table, PATH_LIST_KEYS);
+        if !extended.iter().any(|e| *e == base_path) {
+            extended.push(base_path.to_string());
+        }
+    }
+    chain.pop();
+    merge_repo_file_tables(&mut merged, toml_table, PATH_LIST_KEYS);
+    Ok((version, merged))
+}
+
+/// replaces every `${NAME}` in the strings and table keys of a repo file with
+/// the value of NAME in the `[vars]` of the repo file, or else in the `[vars]` of
+/// the workspace file, or else in the environment variables. `$${` is a literal `${`.
+/// regex replacements are not expanded, because `${name}` is a capture group there
+pub fn expand_repo_file_vars(
+    toml_table: &mut toml::map::Map<String, Value>,
+    workspace_vars: &toml::map::Map<String, Value>,
+) -> io::Result<()> {
+    let vars = match toml_table.remove("vars") {
+        Some(Value::Table(t)) => t,
+        Some(_) => return ioerre!("vars must be a table of NAME = \"value\""),
+        None => toml::map::Map::new(),
+    };
+    let lookup = |name: &str| {
+        vars.get(name).or_else(|| workspace_vars.get(name))
+            .and_then(|v| v.as_str()).map(|s| s.to_string())
+            .or_else(|| std::env::var(name).ok())
+    };
+    let mut errors = vec![];
+    for (key, value) in toml_table.iter_mut() {
+        expand_toml_value(key, value, &lookup, &mut errors);
+    }
+    if !errors.is_empty() {
+        return ioerre!("{}\nVariables can be defined in [vars], in the [vars] of {}, or as environment variables",
+            errors.join("\n"), workspace::WORKSPACE_FILE_NAME);
+    }
+    Ok(())
+}
+
+fn expand_toml_value(
+    name: &str,
+    value: &mut Value,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    errors: &mut Vec<String>,
+) {
+    match value {
+        Value::String(s) => match expand_vars(s, lookup) {
+            Ok(expanded) => *s = expanded,
+            Err(e) => errors.push(format!("{} in {}", e, name)),
+        },
+        Value::Array(a) => {
+            for v in a.iter_mut() {
+                expand_toml_value(name, v, lookup, errors);
+            }
+        }
+        Value::Table(t) => {
+            let is_regex_rename = t.get("rename").map(|src| {
+                toml_value_to_vec(src).iter().any(|s| s.starts_with(REGEX_PATTERN_PREFIX))
+            }).unwrap_or(false);
+            // the keys can be paths too, ie: the sources of include_as
+            let mut expanded = toml::map::Map::new();
+            for (k, mut v) in std::mem::replace(t, toml::map::Map::new()) {
+                let path = format!("{}.{}", name, k);
+                let uses_capture_groups = path == "message.replace"
+                    || (name == "include_as" && k.starts_with(REGEX_PATTERN_PREFIX))
+                    || (is_regex_rename && k == "to");
+                if !uses_capture_groups {
+                    expand_toml_value(&path, &mut v, lookup, errors);
+                }
+                let k = match expand_vars(&k, lookup) {
+                    Ok(expanded_key) => expanded_key,
+                    Err(e) => {
+                        errors.push(format!("{} in the key {}", e, path));
+                        k
+                    }
+                };
+                if expanded.contains_key(&k) {
+                    errors.push(format!("{} is in {} more than once after expanding its variables", k, name));
+                }
+                expanded.insert(k, v);
+            }
+            *t = expanded;
+        }
+        _ => (),
+    }
+}
+
+/// replaces every `${NAME}` in the string. returns an error
+/// for the first variable that is not found
+pub fn expand_vars(s: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        if let Some(escaped) = after.strip_prefix("${") {
+            out.push_str("${");
+            rest = escaped;
+            continue;
+        }
+        let inner = match after.strip_prefix('{') {
+            Some(inner) => inner,
+            None => {
+                out.push('$');
+                rest = after;
+                continue;
+            }
+        };
+        let end = match inner.find('}') {
+            Some(end) => end,
+            None => return Err(format!("'{}' has a ${{ without a closing }}", s)),
+        };
+        let var_name = &inner[..end];
+        match lookup(var_name) {
+            Some(var_value) => out.push_str(&var_value),
+            None => return Err(format!("Unknown variable ${{{}}}", var_name)),
+        }
+        rest = &inner[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// when a repo file extends another, these lists are combined,
+/// instead of replaced
+pub const PATH_LIST_KEYS: &[&str] = &["include", "exclude", "rule"];
+
+/// merges the table of a repo file into the table of the repo file
+/// it extends. the `combined` lists keep the items of both, with the
+/// base items first. for every other key, `toml_table` takes
+/// precedence, and tables are merged key by key.
+/// the `[[rule]]`s are applied in order, so the excluding rules of the base
+/// come after the rules of `toml_table`, otherwise a broad include of the
+/// repo file that extends the base would keep what the base excludes
+pub fn merge_repo_file_tables(
+    base: &mut toml::map::Map<String, Value>,
+    toml_table: toml::map::Map<String, Value>,
+    combined: &[&str],
+) {
+    for (key, value) in toml_table {
+        let combine = combined.contains(&key.as_str());
+        match base.get_mut(&key) {
+            Some(base_value) if combine && key == "rule" => {
+                let is_exclude = |rule: &Value| rule.as_table().map(|t| t.contains_key("exclude")).unwrap_or(false);
+                let (base_excludes, mut list): (Vec<Value>, Vec<Value>) = toml_value_to_list(base_value)
+                    .into_iter().partition(is_exclude);
+                list.extend(toml_value_to_list(&value));
+                list.extend(base_excludes);
+                *base_value = Value::Array(list);
+            }
+            Some(base_value) if combine => {
+                let mut list = toml_value_to_list(base_value);
+                for item in toml_value_to_list(&value) {
+                    if !list.contains(&item) {
+                        list.push(item);
+                    }
+                }
+                *base_value = Value::Array(list);
+            }
+            Some(Value::Table(base_table)) if value.is_table() => {
+                if let Value::Table(t) = value {
+                    merge_repo_file_tables(base_table, t, &[]);
+                }
+            }
+            Some(base_value) => *base_value = value,
+            None => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn toml_value_to_list(toml_value: &Value) -> Vec<Value> {
+    match toml_value {
+        Value::Array(a) => a.clone(),
+        v => vec![v.clone()],
+    }
 }
 
 /// this function will exit on error. if you do not want to exit on error,
@@ -77,18 +646,114 @@ pub fn parse_repo_file_from_toml_path_res<P: AsRef<Path>>(filename: P) -> io::Re
     }
 }
 
-pub fn parse_repo_file_from_toml_lines(lines: Vec<String>) -> io::Result<RepoFile> {
-    // even though this is a toml file, and we have a toml parser
-    // we still want to split by lines, and then parse specific sections
-    // this is because if a user has:
-    // [include]
-    // this = that
-    //
-    // 
-    // exclude = [ something ]
-    // then toml will parse the exclude array as if its part of the include table
-    // so we split the file into sections separated by 2 'break' lines
-    // a break line is a line that only contains whitespace or a comment
+/// the `version = N` of a repo file. repo files without one are version 1.
+/// only the top level of the file is searched, ie: before the first table
+pub fn get_repo_file_version(lines: &[String]) -> io::Result<i64> {
+    for line in lines {
+        if line.trim_start().starts_with('[') {
+            break;
+        }
+        if !line.trim_start().starts_with("version") {
+            continue;
+        }
+        if let Ok(Value::Table(t)) = line.trim().parse::<Value>() {
+            match t.get("version") {
+                Some(Value::Integer(version)) => return Ok(*version),
+                Some(version) => return ioerre!("Invalid repo file version {}. It must be a number", version),
+                None => (),
+            }
+        }
+    }
+    Ok(1)
+}
+
+/// the toml of a repo file. for a version 1 repo file, include, include_as
+/// and exclude are moved to the top level, no matter which of the
+/// supported layouts the repo file uses
+pub fn get_repo_file_toml_table(lines: &[String]) -> io::Result<toml::map::Map<String, Value>> {
+    let version = get_repo_file_version(lines)?;
+    let contents = lines.join("\n");
+    let mut toml_table = match contents.parse::<Value>() {
+        Ok(Value::Table(t)) => t,
+        Ok(_) => return ioerre!("A repo file must be a toml table"),
+        // older repo files were parsed in segments, so they could
+        // define the same key in more than one segment. those still work,
+        // but any other error is reported where it happened
+        Err(e) => return match version {
+            1 => merge_toml_segments(&get_toml_segments(lines))?
+                .ok_or_else(|| make_toml_error(&contents, &e)),
+            _ => Err(make_toml_error(&contents, &e)),
+        },
+    };
+    if version != 1 {
+        return Ok(toml_table);
+    }
+
+    for (table, key) in get_legacy_top_level_keys(lines) {
+        if let Some(value) = remove_nested_key(&mut toml_table, &table, &key) {
+            toml_table.insert(key, value);
+        }
+    }
+    move_path_keys_to_top_level(&mut toml_table)?;
+    Ok(toml_table)
+}
+
+/// applies a toml table from `get_repo_file_toml_table` to a new repo file object
+pub fn parse_repo_file_from_toml_table(
+    version: i64,
+    toml_table: &toml::map::Map<String, Value>,
+) -> io::Result<RepoFile> {
+    let mut repo_file = RepoFile::default();
+    match version {
+        1 => parse_repo_file_table(toml_table, &mut repo_file)?,
+        2 => parse_repo_file_table_v2(toml_table, &mut repo_file)?,
+        version => return ioerre!(
+            "Unsupported repo file version {}. The latest version is {}", version, LATEST_REPO_FILE_VERSION),
+    }
+    validate_repo_file(repo_file)
+}
+
+/// the message and position of a toml error. the message of a toml
+/// error ends with its position, so that part is removed from the message
+pub fn get_toml_error_position(err: &toml::de::Error) -> (String, Option<(usize, usize)>) {
+    let message = err.to_string();
+    match err.line_col() {
+        Some((line, col)) => {
+            let position = format!(" at line {} column {}", line + 1, col + 1);
+            let message = message.strip_suffix(&position).unwrap_or(&message).to_string();
+            (message, Some((line, col)))
+        }
+        None => (message, None),
+    }
+}
+
+/// an error that points at the line and column
+/// of the repo file where the toml is invalid
+pub fn make_toml_error(contents: &str, err: &toml::de::Error) -> io::Error {
+    let (message, (line, col)) = match get_toml_error_position(err) {
+        (message, Some(line_col)) => (message, line_col),
+        (message, None) => return ioerr!("Failed to parse repo file: {}", message),
+    };
+    let line_num = (line + 1).to_string();
+    let line_contents = contents.lines().nth(line).unwrap_or("");
+    ioerr!(
+        "Failed to parse repo file at line {}, column {}: {}\n{} | {}\n{} | {}^",
+        line + 1, col + 1, message,
+        line_num, line_contents,
+        " ".repeat(line_num.len()), " ".repeat(col),
+    )
+}
+
+/// older repo files were split into segments at every 2 break lines,
+/// and every segment was parsed on its own. this lets a file have:
+/// [repo]
+/// name = "my repo"
+///
+///
+/// include = ["this"]
+/// without the include being part of the `[repo]` table.
+/// returns the segments exactly like older versions of mgt did
+pub fn get_toml_segments(lines: &[String]) -> Vec<String> {
     let mut last_line_was_break = false;
     let mut segment_indices = vec![];
     for (line_ind, line) in lines.iter().enumerate() {
@@ -108,28 +773,72 @@ pub fn parse_repo_file_from_toml_lines(lines: Vec<String>) -> io::Result<RepoFil
     let mut current_index = 0;
     let mut toml_segments = vec![];
     for i in segment_indices {
-        let string_vec: Vec<&String> = lines.iter().skip(current_index).take(i - current_index).collect();
-        if string_vec.len() == 0 { continue; }
-
-        // we can calculate exactly how big the toml_segment is. its the sum
-        // of all the lengths of each string in string_vec plus a few
-        // newlines in between each string.
-        let mut string_size = string_vec.iter().map(|s| s.len()).sum();
-        // here we add the number of newlines there will be
-        string_size += string_vec.len() - 1;
+        let segment = &lines[current_index..i];
+        current_index = i;
+        if segment.is_empty() { continue; }
+        toml_segments.push(segment.join("\n"));
+    }
+    toml_segments
+}
 
-        let mut toml_segment = String::with_capacity(string_size);
-        toml_segment.push_str(string_vec[0]);
-        for j in 1..string_vec.len() {
-            toml_segment.push('\n');
-            toml_segment.push_str(string_vec[j]);
+/// the keys at the start of a segment (see `get_toml_segments`) were at the
+/// top level of older repo files, but toml puts them in the table before them.
+/// returns the (table, key) of every one of these keys that can be at the top
+/// level, so that they can be moved back out of their table
+pub fn get_legacy_top_level_keys(lines: &[String]) -> Vec<(String, String)> {
+    let mut out = vec![];
+    let mut table: Option<String> = None;
+    let mut last_line_was_break = false;
+    let mut at_segment_start = false;
+    for line in lines {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            table = Some(trimmed.trim_matches(|c| c == '[' || c == ']').trim().to_string());
+            at_segment_start = false;
+        } else if let (true, Some(table), Some((key, _))) = (at_segment_start, &table, trimmed.split_once('=')) {
+            let key = key.trim().trim_matches('"');
+            if TOP_LEVEL_KEYS.contains(&key) {
+                out.push((table.clone(), key.to_string()));
+            }
+        }
+        if line_is_break(line) {
+            if last_line_was_break {
+                at_segment_start = true;
+                last_line_was_break = false;
+            } else {
+                last_line_was_break = true;
+            }
         }
+    }
+    out
+}
 
-        toml_segments.push(toml_segment);
-        current_index = i;
+/// include, include_as and exclude can be in the `[repo]` or `[filter]`
+/// table instead of at the top level. moves them to the top level
+pub fn move_path_keys_to_top_level(toml_table: &mut toml::map::Map<String, Value>) -> io::Result<()> {
+    for table in PATH_TABLES {
+        let moved: Vec<(&str, Value)> = match toml_table.get_mut(*table) {
+            Some(Value::Table(t)) => PATH_KEYS.iter()
+                .filter_map(|key| t.remove(*key).map(|v| (*key, v))).collect(),
+            _ => continue,
+        };
+        for (key, value) in moved {
+            if toml_table.contains_key(key) {
+                return ioerre!("'{}' is defined more than once. It can be at the top level, or in one of [repo] or [filter]", key);
+            }
+            toml_table.insert(key.to_string(), value);
+        }
     }
+    Ok(())
+}
 
-    parse_repo_file_from_toml_segments(toml_segments)
+/// removes the key from a table that can be nested, ie: `message.replace`
+fn remove_nested_key(toml_table: &mut toml::map::Map<String, Value>, table: &str, key: &str) -> Option<Value> {
+    let mut current = toml_table;
+    for name in table.split('.') {
+        current = current.get_mut(name.trim().trim_matches('"'))?.as_table_mut()?;
+    }
+    current.remove(key)
 }
 
 pub fn toml_value_to_string_opt(toml_value: &Value) -> Option<String> {
@@ -165,6 +874,191 @@ pub fn parse_include_as_section(toml_value: &Value, repofile: &mut RepoFile) {
     }
 }
 
+/// tags can either be:
+/// - a table of local tag name = remote tag name
+/// - a string, which is a prefix that local tags have, but remote tags dont
+/// - true, which keeps every tag with the same name
+pub fn parse_tags_section(toml_value: &Value, repofile: &mut RepoFile) {
+    match toml_value {
+        Value::Table(t) => {
+            let mut tags = vec![];
+            for (k, v) in t {
+                if let Some(s) = v.as_str() {
+                    tags.push(k.to_owned());
+                    tags.push(s.to_string());
+                }
+            }
+            repofile.tags = Some(tags);
+        }
+        Value::String(prefix) => {
+            repofile.tags = Some(vec![format!("{}*", prefix), "*".into()]);
+        }
+        Value::Boolean(true) => {
+            repofile.tags = Some(vec!["*".into(), "*".into()]);
+        }
+        _ => (),
+    }
+}
+
+/// commits is a table of action = matcher, or
+/// action = [matcher, matcher, ...]
+pub fn parse_commits_section(toml_value: &Value, repofile: &mut RepoFile) {
+    if let Value::Table(ref t) = toml_value {
+        let mut commits = vec![];
+        for (k, v) in t {
+            for matcher in toml_value_to_vec(v) {
+                commits.push(k.to_owned());
+                commits.push(matcher);
+            }
+        }
+        repofile.commits = Some(commits);
+    }
+}
+
+/// mailmap can either be:
+/// - a table of commit identity = proper identity, where
+///   the key `file` is the path to a mailmap file
+/// - a string, which is the path to a mailmap file
+pub fn parse_mailmap_section(toml_value: &Value, repofile: &mut RepoFile) {
+    match toml_value {
+        Value::Table(t) => {
+            let mut mailmap = vec![];
+            for (k, v) in t {
+                if let Some(s) = v.as_str() {
+                    if k == "file" {
+                        repofile.mailmap_file = Some(s.to_string());
+                    } else {
+                        mailmap.push(k.to_owned());
+                        mailmap.push(s.to_string());
+                    }
+                }
+            }
+            if !mailmap.is_empty() {
+                repofile.mailmap = Some(mailmap);
+            }
+        }
+        Value::String(path) => {
+            repofile.mailmap_file = Some(path.to_owned());
+        }
+        _ => (),
+    }
+}
+
+/// message is a table that can have:
+/// - replace: a table of regex = replacement
+/// - trailer: true to add the default origin trailer,
+///   or a string which is the key of the origin trailer
+pub fn parse_message_section(toml_value: &Value, repofile: &mut RepoFile) {
+    if let Value::Table(ref t) = toml_value {
+        for (k, v) in t {
+            match (k.as_str(), v) {
+                ("replace", Value::Table(replace)) => {
+                    let mut replacements = vec![];
+                    for (regex, replacement) in replace {
+                        if let Some(s) = replacement.as_str() {
+                            replacements.push(regex.to_owned());
+                            replacements.push(s.to_string());
+                        }
+                    }
+                    repofile.message_replace = Some(replacements);
+                }
+                ("trailer", Value::Boolean(true)) => {
+                    repofile.origin_trailer = Some(DEFAULT_ORIGIN_TRAILER.into());
+                }
+                ("trailer", Value::String(key)) => {
+                    repofile.origin_trailer = Some(key.to_owned());
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// refs can either be:
+/// - a table that has `include`, which is a ref or list of refs,
+///   and optionally `namespace`
+/// - a ref, or list of refs
+pub fn parse_refs_section(toml_value: &Value, repofile: &mut RepoFile) {
+    match toml_value {
+        Value::Table(t) => {
+            for (k, v) in t {
+                match k.as_str() {
+                    "include" => repofile.refs = Some(toml_value_to_vec(v)),
+                    "namespace" => repofile.ref_namespace = toml_value_to_string_opt(v),
+                    _ => (),
+                }
+            }
+        }
+        _ => {
+            let toml_vec = toml_value_to_vec(toml_value);
+            if !toml_vec.is_empty() {
+                repofile.refs = Some(toml_vec);
+            }
+        }
+    }
+}
+
+/// every `[remotes.<name>]` is a table of:
+/// - url = where the remote is
+/// - branch = the branch to pull from and push to (optional)
+/// - pull, push = false to never pull from, or push to the remote (optional)
+pub fn parse_remotes_section(toml_value: &Value, repofile: &mut RepoFile) {
+    if let Value::Table(ref t) = toml_value {
+        let remotes = repofile.remotes.get_or_insert_with(Vec::new);
+        for (name, v) in t {
+            if let Value::Table(ref remote) = v {
+                let get_bool = |key: &str| remote.get(key).and_then(|b| b.as_bool()).unwrap_or(true);
+                remotes.push(Remote {
+                    name: name.to_owned(),
+                    url: remote.get("url").and_then(toml_value_to_string_opt).unwrap_or_default(),
+                    branch: remote.get("branch").and_then(toml_value_to_string_opt),
+                    pull: get_bool("pull"),
+                    push: get_bool("push"),
+                });
+            }
+        }
+    }
+}
+
+/// every `[[rule]]` is a table with one of:
+/// - include = a path, or a list of paths
+/// - exclude = a path, or a list of paths
+/// - rename = a path, and to = what to rename it to
+pub fn parse_rule_section(toml_value: &Value, repofile: &mut RepoFile) -> io::Result<()> {
+    let tables = match toml_value {
+        Value::Array(tables) => tables,
+        _ => return ioerre!("rule must be an array of tables, ie: [[rule]]"),
+    };
+    let rules = repofile.rules.get_or_insert_with(Vec::new);
+    for (i, table) in tables.iter().enumerate() {
+        let rule_num = i + 1;
+        let t = match table.as_table() {
+            Some(t) => t,
+            None => return ioerre!("Rule {} must be a table", rule_num),
+        };
+        if let Some(key) = t.keys().find(|k| !["include", "exclude", "rename", "to"].contains(&k.as_str())) {
+            return ioerre!("Unknown key '{}' in rule {}. A rule must be one of: include, exclude, rename", key, rule_num);
+        }
+        let kinds: Vec<&str> = ["include", "exclude", "rename"].iter()
+            .filter(|kind| t.contains_key(**kind)).copied().collect();
+        let paths = match kinds.as_slice() {
+            [kind] => toml_value_to_vec(&t[*kind]),
+            _ => return ioerre!("Rule {} must have exactly one of: include, exclude, rename", rule_num),
+        };
+        if paths.is_empty() {
+            return ioerre!("The {} of rule {} must be a path, or a list of paths", kinds[0], rule_num);
+        }
+        match (kinds[0], t.get("to").and_then(|to| to.as_str())) {
+            ("include", None) => rules.extend(paths.into_iter().map(PathRule::Include)),
+            ("exclude", None) => rules.extend(paths.into_iter().map(PathRule::Exclude)),
+            ("rename", Some(to)) => rules.extend(paths.into_iter().map(|src| PathRule::Rename(src, to.to_string()))),
+            ("rename", None) => return ioerre!("Rule {} must have a 'to' path to rename to", rule_num),
+            _ => return ioerre!("Only a rename rule can have a 'to' path, but rule {} is an {}", rule_num, kinds[0]),
+        }
+    }
+    Ok(())
+}
+
 pub fn toml_value_to_vec(toml_value: &Value) -> Vec<String> {
     let mut toml_vec = vec![];
     if let Value::Array(ref a) = toml_value {
@@ -194,30 +1088,97 @@ pub fn parse_exclude_section(toml_value: &Value, repofile: &mut RepoFile) {
 }
 
 
-pub fn parse_repo_file_from_toml_segments(
-    toml_segments: Vec<String>
-) -> io::Result<RepoFile> {
-    let mut repo_file = RepoFile::default();
-    // now we have toml_segments where each segment can be its own toml file
-    // we parse each into a toml::Value, and then apply the result into a repo file object
+/// parses every segment of an older repo file on its own, and merges them
+/// into one table. tables that are in several segments are merged too.
+/// returns None if one of the segments is not valid toml
+pub fn merge_toml_segments(
+    toml_segments: &[String]
+) -> io::Result<Option<toml::map::Map<String, Value>>> {
+    let mut merged = toml::map::Map::new();
     for s in toml_segments {
-        let tomlvalue = s.parse::<Value>();
-        if tomlvalue.is_err() { continue; } // TODO: report parse error to user
-        let tomlvalue = tomlvalue.unwrap();
-
-        if let Value::Table(ref t) = tomlvalue {
-            for (k, v) in t {
-                match k.as_str() {
-                    "repo" => parse_repo_section(v, &mut repo_file),
-                    "include_as" => parse_include_as_section(v, &mut repo_file),
-                    "include" => parse_include_section(v, &mut repo_file),
-                    "exclude" => parse_exclude_section(v, &mut repo_file),
-                    _ => (),
-                }
+        let mut segment = match s.parse::<Value>() {
+            Ok(Value::Table(t)) => t,
+            _ => return Ok(None),
+        };
+        move_path_keys_to_top_level(&mut segment)?;
+        for (k, v) in segment {
+            match (merged.get_mut(&k), v) {
+                (Some(Value::Table(existing)), Value::Table(t)) => existing.extend(t),
+                (_, v) => { merged.insert(k, v); }
             }
         }
     }
+    Ok(Some(merged))
+}
 
+/// applies the keys of a version 1 repo file to the repo file object
+pub fn parse_repo_file_table(
+    toml_table: &toml::map::Map<String, Value>,
+    repo_file: &mut RepoFile,
+) -> io::Result<()> {
+    for (k, v) in toml_table {
+        match k.as_str() {
+            "include_as" => parse_include_as_section(v, repo_file),
+            "include" => parse_include_section(v, repo_file),
+            "exclude" => parse_exclude_section(v, repo_file),
+            "rule" => return ioerre!("[[rule]] is only supported in version 2 repo files. Add 'version = 2' at the top of your repo file"),
+            _ => parse_repo_file_key(k, v, repo_file),
+        }
+    }
+    Ok(())
+}
+
+/// a version 2 repo file is parsed as one toml document, so
+/// it can have comments and blank lines anywhere. its paths are
+/// chosen by `[[rule]]`s instead of include, include_as and exclude
+pub fn parse_repo_file_table_v2(
+    toml_table: &toml::map::Map<String, Value>,
+    repo_file: &mut RepoFile,
+) -> io::Result<()> {
+    for (k, v) in toml_table {
+        match k.as_str() {
+            "include" | "exclude" | "include_as" => return ioerre!(
+                "'{}' is not supported in version 2 repo files. Use [[rule]] instead", k),
+            "rule" => parse_rule_section(v, repo_file)?,
+            _ => parse_repo_file_key(k, v, repo_file),
+        }
+    }
+    if repo_file.rules.is_none() {
+        repo_file.rules = Some(vec![]);
+    }
+    Ok(())
+}
+
+/// the keys that mean the same thing in every version of the repo file
+pub fn parse_repo_file_key(key: &str, toml_value: &Value, repo_file: &mut RepoFile) {
+    match key {
+        "repo" => parse_repo_section(toml_value, repo_file),
+        "tags" => parse_tags_section(toml_value, repo_file),
+        "commits" => parse_commits_section(toml_value, repo_file),
+        "mailmap" => parse_mailmap_section(toml_value, repo_file),
+        "message" => parse_message_section(toml_value, repo_file),
+        "refs" => parse_refs_section(toml_value, repo_file),
+        "submodules" => repo_file.submodules = toml_value_to_string_opt(toml_value),
+        "remotes" => parse_remotes_section(toml_value, repo_file),
+        _ => (),
+    }
+}
+
+pub fn validate_repo_file(repo_file: RepoFile) -> io::Result<RepoFile> {
+    validate_path_patterns(&repo_file)?;
+    get_tag_mappings(&repo_file, false)?;
+    get_tag_mappings(&repo_file, true)?;
+    get_commit_rules(&repo_file)?;
+    add_mailmap_entries(&repo_file, &mut Mailmap::default())?;
+    get_message_transforms(&repo_file)?;
+    validate_refs(&repo_file)?;
+    get_submodule_action(&repo_file)?;
+    validate_remotes(&repo_file)?;
+    if let Some(key) = &repo_file.origin_trailer {
+        if key.is_empty() || key.contains(|c: char| c.is_whitespace() || c == ':') {
+            return ioerre!("Invalid trailer '{}'. A trailer cannot be empty, or contain whitespace or ':'", key);
+        }
+    }
     Ok(repo_file)
 }
 
@@ -303,21 +1264,182 @@ pub fn generate_repo_file_section_include_as(
     }
 }
 
+pub fn generate_repo_file_section_tags(
+    repofile: &RepoFile
+) -> Option<toml::Value> {
+    let mut toml_map = toml::map::Map::new();
+    if let Some(ref tags) = repofile.tags {
+        for pair in tags.chunks(2) {
+            if let [pattern, replacement] = pair {
+                toml_map.insert(pattern.clone(), toml::Value::String(replacement.clone()));
+            }
+        }
+    }
+
+    if !toml_map.is_empty() {
+        Some(toml::Value::Table(toml_map))
+    } else {
+        None
+    }
+}
+
+/// consecutive rules with the same action are
+/// grouped into one array. if an action appears again
+/// later, the order of the rules cannot be represented
+/// by a toml table, so the later rules are appended to the first group
+pub fn generate_repo_file_section_commits(
+    repofile: &RepoFile
+) -> Option<toml::Value> {
+    let mut toml_map = toml::map::Map::new();
+    if let Some(ref commits) = repofile.commits {
+        for pair in commits.chunks(2) {
+            if let [action, matcher] = pair {
+                let entry = toml_map.entry(action.clone())
+                    .or_insert_with(|| toml::Value::Array(vec![]));
+                if let toml::Value::Array(ref mut matchers) = entry {
+                    matchers.push(toml::Value::String(matcher.clone()));
+                }
+            }
+        }
+    }
+
+    if !toml_map.is_empty() {
+        Some(toml::Value::Table(toml_map))
+    } else {
+        None
+    }
+}
+
+pub fn generate_repo_file_section_mailmap(
+    repofile: &RepoFile
+) -> Option<toml::Value> {
+    let mut toml_map = toml::map::Map::new();
+    if let Some(ref path) = repofile.mailmap_file {
+        toml_map.insert("file".into(), toml::Value::String(path.clone()));
+    }
+    if let Some(ref mailmap) = repofile.mailmap {
+        for pair in mailmap.chunks(2) {
+            if let [commit_identity, proper_identity] = pair {
+                toml_map.insert(commit_identity.clone(), toml::Value::String(proper_identity.clone()));
+            }
+        }
+    }
+
+    if !toml_map.is_empty() {
+        Some(toml::Value::Table(toml_map))
+    } else {
+        None
+    }
+}
+
+pub fn generate_repo_file_section_message(
+    repofile: &RepoFile
+) -> Option<toml::Value> {
+    let mut toml_map = toml::map::Map::new();
+    if let Some(ref key) = repofile.origin_trailer {
+        toml_map.insert("trailer".into(), toml::Value::String(key.clone()));
+    }
+    if let Some(ref replacements) = repofile.message_replace {
+        let mut replace_map = toml::map::Map::new();
+        for pair in replacements.chunks(2) {
+            if let [regex, replacement] = pair {
+                replace_map.insert(regex.clone(), toml::Value::String(replacement.clone()));
+            }
+        }
+        toml_map.insert("replace".into(), toml::Value::Table(replace_map));
+    }
+
+    if !toml_map.is_empty() {
+        Some(toml::Value::Table(toml_map))
+    } else {
+        None
+    }
+}
+
+pub fn generate_repo_file_section_refs(
+    repofile: &RepoFile
+) -> Option<toml::Value> {
+    let mut toml_map = toml::map::Map::new();
+    if let Some(include) = generate_repo_file_section_from_list(&repofile.refs) {
+        toml_map.insert("include".into(), include);
+    }
+    if let Some(ref namespace) = repofile.ref_namespace {
+        toml_map.insert("namespace".into(), toml::Value::String(namespace.clone()));
+    }
+
+    if !toml_map.is_empty() {
+        Some(toml::Value::Table(toml_map))
+    } else {
+        None
+    }
+}
+
+pub fn generate_repo_file_section_remotes(
+    repofile: &RepoFile
+) -> Option<toml::Value> {
+    let remotes = repofile.remotes.as_ref()?;
+    let mut toml_map = toml::map::Map::new();
+    for remote in remotes {
+        let mut remote_map = toml::map::Map::new();
+        remote_map.insert("url".into(), toml::Value::String(remote.url.clone()));
+        if let Some(ref branch) = remote.branch {
+            remote_map.insert("branch".into(), toml::Value::String(branch.clone()));
+        }
+        if !remote.pull {
+            remote_map.insert("pull".into(), toml::Value::Boolean(false));
+        }
+        if !remote.push {
+            remote_map.insert("push".into(), toml::Value::Boolean(false));
+        }
+        toml_map.insert(remote.name.clone(), toml::Value::Table(remote_map));
+    }
+    Some(toml::Value::Table(toml_map))
+}
+
+pub fn generate_repo_file_section_rule(
+    repofile: &RepoFile
+) -> Option<toml::Value> {
+    let rules = repofile.rules.as_ref()?;
+    let tables = rules.iter().map(|rule| {
+        let mut toml_map = toml::map::Map::new();
+        match rule {
+            PathRule::Include(path) => {
+                toml_map.insert("include".into(), toml::Value::String(path.clone()));
+            }
+            PathRule::Exclude(path) => {
+                toml_map.insert("exclude".into(), toml::Value::String(path.clone()));
+            }
+            PathRule::Rename(src, dest) => {
+                toml_map.insert("rename".into(), toml::Value::String(src.clone()));
+                toml_map.insert("to".into(), toml::Value::String(dest.clone()));
+            }
+        }
+        toml::Value::Table(toml_map)
+    }).collect();
+    Some(toml::Value::Array(tables))
+}
+
 pub fn generate_repo_file_toml(
     repofile: &RepoFile,
 ) -> String {
-    // the include and exclude
-    // sections need to be done
-    // seperately because
-    // they need 2 empty lines between
-    // the other sections to be parsed correctly
     let include_section = generate_repo_file_section_include(repofile);
     let exclude_section = generate_repo_file_section_exclude(repofile);
     let repo_section = generate_repo_file_section_repo(repofile);
     let include_as_section = generate_repo_file_section_include_as(repofile);
+    let tags_section = generate_repo_file_section_tags(repofile);
+    let commits_section = generate_repo_file_section_commits(repofile);
+    let mailmap_section = generate_repo_file_section_mailmap(repofile);
+    let message_section = generate_repo_file_section_message(repofile);
+    let refs_section = generate_repo_file_section_refs(repofile);
+    let rule_section = generate_repo_file_section_rule(repofile);
+    let remotes_section = generate_repo_file_section_remotes(repofile);
 
     let mut toml_map = toml::map::Map::new();
 
+    if rule_section.is_some() {
+        toml_map.insert("version".into(), toml::Value::Integer(LATEST_REPO_FILE_VERSION));
+    }
+
     if let Some(toml_value) = repo_section {
         toml_map.insert("repo".into(), toml_value);
     }
@@ -330,6 +1452,30 @@ pub fn generate_repo_file_toml(
     if let Some(toml_value) = include_as_section {
         toml_map.insert("include_as".into(), toml_value);
     }
+    if let Some(toml_value) = tags_section {
+        toml_map.insert("tags".into(), toml_value);
+    }
+    if let Some(toml_value) = commits_section {
+        toml_map.insert("commits".into(), toml_value);
+    }
+    if let Some(toml_value) = mailmap_section {
+        toml_map.insert("mailmap".into(), toml_value);
+    }
+    if let Some(toml_value) = message_section {
+        toml_map.insert("message".into(), toml_value);
+    }
+    if let Some(toml_value) = refs_section {
+        toml_map.insert("refs".into(), toml_value);
+    }
+    if let Some(ref action) = repofile.submodules {
+        toml_map.insert("submodules".into(), toml::Value::String(action.clone()));
+    }
+    if let Some(toml_value) = remotes_section {
+        toml_map.insert("remotes".into(), toml_value);
+    }
+    if let Some(toml_value) = rule_section {
+        toml_map.insert("rule".into(), toml_value);
+    }
     
     let toml_table = toml::Value::Table(toml_map);
     toml_table.to_string()
@@ -338,8 +1484,23 @@ pub fn generate_repo_file_toml(
 #[cfg(test)]
 mod test {
     use super::RepoFile;
-    use super::parse_repo_file_from_toml_lines;
+    use super::get_repo_file_version;
     use super::generate_repo_file_toml;
+    use super::{parse_path_pattern, PathPattern};
+    use super::get_split_out_refs;
+    use super::get_submodule_action;
+    use super::{PathRule, has_includes};
+    use super::{Remote, get_remotes, get_primary_remote};
+    use super::{get_repo_file_toml_table, parse_repo_file_from_toml_table, merge_repo_file_tables, PATH_LIST_KEYS};
+    use super::{expand_vars, expand_repo_file_vars};
+    use gitfilter::submodules::SubmoduleAction;
+
+    /// a repo file that is not on disk, so it cannot extend other repo files
+    fn parse_repo_file_from_toml_lines(lines: Vec<String>) -> std::io::Result<RepoFile> {
+        let version = get_repo_file_version(&lines)?;
+        let toml_table = get_repo_file_toml_table(&lines)?;
+        parse_repo_file_from_toml_table(version, &toml_table)
+    }
 
     fn parse_from_lines(toml_str: &str) -> RepoFile {
         let lines: Vec<String> = toml_str.split('\n').map(|s| s.to_string()).collect();
@@ -417,6 +1578,74 @@ mod test {
         assert_eq!(include_as[3], " ");
     }
 
+    #[test]
+    fn toml_standard_layouts_work() {
+        let toml_str = r#"
+            [repo]
+            name = "somename"
+            include = ["lib/", "README.md"]
+            exclude = "lib/secret/"
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert_eq!(repofile.repo_name.unwrap(), "somename");
+        assert_eq!(repofile.include.unwrap(), vec!["lib/", "README.md"]);
+        assert_eq!(repofile.exclude.unwrap(), vec!["lib/secret/"]);
+
+        let toml_str = r#"
+            [repo]
+            name = "somename"
+            [filter]
+            include = "README.md"
+            [filter.include_as]
+            "lib/" = " "
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert_eq!(repofile.include.unwrap(), vec!["README.md"]);
+        assert_eq!(repofile.include_as.unwrap(), vec!["lib/", " "]);
+
+        let lines = vec!["include = \"a\"".into(), "[filter]".into(), "include = \"b\"".into()];
+        assert!(parse_repo_file_from_toml_lines(lines).is_err());
+    }
+
+    #[test]
+    fn toml_legacy_segments_still_work() {
+        let toml_str = r#"
+            [repo]
+            name = "somename"
+
+
+            include = "lib/"
+            [include_as]
+            "src/" = " "
+
+
+            exclude = ["lib/secret/"]
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert_eq!(repofile.repo_name.unwrap(), "somename");
+        assert_eq!(repofile.include.unwrap(), vec!["lib/"]);
+        assert_eq!(repofile.include_as.unwrap(), vec!["src/", " "]);
+        assert_eq!(repofile.exclude.unwrap(), vec!["lib/secret/"]);
+
+        // not valid as one toml document, because include is defined twice
+        let toml_str = r#"
+            include = "lib/"
+
+
+            include = "src/"
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert_eq!(repofile.include.unwrap(), vec!["src/"]);
+    }
+
+    #[test]
+    fn toml_parse_errors_have_line_and_column() {
+        let lines = vec!["[repo]".into(), "name = \"x\"".into(), "include [\"a\"]".into()];
+        let err = parse_repo_file_from_toml_lines(lines).unwrap_err().to_string();
+        assert!(err.starts_with("Failed to parse repo file at line 3, column 9: "));
+        assert!(err.ends_with("\n3 | include [\"a\"]\n  |         ^"));
+    }
+
     #[test]
     fn toml_comments_not_included() {
         let toml_str = r#"
@@ -432,6 +1661,333 @@ mod test {
         assert_eq!(repofile.remote_branch.unwrap(), "somebranch");
     }
 
+    #[test]
+    fn toml_parse_patterns_work() {
+        let toml_str = r#"
+            [include_as]
+            "glob:src/**/BUILD" = "build/"
+            'regex:^docs/(.*)\.md$' = "md/$1"
+
+
+            include = ["glob:**/*.rs", "lib/"]
+            exclude = 'regex:_test\.rs$'
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        let include = repofile.include.unwrap();
+        assert_eq!(parse_path_pattern(&include[0]), PathPattern::Glob("**/*.rs"));
+        assert_eq!(parse_path_pattern(&include[1]), PathPattern::Prefix("lib/"));
+        let exclude = repofile.exclude.unwrap();
+        assert_eq!(parse_path_pattern(&exclude[0]), PathPattern::Regex("_test\\.rs$"));
+        let include_as = repofile.include_as.unwrap();
+        assert_eq!(parse_path_pattern(&include_as[2]), PathPattern::Regex("^docs/(.*)\\.md$"));
+    }
+
+    #[test]
+    fn toml_parse_invalid_patterns_fail() {
+        let lines: Vec<String> = vec!["include = \"regex:(unclosed\"".into()];
+        assert!(parse_repo_file_from_toml_lines(lines).is_err());
+        let lines: Vec<String> = vec!["[include_as]".into(), "\"lib/\" = \"glob:*\"".into()];
+        assert!(parse_repo_file_from_toml_lines(lines).is_err());
+    }
+
+    #[test]
+    fn toml_parse_tags_work() {
+        let toml_str = r#"
+            [tags]
+            "v*" = "mylib-v*"
+            "stable" = "mylib-stable"
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert_eq!(repofile.tags.unwrap(), vec![
+            "v*".to_string(), "mylib-v*".into(),
+            "stable".into(), "mylib-stable".into(),
+        ]);
+
+        let repofile = parse_from_lines("tags = \"mylib-\"");
+        assert_eq!(repofile.tags.unwrap(), vec!["mylib-*".to_string(), "*".into()]);
+
+        let repofile = parse_from_lines("tags = true");
+        assert_eq!(repofile.tags.unwrap(), vec!["*".to_string(), "*".into()]);
+
+        let repofile = parse_from_lines("tags = false");
+        assert!(repofile.tags.is_none());
+
+        let lines: Vec<String> = vec!["[tags]".into(), "\"v*.*\" = \"x\"".into()];
+        assert!(parse_repo_file_from_toml_lines(lines).is_err());
+        // has to be valid in both directions
+        let lines: Vec<String> = vec!["[tags]".into(), "\"v*\" = \"stable\"".into()];
+        assert!(parse_repo_file_from_toml_lines(lines).is_err());
+    }
+
+    #[test]
+    fn toml_parse_commits_work() {
+        let toml_str = r#"
+            [commits]
+            keep = "author:^trusted-bot"
+            drop = ["author:bot", "before:2020-01-01"]
+            squash = 'message:^fixup!'
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert_eq!(repofile.commits.unwrap(), vec![
+            "keep".to_string(), "author:^trusted-bot".into(),
+            "drop".into(), "author:bot".into(),
+            "drop".into(), "before:2020-01-01".into(),
+            "squash".into(), "message:^fixup!".into(),
+        ]);
+
+        let lines: Vec<String> = vec!["[commits]".into(), "remove = \"author:bot\"".into()];
+        assert!(parse_repo_file_from_toml_lines(lines).is_err());
+        let lines: Vec<String> = vec!["[commits]".into(), "drop = \"date:2020-01-01\"".into()];
+        assert!(parse_repo_file_from_toml_lines(lines).is_err());
+        let lines: Vec<String> = vec!["[commits]".into(), "drop = \"before:yesterday\"".into()];
+        assert!(parse_repo_file_from_toml_lines(lines).is_err());
+    }
+
+    #[test]
+    fn toml_parse_mailmap_works() {
+        let toml_str = r#"
+            [mailmap]
+            file = "public.mailmap"
+            "<jane@corp.internal>" = "Jane Doe <jane@example.com>"
+            "bob <bob@corp.internal>" = "Bob"
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert_eq!(repofile.mailmap_file.unwrap(), "public.mailmap");
+        assert_eq!(repofile.mailmap.unwrap(), vec![
+            "<jane@corp.internal>".to_string(), "Jane Doe <jane@example.com>".into(),
+            "bob <bob@corp.internal>".into(), "Bob".into(),
+        ]);
+
+        let repofile = parse_from_lines("mailmap = \".mailmap\"");
+        assert_eq!(repofile.mailmap_file.unwrap(), ".mailmap");
+        assert!(repofile.mailmap.is_none());
+
+        let lines: Vec<String> = vec!["[mailmap]".into(), "\"jane\" = \"Jane Doe\"".into()];
+        assert!(parse_repo_file_from_toml_lines(lines).is_err());
+    }
+
+    #[test]
+    fn toml_parse_message_works() {
+        let toml_str = r#"
+            [message]
+            trailer = true
+            [message.replace]
+            '\s*JIRA-[0-9]+' = ""
+            'corp\.internal/(\w+)' = "example.com/$1"
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert_eq!(repofile.origin_trailer.unwrap(), "Mgt-Origin");
+        assert_eq!(repofile.message_replace.unwrap(), vec![
+            "\\s*JIRA-[0-9]+".to_string(), "".into(),
+            "corp\\.internal/(\\w+)".into(), "example.com/$1".into(),
+        ]);
+
+        let repofile = parse_from_lines("message = { trailer = \"Exported-From\" }");
+        assert_eq!(repofile.origin_trailer.unwrap(), "Exported-From");
+
+        let lines: Vec<String> = vec!["[message]".into(), "trailer = \"has space\"".into()];
+        assert!(parse_repo_file_from_toml_lines(lines).is_err());
+        let lines: Vec<String> = vec!["[message.replace]".into(), "\"(\" = \"\"".into()];
+        assert!(parse_repo_file_from_toml_lines(lines).is_err());
+    }
+
+    #[test]
+    fn toml_parse_refs_works() {
+        let toml_str = r#"
+            [refs]
+            include = ["refs/heads/release/*", "hotfix"]
+            namespace = "refs/split/"
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert_eq!(repofile.refs.as_ref().unwrap(), &vec!["refs/heads/release/*".to_string(), "hotfix".into()]);
+        assert_eq!(get_split_out_refs(&repofile, "out").unwrap().1, "refs/split/");
+
+        let repofile = parse_from_lines("refs = \"release/*\"");
+        assert_eq!(get_split_out_refs(&repofile, "out").unwrap(), (vec!["release/*".to_string()], "refs/split-out/out/".to_string()));
+        assert!(get_split_out_refs(&RepoFile::default(), "out").is_none());
+
+        let lines: Vec<String> = vec!["[refs]".into(), "include = \"release/*\"".into(), "namespace = \"split/\"".into()];
+        assert!(parse_repo_file_from_toml_lines(lines).is_err());
+
+        let toml_str = generate_repo_file_toml(&repofile);
+        assert!(toml_str.contains("[refs]"));
+        assert!(toml_str.contains("include = \"release/*\""));
+    }
+
+    #[test]
+    fn toml_parse_submodules_works() {
+        let repofile = parse_from_lines("submodules = \"merge\"");
+        assert_eq!(get_submodule_action(&repofile).unwrap(), SubmoduleAction::Merge);
+        assert_eq!(get_submodule_action(&RepoFile::default()).unwrap(), SubmoduleAction::Untouched);
+
+        let lines: Vec<String> = vec!["submodules = \"flatten\"".into()];
+        assert!(parse_repo_file_from_toml_lines(lines).is_err());
+    }
+
+    #[test]
+    fn toml_parse_v2_rules_keep_their_order() {
+        let toml_str = r#"
+            version = 2
+            # comments and blank lines can go anywhere
+
+            [[rule]]
+            include = ["lib/", "README.md"]
+
+
+            [[rule]]
+            # this overrides the include above
+            exclude = "lib/secret/"
+            [[rule]]
+            rename = "lib/"
+            to = "src/"
+
+            [repo]
+            name = "mylib"
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert_eq!(repofile.rules, Some(vec![
+            PathRule::Include("lib/".into()),
+            PathRule::Include("README.md".into()),
+            PathRule::Exclude("lib/secret/".into()),
+            PathRule::Rename("lib/".into(), "src/".into()),
+        ]));
+        assert_eq!(repofile.repo_name, Some("mylib".into()));
+        assert!(has_includes(&repofile));
+        assert_eq!(repofile.include, None);
+    }
+
+    #[test]
+    fn toml_parse_invalid_v2_rules_fail() {
+        let invalid = [
+            "version = 3",
+            "version = 2\ninclude = \"lib/\"",
+            "version = 2\n[[rule]]\ninclude = \"lib/\"\nexclude = \"lib/a\"",
+            "version = 2\n[[rule]]\nrename = \"lib/\"",
+            "version = 2\n[[rule]]\ninclude = \"lib/\"\nto = \"src/\"",
+            "version = 2\n[[rule]]\nkeep = \"lib/\"",
+            "version = 2\n[[rule]]\nrename = \"lib/\"\nto = \"glob:src/*\"",
+            "[[rule]]\ninclude = \"lib/\"",
+        ];
+        for toml_str in invalid.iter() {
+            let lines: Vec<String> = toml_str.split('\n').map(|s| s.to_string()).collect();
+            assert!(parse_repo_file_from_toml_lines(lines).is_err(), "{}", toml_str);
+        }
+    }
+
+    #[test]
+    fn toml_parse_remotes_works() {
+        let toml_str = r#"
+            [repo]
+            remote = "https://example.com/mylib.git"
+            include = "lib/"
+
+            [remotes.mirror]
+            url = "../mirror"
+            branch = "main"
+            pull = false
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert_eq!(get_remotes(&repofile), vec![
+            Remote { name: "origin".into(), url: "https://example.com/mylib.git".into(), branch: None, pull: true, push: true },
+            Remote { name: "mirror".into(), url: "../mirror".into(), branch: Some("main".into()), pull: false, push: true },
+        ]);
+
+        // without [repo] remote, the first remote that
+        // can be pulled from is used for rebasing
+        let toml_str = r#"
+            include = "lib/"
+            [remotes.backup]
+            url = "../backup"
+            pull = false
+            [remotes.github]
+            url = "../github"
+        "#;
+        let repofile = parse_from_lines(toml_str);
+        assert_eq!(get_primary_remote(&repofile).unwrap().name, "github");
+
+        let invalid = [
+            "[remotes.mirror]\nbranch = \"main\"",
+            "[repo]\nremote = \"a\"\n[remotes.origin]\nurl = \"b\"",
+        ];
+        for toml_str in invalid.iter() {
+            let lines: Vec<String> = toml_str.split('\n').map(|s| s.to_string()).collect();
+            assert!(parse_repo_file_from_toml_lines(lines).is_err(), "{}", toml_str);
+        }
+    }
+
+    #[test]
+    fn merging_extended_repo_files_works() {
+        let parse_table = |toml_str: &str| {
+            let lines: Vec<String> = toml_str.split('\n').map(|s| s.to_string()).collect();
+            get_repo_file_toml_table(&lines).unwrap()
+        };
+        let mut merged = parse_table("exclude = [\".internal/\", \"secrets/\"]\n[repo]\nname = \"common\"\nbranch = \"main\"");
+        let toml_table = parse_table("include = \"lib/\"\nexclude = [\"secrets/\", \"lib/ci/\"]\n[repo]\nname = \"mylib\"");
+        merge_repo_file_tables(&mut merged, toml_table, PATH_LIST_KEYS);
+
+        let repofile = parse_repo_file_from_toml_table(1, &merged).unwrap();
+        assert_eq!(repofile.include, Some(vec!["lib/".into()]));
+        assert_eq!(repofile.exclude, Some(vec![".internal/".into(), "secrets/".into(), "lib/ci/".into()]));
+        assert_eq!(repofile.repo_name, Some("mylib".into()));
+        assert_eq!(repofile.remote_branch, Some("main".into()));
+
+        // the excludes of the base are applied after the rules of the repo file that extends it
+        let mut merged = parse_table("version = 2\n[[rule]]\ninclude = \"README.md\"\n[[rule]]\nexclude = \"lib/secret/\"");
+        let toml_table = parse_table("version = 2\n[[rule]]\ninclude = \"lib/\"\n[[rule]]\nexclude = \"lib/secret/\"");
+        merge_repo_file_tables(&mut merged, toml_table, PATH_LIST_KEYS);
+        let repofile = parse_repo_file_from_toml_table(2, &merged).unwrap();
+        assert_eq!(repofile.rules, Some(vec![
+            PathRule::Include("README.md".into()),
+            PathRule::Include("lib/".into()),
+            PathRule::Exclude("lib/secret/".into()),
+            PathRule::Exclude("lib/secret/".into()),
+        ]));
+    }
+
+    #[test]
+    fn expand_vars_works() {
+        let lookup = |name: &str| match name {
+            "HOST" => Some("git.example.com".to_string()),
+            _ => None,
+        };
+        assert_eq!(expand_vars("https://${HOST}/mylib", &lookup).unwrap(), "https://git.example.com/mylib");
+        assert_eq!(expand_vars("$1 $${HOST} $", &lookup).unwrap(), "$1 ${HOST} $");
+        assert_eq!(expand_vars("${ORG}/mylib", &lookup).unwrap_err(), "Unknown variable ${ORG}");
+        assert!(expand_vars("${HOST", &lookup).is_err());
+    }
+
+    #[test]
+    fn repo_file_vars_are_expanded() {
+        let toml_str = r#"
+            [vars]
+            org = "myorg"
+            [repo]
+            remote = "https://git.example.com/${org}/mylib"
+            [include_as]
+            "lib/${org}/" = "${org}/"
+            "regex:^(?P<name>[a-z]+)/" = "libs/${name}/"
+            [tags]
+            "${org}-*" = "*"
+            [message.replace]
+            "JIRA-(?P<id>[0-9]+)" = "${org}-${id}"
+        "#;
+        let lines: Vec<String> = toml_str.split('\n').map(|s| s.to_string()).collect();
+        let mut toml_table = get_repo_file_toml_table(&lines).unwrap();
+        expand_repo_file_vars(&mut toml_table, &toml::map::Map::new()).unwrap();
+        let repofile = parse_repo_file_from_toml_table(1, &toml_table).unwrap();
+        assert_eq!(repofile.remote_repo.unwrap(), "https://git.example.com/myorg/mylib");
+        assert_eq!(repofile.include_as.unwrap(), vec![
+            "lib/myorg/", "myorg/", "regex:^(?P<name>[a-z]+)/", "libs/${name}/",
+        ]);
+        assert_eq!(repofile.tags.unwrap(), vec!["myorg-*", "*"]);
+        assert_eq!(repofile.message_replace.unwrap(), vec!["JIRA-(?P<id>[0-9]+)", "${org}-${id}"]);
+
+        let lines = vec!["[repo]".into(), "remote = \"${MGT_TEST_UNDEFINED_VAR}\"".into()];
+        let mut toml_table = get_repo_file_toml_table(&lines).unwrap();
+        let err = expand_repo_file_vars(&mut toml_table, &toml::map::Map::new()).unwrap_err().to_string();
+        assert!(err.starts_with("Unknown variable ${MGT_TEST_UNDEFINED_VAR} in repo.remote"), "{}", err);
+    }
+
     #[test]
     fn generate_repo_file_works() {
         let mut repofile = RepoFile::default();
@@ -465,7 +2021,43 @@ mod test {
             "lib/".into(), " ".into(),
             "something.txt".into(), "else.txt".into(),
         ]);
+        repofile.tags = Some(vec!["v*".into(), "mylib-v*".into()]);
+        repofile.commits = Some(vec![
+            "keep".into(), "author:^trusted-bot".into(),
+            "drop".into(), "author:bot".into(),
+            "drop".into(), "message:\\[skip-export\\]".into(),
+        ]);
+        repofile.mailmap = Some(vec![
+            "<jane@corp.internal>".into(), "Jane Doe <jane@example.com>".into(),
+        ]);
+        repofile.mailmap_file = Some(".mailmap".into());
+        repofile.origin_trailer = Some("Mgt-Origin".into());
+        repofile.message_replace = Some(vec!["JIRA-[0-9]+".into(), "".into()]);
+        repofile.submodules = Some("merge".into());
+        repofile.remotes = Some(vec![
+            Remote { name: "github".into(), url: "git@github.com:me/mylib.git".into(), branch: Some("main".into()), pull: true, push: true },
+            Remote { name: "archive".into(), url: "../archive".into(), branch: None, pull: false, push: true },
+        ]);
+        let toml_str = generate_repo_file_toml(&repofile);
+
+        let repofile_parsed = parse_from_lines(toml_str.as_str());
+        assert_eq!(repofile, repofile_parsed);
+    }
+
+    #[test]
+    fn generated_v2_repo_file_can_be_read() {
+        let repofile = RepoFile {
+            repo_name: Some("reponame".into()),
+            rules: Some(vec![
+                PathRule::Include("lib/".into()),
+                PathRule::Exclude("lib/secret/".into()),
+                PathRule::Rename("lib/".into(), "".into()),
+            ]),
+            ..Default::default()
+        };
         let toml_str = generate_repo_file_toml(&repofile);
+        assert!(toml_str.contains("version = 2"));
+        assert!(toml_str.contains("[[rule]]"));
 
         let repofile_parsed = parse_from_lines(toml_str.as_str());
         assert_eq!(repofile, repofile_parsed);
@@ -0,0 +1,505 @@
+use super::cli::MgtCommandReverse;
+use super::die;
+use super::repo_file::{self, RepoFile, PathRule, PathPattern, parse_path_pattern};
+use super::repo_file::{GLOB_PATTERN_PREFIX, REGEX_PATTERN_PREFIX};
+use super::validate::is_dir;
+use super::verify::{self, FileOpType};
+use gitfilter::filter::{FilterRules, GlobPattern};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Kind {
+    Include,
+    Exclude,
+    /// the path that the rule renames to
+    Rename(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Matcher {
+    Path,
+    /// the rest of the glob after its prefix
+    Glob(String),
+    Regex(String),
+}
+
+/// one of the path rules of the repo file. a glob is split into
+/// the directory it starts with, and the rest of it
+#[derive(Debug, Clone)]
+struct Rule {
+    kind: Kind,
+    prefix: String,
+    matcher: Matcher,
+}
+
+impl Rule {
+    fn is_keep(&self) -> bool {
+        self.kind != Kind::Exclude
+    }
+
+    /// the path as it was written in the repo file
+    fn path(&self) -> String {
+        match self.matcher {
+            Matcher::Path => self.prefix.clone(),
+            Matcher::Glob(ref rest) => format!("{}{}{}", GLOB_PATTERN_PREFIX, self.prefix, rest),
+            Matcher::Regex(ref regex) => format!("{}{}", REGEX_PATTERN_PREFIX, regex),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.kind {
+            Kind::Include => format!("include '{}'", self.path()),
+            Kind::Exclude => format!("exclude '{}'", self.path()),
+            Kind::Rename(ref dest) => format!("rename '{}' to '{}'", self.path(), dest),
+        }
+    }
+}
+
+/// a rule that puts the paths under `src` at `dest`.
+/// an include puts them at the same path
+struct Mapping {
+    index: usize,
+    src: String,
+    dest: String,
+}
+
+fn overlaps(a: &str, b: &str) -> bool {
+    a.starts_with(b) || b.starts_with(a)
+}
+
+struct Reverser {
+    rules: Vec<Rule>,
+    problems: Vec<String>,
+}
+
+impl Reverser {
+    fn new(file_ops: &[FileOpType]) -> Reverser {
+        let mut reverser = Reverser { rules: vec![], problems: vec![] };
+        for file_op in file_ops {
+            let (kind, src) = match file_op {
+                FileOpType::Include(src) => (Kind::Include, src.trim_start()),
+                FileOpType::Exclude(src) => (Kind::Exclude, src.trim_start()),
+                FileOpType::IncludeAs(src, dest) => match parse_path_pattern(dest) {
+                    PathPattern::Prefix(dest) => (Kind::Rename(dest.trim_start().to_string()), src.trim_start()),
+                    _ => {
+                        reverser.problems.push(format!("The rename of '{}' to '{}' cannot be reversed, because it renames to a pattern", src, dest));
+                        continue;
+                    }
+                },
+            };
+            let (prefix, matcher) = match parse_path_pattern(src) {
+                PathPattern::Prefix(path) => (path.to_string(), Matcher::Path),
+                PathPattern::Glob(glob) => match GlobPattern::new(glob) {
+                    Ok(pattern) => (pattern.prefix.clone(), Matcher::Glob(glob[pattern.prefix.len()..].to_string())),
+                    Err(e) => {
+                        reverser.problems.push(std::io::Error::from(e).to_string());
+                        continue;
+                    }
+                },
+                PathPattern::Regex(regex) => ("".to_string(), Matcher::Regex(regex.to_string())),
+            };
+            let kind = match kind {
+                // a glob renames its prefix, which is always a directory
+                Kind::Rename(dest) if !dest.is_empty() && !dest.ends_with('/') && matcher != Matcher::Path => {
+                    Kind::Rename(format!("{}/", dest))
+                }
+                kind => kind,
+            };
+            reverser.rules.push(Rule { kind, prefix, matcher });
+        }
+        reverser
+    }
+
+    /// the last plain path rename that renames everything under `path`
+    fn covering_rename(&self, path: &str) -> Option<usize> {
+        self.rules.iter().rposition(|rule| {
+            matches!(rule.kind, Kind::Rename(_)) && rule.matcher == Matcher::Path && path.starts_with(&rule.prefix)
+        })
+    }
+
+    /// where `path` is put by the rule at `index`
+    fn translate_with(&self, index: Option<usize>, path: &str) -> String {
+        match index.map(|i| &self.rules[i]) {
+            Some(Rule { kind: Kind::Rename(dest), prefix, .. }) => format!("{}{}", dest, &path[prefix.len()..]),
+            _ => path.to_string(),
+        }
+    }
+
+    fn translate(&self, path: &str) -> String {
+        self.translate_with(self.covering_rename(path), path)
+    }
+
+    /// true if a rule after the rule at `index` keeps something under `path`
+    fn is_kept_after(&self, index: usize, path: &str) -> bool {
+        self.rules[index + 1..].iter().any(|rule| rule.is_keep() && overlaps(path, &rule.prefix))
+    }
+
+    /// true if everything under `path` is excluded
+    fn is_excluded(&self, path: &str) -> bool {
+        self.rules.iter().enumerate().any(|(k, rule)| {
+            if rule.kind != Kind::Exclude || rule.matcher != Matcher::Path {
+                return false;
+            }
+            // an exact file is always excluded
+            if !is_dir(&rule.prefix) {
+                return path == rule.prefix;
+            }
+            path.starts_with(&rule.prefix) && !self.is_kept_after(k, path)
+        })
+    }
+
+    /// the rules that put something somewhere, and are
+    /// not entirely overridden by the rules after them
+    fn get_mappings(&self) -> Vec<Mapping> {
+        let mut mappings = vec![];
+        for (i, rule) in self.rules.iter().enumerate() {
+            let is_live = match (&rule.kind, &rule.matcher) {
+                (_, Matcher::Regex(_)) | (Kind::Exclude, _) => false,
+                (Kind::Rename(_), Matcher::Path) => self.covering_rename(&rule.prefix) == Some(i),
+                // the last rename of a path wins, even if it is a glob
+                (Kind::Rename(_), _) => self.covering_rename(&rule.prefix).map(|j| j < i).unwrap_or(true),
+                // an include does not undo a rename
+                (Kind::Include, _) => self.covering_rename(&rule.prefix).is_none(),
+            };
+            if !is_live || self.is_excluded(&rule.prefix) {
+                continue;
+            }
+            let dest = match rule.kind {
+                Kind::Rename(ref dest) => dest.clone(),
+                _ => rule.prefix.clone(),
+            };
+            mappings.push(Mapping { index: i, src: rule.prefix.clone(), dest });
+        }
+        mappings
+    }
+
+    /// rules that cannot be put in terms of the paths of the other side
+    fn find_untranslatable_rules(&self, mappings: &[Mapping]) -> Vec<String> {
+        let mut problems = vec![];
+        let has_renames = mappings.iter().any(|m| m.src != m.dest);
+        for (i, rule) in self.rules.iter().enumerate() {
+            match (&rule.kind, &rule.matcher) {
+                (Kind::Rename(_), Matcher::Regex(_)) => {
+                    problems.push(format!("The {} cannot be reversed, because a regex replacement can drop or reorder parts of the path. Use a rename of a path or a glob instead", rule.describe()));
+                    continue;
+                }
+                (_, Matcher::Regex(_)) => {
+                    if has_renames {
+                        problems.push(format!("The {} cannot be reversed, because some of the paths it matches are renamed", rule.describe()));
+                    }
+                    continue;
+                }
+                _ => (),
+            }
+            for mapping in mappings.iter().filter(|m| m.index != i && m.src != m.dest) {
+                let other = &self.rules[mapping.index];
+                let other_is_glob = other.matcher != Matcher::Path;
+                // a glob rename only renames some of the paths under its prefix, so
+                // the rules under it cannot be put in terms of where those paths are put
+                let is_under_glob = other_is_glob && rule.prefix.starts_with(&mapping.src) && rule.prefix != mapping.src;
+                // and a glob cannot be put in terms of the renames under its prefix
+                let is_glob_over_rename = rule.matcher != Matcher::Path
+                    && mapping.src.starts_with(&rule.prefix)
+                    && (mapping.src != rule.prefix || other_is_glob);
+                if is_under_glob || is_glob_over_rename {
+                    problems.push(format!("The {} cannot be reversed, because of the {}", rule.describe(), other.describe()));
+                }
+            }
+        }
+        problems
+    }
+
+    /// two rules that put different paths at the same place
+    fn find_collisions(&self, mappings: &[Mapping]) -> Vec<String> {
+        let mut problems = vec![];
+        let mut reported = vec![];
+        for a in mappings {
+            for b in mappings.iter().filter(|b| b.index != a.index && b.dest.starts_with(&a.dest)) {
+                // the paths that a puts under the destination of b come from here
+                let from = format!("{}{}", a.src, &b.dest[a.dest.len()..]);
+                let pair = (a.index.min(b.index), a.index.max(b.index));
+                if from == b.src || reported.contains(&pair) {
+                    continue;
+                }
+                let rule_a = &self.rules[a.index];
+                let rename_of_from = self.covering_rename(&from);
+                let a_puts_from_there = match (&rule_a.kind, &rule_a.matcher) {
+                    (Kind::Rename(_), Matcher::Path) => rename_of_from == Some(a.index),
+                    (Kind::Rename(_), _) => rename_of_from.map(|j| j < a.index).unwrap_or(true),
+                    _ => rename_of_from.is_none(),
+                };
+                if !a_puts_from_there || self.is_excluded(&from) {
+                    continue;
+                }
+                reported.push(pair);
+                problems.push(format!(
+                    "'{}' and '{}' are both put at '{}' by the {} and the {}, so it is not possible to know which one a path under '{}' came from",
+                    from, self.rules[b.index].path(), b.dest, rule_a.describe(), self.rules[b.index].describe(), b.dest,
+                ));
+            }
+        }
+        problems
+    }
+
+    /// the rules of the repo file that does the reverse of this one
+    fn get_reversed_rules(&self, mappings: &[Mapping]) -> Vec<PathRule> {
+        let with_matcher = |path: String, matcher: &Matcher| match matcher {
+            Matcher::Glob(rest) => format!("{}{}{}", GLOB_PATTERN_PREFIX, path, rest),
+            _ => path,
+        };
+        let mut excludes = vec![];
+        let mut keeps = vec![];
+
+        // a rename under another rule takes its paths somewhere else,
+        // so nothing is left where the other rule would have put them
+        for a in mappings.iter().filter(|a| self.rules[a.index].matcher == Matcher::Path) {
+            let a_is_rename = matches!(self.rules[a.index].kind, Kind::Rename(_));
+            for b in mappings.iter().filter(|b| b.index != a.index && b.src.starts_with(&a.src)) {
+                let rule_b = &self.rules[b.index];
+                let b_overrides_a = matches!(rule_b.kind, Kind::Rename(_)) && (!a_is_rename || b.index > a.index);
+                let left_behind = format!("{}{}", a.dest, &b.src[a.src.len()..]);
+                if b_overrides_a && left_behind != b.dest {
+                    excludes.push(PathRule::Exclude(with_matcher(left_behind, &rule_b.matcher)));
+                }
+            }
+        }
+
+        for (k, rule) in self.rules.iter().enumerate() {
+            match (&rule.kind, &rule.matcher) {
+                (Kind::Exclude, Matcher::Regex(_)) => excludes.push(PathRule::Exclude(rule.path())),
+                (Kind::Exclude, matcher) => {
+                    // an exclude does nothing if a rule after it keeps all of its paths again
+                    let is_undone = *matcher == Matcher::Path && is_dir(&rule.prefix)
+                        && self.rules[k + 1..].iter().any(|r| r.is_keep() && rule.prefix.starts_with(&r.prefix));
+                    let excludes_something = self.rules.iter().any(|r| r.is_keep() && overlaps(&rule.prefix, &r.prefix));
+                    if !is_undone && excludes_something {
+                        excludes.push(PathRule::Exclude(with_matcher(self.translate(&rule.prefix), matcher)));
+                    }
+                }
+                (Kind::Include, Matcher::Regex(_)) => keeps.push(PathRule::Include(rule.path())),
+                (Kind::Include, matcher) => {
+                    // an include that is renamed only matters if it keeps
+                    // something that an exclude before it removed
+                    let is_renamed = self.covering_rename(&rule.prefix).is_some();
+                    let is_kept_again = self.rules[..k].iter().any(|r| {
+                        r.kind == Kind::Exclude && r.matcher == Matcher::Path && rule.prefix.starts_with(&r.prefix)
+                    });
+                    if is_renamed && is_kept_again {
+                        keeps.push(PathRule::Include(with_matcher(self.translate(&rule.prefix), matcher)));
+                    }
+                }
+                (Kind::Rename(_), _) => (),
+            }
+        }
+
+        for mapping in mappings {
+            let rule = &self.rules[mapping.index];
+            let reversed = match rule.kind {
+                Kind::Include => PathRule::Include(rule.path()),
+                Kind::Rename(_) if mapping.src == mapping.dest && rule.matcher == Matcher::Path => {
+                    PathRule::Include(mapping.src.clone())
+                }
+                _ => PathRule::Rename(with_matcher(mapping.dest.clone(), &rule.matcher), mapping.src.clone()),
+            };
+            keeps.push(reversed);
+        }
+
+        // the reversed rules are sorted by the paths they apply to, so that a rule is
+        // applied after the rules of the directories above it, just like a version 1 repo file.
+        // excludes come first, so that a path that something is renamed to is kept
+        let mut reversed: Vec<PathRule> = vec![];
+        for rule in excludes.into_iter().chain(keeps) {
+            if !reversed.contains(&rule) {
+                reversed.push(rule);
+            }
+        }
+        let mut file_ops: Vec<FileOpType> = reversed.iter().map(|rule| match rule {
+            PathRule::Include(path) => FileOpType::Include(path),
+            PathRule::Exclude(path) => FileOpType::Exclude(path),
+            PathRule::Rename(src, dest) => FileOpType::IncludeAs(src, dest),
+        }).collect();
+        verify::sort_vec_of_file_ops(&mut file_ops);
+        file_ops.iter().map(|file_op| match file_op {
+            FileOpType::Include(path) => PathRule::Include(path.to_string()),
+            FileOpType::Exclude(path) => PathRule::Exclude(path.to_string()),
+            FileOpType::IncludeAs(src, dest) => PathRule::Rename(src.to_string(), dest.to_string()),
+        }).collect()
+    }
+}
+
+/// a version 2 repo file that puts every path that `repo_file` splits out
+/// back where it came from, or every reason why that is not possible.
+/// only the paths are reversed, the other sections are left out
+pub fn reverse_repo_file(repo_file: &RepoFile) -> Result<RepoFile, Vec<String>> {
+    let file_ops = verify::get_vec_of_file_ops(repo_file);
+    let reverser = Reverser::new(&file_ops);
+    let mappings = reverser.get_mappings();
+    let mut problems = reverser.problems.clone();
+    problems.extend(reverser.find_untranslatable_rules(&mappings));
+    problems.extend(reverser.find_collisions(&mappings));
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+    let mut reversed = RepoFile::new();
+    reversed.rules = Some(reverser.get_reversed_rules(&mappings));
+    Ok(reversed)
+}
+
+/// every path that does not come back to where it was after it is split
+/// out with the `forward` rules, and then split in with the `reversed` rules
+pub fn find_round_trip_problems(
+    forward: &FilterRules,
+    reversed: &FilterRules,
+    paths: &[String],
+) -> Vec<String> {
+    let mut problems = vec![];
+    for path in paths.iter().filter(|path| !path.is_empty()) {
+        let mut split_out_path = path.clone();
+        if !gitfilter::filter::should_use_file(&mut split_out_path, forward, false) {
+            continue;
+        }
+        let mut split_in_path = split_out_path.clone();
+        if !gitfilter::filter::should_use_file(&mut split_in_path, reversed, false) {
+            problems.push(format!("'{}' is split out to '{}', which the reversed repo file does not keep", path, split_out_path));
+        } else if &split_in_path != path {
+            problems.push(format!("'{}' is split out to '{}', which the reversed repo file puts at '{}'", path, split_out_path, split_in_path));
+        }
+    }
+    problems
+}
+
+pub fn run_reverse(cmd: &mut MgtCommandReverse) {
+    let repo_file_path = if cmd.repo_file.is_empty() {
+        die!("Must provide repo path argument");
+    } else {
+        cmd.repo_file[0].clone()
+    };
+    let repo_file = repo_file::parse_repo_file_from_toml_path(&repo_file_path);
+    let reversed = reverse_repo_file(&repo_file).unwrap_or_else(|problems| {
+        die!("{} cannot be reversed:\n- {}", repo_file_path, problems.join("\n- "));
+    });
+
+    // the static checks cannot know what a glob matches, so also
+    // make sure that every file that exists now comes back where it was
+    let all_files = verify::get_files_to_verify(cmd.stdin);
+    let make_filter_rules = |repo_file: &RepoFile| {
+        verify::make_filter_rules(&mut verify::get_vec_of_file_ops(repo_file)).unwrap_or_else(|e| {
+            die!("Failed to make filter rules from {}:\n{}", repo_file_path, e);
+        })
+    };
+    let problems = find_round_trip_problems(&make_filter_rules(&repo_file), &make_filter_rules(&reversed), &all_files);
+    if !problems.is_empty() {
+        die!("{} cannot be reversed:\n- {}", repo_file_path, problems.join("\n- "));
+    }
+
+    let not_reversed = [
+        (repo_file.mailmap.is_some() || repo_file.mailmap_file.is_some(), "mailmap", "the identities that it rewrites"),
+        (repo_file.message_replace.is_some(), "[message] replace", "the messages that it rewrites"),
+        (repo_file.commits.is_some(), "[commits]", "the commits that it drops or squashes"),
+    ];
+    for (_, section, what) in not_reversed.iter().filter(|(is_used, _, _)| *is_used) {
+        eprintln!("Note: the {} of {} is not reversed, because {} cannot be brought back", section, repo_file_path, what);
+    }
+    print!("{}", repo_file::generate_repo_file_toml(&reversed));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn include(path: &str) -> PathRule { PathRule::Include(path.into()) }
+    fn exclude(path: &str) -> PathRule { PathRule::Exclude(path.into()) }
+    fn rename(src: &str, dest: &str) -> PathRule { PathRule::Rename(src.into(), dest.into()) }
+
+    fn repo_file_with_rules(rules: Vec<PathRule>) -> RepoFile {
+        let mut repo_file = RepoFile::new();
+        repo_file.rules = Some(rules);
+        repo_file
+    }
+
+    /// the reversed rules, after making sure that every path
+    /// comes back to where it was
+    fn reverse_and_round_trip(repo_file: &RepoFile, paths: &[&str]) -> Vec<PathRule> {
+        let reversed = reverse_repo_file(repo_file).unwrap();
+        let forward = verify::make_filter_rules(&mut verify::get_vec_of_file_ops(repo_file)).unwrap();
+        let backward = verify::make_filter_rules(&mut verify::get_vec_of_file_ops(&reversed)).unwrap();
+        let paths: Vec<String> = paths.iter().map(|p| p.to_string()).collect();
+        assert_eq!(find_round_trip_problems(&forward, &backward, &paths), Vec::<String>::new());
+        reversed.rules.unwrap()
+    }
+
+    fn reverse_problems(rules: Vec<PathRule>) -> Vec<String> {
+        reverse_repo_file(&repo_file_with_rules(rules)).unwrap_err()
+    }
+
+    #[test]
+    fn excludes_and_includes_are_reversed_with_the_renames() {
+        let repo_file = repo_file_with_rules(vec![
+            rename("lib/", "src/"),
+            exclude("lib/secret/"),
+            include("lib/secret/public.txt"),
+            include("README.md"),
+        ]);
+        let reversed = reverse_and_round_trip(&repo_file, &[
+            "lib/a.txt", "lib/secret/b.txt", "lib/secret/public.txt", "README.md", "other.txt",
+        ]);
+        assert_eq!(reversed, vec![
+            include("README.md"),
+            rename("src/", "lib/"),
+            exclude("src/secret/"),
+            include("src/secret/public.txt"),
+        ]);
+
+        // a version 1 repo file puts a directory at the root with " "
+        let mut repo_file = RepoFile::new();
+        repo_file.include_as = Some(vec!["lib/".into(), " ".into()]);
+        repo_file.exclude = Some(vec!["lib/secret/".into()]);
+        let reversed = reverse_and_round_trip(&repo_file, &["lib/a.txt", "lib/secret/b.txt", "c.txt"]);
+        assert_eq!(reversed, vec![rename("", "lib/"), exclude("secret/")]);
+    }
+
+    #[test]
+    fn paths_that_are_renamed_away_are_not_kept_where_they_were() {
+        let repo_file = repo_file_with_rules(vec![
+            include("lib/"),
+            rename("lib/docs/", "documentation/"),
+        ]);
+        let reversed = reverse_and_round_trip(&repo_file, &["lib/a.txt", "lib/docs/b.md"]);
+        assert_eq!(reversed, vec![
+            rename("documentation/", "lib/docs/"),
+            include("lib/"),
+            exclude("lib/docs/"),
+        ]);
+
+        let repo_file = repo_file_with_rules(vec![
+            include("lib/"),
+            rename("glob:lib/**/*.md", "docs"),
+        ]);
+        let reversed = reverse_and_round_trip(&repo_file, &["lib/a.md", "lib/b/c.md", "lib/x.rs"]);
+        assert_eq!(reversed, vec![
+            include("lib/"),
+            exclude("glob:lib/**/*.md"),
+            rename("glob:docs/**/*.md", "lib/"),
+        ]);
+    }
+
+    #[test]
+    fn rules_that_cannot_be_reversed_are_explained() {
+        let problems = reverse_problems(vec![rename("a/", "x/"), rename("b/", "x/")]);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("'a/' and 'b/' are both put at 'x/'"));
+
+        let problems = reverse_problems(vec![rename("lib/", ""), include("README.md")]);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("'lib/README.md' and 'README.md' are both put at 'README.md'"));
+        // but not if there is nothing to collide with
+        assert!(reverse_repo_file(&repo_file_with_rules(vec![
+            rename("lib/", ""), exclude("lib/README.md"), include("README.md"),
+        ])).is_ok());
+
+        let problems = reverse_problems(vec![rename("regex:lib/(.*)/src/", "$1/")]);
+        assert!(problems[0].contains("a regex replacement can drop or reorder parts of the path"));
+
+        let problems = reverse_problems(vec![rename("lib/", "src/"), exclude("regex:.*\\.bak$")]);
+        assert!(problems[0].contains("some of the paths it matches are renamed"));
+    }
+}
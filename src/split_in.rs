@@ -1,4 +1,5 @@
 use std::convert::From;
+use std::io;
 
 use super::split_out;
 use super::git_helpers3;
@@ -11,6 +12,9 @@ use super::core;
 use super::verify;
 use super::topbase;
 
+/// when splitting in from a remote repo, its tags are fetched
+/// into this namespace so that they dont conflict with our own tags
+pub const REMOTE_TAGS_NAMESPACE: &str = "refs/mgt-split-in-tags/";
 
 pub fn run_split_in(cmd: &mut MgtCommandSplit) {
     let repo_file_path = if cmd.repo_file.len() < 1 {
@@ -55,6 +59,10 @@ pub fn run_split_in_from_repo_file(
     core::go_to_repo_root();
     core::safe_to_proceed();
     let current_ref = core::get_current_ref();
+    let filter_rules = match generate_gitfilter_filterrules(&repo_file, cmd.verbose) {
+        Ok(rules) => rules,
+        Err(e) => die!("Failed to make filter rules from repo file:\n{}", e),
+    };
 
     let orphan_branch_name = match cmd.output_branch {
         Some(ref s) => s,
@@ -67,10 +75,8 @@ pub fn run_split_in_from_repo_file(
         cmd.verbose,
     );
 
-    let remote_branch: Option<&str> = match &repo_file.remote_branch {
-        Some(branch_name) => Some(branch_name.as_str()),
-        None => None,
-    };
+    let primary_remote = repo_file::get_primary_remote(&repo_file);
+    let remote_branch = primary_remote.as_ref().and_then(|remote| remote.branch.as_deref());
     // if user provided a remote_branch name
     // on the command line, let that override what
     // is present in the repo file:
@@ -87,13 +93,37 @@ pub fn run_split_in_from_repo_file(
         cmd.dry_run
     );
 
+    // if we split in from a local branch, its tags are already
+    // our tags, otherwise we have to fetch them from the remote
+    let should_fetch_tags = repo_file.tags.is_some() && cmd.input_branch.is_none();
+    let tag_source = if should_fetch_tags { REMOTE_TAGS_NAMESPACE } else { "refs/tags/" };
+    let tag_options = match repo_file::get_tag_options(&repo_file, tag_source, true) {
+        Ok(options) => options,
+        Err(e) => die!("Failed to make tag options from repo file:\n{}", e),
+    };
+    if should_fetch_tags {
+        fetch_remote_tags(&repo_file, cmd.num_commits, cmd.dry_run, cmd.verbose);
+    }
+
     let log_p = if cmd.dry_run { "   # " } else { "" };
     if let Some(ref b) = cmd.output_branch {
         println!("{}Running filter commands on temporary branch: {}", log_p, b);
     }
-    
-    let filter_rules = generate_gitfilter_filterrules(&repo_file, cmd.verbose);
-    core::perform_gitfilter(filter_rules, orphan_branch_name.clone(), cmd.dry_run, cmd.verbose);
+
+    // identities and messages are only rewritten when splitting
+    // out, since the originals cannot be recovered
+    let submodules = match repo_file::get_submodule_action(&repo_file) {
+        Ok(action) => action,
+        Err(e) => die!("Failed to get the submodule action from repo file:\n{}", e),
+    };
+    let extras = core::FilterExtras { tag_options, submodules, ..Default::default() };
+    core::perform_gitfilter(filter_rules, orphan_branch_name.clone(), extras,
+        cmd.dry_run, cmd.verbose);
+    if should_fetch_tags && !cmd.dry_run {
+        if let Err(e) = git_helpers3::delete_refs(REMOTE_TAGS_NAMESPACE) {
+            eprintln!("Failed to delete fetched tags in {}: {}", REMOTE_TAGS_NAMESPACE, e);
+        }
+    }
     let res = if cmd.topbase.is_some() {
         println!("{}Topbasing", log_p);
         let should_add_branch_label = false;
@@ -134,10 +164,35 @@ pub fn run_split_in_from_repo_file(
 pub fn generate_gitfilter_filterrules(
     repo_file: &RepoFile,
     _verbose: bool,
-) -> gitfilter::filter::FilterRules {
+) -> io::Result<gitfilter::filter::FilterRules> {
     let mut file_ops = verify::get_vec_of_file_ops_with_order(&repo_file, false);
-    let filter_rules = verify::make_filter_rules(&mut file_ops);
-    filter_rules
+    let mut filter_rules = verify::make_filter_rules(&mut file_ops)?;
+    for rule in repo_file::get_commit_rules(repo_file)? {
+        filter_rules.push(gitfilter::filter::FilterRule::FilterRuleCommit(rule));
+    }
+    Ok(filter_rules)
+}
+
+fn fetch_remote_tags(
+    repo_file: &RepoFile,
+    num_commits: Option<u32>,
+    dry_run: bool,
+    verbose: bool,
+) {
+    let remote_repo = match repo_file::get_primary_remote(repo_file) {
+        Some(remote) => remote.url,
+        None => die!("Must provide a remote_repo in your repofile to fetch its tags"),
+    };
+    if dry_run {
+        println!("git fetch {} +refs/tags/*:{}*", remote_repo, REMOTE_TAGS_NAMESPACE);
+        return;
+    }
+    if let Err(e) = git_helpers3::fetch_tags(&remote_repo, REMOTE_TAGS_NAMESPACE, num_commits) {
+        die!("Failed to fetch tags of {}: {}", remote_repo, e);
+    }
+    if verbose {
+        println!("fetched tags of {} into {}", remote_repo, REMOTE_TAGS_NAMESPACE);
+    }
 }
 
 pub fn generate_repo_file(
@@ -175,21 +230,21 @@ fn validate_repo_file(
     let missing_output_branch = cmd.output_branch.is_none();
     let missing_input_branch = cmd.input_branch.is_none();
     let missing_repo_name = repo_file.repo_name.is_none();
-    let missing_remote_repo = repo_file.remote_repo.is_none();
-    let missing_include_as = repo_file.include_as.is_none();
-    let missing_include = repo_file.include.is_none();
+    let primary_remote = repo_file::get_primary_remote(repo_file);
+    let missing_remote_repo = primary_remote.is_none();
+    let missing_include = !repo_file::has_includes(repo_file);
 
     if missing_remote_repo && missing_input_branch && ! missing_output_branch {
         die!("Must provide either repo_name in your repofile, or specify a --input-branch argument");
     }
 
-    if missing_include && missing_include_as {
-        die!("Must provide either include or include_as in your repofile");
+    if missing_include {
+        die!("Must provide either include or include_as in your repofile, or an include or rename [[rule]]");
     }
 
     if missing_repo_name && !missing_remote_repo && missing_output_branch {
         let output_branch_str = core::try_get_repo_name_from_remote_repo(
-            repo_file.remote_repo.clone().unwrap()
+            primary_remote.unwrap().url
         );
         repo_file.repo_name = Some(output_branch_str.clone());
         cmd.output_branch = Some(output_branch_str);
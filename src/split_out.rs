@@ -4,7 +4,9 @@ use super::repo_file;
 use super::die;
 use super::verify;
 use super::cli::MgtCommandSplit;
+use super::git_helpers3;
 use std::io;
+use gitfilter::submodules::SubmoduleAction;
 use crate::{ioerre, ioerr};
 
 pub fn run_split_out(
@@ -12,6 +14,8 @@ pub fn run_split_out(
 ) {
     let repo_file_path = if cmd.repo_file.len() < 1 {
         die!("Must provide repo path argument");
+    } else if cmd.repo_file.len() > 1 {
+        return run_split_out_many(cmd);
     } else {
         cmd.repo_file[0].clone()
     };
@@ -20,6 +24,97 @@ pub fn run_split_out(
     run_split_out_from_repo_file(cmd, repo_file)
 }
 
+/// splits out every repo file into its own output branch
+/// while only reading the history of the current branch once.
+/// every output branch is named after its repo file
+pub fn run_split_out_many(
+    cmd: &mut MgtCommandSplit,
+) {
+    if cmd.output_branch.is_some() {
+        die!("Cannot use --output-branch when splitting out several repo files. Each output branch is named after its repo file");
+    }
+    if cmd.rebase.is_some() || cmd.topbase.is_some() || cmd.incremental {
+        die!("Cannot use --rebase, --topbase, or --incremental when splitting out several repo files");
+    }
+    let mut repo_files = vec![];
+    for repo_file_path in cmd.repo_file.iter() {
+        let mut repo_file = repo_file::parse_repo_file_from_toml_path(repo_file_path);
+        let mut output_branch = None;
+        if let Err(e) = validate_repo_file_res(&mut repo_file, &mut output_branch) {
+            die!("Invalid repo file {}:\n{}", repo_file_path, e);
+        }
+        let output_branch = match output_branch {
+            Some(o) => o,
+            None => die!("Failed to find output branch of {}", repo_file_path),
+        };
+        repo_files.push((repo_file_path.clone(), repo_file, output_branch));
+    }
+    core::verify_dependencies();
+    core::go_to_repo_root();
+    core::safe_to_proceed();
+
+    let mut targets = vec![];
+    let mut pushes = vec![];
+    for (repo_file_path, repo_file, output_branch) in repo_files {
+        if targets.iter().any(|t: &core::FilterTarget| t.output_branch == output_branch) {
+            die!("{} has the same output branch as another repo file: {}", repo_file_path, output_branch);
+        }
+        if git_helpers3::branch_exists(&output_branch) {
+            die!("Output branch {} of {} already exists", output_branch, repo_file_path);
+        }
+        match make_filter_target(&repo_file, output_branch.clone()) {
+            Ok(target) => targets.push(target),
+            Err(e) => die!("Failed to make filters from repo file {}:\n{}", repo_file_path, e),
+        }
+        pushes.push((repo_file, output_branch));
+    }
+
+    if targets.iter().any(|t| t.extras.submodules == SubmoduleAction::Merge) {
+        core::fetch_submodule_histories(cmd.dry_run);
+    }
+    let input_branch = core::get_current_ref().unwrap_or_else(|| {
+        die!("Failed to get current branch name. Cannot continue")
+    });
+    let log_p = if cmd.dry_run { "   # " } else { "" };
+    let output_branches: Vec<&str> = targets.iter().map(|t| t.output_branch.as_str()).collect();
+    println!("{}Splitting out {} into branches: {}", log_p, input_branch, output_branches.join(", "));
+    if let Err(e) = core::perform_gitfilter_multiplexed_res(
+        input_branch, targets, cmd.dry_run, cmd.verbose
+    ) {
+        die!("{}", e);
+    }
+
+    if ! cmd.no_push {
+        let mut had_error = false;
+        for (repo_file, output_branch) in pushes {
+            if let Err(e) = push_to_remotes(&repo_file, &output_branch, cmd.dry_run) {
+                eprintln!("{}", e);
+                had_error = true;
+            }
+        }
+        if had_error {
+            std::process::exit(1);
+        }
+    }
+}
+
+/// everything from the repo file that is needed to filter it
+pub fn make_filter_target(
+    repo_file: &RepoFile,
+    output_branch: String,
+) -> io::Result<core::FilterTarget> {
+    let filter_rules = generate_gitfilter_filterrules(repo_file, false)?;
+    let tag_options = repo_file::get_tag_options(repo_file, "refs/tags/", false)?;
+    let mailmap = repo_file::get_mailmap(repo_file)?;
+    let message_transforms = repo_file::get_message_transforms(repo_file)?;
+    let other_refs = repo_file::get_split_out_refs(repo_file, &output_branch);
+    let submodules = repo_file::get_submodule_action(repo_file)?;
+    let extras = core::FilterExtras {
+        tag_options, mailmap, message_transforms, other_refs, incremental: None, submodules,
+    };
+    Ok(core::FilterTarget { filter_rules, output_branch, extras })
+}
+
 pub fn run_split_out_as(
     cmd: &mut MgtCommandSplit
 ) {
@@ -48,12 +143,42 @@ pub fn run_split_out_from_repo_file(
     validate_repo_file(&mut repo_file, &mut cmd.output_branch);
     core::go_to_repo_root();
     core::safe_to_proceed();
-    let filter_rules = generate_gitfilter_filterrules(&repo_file, cmd.verbose);
-    core::make_and_checkout_output_branch(
-        &cmd.output_branch,
-        cmd.dry_run,
-        cmd.verbose,
-    );
+    let filter_rules = match generate_gitfilter_filterrules(&repo_file, cmd.verbose) {
+        Ok(rules) => rules,
+        Err(e) => die!("Failed to make filter rules from repo file:\n{}", e),
+    };
+    let tag_options = match repo_file::get_tag_options(&repo_file, "refs/tags/", false) {
+        Ok(options) => options,
+        Err(e) => die!("Failed to make tag options from repo file:\n{}", e),
+    };
+    let mailmap = match repo_file::get_mailmap(&repo_file) {
+        Ok(mailmap) => mailmap,
+        Err(e) => die!("Failed to load mailmap from repo file:\n{}", e),
+    };
+    let message_transforms = match repo_file::get_message_transforms(&repo_file) {
+        Ok(transforms) => transforms,
+        Err(e) => die!("Failed to make message transforms from repo file:\n{}", e),
+    };
+    let submodules = match repo_file::get_submodule_action(&repo_file) {
+        Ok(action) => action,
+        Err(e) => die!("Failed to get the submodule action from repo file:\n{}", e),
+    };
+    if submodules == SubmoduleAction::Merge {
+        core::fetch_submodule_histories(cmd.dry_run);
+    }
+    if cmd.incremental {
+        core::reset_and_checkout_output_branch(
+            &cmd.output_branch,
+            cmd.dry_run,
+            cmd.verbose,
+        );
+    } else {
+        core::make_and_checkout_output_branch(
+            &cmd.output_branch,
+            cmd.dry_run,
+            cmd.verbose,
+        );
+    }
 
     let log_p = if cmd.dry_run { "   # " } else { "" };
     if let Some(ref b) = cmd.output_branch {
@@ -64,7 +189,23 @@ pub fn run_split_out_from_repo_file(
         Some(o) => o.clone(),
         None => die!("Failed to find output branch"),
     };
-    core::perform_gitfilter(filter_rules, output_branch, cmd.dry_run, cmd.verbose);
+    let other_refs = repo_file::get_split_out_refs(&repo_file, &output_branch);
+    if let Some((ref refs, ref namespace)) = other_refs {
+        println!("{}Also splitting out refs {} into {}", log_p, refs.join(", "), namespace);
+    }
+    let incremental = if cmd.incremental {
+        match core::get_incremental_state_dir(&output_branch) {
+            Ok(dir) => Some(dir),
+            Err(e) => die!("{}", e),
+        }
+    } else {
+        None
+    };
+    let extras = core::FilterExtras {
+        tag_options, mailmap, message_transforms, other_refs, incremental, submodules,
+    };
+    core::perform_gitfilter(filter_rules, output_branch, extras,
+        cmd.dry_run, cmd.verbose);
 
     // for split out, rebase is a bit different because
     // we actually need to fetch the remote repo|branch that
@@ -80,10 +221,8 @@ pub fn run_split_out_from_repo_file(
         let tmp_remote_branch = "mgt-remote-branch-tmp";
         core::make_and_checkout_orphan_branch(tmp_remote_branch, cmd.dry_run, cmd.verbose);
 
-        let remote_branch: Option<&str> = match &repo_file.remote_branch {
-            Some(branch_name) => Some(branch_name.as_str()),
-            None => None,
-        };
+        let primary_remote = repo_file::get_primary_remote(&repo_file);
+        let remote_branch = primary_remote.as_ref().and_then(|remote| remote.branch.as_deref());
         // if user provided a remote_branch name
         // on the command line, let that override what
         // is present in the repo file:
@@ -135,6 +274,40 @@ pub fn run_split_out_from_repo_file(
             die!("{}", e);
         }
     }
+
+    if ! cmd.no_push {
+        let output_branch = cmd.output_branch.clone().unwrap_or_default();
+        if let Err(e) = push_to_remotes(&repo_file, &output_branch, cmd.dry_run) {
+            die!("{}", e);
+        }
+    }
+}
+
+/// pushes the output branch to every remote in the `[remotes]` of the
+/// repo file that can be pushed to. the `[repo]` remote is not pushed to.
+/// every remote is tried even if pushing to one of them fails
+pub fn push_to_remotes(
+    repo_file: &RepoFile,
+    output_branch: &str,
+    dry_run: bool,
+) -> io::Result<()> {
+    let mut failed = vec![];
+    for remote in repo_file.remotes.iter().flatten().filter(|remote| remote.push) {
+        let remote_branch = remote.branch.as_deref().unwrap_or(output_branch);
+        if dry_run {
+            println!("git push {} {}:{}", remote.url, output_branch, remote_branch);
+            continue;
+        }
+        println!("Pushing {} to {} ({}:{})", output_branch, remote.name, remote.url, remote_branch);
+        if let Err(e) = git_helpers3::push_branch(&remote.url, output_branch, remote_branch) {
+            eprintln!("Failed to push to {}:\n{}", remote.name, e);
+            failed.push(remote.name.as_str());
+        }
+    }
+    if !failed.is_empty() {
+        return ioerre!("Failed to push {} to: {}", output_branch, failed.join(", "));
+    }
+    Ok(())
 }
 
 pub fn validate_repo_file_res(
@@ -143,21 +316,21 @@ pub fn validate_repo_file_res(
 ) -> io::Result<()> {
     let missing_output_branch = output_branch.is_none();
     let missing_repo_name = repo_file.repo_name.is_none();
-    let missing_remote_repo = repo_file.remote_repo.is_none();
-    let missing_include_as = repo_file.include_as.is_none();
-    let missing_include = repo_file.include.is_none();
+    let primary_remote = repo_file::get_primary_remote(repo_file);
+    let missing_remote_repo = primary_remote.is_none();
+    let missing_include = !repo_file::has_includes(repo_file);
 
     if missing_remote_repo && missing_repo_name && missing_output_branch {
         return ioerre!("Must provide either repo_name or remote_repo in your repofile");
     }
 
-    if missing_include && missing_include_as {
-        return ioerre!("Must provide either include or include_as in your repofile");
+    if missing_include {
+        return ioerre!("Must provide either include or include_as in your repofile, or an include or rename [[rule]]");
     }
 
     if missing_output_branch && missing_repo_name && !missing_remote_repo {
         let output_branch_str = core::try_get_repo_name_from_remote_repo(
-            repo_file.remote_repo.clone().unwrap()
+            primary_remote.unwrap().url
         );
         repo_file.repo_name = Some(output_branch_str.clone());
         *output_branch = Some(output_branch_str);
@@ -185,10 +358,13 @@ pub fn validate_repo_file(
 pub fn generate_gitfilter_filterrules(
     repo_file: &RepoFile,
     _verbose: bool,
-) -> gitfilter::filter::FilterRules {
+) -> io::Result<gitfilter::filter::FilterRules> {
     let mut file_ops = verify::get_vec_of_file_ops(&repo_file);
-    let filter_rules = verify::make_filter_rules(&mut file_ops);
-    filter_rules
+    let mut filter_rules = verify::make_filter_rules(&mut file_ops)?;
+    for rule in repo_file::get_commit_rules(repo_file)? {
+        filter_rules.push(gitfilter::filter::FilterRule::FilterRuleCommit(rule));
+    }
+    Ok(filter_rules)
 }
 
 pub fn get_remote_branch_from_args(
@@ -3,15 +3,21 @@ use super::cli::MgtCommandSync;
 use super::core;
 use super::die;
 use super::git_helpers3;
+use super::commit_map;
 use super::interact;
 use super::repo_file;
-use std::{io, path::PathBuf};
-use crate::{ioerr, topbase, check::blob_path_applies_to_repo_file, split_out::generate_gitfilter_filterrules, ioerre, split_in};
+use super::workspace::{self, SyncDefaults};
+use std::{io, path::PathBuf, collections::HashSet};
+use crate::{ioerr, topbase, check::{blob_path_applies_to_repo_file, RepoFileFilterRules}, split_out::generate_gitfilter_filterrules, ioerre, split_in};
 use git_helpers3::{RawBlobSummary, CommitWithBlobs, Commit};
 use topbase::SuccessfulTopbaseResult;
-use repo_file::RepoFile;
-use std::{fmt::Display, time::{Duration, SystemTime}, process::Stdio};
+use repo_file::{RepoFile, Remote};
+use std::{fmt::Display, time::{Duration, SystemTime}, process::Stdio, str::FromStr};
 use gitfilter::filter::FilterRule;
+use gitfilter::mailmap::Mailmap;
+use gitfilter::message_transforms::MessageTransforms;
+use crate::output::{self, OutputFormat};
+use serde_json::{json, Value};
 
 /// What kind of sync are we doing? There are 5 possible
 /// sync types I can think of:
@@ -21,6 +27,7 @@ use gitfilter::filter::FilterRule;
 /// is a common fork point according to a topbase alg.
 /// Disjoint means neither branch has any common fork point between them, so
 /// probably cannot sync that easily?
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SyncType {
     LocalAhead, // local is ahead of remote's most recent commit
     RemoteAhead, // remote is ahead of local's most recent commit
@@ -29,6 +36,164 @@ pub enum SyncType {
     Disjoint, // failed to find a fork point
 }
 
+impl SyncType {
+    /// the exit code used in --non-interactive mode
+    /// when a repo file is left in this state. They are
+    /// ordered by how much attention they need, so when syncing
+    /// multiple repo files we can just exit with the largest one.
+    /// 1 is reserved for errors.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SyncType::UpToDate => 0,
+            SyncType::LocalAhead => 10,
+            SyncType::RemoteAhead => 11,
+            SyncType::Diverged => 12,
+            SyncType::Disjoint => 13,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SyncType::UpToDate => "up_to_date",
+            SyncType::LocalAhead => "local_ahead",
+            SyncType::RemoteAhead => "remote_ahead",
+            SyncType::Diverged => "diverged",
+            SyncType::Disjoint => "disjoint",
+        }
+    }
+
+    pub fn from_ahead(local_ahead: bool, remote_ahead: bool) -> SyncType {
+        match (local_ahead, remote_ahead) {
+            (false, false) => SyncType::UpToDate,
+            (false, true) => SyncType::RemoteAhead,
+            (true, false) => SyncType::LocalAhead,
+            (true, true) => SyncType::Diverged,
+        }
+    }
+}
+
+/// What to do in --non-interactive mode for a given SyncType.
+/// Not every policy is valid for every SyncType, see `validate_policies`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncPolicy {
+    Pull,
+    Push,
+    Rebase, // pull --rebase, then push
+    Skip,
+    Fail,
+}
+
+impl FromStr for SyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ok = match s {
+            "pull" => SyncPolicy::Pull,
+            "push" => SyncPolicy::Push,
+            "rebase" => SyncPolicy::Rebase,
+            "skip" => SyncPolicy::Skip,
+            "fail" => SyncPolicy::Fail,
+            _ => return Err(format!("{} is not a valid sync policy", s)),
+        };
+        Ok(ok)
+    }
+}
+
+impl Default for SyncPolicy {
+    fn default() -> Self {
+        SyncPolicy::Skip
+    }
+}
+
+/// Whether to merge pulled changes in --non-interactive mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MergePolicy {
+    Always,
+    Never,
+}
+
+impl FromStr for MergePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ok = match s {
+            "always" => MergePolicy::Always,
+            "never" => MergePolicy::Never,
+            _ => return Err(format!("{} is not a valid merge policy", s)),
+        };
+        Ok(ok)
+    }
+}
+
+impl Default for MergePolicy {
+    fn default() -> Self {
+        MergePolicy::Never
+    }
+}
+
+/// returns the policy the user chose for this sync type.
+/// UpToDate, and Disjoint have nothing that can be done, so they are always skipped
+pub fn get_sync_policy(cmd: &MgtCommandSync, sync_type: SyncType) -> SyncPolicy {
+    let policy = match sync_type {
+        SyncType::LocalAhead => cmd.on_local_ahead,
+        SyncType::RemoteAhead => cmd.on_remote_ahead,
+        SyncType::Diverged => cmd.on_diverged,
+        SyncType::UpToDate |
+        SyncType::Disjoint => None,
+    };
+    policy.unwrap_or_default()
+}
+
+pub fn validate_policies(cmd: &MgtCommandSync) -> Result<(), String> {
+    let policies = [
+        ("--on-local-ahead", cmd.on_local_ahead, SyncPolicy::Push),
+        ("--on-remote-ahead", cmd.on_remote_ahead, SyncPolicy::Pull),
+        ("--on-diverged", cmd.on_diverged, SyncPolicy::Rebase),
+    ];
+    for (name, policy, valid_action) in policies.iter() {
+        match policy {
+            Some(SyncPolicy::Skip) | Some(SyncPolicy::Fail) | None => (),
+            Some(p) => if p != valid_action {
+                return Err(format!("{:?} is not a valid policy for {}", p, name));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// a copy of the cmd where the policies that were not
+/// given on the command line are the policies of the subrepo
+pub fn apply_sync_defaults(cmd: &MgtCommandSync, defaults: SyncDefaults) -> MgtCommandSync {
+    let mut subrepo_cmd = cmd.clone();
+    subrepo_cmd.on_remote_ahead = cmd.on_remote_ahead.or(defaults.on_remote_ahead);
+    subrepo_cmd.on_local_ahead = cmd.on_local_ahead.or(defaults.on_local_ahead);
+    subrepo_cmd.on_diverged = cmd.on_diverged.or(defaults.on_diverged);
+    subrepo_cmd.merge = cmd.merge.or(defaults.merge);
+    subrepo_cmd
+}
+
+/// the repo files to sync, and the cmd to sync each of them with.
+/// with --group or --all-subrepos they are the subrepos of the workspace file,
+/// otherwise they are the repo files that were given on the command line
+pub fn get_repo_files_to_sync(cmd: &MgtCommandSync) -> io::Result<Vec<(PathBuf, MgtCommandSync)>> {
+    if cmd.group.is_none() && !cmd.all_subrepos {
+        validate_policies(cmd).map_err(|e| ioerr!("{}", e))?;
+        let all_repo_files = get_all_repo_files_ex(&cmd.repo_files);
+        return Ok(all_repo_files.into_iter().map(|path| (path, cmd.clone())).collect());
+    }
+    if !cmd.repo_files.is_empty() {
+        return ioerre!("Cannot use --group or --all-subrepos with a repo file path");
+    }
+    let workspace = workspace::get_current_workspace()?;
+    let mut out = vec![];
+    for subrepo in workspace.get_subrepos(cmd.group.as_deref())? {
+        let subrepo_cmd = apply_sync_defaults(cmd, subrepo.sync);
+        validate_policies(&subrepo_cmd).map_err(|e| ioerr!("Invalid sync policy of {}: {}", subrepo.name, e))?;
+        out.push((subrepo.repo_file.clone(), subrepo_cmd));
+    }
+    Ok(out)
+}
+
 pub fn get_all_repo_files_ex(list: &Vec<PathBuf>) -> Vec<PathBuf> {
     let mut out_vec = vec![];
     for path in list {
@@ -186,12 +351,20 @@ pub fn try_perform_gitfilter(
     branch: String,
     starting_branch_name: &str,
     filter_rules: Vec<FilterRule>,
+    mailmap: Option<Mailmap>,
+    message_transforms: Option<MessageTransforms>,
 ) -> io::Result<String> {
     let is_verbose = false;
     let is_dry_run = false;
+    let extras = core::FilterExtras {
+        mailmap,
+        message_transforms,
+        ..Default::default()
+    };
     let filtered = core::perform_gitfilter_res(
         filter_rules,
         branch.clone(),
+        extras,
         is_dry_run,
         is_verbose,
     );
@@ -229,6 +402,47 @@ pub fn try_rebase_onto(
         return ioerre!("Failed to rebase top {} commits of {} onto {} because\n{}\nLeaving you with a git interactive rebase in progress. Go back with 'git rebase --abort', or otherwise rebase manually and then finish with 'git rebase --continue'", top_num_commits, top_name, onto_fork_point, err);
     }
 
+    try_record_rebased_commits(onto_fork_point, top_name, interactive_rebase_str);
+    Ok(())
+}
+
+/// the rebase gave new ids to the commits we picked, so
+/// we record them in the commit map. this is not fatal if it
+/// fails, the next sync can always fall back to comparing blobs
+pub fn try_record_rebased_commits(
+    onto: &str,
+    top_name: &str,
+    interactive_rebase_str: &str,
+) {
+    if let Err(e) = commit_map::record_rebased_commits(onto, top_name, interactive_rebase_str) {
+        eprintln!("Warning: failed to record the commit map after rebasing: {}", e);
+    }
+}
+
+/// like `try_rebase_onto`, but if the rebase fails, we abort it
+/// and go back to the starting branch. This is used when we
+/// operate on more than one temporary branch, in which case
+/// leaving the user in the middle of a rebase would leave the
+/// rest of the operation in an unknown state.
+pub fn try_rebase_onto_or_rollback(
+    onto: &str,
+    top_name: &str,
+    top_num_commits: usize,
+    interactive_rebase_str: &str,
+    starting_branch_name: &str,
+) -> io::Result<()> {
+    let rebase_res = git_helpers3::rebase_interactively_with_commits(
+        onto, top_name, top_num_commits, interactive_rebase_str);
+
+    if let Err(err) = rebase_res {
+        let mut err_msg = format!("Failed to rebase top {} commits of {} onto {} because\n{}", top_num_commits, top_name, onto, err);
+        if let Err(e) = git_helpers3::rebase_abort() {
+            err_msg = format!("{}\nALSO: failed to abort the rebase because:\n{}", err_msg, e);
+        }
+        return Err(try_back_to_start_and_delete_branch(starting_branch_name, top_name, err_msg));
+    }
+
+    try_record_rebased_commits(onto, top_name, interactive_rebase_str);
     Ok(())
 }
 
@@ -237,6 +451,10 @@ pub fn try_get_output_branch_name(
     random_branch: &str,
     starting_branch_name: &str,
 ) -> io::Result<String> {
+    if cmd.non_interactive {
+        // same as if the user hit Enter
+        return Ok(random_branch.to_string());
+    }
     let message = "Enter the desired branch name to be created on the remote repo (hit Enter to use an auto-generated branch name)";
     let mut interact_choice = interact::InteractChoices::choose_word(&message);
     interact_choice.max_loop = cmd.max_interactive_attempts;
@@ -332,7 +550,24 @@ pub fn get_new_commits_after_filter(
     // this is probably a reasonable assumption, but if theres ever
     // a weird bug of 'why isnt this commit being included?'
     // look here...
-    Ok(commits)
+    // the exception is when the repo file has commit rules
+    // that drop or squash some of those commits. then the top N
+    // commits reach past the ones we wanted, so we use the
+    // commit map to only take the ones that came from our commits
+    let commit_map = match commit_map::load_commit_map() {
+        Ok(m) => m,
+        Err(_) => return Ok(commits),
+    };
+    let wanted: HashSet<&str> = commits_before_filter.iter()
+        .map(|c| c.commit.id.hash.as_str()).collect();
+    let mapped: Vec<Commit> = commits.iter().filter(|c| {
+        wanted.contains(c.id.hash.as_str()) ||
+            commit_map.counterparts(&c.id.hash).iter().any(|id| wanted.contains(id))
+    }).cloned().collect();
+    if mapped.is_empty() {
+        return Ok(commits);
+    }
+    Ok(mapped)
 }
 
 pub fn try_get_new_commits_after_filter(
@@ -382,6 +617,9 @@ pub fn try_get_merge_choice(
     branch_name: &str,
     starting_branch_name: &str,
 ) -> io::Result<bool> {
+    if cmd.non_interactive {
+        return Ok(cmd.merge.unwrap_or_default() == MergePolicy::Always);
+    }
     let ff_merge_str = format!("Merge {} into {} by fast-forwarding", starting_branch_name, branch_name);
     let rename_option = format!("Leave the {} branch as is, and manually merge after review", branch_name);
     let merge_options = [
@@ -421,7 +659,29 @@ pub fn try_fast_forward_merge(
     Ok(())
 }
 
+/// the user did not want to merge, so we keep the branch
+/// for them to review. In --non-interactive mode there is nobody to review it
+/// right now, and we might have more repo files to sync, so we go back to
+/// the starting branch instead of leaving them on it
+pub fn leave_branch_for_review(
+    cmd: &MgtCommandSync,
+    branch_name: &str,
+    starting_branch_name: &str,
+) -> io::Result<()> {
+    if ! cmd.non_interactive {
+        println!("- Leaving you on {} to review and manually merge", branch_name);
+        return Ok(());
+    }
+
+    if let Err(e) = git_helpers3::checkout_branch(starting_branch_name, false) {
+        return ioerre!("failed to checkout back to {} because:\n{}\nThis is probably a bug; please report this.", starting_branch_name, e);
+    }
+    println!("- Leaving {} to review and manually merge", branch_name);
+    Ok(())
+}
+
 // AKA: pull remote changes into local
+/// returns true if the pulled changes were merged into the starting branch
 pub fn try_sync_in(
     cmd: &MgtCommandSync,
     repo_file: &RepoFile,
@@ -429,10 +689,10 @@ pub fn try_sync_in(
     fork_point_local: &str,
     // num_commits_to_pull: usize,
     commits_to_pull: &Vec<CommitWithBlobs>,
-) -> io::Result<()> {
+) -> io::Result<bool> {
     // eprintln!("Try sync in commits to pull: {:#?}", commits_to_pull);
     let is_verbose = false;
-    let filter_rules = split_in::generate_gitfilter_filterrules(&repo_file, is_verbose);
+    let filter_rules = split_in::generate_gitfilter_filterrules(&repo_file, is_verbose)?;
     let random_number = match repo_file.remote_repo {
         Some(ref s) => s.len(),
         None => 12353143, // very secure, got it from some .gov website
@@ -445,7 +705,7 @@ pub fn try_sync_in(
 
     println!("- Filtering branch according to repo file");
     let random_branch = try_perform_gitfilter(
-        random_branch, starting_branch_name, filter_rules)?;
+        random_branch, starting_branch_name, filter_rules, None, None)?;
 
     let new_commits_to_pull = try_get_new_commits_after_filter(&random_branch, &commits_to_pull, starting_branch_name)?;
     // eprintln!("New commits to pull: {:#?}", new_commits_to_pull);
@@ -473,15 +733,15 @@ pub fn try_sync_in(
         // if that succeeded, then we can delete the temporary branch
         println!("- Successfully merged. Deleting temporary branch");
         git_helpers3::delete_branch(&random_branch).map_err(|e| ioerr!("{}", e))?;
-        return Ok(());
+        return Ok(true);
     }
     // otherwise, if user did not want to merge,
     // we do not delete the branch because obviously the user
     // wants to review it.
     // so I guess we are done here.
-    println!("- Leaving you on {} to review and manually merge", random_branch);
+    leave_branch_for_review(cmd, &random_branch, starting_branch_name)?;
 
-    Ok(())
+    Ok(false)
 }
 
 /// AKA: push local changes to remote
@@ -499,7 +759,9 @@ pub fn try_sync_out(
     commits_to_push: &Vec<CommitWithBlobs>,
 ) -> io::Result<()> {
     let is_verbose = false;
-    let filter_rules = generate_gitfilter_filterrules(&repo_file, is_verbose);
+    let filter_rules = generate_gitfilter_filterrules(&repo_file, is_verbose)?;
+    let mailmap = repo_file::get_mailmap(repo_file)?;
+    let message_transforms = repo_file::get_message_transforms(repo_file)?;
     let random_number = match repo_file.remote_repo {
         Some(ref s) => s.len(),
         None => 12353143, // very secure, got it from some .gov website
@@ -515,7 +777,7 @@ pub fn try_sync_out(
 
     println!("- Filtering branch according to repo file");
     let random_branch = try_perform_gitfilter(
-        random_branch, starting_branch_name, filter_rules)?;
+        random_branch, starting_branch_name, filter_rules, mailmap, message_transforms)?;
 
     let new_commits_to_push = try_get_new_commits_after_filter(&random_branch, &commits_to_push, starting_branch_name)?;
     let (num_commits_to_push, rebase_interactive_string) = get_rebase_interactive_string_and_number(
@@ -540,33 +802,162 @@ pub fn try_sync_out(
     Ok(())
 }
 
-// TODO: does this need to be a seperate function?
-// wouldnt a sync in do a topbase anyway?
-// err, i guess the result would be in
-// sync_in_then_out that we rebase
-// OUR LOCAL CHANGES on top of whatever
-// remote has, and then we push our newly
-// rebased local branch out, whereas
-// try_sync_in would only take
-// the latest remote commits
-// and put them ON TOP OF whatever we have locally
+/// AKA: pull --rebase, then push.
+/// Used when local and remote have diverged. First the commits
+/// we pulled from the remote are filtered into our local paths,
+/// and put on top of our starting branch, the same way that
+/// `try_sync_in` does. Then we rebase OUR LOCAL CHANGES on
+/// top of whatever the remote has, and push them out.
+/// The sync-in goes first so that the commit map already knows
+/// which of our commits came from the remote before we push. After a
+/// successful push, the new tip of the remote and the new tip of our
+/// starting branch have the same changes, so we record them as
+/// counterparts, and the next sync finds that they are up to date.
+/// returns true if the pulled changes were merged into the starting branch
 pub fn try_sync_in_then_out(
+    cmd: &MgtCommandSync,
+    repo_file: &RepoFile,
+    repo_remote_url: &str,
+    starting_branch_name: &str,
+    commits_to_push: &Vec<CommitWithBlobs>,
+    commits_to_pull: &Vec<CommitWithBlobs>,
+) -> io::Result<bool> {
+    let is_verbose = false;
+    // make both sets of rules before we touch any branches so that
+    // an invalid repo file does not leave us halfway done
+    let filter_rules_out = generate_gitfilter_filterrules(repo_file, is_verbose)?;
+    let filter_rules_in = split_in::generate_gitfilter_filterrules(repo_file, is_verbose)?;
+    let mailmap = repo_file::get_mailmap(repo_file)?;
+    let message_transforms = repo_file::get_message_transforms(repo_file)?;
+    let random_number = match repo_file.remote_repo {
+        Some(ref s) => s.len(),
+        None => 12353143, // very secure, got it from some .gov website
+    };
+    let random_branch = make_random_branch_name(random_number);
+    let out_branch = format!("{}-out", random_branch);
+    let in_branch = format!("{}-in", random_branch);
+
+    // the starting branch can move when we merge the pulled commits,
+    // so we remember where it was to split our local changes
+    // out from, and to tell the user how to undo the merge
+    let starting_commit = git_helpers3::get_commit_id(starting_branch_name)
+        .map_err(|e| ioerr!("Failed to find the commit of {} because\n{}", starting_branch_name, e))?;
+    let remote_commit = git_helpers3::get_commit_id("FETCH_HEAD")
+        .map_err(|e| ioerr!("Failed to find the commit of FETCH_HEAD because\n{}", e))?;
+
+    // first we bring the commits we pulled into our local branch.
+    // Our starting branch is ahead of the fork point, so we rebase
+    // onto the tip of our starting branch instead of onto the
+    // fork point so that we can fast-forward afterwards.
+    println!("- Making temporary branch");
+    try_making_branch_from(&in_branch, &remote_commit, starting_branch_name)?;
 
-) -> io::Result<()> {
-    println!("Not implemented yet, skipping...");
-    Ok(())
+    println!("- Filtering branch according to repo file");
+    let in_branch = try_perform_gitfilter(
+        in_branch, starting_branch_name, filter_rules_in, None, None)?;
+
+    let new_commits_to_pull = try_get_new_commits_after_filter(&in_branch, commits_to_pull, starting_branch_name)?;
+    let (num_commits_to_pull, rebase_interactive_string) = get_rebase_interactive_string_and_number(
+        &new_commits_to_pull);
+
+    println!("- Rebasing onto {}", starting_branch_name);
+    try_rebase_onto_or_rollback(starting_branch_name, &in_branch,
+        num_commits_to_pull, &rebase_interactive_string, starting_branch_name)?;
+    println!("- Successfully rebased temporary branch");
+
+    let user_wants_to_merge = try_get_merge_choice(cmd, &in_branch, starting_branch_name)?;
+    println!("- Checking out back to {}", starting_branch_name);
+    if let Err(e) = git_helpers3::checkout_branch(starting_branch_name, false) {
+        return ioerre!("failed to checkout back to {} because:\n{}\nThis is probably a bug; please report this.", starting_branch_name, e);
+    }
+    if user_wants_to_merge {
+        println!("- Fast-forward merging {}", starting_branch_name);
+        if let Err(e) = try_fast_forward_merge(&in_branch, starting_branch_name) {
+            return Err(try_back_to_start_and_delete_branch(starting_branch_name, &in_branch, e));
+        }
+        println!("- Successfully merged. Deleting temporary branch");
+        git_helpers3::delete_branch(&in_branch).map_err(|e| ioerr!("{}", e))?;
+    }
+    // if the push fails, the pulled commits stay where they are,
+    // so we tell the user what is left over and how to undo it
+    let left_behind = if user_wants_to_merge {
+        format!("The commits that were pulled were already merged into {}. To undo that, run:\ngit reset --keep {}", starting_branch_name, starting_commit)
+    } else {
+        format!("The commits that were pulled were left on {}. To undo that, run:\ngit branch -D {}", in_branch, in_branch)
+    };
+
+    // now we split out our local changes as they were before
+    // the merge, and rebase them on top of the remote's most
+    // recent commit. we are not rebasing onto the fork point here because
+    // the remote has commits ahead of the fork point that we
+    // want to be underneath ours.
+    println!("- Making temporary branch");
+    try_making_branch_from(&out_branch, &starting_commit, starting_branch_name)
+        .map_err(|e| ioerr!("{}\n{}", e, left_behind))?;
+
+    println!("- Filtering branch according to repo file");
+    let out_branch = try_perform_gitfilter(
+        out_branch, starting_branch_name, filter_rules_out, mailmap, message_transforms)
+        .map_err(|e| ioerr!("{}\n{}", e, left_behind))?;
+
+    let new_commits_to_push = try_get_new_commits_after_filter(&out_branch, commits_to_push, starting_branch_name)
+        .map_err(|e| ioerr!("{}\n{}", e, left_behind))?;
+    let (num_commits_to_push, rebase_interactive_string) = get_rebase_interactive_string_and_number(
+        &new_commits_to_push);
+
+    println!("- Rebasing on top of remote changes");
+    try_rebase_onto_or_rollback(&remote_commit, &out_branch,
+        num_commits_to_push, &rebase_interactive_string, starting_branch_name)
+        .map_err(|e| ioerr!("{}\n{}", e, left_behind))?;
+
+    let push_branch_name = try_get_output_branch_name(cmd, &out_branch, starting_branch_name)
+        .map_err(|e| ioerr!("{}\n{}", e, left_behind))?;
+    let pushed_commit = git_helpers3::get_commit_id(&out_branch);
+    println!("- git push {} {}:{}", repo_remote_url, out_branch, push_branch_name);
+    try_push_out(repo_remote_url, &out_branch, &push_branch_name, starting_branch_name)
+        .map_err(|e| ioerr!("{}\n{}", e, left_behind))?;
+
+    println!("- Successfully git pushed. Changing back to original branch: {}", starting_branch_name);
+    if let Err(e) = git_helpers3::checkout_branch(starting_branch_name, false) {
+        return ioerre!("failed to checkout back to {} because:\n{}\nThis is probably a bug; please report this.", starting_branch_name, e);
+    }
+    println!("- Deleting temporary branch");
+    if let Err(e) = git_helpers3::delete_branch(&out_branch) {
+        return ioerre!("failed to delete branch {} because:\n{}\nThis is probably a bug; please report this.", &out_branch, e);
+    }
+
+    if ! user_wants_to_merge {
+        if ! cmd.non_interactive {
+            if let Err(e) = git_helpers3::checkout_branch(&in_branch, false) {
+                return ioerre!("failed to checkout {} because:\n{}\nThis is probably a bug; please report this.", in_branch, e);
+            }
+        }
+        leave_branch_for_review(cmd, &in_branch, starting_branch_name)?;
+        return Ok(false);
+    }
+
+    // like the rebased commits, this is not fatal if it fails
+    let merged_commit = git_helpers3::get_commit_id(starting_branch_name);
+    let recorded = match (pushed_commit, merged_commit) {
+        (Ok(pushed), Ok(merged)) => commit_map::record_pairs(&[(pushed, merged)]),
+        (Err(e), _) | (_, Err(e)) => Err(ioerr!("{}", e)),
+    };
+    if let Err(e) = recorded {
+        eprintln!("Warning: failed to record the commit map after pushing: {}", e);
+    }
+    Ok(true)
 }
 
 pub fn handle_sync2(
     cmd: &MgtCommandSync,
-    remote_url: &str,
+    remote: &Remote,
     repo_file_path: &PathBuf,
     repo_file: &RepoFile,
     sync_type: SyncType,
     topbase_success: SuccessfulTopbaseResult<CommitWithBlobs>,
     starting_branch_name: &str,
     can_push_pull: bool,
-) -> io::Result<()> {
+) -> io::Result<SyncType> {
     let only_summary = ! can_push_pull;
     let (left_ahead, right_ahead) = match sync_type {
         SyncType::LocalAhead |
@@ -576,10 +967,10 @@ pub fn handle_sync2(
         }
         SyncType::UpToDate => {
             println!("Up to date. Nothing to do.");
-            return Ok(());
+            return Ok(sync_type);
         }
         // this is handled in handle_sync
-        SyncType::Disjoint => return Ok(()),
+        SyncType::Disjoint => return Ok(sync_type),
     };
 
     let mut choices = vec![];
@@ -587,6 +978,18 @@ pub fn handle_sync2(
     choices.push("skip");
     let mut can_push = ! left_ahead.is_empty();
     let mut can_pull = ! right_ahead.is_empty();
+    // the remote can say that it should never be pushed to, or pulled from
+    let mut not_allowed = false;
+    if can_push && ! remote.push {
+        println!("\nNot pushing to {} because it has push = false", remote.name);
+        can_push = false;
+        not_allowed = true;
+    }
+    if can_pull && ! remote.pull {
+        println!("\nNot pulling from {} because it has pull = false", remote.name);
+        can_pull = false;
+        not_allowed = true;
+    }
     if can_push {
         let mut has_non_merge = false;
         let mut out_str = "\nYou can push:".to_string();
@@ -638,8 +1041,11 @@ pub fn handle_sync2(
     // they have an unclean index. in this case,
     // we dont present any interaction choices. we just
     // show the output above, and continue
+    // merge commits are not shown to the user, so this can
+    // be different from the sync_type we were given
+    let sync_type = SyncType::from_ahead(can_push, can_pull);
     if only_summary {
-        return Ok(());
+        return Ok(sync_type);
     }
 
     // we had commits to pull/push, but when we iterated over them
@@ -648,80 +1054,174 @@ pub fn handle_sync2(
     // they can use these. So treat this as
     // the same case as UpToDate:
     if !can_pull && !can_push {
-        println!("Up to date. Nothing to do.");
-        return Ok(());
+        if not_allowed {
+            println!("Nothing to do.");
+        } else {
+            println!("Up to date. Nothing to do.");
+        }
+        return Ok(sync_type);
     }
 
-    // the nicest order is actually the reverse because
-    // we want exit and skip to be at the bottom:
-    choices.reverse();
-
-    println!();
-    let mut i_choices: interact::InteractChoices = (&choices[..]).into();
-    i_choices.max_loop = cmd.max_interactive_attempts;
-    let selection = interact::interact_number(i_choices)?;
-    let selection_index = selection - 1;
-    let selection = choices[selection_index];
+    let selection = if cmd.non_interactive {
+        match get_sync_policy(cmd, sync_type) {
+            SyncPolicy::Pull => "pull",
+            SyncPolicy::Push => "push",
+            SyncPolicy::Rebase => "pull --rebase, then push",
+            // failing is handled by the caller since
+            // it knows if there are more repo files to sync
+            SyncPolicy::Skip | SyncPolicy::Fail => "skip",
+        }
+    } else {
+        // the nicest order is actually the reverse because
+        // we want exit and skip to be at the bottom:
+        choices.reverse();
+
+        println!();
+        let mut i_choices: interact::InteractChoices = (&choices[..]).into();
+        i_choices.max_loop = cmd.max_interactive_attempts;
+        let selection = interact::interact_number(i_choices)?;
+        let selection_index = selection - 1;
+        choices[selection_index]
+    };
 
     match selection {
-        "skip" => return Ok(()),
+        "skip" => Ok(sync_type),
         "exit" => std::process::exit(0),
         "pull" => {
             let local_fork = &topbase_success.fork_point.0.commit.id.hash;
             let take_commits = &topbase_success.top_right_commits;
-            try_sync_in(cmd, &repo_file, starting_branch_name,
-                local_fork, take_commits)
+            let merged = try_sync_in(cmd, &repo_file, starting_branch_name,
+                local_fork, take_commits)?;
+            Ok(if merged { SyncType::UpToDate } else { SyncType::RemoteAhead })
         },
         "push" => {
             let remote_fork = &topbase_success.fork_point.1.commit.id.hash;
             let take_commits = &topbase_success.top_commits;
-            try_sync_out(cmd, &repo_file, remote_url,
-                starting_branch_name, remote_fork, take_commits)
+            try_sync_out(cmd, &repo_file, &remote.url,
+                starting_branch_name, remote_fork, take_commits)?;
+            Ok(SyncType::UpToDate)
         }
 
         // this is pull --rebase then push:
-        _ => try_sync_in_then_out(),
+        _ => {
+            let push_commits = &topbase_success.top_commits;
+            let pull_commits = &topbase_success.top_right_commits;
+            // after pushing, the remote has everything, so if we
+            // did not merge then we are only missing what we pulled
+            let merged = try_sync_in_then_out(cmd, repo_file, &remote.url,
+                starting_branch_name, push_commits, pull_commits)?;
+            Ok(if merged { SyncType::UpToDate } else { SyncType::RemoteAhead })
+        }
     }
 }
 
 pub fn handle_sync(
     cmd: &MgtCommandSync,
-    remote_url: &str,
+    remote: &Remote,
     repo_file_path: &PathBuf,
     repo_file: &RepoFile,
     sync_type: SyncType,
     topbase_opt: Option<SuccessfulTopbaseResult<CommitWithBlobs>>,
     starting_branch_name: &str,
     can_push_pull: bool,
-) -> io::Result<()> {
+) -> io::Result<SyncType> {
     match topbase_opt {
         None => {
             // TODO: come up with something better than just saying this
             println!("Branches are disjoint. cannot sync");
-            Ok(())
+            Ok(SyncType::Disjoint)
         },
-        Some(s) => handle_sync2(cmd, remote_url,
+        Some(s) => handle_sync2(cmd, remote,
             repo_file_path, repo_file,
             sync_type, s,
             starting_branch_name, can_push_pull),
     }
 }
 
+fn get_remotes_to_sync(repo_file_path: &PathBuf, repo_file: &RepoFile) -> io::Result<Vec<Remote>> {
+    let remotes = repo_file::get_remotes(repo_file);
+    if remotes.is_empty() {
+        return ioerre!("Failed to find a remote repo in the repo file: {:?}", repo_file_path);
+    }
+    Ok(remotes)
+}
+
+/// returns the state the repo file was left in. ie: if the remote
+/// was ahead, and we pulled and merged, then it is now UpToDate.
+/// every remote of the repo file is synced one after the other, and the
+/// state is of whichever remote is the furthest from being up to date
 pub fn sync_repo_file(
     starting_branch_name: &str,
     repo_file_path: &PathBuf,
     cmd: &MgtCommandSync,
     can_push_pull: bool,
-) -> io::Result<()> {
+) -> io::Result<SyncType> {
+    let repo_file = repo_file::parse_repo_file_from_toml_path_res(
+        repo_file_path)?;
+    let mut out_sync_type = SyncType::UpToDate;
+    for remote in get_remotes_to_sync(repo_file_path, &repo_file)? {
+        let (_, sync_type, topbase_ok) = fetch_and_find_sync_type(
+            &repo_file, &remote, cmd)?;
+        let sync_type = handle_sync(cmd, &remote, repo_file_path,
+            &repo_file, sync_type, topbase_ok,
+            starting_branch_name, can_push_pull)?;
+        if sync_type.exit_code() > out_sync_type.exit_code() {
+            out_sync_type = sync_type;
+        }
+    }
+    Ok(out_sync_type)
+}
+
+/// like `sync_repo_file` with --summary-only, but instead of
+/// printing the summary, return a json object for every remote.
+pub fn summarize_repo_file_json(
+    repo_file_path: &PathBuf,
+    cmd: &MgtCommandSync,
+) -> io::Result<Vec<(SyncType, Value)>> {
     let repo_file = repo_file::parse_repo_file_from_toml_path_res(
         repo_file_path)?;
+    let mut summaries = vec![];
+    for remote in get_remotes_to_sync(repo_file_path, &repo_file)? {
+        let (repo_branch, sync_type, topbase_ok) = fetch_and_find_sync_type(
+            &repo_file, &remote, cmd)?;
+        // merge commits are not shown to the user, so they don't count
+        // towards being ahead, and neither do commits that the remote
+        // does not allow us to push or pull. see `handle_sync2`
+        let sync_type = match topbase_ok {
+            Some(ref res) => SyncType::from_ahead(
+                remote.push && res.top_commits.iter().any(|c| ! c.commit.is_merge),
+                remote.pull && res.top_right_commits.iter().any(|c| ! c.commit.is_merge),
+            ),
+            None => sync_type,
+        };
+        let mut out = json!({
+            "repo_file": repo_file_path,
+            "remote": remote.name,
+            "remote_repo": remote.url,
+            "remote_branch": repo_branch,
+            "sync_type": sync_type.name(),
+        });
+        output::extend_json_object(&mut out, output::topbase_result_to_json(topbase_ok.as_ref()));
+        summaries.push((sync_type, out));
+    }
+    Ok(summaries)
+}
+
+/// fetches one of the remotes of this repo file, and then finds
+/// the fork point between our local branch and what we fetched.
+/// returns the remote branch that was fetched, and
+/// what kind of sync we can do.
+pub fn fetch_and_find_sync_type(
+    repo_file: &RepoFile,
+    remote: &Remote,
+    cmd: &MgtCommandSync,
+) -> io::Result<(String, SyncType, Option<SuccessfulTopbaseResult<CommitWithBlobs>>)> {
+    let is_json = cmd.format.unwrap_or_default() == OutputFormat::Json;
     let default_branch = "HEAD".to_string();
-    let repo_url = repo_file.remote_repo.as_ref()
-        .ok_or(ioerr!("Failed to find a remote repo in the repo file: {:?}", repo_file_path))?;
-    let repo_branch = repo_file.remote_branch.as_ref().unwrap_or(&default_branch);
+    let repo_url = &remote.url;
+    let repo_branch = remote.branch.as_ref().unwrap_or(&default_branch);
 
-    // TODO: add a --no-interact mode which would override --ask-branches
-    let repo_branch = if cmd.ask_branches {
+    let repo_branch = if cmd.ask_branches && ! cmd.non_interactive {
         let mut desired_branch_choice = interact::InteractChoices::choose_word(
             &format!("What remote branch would you like to fetch? (hit Enter to use {})", repo_branch));
         let description = format!("About to fetch {}", repo_url);
@@ -737,10 +1237,25 @@ pub fn sync_repo_file(
         }
     } else { repo_branch.to_string() };
 
-    let divider = "=".repeat(15);
-    println!("\n{} Fetching {}:{} {}", divider, repo_url, repo_branch, divider);
+    if ! is_json {
+        let divider = "=".repeat(15);
+        if repo_file.remotes.is_some() {
+            println!("\n{} Fetching {} ({}:{}) {}", divider, remote.name, repo_url, repo_branch, divider);
+        } else {
+            println!("\n{} Fetching {}:{} {}", divider, repo_url, repo_branch, divider);
+        }
+    }
     git_helpers3::fetch_branch(repo_url, &repo_branch).map_err(|e| ioerr!("{}", e))?;
 
+    // commits that we split out with an origin trailer say exactly
+    // which of our commits they came from, so we add that to the
+    // commit map before looking for the fork point
+    if let Some(ref trailer) = repo_file.origin_trailer {
+        if let Err(e) = commit_map::record_origin_trailers("FETCH_HEAD", trailer) {
+            eprintln!("Warning: failed to record {} trailers in the commit map: {}", trailer, e);
+        }
+    }
+
     // TODO: support sync from a different branch other than the one
     // we are currently on?
     let local_branch = "HEAD";
@@ -755,9 +1270,10 @@ pub fn sync_repo_file(
     // we don't know which one is ahead, so we want to rewind the B branch
     // and see where the differences are from the most recent fork point
     let should_rewind = true;
+    let repo_file_rules = RepoFileFilterRules::new(repo_file)?;
     let should_use_blob_cb = |c: &mut RawBlobSummary, b: &str| {
         let this_is_a_remote_blob = b == remote_branch;
-        blob_path_applies_to_repo_file(&c.path_dest, &repo_file, this_is_a_remote_blob)
+        blob_path_applies_to_repo_file(&c.path_dest, &repo_file_rules, this_is_a_remote_blob)
     };
     let topbase_ok = topbase::find_a_b_difference2::<CommitWithBlobs, _>(
         local_branch, remote_branch, Some(traverse_at_a_time),
@@ -788,18 +1304,11 @@ pub fn sync_repo_file(
             // that could be an issue if that is ever possible.
             let local_empty = o.top_commits.is_empty();
             let remote_empty = o.top_right_commits.is_empty();
-            let sync_type = match (local_empty, remote_empty) {
-                (true, true) => SyncType::UpToDate,
-                (true, false) => SyncType::RemoteAhead,
-                (false, true) => SyncType::LocalAhead,
-                (false, false) => SyncType::Diverged,
-            };
+            let sync_type = SyncType::from_ahead(! local_empty, ! remote_empty);
             (sync_type, Some(o))
         }
     };
-    handle_sync(cmd, repo_url, repo_file_path,
-        &repo_file, sync_type, topbase_ok,
-        starting_branch_name, can_push_pull)
+    Ok((repo_branch, sync_type, topbase_ok))
 }
 
 pub fn canonicalize_all_repo_file_paths(paths: &Vec<PathBuf>) -> Vec<PathBuf> {
@@ -850,25 +1359,86 @@ pub fn ask_user_how_to_proceed() -> (bool, bool) {
 }
 
 /// returns (should_stash_pop, can_pull_push)
-pub fn how_to_proceed() -> (bool, bool) {
+pub fn how_to_proceed(non_interactive: bool) -> (bool, bool) {
     match core::safe_to_proceed_res() {
         Err(e) => die!("Failed to determine state of your index:\n{}\nThis is probably a bug, please report this.", e),
         Ok(is_safe) => match is_safe {
             // safe to proceed, so no need to stash, and user
             // is free to pull/push/etc.
             true => (false, true),
-            false => ask_user_how_to_proceed(),
+            false => if non_interactive {
+                die!("You have staged and/or modified changes. mgt cannot safely sync with --non-interactive unless the index is clean. Stash or commit your changes, or use --summary-only");
+            } else {
+                ask_user_how_to_proceed()
+            }
         }
     }
 }
 
+pub fn try_stash_pop(should_stash_pop: bool) {
+    if should_stash_pop {
+        if let Err(e) = git_helpers3::stash(true) {
+            die!("Failed to perform git stash pop:\n{}\nThis might be because the sync operation resulted in your files being modified. Check if you have a conflict and resolve it. Otherwise if this error occurred, but you do not have a conflict, then this is an unexpected error and you should report it.", e);
+        }
+    }
+}
+
+/// --summary-only --format json. The only thing printed to stdout
+/// is the final json list. If a repo file fails, its error is in that
+/// list instead of the sync type.
+pub fn run_sync_summary_json(cmd: &MgtCommandSync, mut all_repo_files: Vec<(PathBuf, MgtCommandSync)>) {
+    let mut had_error = false;
+    let mut exit_code = SyncType::UpToDate.exit_code();
+    let mut json_out = vec![];
+    // every repo file is compared to the same local branch,
+    // so its history is only walked once
+    git_helpers3::with_shared_blob_log(|| for (repo_file, repo_file_cmd) in all_repo_files.drain(..) {
+        match summarize_repo_file_json(&repo_file, &repo_file_cmd) {
+            Err(e) => {
+                had_error = true;
+                json_out.push(json!({
+                    "repo_file": repo_file,
+                    "error": e.to_string(),
+                }));
+                if cmd.fail_fast {
+                    break;
+                }
+            }
+            Ok(summaries) => {
+                for (sync_type, out) in summaries {
+                    exit_code = exit_code.max(sync_type.exit_code());
+                    json_out.push(out);
+                }
+            }
+        }
+    });
+
+    output::print_json(&Value::Array(json_out));
+    if had_error && (cmd.fail_fast || cmd.non_interactive) {
+        std::process::exit(1);
+    }
+    if cmd.non_interactive {
+        std::process::exit(exit_code);
+    }
+}
+
 pub fn run_sync(cmd: &mut MgtCommandSync) {
     // before we go to the repo root, we want to canonicalize
     // all of the paths the user provided, otherwise they wont work anymore
     // from a new directory
     cmd.repo_files = canonicalize_all_repo_file_paths(&cmd.repo_files);
+    let mut all_repo_files = get_repo_files_to_sync(cmd).unwrap_or_else(|e| {
+        die!("{}", e);
+    });
+    let is_json = cmd.format.unwrap_or_default() == OutputFormat::Json;
+    if is_json && ! cmd.summary_only {
+        die!("--format json can only be used with --summary-only");
+    }
     core::verify_dependencies();
     core::go_to_repo_root();
+    if is_json {
+        return run_sync_summary_json(cmd, all_repo_files);
+    }
 
     // if --summary-only is passed, then we do not need to
     // stash pop at the end, but the user cannot perform pull/push operations
@@ -877,7 +1447,7 @@ pub fn run_sync(cmd: &mut MgtCommandSync) {
     } else {
         // otherwise figure out from the index if its safe
         // to proceed, or otherwise ask the user what they want to do
-        how_to_proceed()
+        how_to_proceed(cmd.non_interactive)
     };
     if should_stash_pop {
         if let Err(e) = git_helpers3::stash(false) {
@@ -888,28 +1458,44 @@ pub fn run_sync(cmd: &mut MgtCommandSync) {
     let starting_branch_name = core::get_current_ref().unwrap_or_else(|| {
         die!("Failed to get current branch name. Cannot continue")
     });
-    let mut all_repo_files = get_all_repo_files_ex(&cmd.repo_files);
-    println!("Found {:#?} repo files to sync", all_repo_files);
+    println!("Found {:#?} repo files to sync", all_repo_files.iter().map(|(path, _)| path).collect::<Vec<_>>());
     println!("Found {} repo files to sync", all_repo_files.len());
 
-    for (_index, repo_file) in all_repo_files.drain(..).enumerate() {
+    // only used for --non-interactive
+    let mut had_error = false;
+    let mut exit_code = SyncType::UpToDate.exit_code();
+    // every repo file is compared to the local branch, so the parts
+    // of its history that did not change are only walked once
+    git_helpers3::with_shared_blob_log(|| for (_index, (repo_file, repo_file_cmd)) in all_repo_files.drain(..).enumerate() {
+        let cmd = &repo_file_cmd;
         let potential_err = format!("Error trying to sync {:?} :", repo_file);
-        if let Err(e) = sync_repo_file(&starting_branch_name, &repo_file, cmd, can_pull_push) {
-            eprintln!("{}\n{}", potential_err, e);
-            if cmd.fail_fast {
-                if should_stash_pop {
-                    if let Err(e) = git_helpers3::stash(true) {
-                        die!("Failed to perform git stash pop:\n{}\nThis might be because the sync operation resulted in your files being modified. Check if you have a conflict and resolve it. Otherwise if this error occurred, but you do not have a conflict, then this is an unexpected error and you should report it.", e);
-                    }
+        match sync_repo_file(&starting_branch_name, &repo_file, cmd, can_pull_push) {
+            Err(e) => {
+                eprintln!("{}\n{}", potential_err, e);
+                if cmd.fail_fast {
+                    try_stash_pop(should_stash_pop);
+                    std::process::exit(1);
                 }
-                std::process::exit(1);
+                had_error = true;
+            }
+            Ok(sync_type) => {
+                if cmd.non_interactive && ! can_pull_push {
+                    // --summary-only just reports what it found
+                } else if cmd.non_interactive && get_sync_policy(cmd, sync_type) == SyncPolicy::Fail {
+                    eprintln!("Stopping because {:?} is {:?}", repo_file, sync_type);
+                    try_stash_pop(should_stash_pop);
+                    std::process::exit(sync_type.exit_code());
+                }
+                exit_code = exit_code.max(sync_type.exit_code());
             }
         }
-    }
+    });
 
-    if should_stash_pop {
-        if let Err(e) = git_helpers3::stash(true) {
-            die!("Failed to perform git stash pop:\n{}\nThis might be because the sync operation resulted in your files being modified. Check if you have a conflict and resolve it. Otherwise if this error occurred, but you do not have a conflict, then this is an unexpected error and you should report it.", e);
+    try_stash_pop(should_stash_pop);
+    if cmd.non_interactive {
+        if had_error {
+            std::process::exit(1);
         }
+        std::process::exit(exit_code);
     }
 }
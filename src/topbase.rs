@@ -1,8 +1,9 @@
-use std::{io, collections::HashSet, process::Stdio, str::FromStr, hash::Hash, fmt::Debug};
+use std::{io, collections::{HashSet, HashMap}, process::Stdio, str::FromStr, hash::Hash, fmt::Debug};
 use io::{BufReader, BufRead};
 
 use super::ioerr;
 use super::git_helpers3;
+use super::commit_map;
 use super::git_helpers3::Commit;
 use super::git_helpers3::CommitWithBlobs;
 use super::git_helpers3::{RawBlobSummaryWithoutPath, RawBlobSummary};
@@ -273,10 +274,6 @@ fn get_current_branch() -> String {
     }
 }
 
-// TODO:
-// add fullbase traversal mode implementation
-
-
 /// In a Topbase traversal mode, the A branch is considered the 'top', and
 /// the B branch is the 'bottom'. In this traversal mode, we load the entire B
 /// branch and then traverse the A branch, and we stop as soon as we find a commit
@@ -345,10 +342,13 @@ fn get_current_branch() -> String {
 ///   A2*      B5*
 ///   |         |
 ///   A3        B6
-/// # in topbase, both branches commits/blobs are fully loaded into memory
+/// # in fullbase, both branches commits/blobs are fully loaded into memory
 /// # first, and then we traverse A, and then traverse B. there is nothing smart
-/// # about it. It is both slow, and uses a lot of memory, but it is 100% correct.
-/// # we find the exact scenario. In this case, we would report to the user:
+/// # about it. It is both slow, and uses a lot of memory, but it does not stop
+/// # at the first fork point. it finds the most fork points that can be,
+/// # in the same order in both branches. the fork points are still found by
+/// # comparing blobs, so commits that happen to change the same blobs can
+/// # be reported as a fork point. In this case, we would report to the user:
 /// #  A1     B4
 /// #   \     /
 /// #    \  /
@@ -551,6 +551,13 @@ impl<'a, T: Debug + Default + From<RawBlobSummary> + Eq + Hash> BranchIterativeC
         None
     }
 
+    /// the most recent commit we loaded that is one of these commit ids
+    pub fn find_first_of(&self, commit_ids: &HashSet<&str>) -> Option<CommitWithBlobs> {
+        self.groups.iter().flatten()
+            .find(|(our_commit, _)| commit_ids.contains(our_commit.commit.id.hash.as_str()))
+            .map(|(our_commit, _)| our_commit.clone())
+    }
+
     pub fn get_all_above<C: From<CommitWithBlobs>>(
         &mut self,
         commit_id: &str
@@ -585,6 +592,13 @@ impl From<CommitWithBlobs> for Commit {
     }
 }
 
+impl AsRef<Commit> for CommitWithBlobs {
+    fn as_ref(&self) -> &Commit { &self.commit }
+}
+impl AsRef<Commit> for Commit {
+    fn as_ref(&self) -> &Commit { self }
+}
+
 pub fn get_rewind_commits_from_loader<
     T: Debug + Default + From<RawBlobSummary> + Eq + Hash,
     C: From<CommitWithBlobs>
@@ -604,8 +618,24 @@ pub fn get_rewind_commits_from_loader<
     }
 }
 
+/// the most recent commit that the loader has loaded that
+/// corresponds to `commit_id` according to the commit map
+fn find_counterpart_in_loader<T: Debug + Default + From<RawBlobSummary> + Eq + Hash>(
+    commit_map: Option<&commit_map::CommitMap>,
+    commit_id: &str,
+    loader: &BranchIterativeCommitLoader<T>,
+) -> Option<CommitWithBlobs> {
+    let map = commit_map?;
+    if ! map.contains(commit_id) {
+        return None;
+    }
+    loader.find_first_of(&map.counterparts(commit_id))
+}
+
 /// this is called by `find_a_b_difference2` if you
 /// passed a Some(n) for the traverse n at a time.
+/// a commit that the commit map says corresponds to a commit of the
+/// other branch is a fork point, even if their blobs are different
 pub fn find_a_b_difference2_iterative_traversal<
     T: Debug + Default + From<RawBlobSummary> + Eq + Hash,
     C: From<CommitWithBlobs>,
@@ -614,6 +644,7 @@ pub fn find_a_b_difference2_iterative_traversal<
     a_committish: &str, b_committish: &str,
     traverse_n: usize,
     should_rewind: bool,
+    commit_map: Option<&commit_map::CommitMap>,
     should_use_blob_cb: B,
 ) -> io::Result<Option<SuccessfulTopbaseResult<C>>> {
     let mut should_use_blob_cb = should_use_blob_cb;
@@ -627,7 +658,9 @@ pub fn find_a_b_difference2_iterative_traversal<
     while ! a_loader.entirely_loaded || ! b_loader.entirely_loaded {
         // we check if A's next blob set is a subset of anything in B we've loaded so far
         a_loader.load_next(&mut should_use_blob_cb, |(a_commit, a_blob_set)| {
-            if let Some(b_side_fork) = b_loader.contains_superset_of(a_blob_set) {
+            let b_side_fork = find_counterpart_in_loader(commit_map, &a_commit.commit.id.hash, &b_loader)
+                .or_else(|| b_loader.contains_superset_of(a_blob_set));
+            if let Some(b_side_fork) = b_side_fork {
                 found_fork_point = Some((a_commit.clone(), b_side_fork));
                 // true because now that we found our fork point we can stop reading the stream
                 true
@@ -650,7 +683,9 @@ pub fn find_a_b_difference2_iterative_traversal<
         // if we failed to find the fork point after searching A's next group,
         // then we load B's next group, and search through all of A:
         b_loader.load_next(&mut should_use_blob_cb, |(b_commit, b_blob_set)| {
-            if let Some(a_side_fork) = a_loader.contains_subset_of(b_blob_set) {
+            let a_side_fork = find_counterpart_in_loader(commit_map, &b_commit.commit.id.hash, &a_loader)
+                .or_else(|| a_loader.contains_subset_of(b_blob_set));
+            if let Some(a_side_fork) = a_side_fork {
                 found_fork_point = Some((a_side_fork, b_commit.clone()));
                 // true because now that we found our fork point we can stop reading the stream
                 true
@@ -686,14 +721,197 @@ pub fn find_a_b_difference2_iterative_traversal_opt<
     a_committish: &str, b_committish: &str,
     traverse_n: usize,
     should_rewind: bool,
+    commit_map: Option<&commit_map::CommitMap>,
     should_use_blob_cb: Option<B>,
 ) -> io::Result<Option<SuccessfulTopbaseResult<C>>> {
     if let Some(cb) = should_use_blob_cb {
-        return find_a_b_difference2_iterative_traversal::<T, C, B>(a_committish, b_committish, traverse_n, should_rewind, cb);
+        return find_a_b_difference2_iterative_traversal::<T, C, B>(a_committish, b_committish, traverse_n, should_rewind, commit_map, cb);
     }
     
     let default_cb = |_: &mut RawBlobSummary, _: &str| true;
-    find_a_b_difference2_iterative_traversal::<T, C, NopCB>(a_committish, b_committish, traverse_n, should_rewind, default_cb)
+    find_a_b_difference2_iterative_traversal::<T, C, NopCB>(a_committish, b_committish, traverse_n, should_rewind, commit_map, default_cb)
+}
+
+/// one part of a fullbase traversal: the commits of each branch
+/// that are between the previous fork point and this fork point.
+/// the last segment has no fork point, and its commits are
+/// the commits of each branch below the oldest fork point.
+pub struct FullbaseSegment<C: From<CommitWithBlobs>> {
+    pub left_commits: Vec<C>,
+    pub right_commits: Vec<C>,
+    pub fork_point: Option<(C, C)>,
+}
+
+/// given the blob sets of the commits of A and B from most recent
+/// to oldest, returns the indices of every pair of commits that match,
+/// from most recent to oldest. an A commit matches a B commit if its blob set
+/// is a subset of the B commit's blob set, the same as in a topbase.
+/// the pairs are always in the same order in both branches, and this
+/// finds the most pairs that can be, like a longest common subsequence.
+/// when there are several ways to get the most pairs, the more recent A
+/// commits are preferred. empty blob sets (ie: merge commits) never match anything.
+/// only the pairs that match are kept in memory, and the most pairs are
+/// the longest increasing subsequence of them (Hunt-Szymanski), so
+/// this does not need a table of every A commit by every B commit
+pub fn find_matching_pairs<T: Eq + Hash>(
+    a_blob_sets: &[&HashSet<T>],
+    b_blob_sets: &[&HashSet<T>],
+) -> Vec<(usize, usize)> {
+    // every B commit that has a blob, from most recent to oldest
+    let mut b_commits_with_blob: HashMap<&T, Vec<usize>> = HashMap::new();
+    for (b, blob_set) in b_blob_sets.iter().enumerate() {
+        for blob in blob_set.iter() {
+            b_commits_with_blob.entry(blob).or_default().push(b);
+        }
+    }
+
+    // the pairs are found from the oldest A commit to the most recent one,
+    // so that the chains that start with more recent A commits replace the
+    // others. every pair points to the next pair of its chain, and
+    // chain_starts[k] is the pair with the oldest B commit that starts
+    // a chain of k + 1 pairs
+    let mut pairs: Vec<(usize, usize, Option<usize>)> = vec![];
+    let mut chain_starts: Vec<usize> = vec![];
+    for a in (0..a_blob_sets.len()).rev() {
+        // every B commit that matches has every blob of A,
+        // so only the B commits of its least common blob are checked
+        let candidates = a_blob_sets[a].iter()
+            .map(|blob| b_commits_with_blob.get(blob).map(|v| v.as_slice()).unwrap_or(&[]))
+            .min_by_key(|b_commits| b_commits.len());
+        let candidates = match candidates {
+            Some(c) => c,
+            None => continue,
+        };
+        // the more recent B commits first, so that the pairs of
+        // the same A commit cannot be part of the same chain
+        for &b in candidates {
+            if !a_blob_sets[a].is_subset(b_blob_sets[b]) {
+                continue;
+            }
+            let k = chain_starts.partition_point(|&pair| pairs[pair].1 > b);
+            let next = if k > 0 { Some(chain_starts[k - 1]) } else { None };
+            pairs.push((a, b, next));
+            if k == chain_starts.len() {
+                chain_starts.push(pairs.len() - 1);
+            } else {
+                chain_starts[k] = pairs.len() - 1;
+            }
+        }
+    }
+
+    let mut out = vec![];
+    let mut current = chain_starts.last().copied();
+    while let Some(pair) = current {
+        let (a, b, next) = pairs[pair];
+        out.push((a, b));
+        current = next;
+    }
+    out
+}
+
+/// loads every commit of the committish along with its blob set.
+/// like the `BranchIterativeCommitLoader`, commits that
+/// are not merges, and do not have any blobs we should use are skipped
+pub fn load_all_commits_with_blob_sets<
+    T: From<RawBlobSummary> + Eq + Hash,
+    B: FnMut(&mut RawBlobSummary, &str) -> bool,
+>(
+    committish: &str,
+    should_use_blob_cb: &mut Option<B>,
+) -> io::Result<Vec<(CommitWithBlobs, HashSet<T>)>> {
+    let mut out = vec![];
+    let mut seen = HashSet::new();
+    git_helpers3::iterate_blob_log(committish, None, |c| {
+        // merge commits appear once for every parent
+        if ! seen.insert(c.commit.id.hash.clone()) {
+            return false;
+        }
+        let blobs: Vec<RawBlobSummary> = c.blobs.iter().cloned().filter_map(|mut blob| {
+            let should_use = match should_use_blob_cb {
+                Some(ref mut cb) => cb(&mut blob, committish),
+                None => true,
+            };
+            if should_use { Some(blob) } else { None }
+        }).collect();
+        if c.commit.is_merge || ! blobs.is_empty() {
+            let blob_set = blobs.into_iter().map(T::from).collect();
+            out.push((c, blob_set));
+        }
+        false
+    })?;
+    Ok(out)
+}
+
+pub fn find_a_b_fullbase_inner<
+    T: From<RawBlobSummary> + Eq + Hash,
+    C: From<CommitWithBlobs>,
+    B: FnMut(&mut RawBlobSummary, &str) -> bool,
+>(
+    a_committish: &str, b_committish: &str,
+    should_use_blob_cb: Option<B>,
+) -> io::Result<Vec<FullbaseSegment<C>>> {
+    let mut should_use_blob_cb = should_use_blob_cb;
+    let a_commits = load_all_commits_with_blob_sets::<T, B>(a_committish, &mut should_use_blob_cb)?;
+    let b_commits = load_all_commits_with_blob_sets::<T, B>(b_committish, &mut should_use_blob_cb)?;
+    let a_blob_sets: Vec<&HashSet<T>> = a_commits.iter().map(|(_, s)| s).collect();
+    let b_blob_sets: Vec<&HashSet<T>> = b_commits.iter().map(|(_, s)| s).collect();
+    let pairs = find_matching_pairs(&a_blob_sets, &b_blob_sets);
+    if pairs.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut a_commits = a_commits.into_iter().map(|(c, _)| c).enumerate();
+    let mut b_commits = b_commits.into_iter().map(|(c, _)| c).enumerate();
+    let mut segments = vec![];
+    for (a_index, b_index) in pairs {
+        let mut left_commits = vec![];
+        let mut right_commits = vec![];
+        let mut a_fork = None;
+        let mut b_fork = None;
+        for (i, c) in a_commits.by_ref() {
+            if i == a_index { a_fork = Some(c); break; }
+            left_commits.push(C::from(c));
+        }
+        for (i, c) in b_commits.by_ref() {
+            if i == b_index { b_fork = Some(c); break; }
+            right_commits.push(C::from(c));
+        }
+        let fork_point = match (a_fork, b_fork) {
+            (Some(a), Some(b)) => Some((a.into(), b.into())),
+            _ => None,
+        };
+        segments.push(FullbaseSegment { left_commits, right_commits, fork_point });
+    }
+    let left_commits: Vec<C> = a_commits.map(|(_, c)| C::from(c)).collect();
+    let right_commits: Vec<C> = b_commits.map(|(_, c)| C::from(c)).collect();
+    if ! left_commits.is_empty() || ! right_commits.is_empty() {
+        segments.push(FullbaseSegment { left_commits, right_commits, fork_point: None });
+    }
+    Ok(segments)
+}
+
+/// unlike a topbase, a fullbase does not stop at the most recent
+/// fork point. it loads both branches entirely, and finds
+/// every pair of matching commits. returns an empty list if
+/// there are no matching commits at all
+pub fn find_a_b_fullbase<
+    C: From<CommitWithBlobs>,
+    B: FnMut(&mut RawBlobSummary, &str) -> bool,
+>(
+    a_committish: &str, b_committish: &str,
+    hashing_mode: BlobHashingMode,
+    should_use_blob_cb: Option<B>,
+) -> io::Result<Vec<FullbaseSegment<C>>> {
+    match hashing_mode {
+        BlobHashingMode::Full => find_a_b_fullbase_inner::<RawBlobSummary, C, B>(
+            a_committish, b_committish, should_use_blob_cb),
+        BlobHashingMode::WithoutPath => find_a_b_fullbase_inner::<RawBlobSummaryWithoutPath, C, B>(
+            a_committish, b_committish, should_use_blob_cb),
+        BlobHashingMode::EndState => find_a_b_fullbase_inner::<RawBlobSummaryEndState, C, B>(
+            a_committish, b_committish, should_use_blob_cb),
+        BlobHashingMode::EndStateWithoutPath => find_a_b_fullbase_inner::<RawBlobSummaryEndStateWithoutPath, C, B>(
+            a_committish, b_committish, should_use_blob_cb),
+    }
 }
 
 /// An alternative of `find_a_b_difference` that allows
@@ -731,6 +949,20 @@ pub fn find_a_b_difference2<
     should_rewind: bool,
     should_use_blob_cb: Option<B>,
 ) -> io::Result<Option<SuccessfulTopbaseResult<C>>> {
+    // if mgt has recorded which commits correspond to each other, then
+    // the commits are looked up in it while we traverse, so that
+    // a fork point is found even if the blobs of its commits are different
+    let map = match commit_map::commit_map_exists() {
+        true => match commit_map::load_commit_map() {
+            Ok(map) => Some(map),
+            Err(e) => {
+                eprintln!("Warning: failed to load the commit map, so only blobs are compared to find the fork point: {}", e);
+                None
+            }
+        },
+        false => None,
+    };
+
     if let Some(n) = traverse_n_at_a_time {
         // 0 is not a valid value of N
         if n == 0 {
@@ -739,13 +971,13 @@ pub fn find_a_b_difference2<
 
         match hashing_mode {
             BlobHashingMode::Full => find_a_b_difference2_iterative_traversal_opt::<RawBlobSummary, C, _>(
-                a_committish, b_committish, n, should_rewind, should_use_blob_cb),
+                a_committish, b_committish, n, should_rewind, map.as_ref(), should_use_blob_cb),
             BlobHashingMode::WithoutPath => find_a_b_difference2_iterative_traversal_opt::<RawBlobSummaryWithoutPath, C, _>(
-                a_committish, b_committish, n, should_rewind, should_use_blob_cb),
+                a_committish, b_committish, n, should_rewind, map.as_ref(), should_use_blob_cb),
             BlobHashingMode::EndState => find_a_b_difference2_iterative_traversal_opt::<RawBlobSummaryEndState, C, _>(
-                a_committish, b_committish, n, should_rewind, should_use_blob_cb),
+                a_committish, b_committish, n, should_rewind, map.as_ref(), should_use_blob_cb),
             BlobHashingMode::EndStateWithoutPath => find_a_b_difference2_iterative_traversal_opt::<RawBlobSummaryEndStateWithoutPath, C, _>(
-                a_committish, b_committish, n, should_rewind, should_use_blob_cb),
+                a_committish, b_committish, n, should_rewind, map.as_ref(), should_use_blob_cb),
         }
     } else {
         simplest_topbase(a_committish, b_committish, hashing_mode)
@@ -951,6 +1183,47 @@ mod tests {
         assert_eq!(loader.groups[0].len(), num_commits);
     }
 
+    #[test]
+    fn find_matching_pairs_finds_every_fork_point() {
+        let set = |blobs: &[u32]| -> HashSet<u32> { blobs.iter().cloned().collect() };
+        // the A3/B6 example from the ABTraversalMode docs, but
+        // with an older fork point below A3 and B6
+        let a = [set(&[1]), set(&[2]), set(&[3]), set(&[4])];
+        let b = [set(&[5]), set(&[2]), set(&[6]), set(&[4, 7])];
+        let a: Vec<&HashSet<u32>> = a.iter().collect();
+        let b: Vec<&HashSet<u32>> = b.iter().collect();
+        assert_eq!(find_matching_pairs(&a, &b), vec![(1, 1), (3, 3)]);
+
+        // pairs have to be in the same order in both branches
+        let a = [set(&[1]), set(&[2])];
+        let b = [set(&[2]), set(&[1])];
+        let a: Vec<&HashSet<u32>> = a.iter().collect();
+        let b: Vec<&HashSet<u32>> = b.iter().collect();
+        assert_eq!(find_matching_pairs(&a, &b), vec![(0, 1)]);
+
+        // the most pairs are found, even if a more recent A commit
+        // would match a B commit that is below the others
+        let a = [set(&[1]), set(&[2]), set(&[3])];
+        let b = [set(&[2]), set(&[3]), set(&[1])];
+        let a: Vec<&HashSet<u32>> = a.iter().collect();
+        let b: Vec<&HashSet<u32>> = b.iter().collect();
+        assert_eq!(find_matching_pairs(&a, &b), vec![(1, 0), (2, 1)]);
+
+        // a commits blob set only has to be a subset of the B commits blob set
+        let a = [set(&[1, 2]), set(&[3])];
+        let b = [set(&[1]), set(&[1, 2, 4]), set(&[3, 5])];
+        let a: Vec<&HashSet<u32>> = a.iter().collect();
+        let b: Vec<&HashSet<u32>> = b.iter().collect();
+        assert_eq!(find_matching_pairs(&a, &b), vec![(0, 1), (1, 2)]);
+
+        // empty sets (merge commits) dont match
+        let a = [set(&[])];
+        let b = [set(&[1])];
+        let a: Vec<&HashSet<u32>> = a.iter().collect();
+        let b: Vec<&HashSet<u32>> = b.iter().collect();
+        assert!(find_matching_pairs(&a, &b).is_empty());
+    }
+
     // TODO: this test wont work in github pipeline
     // because I think github downloads only one commit, so
     // it fails to load the second time.. sad
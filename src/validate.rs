@@ -0,0 +1,523 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+use toml::Value;
+use toml::map::Map;
+
+use super::repo_file;
+use super::workspace;
+use super::verify::{self, FileOpType};
+use super::ioerre;
+use repo_file::TOP_LEVEL_KEYS;
+
+/// the top level keys of a version 2 repo file. include, include_as and exclude
+/// are not allowed, but that is reported by the parser, so they are not unknown
+pub const TOP_LEVEL_KEYS_V2: &[&str] = &[
+    "version", "repo", "tags", "commits", "mailmap", "message", "refs", "submodules", "rule", "remotes", "extends", "vars",
+    "include", "include_as", "exclude",
+];
+pub const REPO_KEYS: &[&str] = &["remote", "name", "branch"];
+pub const MESSAGE_KEYS: &[&str] = &["replace", "trailer"];
+pub const REFS_KEYS: &[&str] = &["include", "namespace"];
+pub const REMOTE_KEYS: &[&str] = &["url", "branch", "pull", "push"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// the repo file can be used, but probably
+    /// does not do what it was meant to do
+    Warning,
+    /// the repo file cannot be used
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// a problem with a repo file. the line and column
+/// start at 1, and are None if they are not known
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub path: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if let Some(column) = self.column {
+            write!(f, ":{}", column)?;
+        }
+        write!(f, ": {}: {}", self.severity, self.message)
+    }
+}
+
+/// collects the diagnostics of one repo file, and finds
+/// the lines that the diagnostics are about
+struct Validator<'a> {
+    path: &'a str,
+    lines: &'a [String],
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Validator<'a> {
+    fn push(&mut self, severity: Severity, line: Option<usize>, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity, path: self.path.to_string(), line, column: None, message,
+        });
+    }
+
+    fn error(&mut self, line: Option<usize>, message: String) {
+        self.push(Severity::Error, line, message);
+    }
+
+    fn warning(&mut self, line: Option<usize>, message: String) {
+        self.push(Severity::Warning, line, message);
+    }
+
+    /// the line that defines the key, either as `key = ...` or as a `[key]`
+    /// table. a key in `table` is preferred, but because a key can be in more
+    /// than one layout, a key in any other table is used if there is none
+    fn find_key_line(&self, table: Option<&str>, key: &str) -> Option<usize> {
+        let mut current_table: Option<String> = None;
+        let mut fallback = None;
+        for (i, line) in self.lines.iter().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') {
+                let name = trimmed.trim_matches(|c| c == '[' || c == ']').trim();
+                if table.is_none() && (name == key || name.starts_with(&format!("{}.", key))) {
+                    return Some(i + 1);
+                }
+                current_table = Some(name.to_string());
+                continue;
+            }
+            let line_key = match trimmed.split_once('=') {
+                Some((line_key, _)) => line_key.trim().trim_matches('"'),
+                None => continue,
+            };
+            if line_key != key {
+                continue;
+            }
+            if current_table.as_deref() == table {
+                return Some(i + 1);
+            }
+            fallback = fallback.or(Some(i + 1));
+        }
+        fallback
+    }
+
+    /// the line of the nth (starting at 0) time that the path is written
+    fn find_path_line(&self, path: &str, nth: usize) -> Option<usize> {
+        let quoted = format!("\"{}\"", path);
+        self.lines.iter().enumerate()
+            .filter(|(_, line)| line.contains(&quoted))
+            .nth(nth)
+            .map(|(i, _)| i + 1)
+    }
+
+    fn check_keys(&mut self, table: Option<&str>, toml_table: &Map<String, Value>, allowed: &[&str]) {
+        for key in toml_table.keys() {
+            if allowed.contains(&key.as_str()) {
+                continue;
+            }
+            let line = self.find_key_line(table, key);
+            let location = match table {
+                Some(table) => format!(" in [{}]", table),
+                None => "".into(),
+            };
+            let expected = if allowed.is_empty() {
+                "".into()
+            } else {
+                format!(". Expected one of: {}", allowed.join(", "))
+            };
+            self.warning(line, format!("Unknown key '{}'{}{}", key, location, expected));
+        }
+    }
+
+    fn type_error(&mut self, table: Option<&str>, key: &str, expected: &str, value: &Value) {
+        let line = self.find_key_line(table, key);
+        let name = match table {
+            Some(table) => format!("{}.{}", table, key),
+            None => key.to_string(),
+        };
+        let type_str = value.type_str();
+        let article = if type_str.starts_with(|c| "aeiou".contains(c)) { "an" } else { "a" };
+        self.error(line, format!("'{}' must be {}, but it is {} {}", name, expected, article, type_str));
+    }
+
+    fn check_string(&mut self, table: Option<&str>, key: &str, value: &Value) {
+        if !value.is_str() {
+            self.type_error(table, key, "a string", value);
+        }
+    }
+
+    fn check_string_or_list(&mut self, table: Option<&str>, key: &str, value: &Value) {
+        if !is_string_or_list(value) {
+            self.type_error(table, key, "a string, or a list of strings", value);
+        }
+    }
+
+    /// a table where every value is a string, or if
+    /// `lists` is true, a string or a list of strings
+    fn check_string_table(&mut self, table: &str, value: &Value, lists: bool) {
+        let t = match value.as_table() {
+            Some(t) => t,
+            None => return self.type_error(None, table, "a table", value),
+        };
+        for (k, v) in t {
+            if lists && !is_string_or_list(v) {
+                self.type_error(Some(table), k, "a string, or a list of strings", v);
+            } else if !lists && !v.is_str() {
+                self.type_error(Some(table), k, "a string", v);
+            }
+        }
+    }
+
+    fn check_toml_table(&mut self, version: i64, toml_table: &Map<String, Value>) {
+        let top_level_keys: Vec<&str> = match version {
+            1 => TOP_LEVEL_KEYS.iter().copied().chain(["version"]).collect(),
+            _ => TOP_LEVEL_KEYS_V2.to_vec(),
+        };
+        self.check_keys(None, toml_table, &top_level_keys);
+
+        for (key, value) in toml_table {
+            match key.as_str() {
+                "repo" => match value.as_table() {
+                    Some(t) => {
+                        self.check_keys(Some("repo"), t, REPO_KEYS);
+                        for (k, v) in t {
+                            self.check_string(Some("repo"), k, v);
+                        }
+                    }
+                    None => self.type_error(None, key, "a table", value),
+                },
+                // every key that is allowed in [filter] was
+                // moved to the top level, so the rest are unknown
+                "filter" => match value.as_table() {
+                    Some(t) => self.check_keys(Some("filter"), t, &[]),
+                    None => self.type_error(None, key, "a table", value),
+                },
+                "include" | "exclude" if version == 1 => self.check_string_or_list(None, key, value),
+                "include_as" if version == 1 => self.check_string_table(key, value, false),
+                "tags" => match value {
+                    Value::String(_) | Value::Boolean(true) => (),
+                    Value::Table(_) => self.check_string_table(key, value, false),
+                    _ => self.type_error(None, key, "a table, a string, or true", value),
+                },
+                "commits" => self.check_string_table(key, value, true),
+                "mailmap" => match value {
+                    Value::String(_) => (),
+                    _ => self.check_string_table(key, value, false),
+                },
+                "message" => match value.as_table() {
+                    Some(t) => {
+                        self.check_keys(Some("message"), t, MESSAGE_KEYS);
+                        if let Some(replace) = t.get("replace") {
+                            self.check_string_table("message.replace", replace, false);
+                        }
+                        match t.get("trailer") {
+                            None | Some(Value::Boolean(_)) | Some(Value::String(_)) => (),
+                            Some(trailer) => self.type_error(Some("message"), "trailer", "true, or a string", trailer),
+                        }
+                    }
+                    None => self.type_error(None, key, "a table", value),
+                },
+                "refs" => match value.as_table() {
+                    Some(t) => {
+                        self.check_keys(Some("refs"), t, REFS_KEYS);
+                        if let Some(include) = t.get("include") {
+                            self.check_string_or_list(Some("refs"), "include", include);
+                        }
+                        if let Some(namespace) = t.get("namespace") {
+                            self.check_string(Some("refs"), "namespace", namespace);
+                        }
+                    }
+                    None => self.check_string_or_list(None, key, value),
+                },
+                "submodules" => self.check_string(None, key, value),
+                "extends" => self.check_string_or_list(None, key, value),
+                "vars" => self.check_string_table(key, value, false),
+                "remotes" => match value.as_table() {
+                    Some(t) => {
+                        for (name, remote) in t {
+                            self.check_remote(name, remote);
+                        }
+                    }
+                    None => self.type_error(None, key, "a table", value),
+                },
+                _ => (),
+            }
+        }
+    }
+
+    fn check_remote(&mut self, name: &str, value: &Value) {
+        let table = format!("remotes.{}", name);
+        let t = match value.as_table() {
+            Some(t) => t,
+            None => return self.type_error(Some("remotes"), name, "a table", value),
+        };
+        self.check_keys(Some(&table), t, REMOTE_KEYS);
+        if !t.contains_key("url") {
+            let line = self.find_key_line(None, &table);
+            self.error(line, format!("Remote '{}' must have a url", name));
+        }
+        for (k, v) in t {
+            match k.as_str() {
+                "url" | "branch" => self.check_string(Some(&table), k, v),
+                "pull" | "push" if !v.is_bool() => self.type_error(Some(&table), k, "true or false", v),
+                _ => (),
+            }
+        }
+        let is_false = |key: &str| t.get(key).and_then(|v| v.as_bool()) == Some(false);
+        if is_false("pull") && is_false("push") {
+            let line = self.find_key_line(None, &table);
+            self.warning(line, format!("Remote '{}' is never pulled from or pushed to", name));
+        }
+    }
+
+    /// duplicate and overlapping rules, excludes that shadow
+    /// nothing, and include_as targets that collide
+    fn check_file_ops(&mut self, file_ops: &[FileOpType]) {
+        // with a glob or regex, we cannot know which paths a rule applies to
+        if file_ops.iter().any(verify::file_op_is_pattern) {
+            return;
+        }
+        // a repo file that keeps nothing is only useful to be extended
+        // by other repo files, so its excludes apply to their includes
+        if file_ops.iter().all(|op| matches!(op, FileOpType::Exclude(_))) {
+            return;
+        }
+        let ops: Vec<(&str, &str, Option<&str>)> = file_ops.iter().map(|op| match op {
+            FileOpType::Include(src) => ("include", src.trim_start(), None),
+            FileOpType::Exclude(src) => ("exclude", src.trim_start(), None),
+            FileOpType::IncludeAs(src, dest) => ("include_as", src.trim_start(), Some(dest.trim_start())),
+        }).collect();
+
+        for (i, (kind, src, dest)) in ops.iter().enumerate() {
+            if let Some(previous) = ops[..i].iter().find(|(_, prev_src, _)| prev_src == src) {
+                let line = self.find_path_line(src, 1);
+                if previous == &ops[i] {
+                    self.warning(line, format!("'{}' is in {} more than once", src, kind));
+                } else if previous.0 == "exclude" || *kind == "exclude" {
+                    self.warning(line, format!("'{}' is both kept and excluded", src));
+                } else {
+                    self.warning(line, format!("'{}' is kept by more than one rule", src));
+                }
+                continue;
+            }
+
+            let is_kept_before = |path: &str| ops[..i].iter()
+                .any(|(k, s, _)| *k != "exclude" && (path.starts_with(s) || s.starts_with(path)));
+            match *kind {
+                "include" => {
+                    // an include is redundant if a directory above it is already
+                    // included, and nothing in between changes what is kept
+                    let covering = ops.iter().enumerate().find(|(j, (k, s, _))| {
+                        *j != i && *k == "include" && is_dir(s) && src.starts_with(s)
+                    });
+                    if let Some((_, (_, covering_src, _))) = covering {
+                        let in_between = ops.iter().any(|(k, s, _)| {
+                            *k != "include" && s.starts_with(covering_src) && src.starts_with(s) && s != covering_src
+                        });
+                        if !in_between {
+                            let line = self.find_path_line(src, 0);
+                            self.warning(line, format!("include '{}' is already included by '{}'", src, covering_src));
+                        }
+                    }
+                }
+                "exclude" => {
+                    // an exact file is always excluded, otherwise an exclude
+                    // only removes what the rules before it kept
+                    let is_kept = if is_dir(src) {
+                        is_kept_before(src)
+                    } else {
+                        ops.iter().any(|(k, s, _)| *k != "exclude" && src.starts_with(s))
+                    };
+                    if !is_kept {
+                        let line = self.find_path_line(src, 0);
+                        self.warning(line, format!("exclude '{}' does not exclude anything, because nothing under it is kept", src));
+                    }
+                }
+                _ => (),
+            }
+
+            let dest = match dest {
+                Some(dest) => *dest,
+                None => continue,
+            };
+            let collision = ops[..i].iter().find(|(k, s, d)| match *k {
+                "include_as" => *d == Some(dest),
+                "include" => *s == dest,
+                _ => false,
+            });
+            if let Some((_, other_src, _)) = collision {
+                let line = self.find_path_line(src, 0);
+                let message = format!("'{}' and '{}' are both kept at '{}'", other_src, src, dest);
+                // two files renamed to the same path always overwrite each other
+                if is_dir(dest) {
+                    self.warning(line, message);
+                } else {
+                    self.error(line, message);
+                }
+            }
+        }
+    }
+}
+
+fn is_string_or_list(value: &Value) -> bool {
+    match value {
+        Value::String(_) => true,
+        Value::Array(a) => a.iter().all(Value::is_str),
+        _ => false,
+    }
+}
+
+/// an empty path is the root of the repository, which is a directory
+pub fn is_dir(path: &str) -> bool {
+    path.is_empty() || path.ends_with('/')
+}
+
+/// checks the contents of the repo file at `path`, and returns every problem
+/// that was found, in the order of the lines they are on.
+/// if the repo file cannot be parsed, only the error that stopped the parser is returned
+pub fn validate_repo_file_lines(path: &str, lines: &[String]) -> Vec<Diagnostic> {
+    let mut validator = Validator { path, lines, diagnostics: vec![] };
+    let version = match repo_file::get_repo_file_version(lines) {
+        Ok(version) => version,
+        Err(e) => {
+            let line = validator.find_key_line(None, "version");
+            validator.error(line, e.to_string());
+            return validator.diagnostics;
+        }
+    };
+    let toml_table = match repo_file::get_repo_file_toml_table(lines) {
+        Ok(t) => t,
+        Err(e) => {
+            match lines.join("\n").parse::<Value>() {
+                Err(toml_err) => {
+                    let (message, position) = repo_file::get_toml_error_position(&toml_err);
+                    validator.diagnostics.push(Diagnostic {
+                        severity: Severity::Error,
+                        path: path.to_string(),
+                        line: position.map(|(line, _)| line + 1),
+                        column: position.map(|(_, col)| col + 1),
+                        message: format!("Invalid toml: {}", message),
+                    });
+                }
+                Ok(_) => validator.error(None, e.to_string()),
+            }
+            return validator.diagnostics;
+        }
+    };
+
+    validator.check_toml_table(version, &toml_table);
+    // the paths can only be checked after their variables are expanded.
+    // if a variable is not found, it can be defined by a repo file
+    // that extends this one, so the paths are checked when that is parsed
+    let mut expanded = toml_table;
+    let workspace_vars = workspace::get_workspace_vars(Path::new(path)).unwrap_or_default();
+    if repo_file::expand_repo_file_vars(&mut expanded, &workspace_vars).is_ok() {
+        match repo_file::parse_repo_file_from_toml_table(version, &expanded) {
+            Ok(repo_file) => {
+                let file_ops = verify::get_vec_of_file_ops_with_order(&repo_file, true);
+                validator.check_file_ops(&file_ops);
+            }
+            // the checks above can have already found it, with its line
+            Err(e) => if !validator.diagnostics.iter().any(|d| d.message == e.to_string()) {
+                validator.error(None, e.to_string());
+            },
+        }
+    }
+
+    let mut diagnostics = validator.diagnostics;
+    // diagnostics without a line come last
+    diagnostics.sort_by_key(|d| (d.line.is_none(), d.line));
+    diagnostics
+}
+
+/// prints the warnings to stderr, and returns an error
+/// with every error diagnostic if there are any
+pub fn report_diagnostics(diagnostics: &[Diagnostic]) -> io::Result<()> {
+    let mut errors = vec![];
+    for diagnostic in diagnostics {
+        match diagnostic.severity {
+            Severity::Warning => eprintln!("{}", diagnostic),
+            Severity::Error => errors.push(diagnostic.to_string()),
+        }
+    }
+    if !errors.is_empty() {
+        return ioerre!("{}", errors.join("\n"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn validate(toml_str: &str) -> Vec<(Severity, Option<usize>, String)> {
+        let lines: Vec<String> = toml_str.split('\n').map(|s| s.to_string()).collect();
+        validate_repo_file_lines("repo.rf", &lines).into_iter()
+            .map(|d| (d.severity, d.line, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn unknown_keys_and_type_mismatches_are_found() {
+        let toml_str = "[repo]\nnmae = \"x\"\nremote = 5\n[include_as]\n\"lib/\" = 1\n";
+        assert_eq!(validate(toml_str), vec![
+            (Severity::Warning, Some(2), "Unknown key 'nmae' in [repo]. Expected one of: remote, name, branch".into()),
+            (Severity::Error, Some(3), "'repo.remote' must be a string, but it is an integer".into()),
+            (Severity::Error, Some(5), "'include_as.lib/' must be a string, but it is an integer".into()),
+        ]);
+    }
+
+    #[test]
+    fn duplicate_and_shadowing_rules_are_found() {
+        let toml_str = "include = [\"lib/\", \"lib/a/\", \"docs/\"]\nexclude = [\"src/\", \"docs/\"]\n";
+        assert_eq!(validate(toml_str), vec![
+            (Severity::Warning, Some(1), "include 'lib/a/' is already included by 'lib/'".into()),
+            (Severity::Warning, Some(2), "'docs/' is both kept and excluded".into()),
+            (Severity::Warning, Some(2), "exclude 'src/' does not exclude anything, because nothing under it is kept".into()),
+        ]);
+        // an include is not redundant if something above it was excluded
+        assert!(validate("include = [\"lib/\", \"lib/a/b/\"]\nexclude = \"lib/a/\"").is_empty());
+    }
+
+    #[test]
+    fn colliding_include_as_targets_are_found() {
+        let toml_str = "[include_as]\n\"a/\" = \"x/\"\n\"b/\" = \"x/\"\n\"c.txt\" = \"y.txt\"\n\"d.txt\" = \"y.txt\"\n";
+        assert_eq!(validate(toml_str), vec![
+            (Severity::Warning, Some(3), "'a/' and 'b/' are both kept at 'x/'".into()),
+            (Severity::Error, Some(5), "'c.txt' and 'd.txt' are both kept at 'y.txt'".into()),
+        ]);
+    }
+
+    #[test]
+    fn invalid_remotes_are_found() {
+        let toml_str = "[remotes.mirror]\nbranch = 1\npull = false\npush = false\n";
+        assert_eq!(validate(toml_str), vec![
+            (Severity::Error, Some(1), "Remote 'mirror' must have a url".into()),
+            (Severity::Warning, Some(1), "Remote 'mirror' is never pulled from or pushed to".into()),
+            (Severity::Error, Some(2), "'remotes.mirror.branch' must be a string, but it is an integer".into()),
+        ]);
+    }
+
+    #[test]
+    fn parse_errors_have_a_position() {
+        let diagnostics = validate_repo_file_lines("repo.rf", &["[repo]".into(), "include [\"a\"]".into()]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].to_string(), "repo.rf:2:9: error: Invalid toml: expected an equals, found a left bracket");
+    }
+}
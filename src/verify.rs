@@ -2,9 +2,14 @@ use super::cli::MgtCommandVerify;
 use super::die;
 use super::repo_file;
 use super::git_helpers3;
+use super::core;
+use super::ioerre;
+use super::ioerr;
 use std::io::{self, BufRead};
 use gitfilter::filter::FilterRules;
 use gitfilter::filter::FilterRule;
+use gitfilter::filter::{GlobPattern, Regex, regex_from_replacement};
+use repo_file::{parse_path_pattern, PathPattern, PathRule};
 
 #[derive(Debug)]
 pub enum FileOpType<'a> {
@@ -13,12 +18,25 @@ pub enum FileOpType<'a> {
     Exclude(&'a str),
 }
 
-/// iterate over the repo file include, include_as, and
-/// exclude. create a vec of file ops from that without sorting
+/// create a vec of file ops in the order that they should be applied.
+/// the `[[rule]]`s of a version 2 repo file are used in the order they
+/// were written. a version 1 repo file has no order, so its include,
+/// include_as, and exclude are sorted. see `sort_vec_of_file_ops`
 pub fn get_vec_of_file_ops_with_order<'a>(
     repo_file: &'a repo_file::RepoFile,
     src_to_dest: bool,
 ) -> Vec<FileOpType<'a>> {
+    if let Some(ref rules) = repo_file.rules {
+        return rules.iter().map(|rule| match rule {
+            PathRule::Include(path) => FileOpType::Include(path),
+            PathRule::Exclude(path) => FileOpType::Exclude(path),
+            PathRule::Rename(src, dest) => if src_to_dest {
+                FileOpType::IncludeAs(src, dest)
+            } else {
+                FileOpType::IncludeAs(dest, src)
+            }
+        }).collect();
+    }
     let mut out_vec = vec![];
     if let Some(ref include_as) = repo_file.include_as {
         for (i, _path) in include_as.iter().enumerate() {
@@ -41,7 +59,8 @@ pub fn get_vec_of_file_ops_with_order<'a>(
             out_vec.push(FileOpType::Exclude(path));
         }
     }
-    
+    sort_vec_of_file_ops(&mut out_vec);
+
     out_vec
 }
 
@@ -49,15 +68,35 @@ pub fn get_vec_of_file_ops<'a>(repo_file: &'a repo_file::RepoFile) -> Vec<FileOp
     get_vec_of_file_ops_with_order(repo_file, true)
 }
 
+/// returns true if this file op uses a glob or regex
+/// instead of a plain path
+pub fn file_op_is_pattern(file_op: &FileOpType) -> bool {
+    match file_op {
+        FileOpType::IncludeAs(src, dest) => {
+            ! parse_path_pattern(src).is_prefix() || ! parse_path_pattern(dest).is_prefix()
+        }
+        FileOpType::Include(src) |
+        FileOpType::Exclude(src) => ! parse_path_pattern(src).is_prefix(),
+    }
+}
+
 /// given a vec of fileops, sort it by the src/ path.
 /// this means if your vec has paths like:
 /// src/a/
 /// src/
 /// then src/ will come before src/a/
 /// this is useful to establish a correct order of operations
-/// when filtering
+/// when filtering.
+/// glob and regex patterns cannot be sorted this way, so they
+/// are put after all of the plain paths in the order they were given
 pub fn sort_vec_of_file_ops<'a>(file_ops: &mut Vec<FileOpType<'a>>) {
     file_ops.sort_by(|a, b| {
+        match (file_op_is_pattern(a), file_op_is_pattern(b)) {
+            (true, true) => return std::cmp::Ordering::Equal,
+            (true, false) => return std::cmp::Ordering::Greater,
+            (false, true) => return std::cmp::Ordering::Less,
+            (false, false) => (),
+        }
         match a {
             FileOpType::IncludeAs(src_a, _) |
             FileOpType::Include(src_a) |
@@ -72,62 +111,94 @@ pub fn sort_vec_of_file_ops<'a>(file_ops: &mut Vec<FileOpType<'a>>) {
     });
 }
 
-/// this will first sort your file_ops for you,
-/// and then create the FilterRules to pass to
-/// gitfilter to do the actual filtering
-pub fn make_filter_rules<'a>(
-    file_ops: &mut Vec<FileOpType<'a>>
-) -> FilterRules {
-    sort_vec_of_file_ops(file_ops);
-    // // TODO: need to handle grouping by largest consecutive path?
-    // // originally i thought i needed to group by largest common path
-    // // and then on each group: order the include/exclude/include-as
-    // // but now that I think about it, gitfilter should probably handle
-    // // ordering correctly so long as the filter rules are given in
-    // // lexographic order which our sort_vec_of_file_ops does above.
-    // // i propose this:
-    // // have 2 modes of operation for now:
-    // // default is sort lexographically, and pass as-is to gitfilter
-    // // second is an optional setting that says: dont parse, and
-    // // instead let the user explicitly pick their order that they want
-    // // and finally, if there later is a need for mgt
-    // // to 'smartly figure out the correct filter order' then re-implement this
-    // // and add logic to sort the filter ops within each group in the map:
-    // let mut map: HashMap<&'a str, Vec<&mut FileOpType<'a>>> = HashMap::new();
-    // let mut previous_key: &str = "";
-    // for op in file_ops {
-    //     let this_key = match op {
-    //         FileOpType::IncludeAs(src, _) |
-    //         FileOpType::Include(src) |
-    //         FileOpType::Exclude(src) => &src[..],
-    //     };
-    //     if this_key.starts_with(previous_key) {
-    //         match map.get_mut(previous_key) {
-    //             Some(existing_vec) => { existing_vec.push(op); },
-    //             None => { map.insert(previous_key, vec![op]); },
-    //         }
-    //     } else {
-    //         map.insert(this_key, vec![op]);
-    //         previous_key = this_key;
-    //     }
-    // }
-
-    file_ops.drain(..).map(|fileop| {
-        match fileop {
-            FileOpType::IncludeAs(src, dest) => {
-                // previously the requirement for include_as for renaming something to root/
-                // was the second component needed to be an empty space
-                // this is unnecessary for gitfilter, so we look for that, and clean it up here
-                FilterRule::FilterRulePathRename(src.trim_start().into(), dest.trim_start().into())
-            }
-            FileOpType::Include(src) => {
-                FilterRule::FilterRulePathInclude(src.into())
-            }
-            FileOpType::Exclude(src) => {
-                FilterRule::FilterRulePathExclude(src.into())
+pub fn make_regex(regex: &str) -> io::Result<Regex> {
+    Regex::new(regex).map_err(|e| ioerr!("Invalid regex pattern '{}': {}", regex, e))
+}
+
+/// if for_matching is true, the filter rules are only going
+/// to be used to check if a path applies to the repo file, and
+/// not to actually rename anything. this allows us to use
+/// an include_as regex in reverse, which otherwise is not possible.
+pub fn make_filter_rule(
+    fileop: FileOpType,
+    for_matching: bool,
+) -> io::Result<FilterRule> {
+    let rule = match fileop {
+        FileOpType::IncludeAs(src, dest) => {
+            // previously the requirement for include_as for renaming something to root/
+            // was the second component needed to be an empty space
+            // this is unnecessary for gitfilter, so we look for that, and clean it up here
+            match (parse_path_pattern(src), parse_path_pattern(dest)) {
+                (PathPattern::Prefix(src), PathPattern::Prefix(dest)) => {
+                    FilterRule::FilterRulePathRename(src.trim_start().into(), dest.trim_start().into())
+                }
+                (PathPattern::Glob(glob), PathPattern::Prefix(dest)) => {
+                    FilterRule::FilterRuleGlobRename(GlobPattern::new(glob)?, dest.trim_start().into())
+                }
+                (PathPattern::Regex(regex), PathPattern::Prefix(dest)) => {
+                    FilterRule::FilterRuleRegexRename(make_regex(regex)?, dest.trim_start().into())
+                }
+                // the dest side is the pattern, which means
+                // this include_as is being applied in reverse (ie: split-in)
+                (PathPattern::Prefix(src), PathPattern::Glob(glob)) => {
+                    // the glob renamed its prefix to src, so in reverse
+                    // we match the rest of the glob under src, and rename src back to the prefix
+                    let glob = GlobPattern::new(glob)?;
+                    let src = src.trim_start();
+                    let reversed_glob = if src.is_empty() || src.ends_with('/') {
+                        format!("{}{}", src, &glob.glob[glob.prefix.len()..])
+                    } else {
+                        format!("{}/{}", src, &glob.glob[glob.prefix.len()..])
+                    };
+                    FilterRule::FilterRuleGlobRename(GlobPattern::new(&reversed_glob)?, glob.prefix)
+                }
+                (PathPattern::Prefix(src), PathPattern::Regex(regex)) => {
+                    if ! for_matching {
+                        return ioerre!("Cannot apply the include_as regex '{}' = '{}' in reverse", regex, src);
+                    }
+                    FilterRule::FilterRuleRegexInclude(regex_from_replacement(src.trim_start())?)
+                }
+                _ => return ioerre!("An include_as cannot have a pattern on both sides: '{}' = '{}'", src, dest),
             }
         }
-    }).collect()
+        FileOpType::Include(src) => match parse_path_pattern(src) {
+            PathPattern::Prefix(src) => FilterRule::FilterRulePathInclude(src.into()),
+            PathPattern::Glob(glob) => FilterRule::FilterRuleGlobInclude(GlobPattern::new(glob)?),
+            PathPattern::Regex(regex) => FilterRule::FilterRuleRegexInclude(make_regex(regex)?),
+        }
+        FileOpType::Exclude(src) => match parse_path_pattern(src) {
+            PathPattern::Prefix(src) => FilterRule::FilterRulePathExclude(src.into()),
+            PathPattern::Glob(glob) => FilterRule::FilterRuleGlobExclude(GlobPattern::new(glob)?),
+            PathPattern::Regex(regex) => FilterRule::FilterRuleRegexExclude(make_regex(regex)?),
+        }
+    };
+    Ok(rule)
+}
+
+/// create the FilterRules to pass to gitfilter to do the
+/// actual filtering. the file_ops are used in the order they are
+/// given, so get them from `get_vec_of_file_ops_with_order`
+pub fn make_filter_rules<'a>(
+    file_ops: &mut Vec<FileOpType<'a>>
+) -> io::Result<FilterRules> {
+    let for_matching = false;
+    make_filter_rules_ex(file_ops, for_matching)
+}
+
+/// like `make_filter_rules`, but the rules are only meant for checking if
+/// a path applies to a repo file. See `make_filter_rule`
+pub fn make_filter_rules_for_matching<'a>(
+    file_ops: &mut Vec<FileOpType<'a>>
+) -> io::Result<FilterRules> {
+    let for_matching = true;
+    make_filter_rules_ex(file_ops, for_matching)
+}
+
+pub fn make_filter_rules_ex<'a>(
+    file_ops: &mut Vec<FileOpType<'a>>,
+    for_matching: bool,
+) -> io::Result<FilterRules> {
+    file_ops.drain(..).map(|fileop| make_filter_rule(fileop, for_matching)).collect()
 }
 
 /// need to form input that gitfilter expects
@@ -149,18 +220,62 @@ pub fn apply_expected_gitfilter<'a>(
     filtered
 }
 
-pub fn run_verify(
-    cmd: &mut MgtCommandVerify,
-) {
-    let repo_file_path = if cmd.repo_file.len() < 1 {
-        die!("Must provide repo path argument");
-    } else {
-        cmd.repo_file[0].clone()
+/// prints every identity in the history of HEAD
+/// that the mailmap of the repo file would rewrite
+pub fn print_mailmap_preview(repo_file: &repo_file::RepoFile) {
+    if repo_file.mailmap.is_none() && repo_file.mailmap_file.is_none() {
+        return;
+    }
+    // the mailmap file is relative to the root of the repository
+    core::go_to_repo_root();
+    let mailmap = match repo_file::get_mailmap(repo_file) {
+        Ok(Some(mailmap)) => mailmap,
+        Ok(None) => return,
+        Err(e) => die!("Failed to load mailmap from repo file:\n{}", e),
     };
-    let repo_file = repo_file::parse_repo_file_from_toml_path(&repo_file_path);
-    let mut file_ops = get_vec_of_file_ops(&repo_file);
-    let filter_rules = make_filter_rules(&mut file_ops);
-    let all_files: Vec<String> = if cmd.stdin {
+    let identities = match git_helpers3::get_all_identities() {
+        Ok(identities) => identities,
+        Err(e) => die!("Failed to get the authors and committers of HEAD:\n{}", e),
+    };
+
+    println!("\nIdentities that would change:");
+    for (name, email) in identities {
+        let name = if name.is_empty() { None } else { Some(name.as_str()) };
+        let (new_name, new_email) = mailmap.map_identity(name, &email);
+        if new_name.as_deref() == name && new_email == email {
+            continue;
+        }
+        println!("{} <{}> -> {} <{}>",
+            name.unwrap_or(""), email, new_name.unwrap_or_default(), new_email);
+    }
+}
+
+/// prints what happens to every submodule of HEAD
+/// that the filter rules keep
+pub fn print_submodule_preview(repo_file: &repo_file::RepoFile, filter_rules: &FilterRules) {
+    core::go_to_repo_root();
+    let paths = match git_helpers3::get_submodule_paths() {
+        Ok(paths) => paths,
+        Err(e) => die!("Failed to read the submodules from .gitmodules:\n{}", e),
+    };
+    let action = match repo_file::get_submodule_action(repo_file) {
+        Ok(action) => action,
+        Err(e) => die!("Failed to get the submodule action from repo file:\n{}", e),
+    };
+    let kept = apply_expected_gitfilter(&paths, filter_rules);
+    if kept.is_empty() {
+        return;
+    }
+    println!("\nSubmodules ({:?}):", action);
+    for (original_index, path) in kept {
+        println!("{} -> {}", paths[original_index], path);
+    }
+}
+
+/// the files to verify a repo file with, one per line of stdin,
+/// or every file of HEAD
+pub fn get_files_to_verify(stdin: bool) -> Vec<String> {
+    if stdin {
         let stdin = io::stdin();
         let mut out = vec![];
         for line in stdin.lock().lines() {
@@ -180,7 +295,83 @@ pub fn run_verify(
                 die!("Failed to get all files in git repo:\n{}", e);
             }
         }
+    }
+}
+
+/// the contents of a version 2 repo file that chooses the same paths as the
+/// version 1 repo file of `lines`. its include, include_as, and exclude become
+/// `[[rule]]`s in the order that version 1 applies them, and every other key is
+/// kept as it is. variables are not expanded, and comments are not kept
+pub fn migrate_repo_file_lines(lines: &[String]) -> io::Result<String> {
+    let version = repo_file::get_repo_file_version(lines)?;
+    if version != 1 {
+        return ioerre!("Only version 1 repo files can be migrated, but this repo file is version {}", version);
+    }
+    let mut toml_table = repo_file::get_repo_file_toml_table(lines)?;
+    let mut paths = repo_file::RepoFile::default();
+    repo_file::parse_repo_file_table(&toml_table, &mut paths)?;
+    let rules = get_vec_of_file_ops(&paths).iter().map(|op| match op {
+        FileOpType::Include(path) => PathRule::Include(path.to_string()),
+        FileOpType::Exclude(path) => PathRule::Exclude(path.to_string()),
+        FileOpType::IncludeAs(src, dest) => PathRule::Rename(src.to_string(), dest.to_string()),
+    }).collect();
+    let migrated = repo_file::RepoFile { rules: Some(rules), ..Default::default() };
+
+    let mut out = toml::map::Map::new();
+    out.insert("version".into(), toml::Value::Integer(repo_file::LATEST_REPO_FILE_VERSION));
+    for key in ["version", "include", "include_as", "exclude"] {
+        toml_table.remove(key);
+    }
+    out.extend(toml_table);
+    if let Some(rule_section) = repo_file::generate_repo_file_section_rule(&migrated) {
+        out.insert("rule".into(), rule_section);
+    }
+    Ok(toml::Value::Table(out).to_string())
+}
+
+/// rewrites the version 1 repo file at `repo_file_path` as a version 2 repo file.
+/// returns true if the repo file extends other repo files, because those
+/// have to be migrated as well
+pub fn migrate_repo_file(repo_file_path: &str) -> io::Result<bool> {
+    let lines = repo_file::read_file_into_lines(repo_file_path)?;
+    let contents = migrate_repo_file_lines(&lines)?;
+    let extends = repo_file::get_repo_file_toml_table(&lines)?.contains_key("extends");
+    std::fs::write(repo_file_path, contents)
+        .map_err(|e| ioerr!("Failed to write {}: {}", repo_file_path, e))?;
+    Ok(extends)
+}
+
+pub fn run_verify(
+    cmd: &mut MgtCommandVerify,
+) {
+    let repo_file_path = if cmd.repo_file.len() < 1 {
+        die!("Must provide repo path argument");
+    } else {
+        cmd.repo_file[0].clone()
+    };
+    if cmd.migrate {
+        let extends = match migrate_repo_file(&repo_file_path) {
+            Ok(extends) => extends,
+            Err(e) => die!("Failed to migrate {}:\n{}", repo_file_path, e),
+        };
+        println!("Rewrote {} as a version {} repo file", repo_file_path, repo_file::LATEST_REPO_FILE_VERSION);
+        if extends {
+            println!("A repo file can only extend repo files of the same version, so migrate the repo files it extends too");
+        }
+        return;
+    }
+    let repo_file = repo_file::parse_repo_file_from_toml_path(&repo_file_path);
+    // show what the repo file is after merging in the repo files it extends
+    if let Some(ref extends) = repo_file.extends {
+        println!("{} extends {}. The merged repo file is:\n", repo_file_path, extends.join(", "));
+        println!("{}", repo_file::generate_repo_file_toml(&repo_file));
+    }
+    let mut file_ops = get_vec_of_file_ops(&repo_file);
+    let filter_rules = match make_filter_rules(&mut file_ops) {
+        Ok(rules) => rules,
+        Err(e) => die!("Failed to make filter rules from {}:\n{}", repo_file_path, e),
     };
+    let all_files = get_files_to_verify(cmd.stdin);
 
     // eprintln!("ALL FILES: {:?}", all_files);
     let remaining_files = apply_expected_gitfilter(&all_files, &filter_rules);
@@ -229,6 +420,10 @@ pub fn run_verify(
             println!("{}", file);
         }
 
+        print_mailmap_preview(&repo_file);
+        if !cmd.stdin {
+            print_submodule_preview(&repo_file, &filter_rules);
+        }
         return;
     }
 
@@ -240,4 +435,48 @@ pub fn run_verify(
             println!("{}", file);
         }
     }
+    print_mailmap_preview(&repo_file);
+    if !cmd.stdin {
+        print_submodule_preview(&repo_file, &filter_rules);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::migrate_repo_file_lines;
+    use crate::repo_file::{get_repo_file_version, get_repo_file_toml_table, parse_repo_file_from_toml_table, PathRule};
+
+    fn to_lines(s: &str) -> Vec<String> {
+        s.lines().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn migrated_repo_files_keep_the_order_of_version_1() {
+        let lines = to_lines(r#"
+            [repo]
+            remote = "https://github.com/myorg/lib"
+            include = ["README.md", "docs/*.md"]
+            exclude = "${LIB}/src/generated/"
+
+            [vars]
+            LIB = "lib"
+
+            [include_as]
+            "${LIB}/src/" = "src/"
+        "#);
+        let migrated = to_lines(&migrate_repo_file_lines(&lines).unwrap());
+        assert_eq!(migrated[0], "version = 2");
+        assert_eq!(get_repo_file_version(&migrated).unwrap(), 2);
+        let toml_table = get_repo_file_toml_table(&migrated).unwrap();
+        assert!(toml_table.contains_key("vars"));
+        let repo_file = parse_repo_file_from_toml_table(2, &toml_table).unwrap();
+        assert_eq!(repo_file.remote_repo.unwrap(), "https://github.com/myorg/lib");
+        assert_eq!(repo_file.rules.unwrap(), vec![
+            PathRule::Rename("${LIB}/src/".into(), "src/".into()),
+            PathRule::Exclude("${LIB}/src/generated/".into()),
+            PathRule::Include("README.md".into()),
+            PathRule::Include("docs/*.md".into()),
+        ]);
+        assert!(migrate_repo_file_lines(&migrated).is_err());
+    }
 }
@@ -0,0 +1,291 @@
+use std::path::{Path, PathBuf};
+use std::io;
+use std::str::FromStr;
+use toml::Value;
+use toml::map::Map;
+
+use super::ioerre;
+use super::ioerr;
+use super::sync::{SyncPolicy, MergePolicy};
+
+/// the file at the root of a repository that has the
+/// settings that are shared by all of its repo files
+pub const WORKSPACE_FILE_NAME: &str = "mgt.toml";
+
+/// the workspace file in this directory, or in one of its parents.
+/// the search stops at the root of the git repository
+pub fn find_workspace_file(start_dir: &Path) -> Option<PathBuf> {
+    for dir in start_dir.ancestors() {
+        let workspace_file = dir.join(WORKSPACE_FILE_NAME);
+        if workspace_file.is_file() {
+            return Some(workspace_file);
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    None
+}
+
+pub fn read_workspace_file(path: &Path) -> io::Result<Map<String, Value>> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        ioerr!("Failed to read {}: {}", path.display(), e)
+    })?;
+    match contents.parse::<Value>() {
+        Ok(Value::Table(t)) => Ok(t),
+        Ok(_) => ioerre!("{} must be a toml table", path.display()),
+        Err(e) => ioerre!("Failed to parse {}: {}", path.display(), e),
+    }
+}
+
+pub const WORKSPACE_KEYS: &[&str] = &["vars", "sync", "subrepo"];
+pub const SUBREPO_KEYS: &[&str] = &["name", "repo_file", "groups", "sync"];
+pub const SYNC_KEYS: &[&str] = &["on_remote_ahead", "on_local_ahead", "on_diverged", "merge"];
+
+/// what `mgt sync --non-interactive` does with a subrepo, unless
+/// the policies are given on the command line
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SyncDefaults {
+    pub on_remote_ahead: Option<SyncPolicy>,
+    pub on_local_ahead: Option<SyncPolicy>,
+    pub on_diverged: Option<SyncPolicy>,
+    pub merge: Option<MergePolicy>,
+}
+
+impl SyncDefaults {
+    /// the policies of self, or else the policies of `other`
+    pub fn or(self, other: SyncDefaults) -> SyncDefaults {
+        SyncDefaults {
+            on_remote_ahead: self.on_remote_ahead.or(other.on_remote_ahead),
+            on_local_ahead: self.on_local_ahead.or(other.on_local_ahead),
+            on_diverged: self.on_diverged.or(other.on_diverged),
+            merge: self.merge.or(other.merge),
+        }
+    }
+}
+
+/// one of the `[[subrepo]]`s of the workspace file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subrepo {
+    pub name: String,
+    /// relative to the directory of the workspace file
+    pub repo_file: PathBuf,
+    pub groups: Vec<String>,
+    /// the `sync` of the subrepo, combined with the `[sync]` of the workspace
+    pub sync: SyncDefaults,
+}
+
+/// the subrepos that are listed in a workspace file,
+/// ie: the mgt.toml at the root of a repository
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workspace {
+    pub path: PathBuf,
+    pub subrepos: Vec<Subrepo>,
+}
+
+impl Workspace {
+    /// every subrepo that is in the group,
+    /// or every subrepo if group is None
+    pub fn get_subrepos(&self, group: Option<&str>) -> io::Result<Vec<&Subrepo>> {
+        let group = match group {
+            Some(group) => group,
+            None => return Ok(self.subrepos.iter().collect()),
+        };
+        let subrepos: Vec<&Subrepo> = self.subrepos.iter()
+            .filter(|subrepo| subrepo.groups.iter().any(|g| g == group)).collect();
+        if subrepos.is_empty() {
+            let mut groups: Vec<&str> = self.subrepos.iter()
+                .flat_map(|subrepo| subrepo.groups.iter().map(|g| g.as_str())).collect();
+            groups.sort_unstable();
+            groups.dedup();
+            return ioerre!("No subrepo of {} is in the group '{}'. The groups are: {}",
+                self.path.display(), group, groups.join(", "));
+        }
+        Ok(subrepos)
+    }
+}
+
+/// the workspace that the current directory is in
+pub fn get_current_workspace() -> io::Result<Workspace> {
+    let current_dir = std::env::current_dir()?;
+    match find_workspace_file(&current_dir) {
+        Some(path) => parse_workspace_file(&path),
+        None => ioerre!("Failed to find a {} in {} or any of its parents", WORKSPACE_FILE_NAME, current_dir.display()),
+    }
+}
+
+pub fn parse_workspace_file(path: &Path) -> io::Result<Workspace> {
+    let workspace_table = read_workspace_file(path)?;
+    parse_workspace_table(path, &workspace_table).map_err(|e| {
+        ioerr!("Invalid {}: {}", path.display(), e)
+    })
+}
+
+fn check_keys(table: &Map<String, Value>, allowed: &[&str], location: &str) -> io::Result<()> {
+    match table.keys().find(|key| !allowed.contains(&key.as_str())) {
+        Some(key) => ioerre!("Unknown key '{}' in {}. Expected one of: {}", key, location, allowed.join(", ")),
+        None => Ok(()),
+    }
+}
+
+fn parse_sync_defaults(value: &Value, location: &str) -> io::Result<SyncDefaults> {
+    let table = match value.as_table() {
+        Some(t) => t,
+        None => return ioerre!("{} must be a table", location),
+    };
+    check_keys(table, SYNC_KEYS, location)?;
+    let get_str = |key: &str| match table.get(key) {
+        None => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.as_str())),
+        Some(_) => ioerre!("{}.{} must be a string", location, key),
+    };
+    let get_policy = |key: &str| -> io::Result<Option<SyncPolicy>> {
+        get_str(key)?.map(|s| SyncPolicy::from_str(s).map_err(|e| ioerr!("{}.{}: {}", location, key, e))).transpose()
+    };
+    Ok(SyncDefaults {
+        on_remote_ahead: get_policy("on_remote_ahead")?,
+        on_local_ahead: get_policy("on_local_ahead")?,
+        on_diverged: get_policy("on_diverged")?,
+        merge: get_str("merge")?.map(|s| MergePolicy::from_str(s).map_err(|e| ioerr!("{}.merge: {}", location, e))).transpose()?,
+    })
+}
+
+/// the repo files of the subrepos are relative to the workspace file
+pub fn parse_workspace_table(path: &Path, workspace_table: &Map<String, Value>) -> io::Result<Workspace> {
+    check_keys(workspace_table, WORKSPACE_KEYS, "the workspace")?;
+    let default_sync = match workspace_table.get("sync") {
+        Some(sync) => parse_sync_defaults(sync, "[sync]")?,
+        None => SyncDefaults::default(),
+    };
+    let workspace_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let subrepo_tables = match workspace_table.get("subrepo") {
+        None => vec![],
+        Some(Value::Array(tables)) => tables.clone(),
+        Some(_) => return ioerre!("subrepo must be an array of tables, ie: [[subrepo]]"),
+    };
+
+    let mut subrepos: Vec<Subrepo> = vec![];
+    for (i, table) in subrepo_tables.iter().enumerate() {
+        let location = format!("subrepo {}", i + 1);
+        let t = match table.as_table() {
+            Some(t) => t,
+            None => return ioerre!("{} must be a table", location),
+        };
+        check_keys(t, SUBREPO_KEYS, &location)?;
+        let repo_file = match t.get("repo_file").and_then(|v| v.as_str()) {
+            Some(repo_file) => repo_file,
+            None => return ioerre!("{} must have a repo_file", location),
+        };
+        // the name of a subrepo is its repo file without the extension by default
+        let name = match t.get("name") {
+            Some(Value::String(name)) => name.clone(),
+            Some(_) => return ioerre!("The name of {} must be a string", location),
+            None => Path::new(repo_file).file_stem()
+                .map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default(),
+        };
+        if subrepos.iter().any(|subrepo| subrepo.name == name) {
+            return ioerre!("There is more than one subrepo named '{}'", name);
+        }
+        let groups = match t.get("groups") {
+            None => vec![],
+            Some(Value::String(group)) => vec![group.clone()],
+            Some(Value::Array(groups)) if groups.iter().all(|g| g.is_str()) => {
+                groups.iter().filter_map(|g| g.as_str()).map(|g| g.to_string()).collect()
+            }
+            Some(_) => return ioerre!("The groups of {} must be a string, or a list of strings", name),
+        };
+        let sync = match t.get("sync") {
+            Some(sync) => parse_sync_defaults(sync, &format!("the sync of {}", name))?,
+            None => SyncDefaults::default(),
+        };
+        subrepos.push(Subrepo {
+            name,
+            repo_file: workspace_dir.join(repo_file),
+            groups,
+            sync: sync.or(default_sync),
+        });
+    }
+    Ok(Workspace { path: path.to_path_buf(), subrepos })
+}
+
+/// the `[vars]` of the workspace file that the repo file is in,
+/// or an empty table if it is not in a workspace
+pub fn get_workspace_vars(repo_file_path: &Path) -> io::Result<Map<String, Value>> {
+    let repo_file_dir = std::fs::canonicalize(repo_file_path)?
+        .parent().map(|p| p.to_path_buf()).unwrap_or_default();
+    let workspace_file = match find_workspace_file(&repo_file_dir) {
+        Some(path) => path,
+        None => return Ok(Map::new()),
+    };
+    let mut workspace = read_workspace_file(&workspace_file)?;
+    match workspace.remove("vars") {
+        None => Ok(Map::new()),
+        Some(Value::Table(vars)) => Ok(vars),
+        Some(_) => ioerre!("[vars] of {} must be a table", workspace_file.display()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(contents: &str) -> io::Result<Workspace> {
+        let table = contents.parse::<Value>().unwrap().as_table().unwrap().clone();
+        parse_workspace_table(Path::new("/repo/mgt.toml"), &table)
+    }
+
+    #[test]
+    fn workspace_subrepos_work() {
+        let workspace = parse(r#"
+            [sync]
+            on_remote_ahead = "pull"
+
+            [[subrepo]]
+            repo_file = "lib/mylib.rf"
+            groups = ["libs"]
+
+            [[subrepo]]
+            name = "docs"
+            repo_file = "repo_files/documentation.rf"
+            groups = ["libs", "public"]
+            sync = { on_remote_ahead = "skip", on_local_ahead = "push", merge = "always" }
+
+            [[subrepo]]
+            repo_file = "tools.rf"
+        "#).unwrap();
+        assert_eq!(workspace.subrepos.len(), 3);
+        let mylib = &workspace.subrepos[0];
+        assert_eq!(mylib.name, "mylib");
+        assert_eq!(mylib.repo_file, PathBuf::from("/repo/lib/mylib.rf"));
+        assert_eq!(mylib.sync.on_remote_ahead, Some(SyncPolicy::Pull));
+        assert_eq!(mylib.sync.on_local_ahead, None);
+        let docs = &workspace.subrepos[1];
+        assert_eq!(docs.name, "docs");
+        assert_eq!(docs.sync, SyncDefaults {
+            on_remote_ahead: Some(SyncPolicy::Skip),
+            on_local_ahead: Some(SyncPolicy::Push),
+            on_diverged: None,
+            merge: Some(MergePolicy::Always),
+        });
+
+        let names = |group| -> Vec<String> {
+            workspace.get_subrepos(group).unwrap().iter().map(|s| s.name.clone()).collect()
+        };
+        assert_eq!(names(Some("libs")), vec!["mylib", "docs"]);
+        assert_eq!(names(Some("public")), vec!["docs"]);
+        assert_eq!(names(None), vec!["mylib", "docs", "tools"]);
+        let err = workspace.get_subrepos(Some("apps")).unwrap_err().to_string();
+        assert!(err.contains("The groups are: libs, public"));
+    }
+
+    #[test]
+    fn invalid_workspaces_are_errors() {
+        let err = |contents: &str| parse(contents).unwrap_err().to_string();
+        assert!(err("[[subrepo]]\nname = \"a\"").contains("must have a repo_file"));
+        assert!(err("[[subrepo]]\nrepo_file = \"a.rf\"\n[[subrepo]]\nrepo_file = \"x/a.rf\"")
+            .contains("more than one subrepo named 'a'"));
+        assert!(err("[[subrepo]]\nrepo_file = \"a.rf\"\nremote = \"x\"").contains("Unknown key 'remote'"));
+        assert!(err("[sync]\non_local_ahead = true").contains("[sync].on_local_ahead must be a string"));
+        assert!(err("[sync]\nmerge = \"sometimes\"").contains("not a valid merge policy"));
+    }
+}
@@ -573,3 +573,112 @@ function setup() {
     [[ $status == "0" ]]
     [[ $output == *"$commit_to_take"* ]]
 }
+
+@test '--format json outputs the commits to take and the fork point' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    remote_fork="$(git rev-parse HEAD)"
+    cd "$curr_dir"
+
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    
+
+    include=[\"abc.txt\", \"xyz.txt\"]
+    "
+    echo "$repo_file_contents" > repo_file.sh
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    local_fork="$(git rev-parse HEAD)"
+    echo "xyz" > xyz.txt && git add xyz.txt && git commit -m "xyz"
+    commit_to_take="$(git rev-parse HEAD)"
+
+    run $PROGRAM_PATH check repo_file.sh --local --format json
+    echo "$output"
+    [[ $status == "0" ]]
+    # only json should be output, no human readable text
+    [[ $output == "["* ]]
+    [[ $output == *"]" ]]
+    [[ $output != *"Checking"* ]]
+    [[ $output == *"\"repo_file\": \"repo_file.sh\""* ]]
+    [[ $output == *"\"oid\": \"$commit_to_take\""* ]]
+    [[ $output == *"\"oid\": \"$local_fork\""* ]]
+    [[ $output == *"\"oid\": \"$remote_fork\""* ]]
+    [[ $output == *"\"summary\": \"xyz\""* ]]
+    [[ $output == *"\"is_merge\": false"* ]]
+    [[ $output == *"\"commits_to_take\": ["* ]]
+    [[ $output == *"\"sync_type\": \"local_ahead\""* ]]
+}
+
+@test 'reports every remote that can be pushed to on its own' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    cd "$curr_dir"
+
+    repo_file_contents="
+    include=[\"abc.txt\", \"xyz.txt\"]
+
+    [remotes.mirror1]
+    url = \"..$SEP$test_remote_repo2\"
+
+    [remotes.mirror2]
+    url = \"..$SEP$test_remote_repo2\"
+
+    [remotes.readonly]
+    url = \"..$SEP$test_remote_repo2\"
+    push = false
+    "
+    echo "$repo_file_contents" > repo_file.sh
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    echo "xyz" > xyz.txt && git add xyz.txt && git commit -m "xyz"
+    commit_to_take="$(git rev-parse --short HEAD)"
+
+    run $PROGRAM_PATH check repo_file.sh --local
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ $output == *"Remote: mirror1"* ]]
+    [[ $output == *"Remote: mirror2"* ]]
+    [[ $output != *"Remote: readonly"* ]]
+    [[ "$(echo "$output" | grep -c "$commit_to_take xyz")" == "2" ]]
+}
+
+@test 'can check the subrepos of mgt.toml' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    cd "$curr_dir"
+
+    mkdir -p rfs
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    include=[\"abc.txt\", \"xyz.txt\"]
+    "
+    echo "$repo_file_contents" > rfs/a.rf
+    echo "$repo_file_contents" > rfs/b.rf
+    workspace_contents="
+    [[subrepo]]
+    repo_file = \"rfs/a.rf\"
+    groups = [\"libs\"]
+
+    [[subrepo]]
+    repo_file = \"rfs/b.rf\"
+    "
+    echo "$workspace_contents" > mgt.toml
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    echo "xyz" > xyz.txt && git add xyz.txt && git commit -m "xyz"
+
+    run $PROGRAM_PATH check --all-subrepos --local
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ $output == *"a.rf"* ]]
+    [[ $output == *"b.rf"* ]]
+
+    run $PROGRAM_PATH check --group libs --local
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ $output == *"a.rf"* ]]
+    [[ $output != *"b.rf"* ]]
+}
@@ -143,3 +143,83 @@ function teardown() {
     [[ "$status" != "0" ]]
     [[ "$output" == *"Failed to checkout"* ]]
 }
+
+@test 'diff-log fullbase mode shows every fork point' {
+    git checkout -b branch_a
+    git checkout -b branch_b
+    git checkout branch_a
+    echo "a1" > a1.txt && git add a1.txt && git commit -m "a1"
+    echo "shared" > shared.txt && git add shared.txt && git commit -m "shared on a"
+    echo "a3" > a3.txt && git add a3.txt && git commit -m "a3"
+    git checkout branch_b
+    echo "b1" > b1.txt && git add b1.txt && git commit -m "b1"
+    echo "shared" > shared.txt && git add shared.txt && git commit -m "shared on b"
+    echo "b3" > b3.txt && git add b3.txt && git commit -m "b3"
+
+    run $PROGRAM_PATH diff-log branch_a branch_b -m fullbase --term-width 80
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$output" == *"shared on a"*"<===>"*"shared on b"* ]]
+    [[ "$output" == *"a1"*"b1"* ]]
+    # the initial commit is the oldest fork point
+    [[ "$output" == *"initial commit"*"<===>"*"initial commit"* ]]
+
+    run $PROGRAM_PATH diff-log branch_a branch_b -m fullbase --format json
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$output" == *"\"segments\""* ]]
+}
+
+@test 'reverse-rf prints a repo file that splits the split out paths back where they came from' {
+    repo_file_contents="
+    include = \"lib/secret/public.txt\"
+    exclude = \"lib/secret/\"
+    [include_as]
+    \"lib/\" = \" \"
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    mkdir -p lib/secret
+    echo "a" > lib/a.txt
+    echo "private" > lib/secret/private.txt
+    echo "public" > lib/secret/public.txt
+    git add lib/ repo_file.rf && git commit -m "lib files"
+
+    run $PROGRAM_PATH reverse-rf repo_file.rf
+    echo "$output"
+    [[ $status == "0" ]]
+    $PROGRAM_PATH reverse-rf repo_file.rf > "$BATS_TMPDIR/reversed.rf"
+
+    run $PROGRAM_PATH split-out repo_file.rf -o split
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$(git ls-tree -r --name-only split)" == "a.txt
+secret/public.txt" ]]
+
+    # a file that was added to the excluded directory is not split back in
+    git checkout split
+    echo "new" > secret/new.txt
+    git add secret/ && git commit -m "new secret"
+    run $PROGRAM_PATH split-out "$BATS_TMPDIR/reversed.rf" -o back
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$(git ls-tree -r --name-only back)" == "lib/a.txt
+lib/secret/public.txt" ]]
+    rm "$BATS_TMPDIR/reversed.rf"
+}
+
+@test 'reverse-rf explains why a repo file cannot be reversed' {
+    repo_file_contents="
+    include = \"README.md\"
+    [include_as]
+    \"lib/\" = \" \"
+    \"regex:^other/(.*)/src/\" = \"\$1/\"
+    "
+    echo "$repo_file_contents" > repo_file.rf
+
+    run $PROGRAM_PATH reverse-rf repo_file.rf
+    echo "$output"
+    [[ $status != "0" ]]
+    [[ "$output" == *"repo_file.rf cannot be reversed"* ]]
+    [[ "$output" == *"'lib/README.md' and 'README.md' are both put at 'README.md'"* ]]
+    [[ "$output" == *"a regex replacement can drop or reorder parts of the path"* ]]
+}
@@ -888,4 +888,69 @@ function teardown() {
     echo "$output"
     [[ $status == "0" ]]
     [[ -d test_remote_repo2/ ]]
-}
\ No newline at end of file
+}
+
+@test 'can carry tags of the remote repo across with a tag prefix' {
+    repo_file_contents="
+    tags = \"mylib-\"
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    name = \"inbranch\"
+    [include_as]
+    \"lib/\" = \" \"
+    "
+    echo "$repo_file_contents" > repo_file.sh
+
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    git tag v1
+    echo "a" > a.txt && git add a.txt && git commit -m "a"
+    git tag -a v2 -m "release two"
+    cd "$curr_dir"
+
+    run $PROGRAM_PATH split-in repo_file.sh --verbose
+    echo "$output"
+    [[ $status == "0" ]]
+
+    echo "$(git tag)"
+    [[ "$(git cat-file -t mylib-v1)" == "commit" ]]
+    [[ "$(git cat-file -t mylib-v2)" == "tag" ]]
+    [[ "$(git rev-parse mylib-v2^{commit})" == "$(git rev-parse inbranch)" ]]
+    [[ "$(git show mylib-v1:lib/test_remote_repo2.txt)" == *"test_remote_repo2"* ]]
+    # the remote tags themselves are not kept around
+    [[ -z "$(git tag -l v1 v2)" ]]
+    [[ -z "$(git for-each-ref refs/mgt-split-in-tags)" ]]
+}
+
+@test 'tags that were carried across before are updated' {
+    repo_file_contents="
+    tags = true
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    [include_as]
+    \"lib/\" = \" \"
+    "
+    echo "$repo_file_contents" > repo_file.sh
+
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    git tag v1
+    cd "$curr_dir"
+
+    run $PROGRAM_PATH split-in repo_file.sh -o inbranch1
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$(git rev-parse v1)" == "$(git rev-parse inbranch1)" ]]
+
+    # the remote moves its tag to a new commit
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    echo "a" > a.txt && git add a.txt && git commit -m "a"
+    git tag -f v1
+    cd "$curr_dir"
+
+    run $PROGRAM_PATH split-in repo_file.sh -o inbranch2
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$(git rev-parse v1)" == "$(git rev-parse inbranch2)" ]]
+    [[ "$(git rev-parse v1)" != "$(git rev-parse inbranch1)" ]]
+}
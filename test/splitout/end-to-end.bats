@@ -516,6 +516,106 @@ function teardown() {
     [[ -f lib/a2.txt ]]
 }
 
+@test 'can include_as and exclude using glob patterns' {
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    [include_as]
+    \"glob:a/*/src/**\" = \"lib\"
+
+
+    exclude = [
+        \"glob:**/*.log\",
+    ]
+    "
+
+    echo "$repo_file_contents" > repo_file.sh
+
+    mkdir -p a/x/src/deep
+    mkdir -p a/y/src
+    mkdir -p a/y/docs
+    echo "x" > a/x/src/x.txt
+    echo "deep" > a/x/src/deep/deep.txt
+    echo "log" > a/x/src/deep/deep.log
+    echo "y" > a/y/src/y.txt
+    echo "docs" > a/y/docs/docs.txt
+    git add a
+    git commit -m "a"
+
+    run $PROGRAM_PATH split-out repo_file.sh --verbose
+
+    echo "$output"
+    echo "$(find -L .)"
+    [[ $status == "0" ]]
+
+    # the prefix before the first wildcard (a/) is what gets renamed
+    [[ ! -d a ]]
+    [[ -f lib/x/src/x.txt ]]
+    [[ -f lib/x/src/deep/deep.txt ]]
+    [[ -f lib/y/src/y.txt ]]
+    [[ ! -f lib/x/src/deep/deep.log ]]
+    [[ ! -d lib/y/docs ]]
+}
+
+@test 'can include and rename using regex patterns' {
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    [include_as]
+    'regex:^pkgs/([^/]+)/lib/' = 'libs/\$1/'
+
+
+    include = [
+        'regex:^README\.(md|txt)$',
+    ]
+    "
+
+    echo "$repo_file_contents" > repo_file.sh
+
+    mkdir -p pkgs/one/lib
+    mkdir -p pkgs/one/test
+    mkdir -p pkgs/two/lib
+    echo "one" > pkgs/one/lib/one.txt
+    echo "test" > pkgs/one/test/test.txt
+    echo "two" > pkgs/two/lib/two.txt
+    echo "readme" > README.md
+    echo "readme" > README.rst
+    git add pkgs README.md README.rst
+    git commit -m "pkgs"
+
+    run $PROGRAM_PATH split-out repo_file.sh --verbose
+
+    echo "$output"
+    echo "$(find -L .)"
+    [[ $status == "0" ]]
+
+    [[ ! -d pkgs ]]
+    [[ -f libs/one/one.txt ]]
+    [[ -f libs/two/two.txt ]]
+    [[ ! -d libs/one/test ]]
+    [[ -f README.md ]]
+    [[ ! -f README.rst ]]
+}
+
+@test 'invalid regex patterns give a proper error' {
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+
+
+    include = 'regex:a/(b'
+    "
+
+    echo "$repo_file_contents" > repo_file.sh
+
+    run $PROGRAM_PATH split-out repo_file.sh --verbose
+
+    echo "$output"
+    [[ $status != "0" ]]
+    [[ $output == *"a/(b"* ]]
+    [[ "$(git branch --show-current)" == "master" ]]
+}
+
 @test 'dont need a repo_name if providing a remote_repo uri (out)' {
     # from test_remote_repo, we split out the file test_remote_repo.txt
     # and into a repo called test_remote_repo2:
@@ -959,3 +1059,728 @@ function teardown() {
     [[ $output == *"Success"* ]]
     [[ $status == "0" ]]
 }
+
+@test 'records which commits were split out in the commit map' {
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+
+    include = \"a.txt\"
+    "
+    echo "$repo_file_contents" > repo_file.sh
+    echo "a" > a.txt && git add a.txt && git commit -m "a"
+    monorepo_a="$(git rev-parse HEAD)"
+    echo "b" > b.txt && git add b.txt && git commit -m "b"
+
+    run $PROGRAM_PATH split-out repo_file.sh --output-branch outbranch
+    echo "$output"
+    [[ $status == "0" ]]
+
+    cat .git/mgt/commit-map
+    grep -q "^$monorepo_a $(git rev-parse outbranch)$" .git/mgt/commit-map
+    # commits that were filtered out are not in the map
+    [[ "$(wc -l < .git/mgt/commit-map)" == "1" ]]
+}
+
+@test 'can carry tags across with a tag mapping' {
+    repo_file_contents="
+    include = \"lib/\"
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    name = \"outbranch\"
+    [tags]
+    \"v*\" = \"mylib-v*\"
+    "
+    echo "$repo_file_contents" > repo_file.sh
+
+    mkdir -p lib/
+    echo "libfile1" > lib/libfile1.txt && git add lib/libfile1.txt && git commit -m "libfile1"
+    git tag v1
+    echo "b" > b.txt && git add b.txt && git commit -m "not lib"
+    git tag v3
+    echo "libfile2" > lib/libfile2.txt && git add lib/libfile2.txt && git commit -m "libfile2"
+    git tag -a v2 -m "release two"
+    git tag other
+
+    run $PROGRAM_PATH split-out repo_file.sh --verbose
+    echo "$output"
+    [[ $status == "0" ]]
+
+    echo "$(git tag)"
+    # lightweight and annotated tags are rewritten
+    [[ "$(git cat-file -t mylib-v1)" == "commit" ]]
+    [[ "$(git cat-file -t mylib-v2)" == "tag" ]]
+    [[ "$(git tag -l --format='%(contents)' mylib-v2)" == *"release two"* ]]
+    # and they point to the filtered commits
+    [[ "$(git log --format=%H outbranch)" == *"$(git rev-parse mylib-v1^{commit})"* ]]
+    [[ "$(git rev-parse mylib-v2^{commit})" == "$(git rev-parse outbranch)" ]]
+    [[ "$(git show mylib-v1:lib/libfile1.txt)" == "libfile1" ]]
+    # tags of commits that were filtered out are not kept
+    [[ "$(git tag)" != *"mylib-v3"* ]]
+    # tags that dont match the mapping are not kept
+    [[ "$(git tag)" != *"mylib-other"* ]]
+    # and the original tags are left alone
+    [[ "$(git rev-parse v1)" != "$(git rev-parse mylib-v1)" ]]
+}
+
+@test 'does not carry tags across by default' {
+    repo_file_contents="
+    include = \"lib/\"
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    name = \"outbranch\"
+    "
+    echo "$repo_file_contents" > repo_file.sh
+
+    mkdir -p lib/
+    echo "libfile1" > lib/libfile1.txt && git add lib/libfile1.txt && git commit -m "libfile1"
+    git tag v1
+
+    run $PROGRAM_PATH split-out repo_file.sh --verbose
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$(git tag)" == "v1" ]]
+}
+
+@test 'can drop and squash commits according to the commits section' {
+    repo_file_contents="
+    include = \"lib/\"
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    [commits]
+    drop = [\"author:^dependabot\", \"message:\\\\[skip-export\\\\]\"]
+    squash = \"message:^fixup\"
+    "
+    echo "$repo_file_contents" > repo_file.sh
+
+    mkdir -p lib/
+    echo "libfile1" > lib/libfile1.txt && git add lib/libfile1.txt && git commit -m "libfile1"
+    echo "libfile2" > lib/libfile2.txt && git add lib/libfile2.txt && git commit -m "libfile2 [skip-export]"
+    echo "libfile3" > lib/libfile3.txt && git add lib/libfile3.txt && git commit -m "libfile3" --author "dependabot <bot@example.com>"
+    echo "libfile4" > lib/libfile4.txt && git add lib/libfile4.txt && git commit -m "libfile4"
+    echo "fixed" >> lib/libfile4.txt && git add lib/libfile4.txt && git commit -m "fixup libfile4"
+
+    run $PROGRAM_PATH split-out repo_file.sh --output-branch outbranch
+    echo "$output"
+    [[ $status == "0" ]]
+
+    git checkout outbranch
+    git log --oneline
+    [[ "$(git log --format=%s)" == "libfile4
+libfile1" ]]
+    [[ "$(cat lib/libfile4.txt)" == "libfile4
+fixed" ]]
+    [[ ! -f lib/libfile2.txt ]]
+    [[ ! -f lib/libfile3.txt ]]
+}
+
+@test 'can rewrite authors and committers with a mailmap' {
+    repo_file_contents="
+    include = \"lib/\"
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    [mailmap]
+    file = \"public.mailmap\"
+    \"<jane@corp.internal>\" = \"Jane Doe <jane@example.com>\"
+    "
+    echo "$repo_file_contents" > repo_file.sh
+    echo "Bob Smith <bob@example.com> <bob@corp.internal>" > public.mailmap
+    git add public.mailmap && git commit -m "mailmap"
+
+    mkdir -p lib/
+    echo "libfile1" > lib/libfile1.txt && git add lib/libfile1.txt
+    git commit -m "libfile1" --author "jane <jane@corp.internal>"
+    echo "libfile2" > lib/libfile2.txt && git add lib/libfile2.txt
+    GIT_COMMITTER_NAME="bob" GIT_COMMITTER_EMAIL="bob@corp.internal" git commit -m "libfile2" --author "someone <someone@example.com>"
+
+    run $PROGRAM_PATH verify-rf repo_file.sh
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ $output == *"jane <jane@corp.internal> -> Jane Doe <jane@example.com>"* ]]
+    [[ $output == *"bob <bob@corp.internal> -> Bob Smith <bob@example.com>"* ]]
+    [[ $output != *"someone <someone@example.com> ->"* ]]
+
+    run $PROGRAM_PATH split-out repo_file.sh --output-branch outbranch
+    echo "$output"
+    [[ $status == "0" ]]
+
+    git log --format="%an <%ae> %cn <%ce>" outbranch
+    [[ "$(git log --format="%an <%ae>" -n 1 outbranch~1)" == "Jane Doe <jane@example.com>" ]]
+    [[ "$(git log --format="%an <%ae>" -n 1 outbranch)" == "someone <someone@example.com>" ]]
+    [[ "$(git log --format="%cn <%ce>" -n 1 outbranch)" == "Bob Smith <bob@example.com>" ]]
+}
+
+@test 'can rewrite commit messages and add an origin trailer' {
+    repo_file_contents="
+    include = \"lib/\"
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    [message]
+    trailer = true
+    [message.replace]
+    ' ?\\(?JIRA-[0-9]+\\)?' = \"\"
+    "
+    echo "$repo_file_contents" > repo_file.sh
+
+    mkdir -p lib/
+    echo "libfile1" > lib/libfile1.txt && git add lib/libfile1.txt && git commit -m "libfile1 (JIRA-123)"
+    monorepo_commit="$(git rev-parse HEAD)"
+
+    run $PROGRAM_PATH split-out repo_file.sh --output-branch outbranch
+    echo "$output"
+    [[ $status == "0" ]]
+
+    git log -n 1 outbranch
+    [[ "$(git log --format=%B -n 1 outbranch)" == "libfile1
+
+Mgt-Origin: $monorepo_commit" ]]
+}
+
+@test 'can split out several refs in one pass' {
+    repo_file_contents="
+    include = \"lib/\"
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    [refs]
+    include = \"refs/heads/release/*\"
+    "
+    echo "$repo_file_contents" > repo_file.sh
+
+    mkdir -p lib/
+    echo "libfile1" > lib/libfile1.txt && git add lib/libfile1.txt && git commit -m "libfile1"
+    git branch release/1.0
+    git branch release/2.0
+    echo "libfile2" > lib/libfile2.txt && git add lib/libfile2.txt && git commit -m "libfile2"
+    git checkout release/1.0
+    echo "fix" > lib/fix.txt && git add lib/fix.txt && git commit -m "fix on 1.0"
+    echo "other" > other.txt && git add other.txt && git commit -m "other on 1.0"
+    git checkout release/2.0
+    echo "other" > other.txt && git add other.txt && git commit -m "other on 2.0"
+    git checkout master
+
+    run $PROGRAM_PATH split-out repo_file.sh --output-branch outbranch
+    echo "$output"
+    [[ $status == "0" ]]
+
+    git log --oneline --all --graph
+    # the output branch is split out like before
+    [[ "$(git log --format=%s outbranch)" == "libfile2
+libfile1" ]]
+    # the other refs are written under the default namespace
+    [[ "$(git log --format=%s refs/split-out/outbranch/release/1.0)" == "fix on 1.0
+libfile1" ]]
+    [[ "$(git log --format=%s refs/split-out/outbranch/release/2.0)" == "libfile1" ]]
+    # the filtered branches share their history
+    [[ "$(git rev-parse outbranch~1)" == "$(git rev-parse refs/split-out/outbranch/release/2.0)" ]]
+    # and the original branches are left alone
+    [[ "$(git log -n 1 --format=%s release/1.0)" == "other on 1.0" ]]
+}
+
+@test 'can split out notes and annotated tags with the refs section' {
+    repo_file_contents="
+    include = \"lib/\"
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    [refs]
+    include = [\"refs/notes/commits\", \"refs/tags/*\"]
+    namespace = \"refs/split/\"
+    "
+    echo "$repo_file_contents" > repo_file.sh
+
+    mkdir -p lib/
+    echo "libfile1" > lib/libfile1.txt && git add lib/libfile1.txt && git commit -m "libfile1"
+    git notes add -m "note for libfile1"
+    git tag -a v1 -m "version 1"
+    echo "other" > other.txt && git add other.txt && git commit -m "other"
+    git notes add -m "note for other"
+    echo "libfile2" > lib/libfile2.txt && git add lib/libfile2.txt && git commit -m "libfile2"
+    git notes add -m "note for libfile2"
+
+    run $PROGRAM_PATH split-out repo_file.sh --output-branch outbranch
+    echo "$output"
+    [[ $status == "0" ]]
+
+    git log --oneline --all --graph
+    [[ "$(git log --format=%s outbranch)" == "libfile2
+libfile1" ]]
+    # the notes point to the split out commits, and the
+    # note of the commit that was filtered out is dropped
+    [[ "$(git notes --ref split/commits show outbranch)" == "note for libfile2" ]]
+    [[ "$(git notes --ref split/commits show outbranch~1)" == "note for libfile1" ]]
+    [[ "$(git notes --ref split/commits list | wc -l)" == "2" ]]
+    # the tag is written under the namespace, and is still annotated
+    [[ "$(git rev-parse refs/tags/split/v1^{commit})" == "$(git rev-parse outbranch~1)" ]]
+    [[ "$(git cat-file -t refs/tags/split/v1)" == "tag" ]]
+    # and the original notes and tag are left alone
+    [[ "$(git notes show master~1)" == "note for other" ]]
+    [[ "$(git cat-file -t v1)" == "tag" ]]
+}
+
+@test 'can split out incrementally' {
+    repo_file_contents="
+    include = \"lib/\"
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    "
+    echo "$repo_file_contents" > repo_file.sh
+
+    mkdir -p lib/
+    echo "libfile1" > lib/libfile1.txt && git add lib/libfile1.txt && git commit -m "libfile1"
+    echo "other" > other.txt && git add other.txt && git commit -m "other"
+
+    run $PROGRAM_PATH split-out repo_file.sh --output-branch outbranch --incremental
+    echo "$output"
+    [[ $status == "0" ]]
+    git checkout master
+    [[ "$(git log --format=%s outbranch)" == "libfile1" ]]
+    first_tip="$(git rev-parse outbranch)"
+
+    # nothing new to filter leaves the output branch where it was
+    run $PROGRAM_PATH split-out repo_file.sh --output-branch outbranch --incremental
+    echo "$output"
+    [[ $status == "0" ]]
+    git checkout master
+    [[ "$(git rev-parse outbranch)" == "$first_tip" ]]
+
+    echo "libfile2" > lib/libfile2.txt && git add lib/libfile2.txt && git commit -m "libfile2"
+    echo "other2" > other.txt && git add other.txt && git commit -m "other2"
+    run $PROGRAM_PATH split-out repo_file.sh --output-branch outbranch --incremental
+    echo "$output"
+    [[ $status == "0" ]]
+    git checkout master
+
+    git log --oneline --all --graph
+    # the new commits are appended to the previously filtered commits
+    [[ "$(git log --format=%s outbranch)" == "libfile2
+libfile1" ]]
+    [[ "$(git rev-parse outbranch~1)" == "$first_tip" ]]
+    [[ "$(git show outbranch:lib/libfile2.txt)" == "libfile2" ]]
+
+    # and it is the same as splitting out everything again
+    run $PROGRAM_PATH split-out repo_file.sh --output-branch fullbranch
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$(git rev-parse outbranch)" == "$(git rev-parse fullbranch)" ]]
+}
+
+@test 'can split out several repo files in one pass' {
+    echo "
+    include = \"lib/\"
+    [repo]
+    name = \"libout\"
+    " > lib.rf
+    echo "
+    [repo]
+    name = \"docsout\"
+    [include_as]
+    \"docs/\" = \" \"
+    " > docs.rf
+
+    mkdir -p lib/ docs/
+    echo "libfile1" > lib/libfile1.txt && git add lib/libfile1.txt && git commit -m "libfile1"
+    echo "docfile1" > docs/docfile1.txt && git add docs/docfile1.txt && git commit -m "docfile1"
+    echo "libfile2" > lib/libfile2.txt && echo "docfile2" > docs/docfile2.txt
+    git add lib/ docs/ && git commit -m "both"
+
+    run $PROGRAM_PATH split-out lib.rf docs.rf
+    echo "$output"
+    [[ $status == "0" ]]
+    # the current branch is left alone
+    [[ "$(git rev-parse --abbrev-ref HEAD)" == "master" ]]
+    [[ "$(git log -n 1 --format=%s master)" == "both" ]]
+
+    git log --oneline --all --graph
+    [[ "$(git log --format=%s libout)" == "both
+libfile1" ]]
+    [[ "$(git log --format=%s docsout)" == "both
+docfile1" ]]
+    [[ "$(git show docsout:docfile2.txt)" == "docfile2" ]]
+
+    # it is the same as splitting out each repo file by itself
+    run $PROGRAM_PATH split-out lib.rf --output-branch libout2
+    [[ $status == "0" ]]
+    git checkout master
+    run $PROGRAM_PATH split-out docs.rf --output-branch docsout2
+    [[ $status == "0" ]]
+    git checkout master
+    [[ "$(git rev-parse libout)" == "$(git rev-parse libout2)" ]]
+    [[ "$(git rev-parse docsout)" == "$(git rev-parse docsout2)" ]]
+
+    # the output branches must not exist yet
+    run $PROGRAM_PATH split-out lib.rf docs.rf
+    echo "$output"
+    [[ $status != "0" ]]
+    [[ $output == *"Output branch libout of lib.rf already exists"* ]]
+}
+
+@test 'can keep, drop, or merge in submodules' {
+    repo_file_contents="
+    [include_as]
+    \"lib/\" = \" \"
+    "
+    echo "$repo_file_contents" > untouched.rf
+    echo "include = \".gitmodules\"" > keep.rf
+    echo "submodules = \"keep\"" >> keep.rf
+    echo "$repo_file_contents" >> keep.rf
+    echo "submodules = \"keep\"" > keep_excluded.rf
+    echo "$repo_file_contents" >> keep_excluded.rf
+    echo "$repo_file_contents" > drop.rf
+    echo "submodules = \"drop\"" >> drop.rf
+    echo "$repo_file_contents" > merge.rf
+    echo "submodules = \"merge\"" >> merge.rf
+
+    mkdir -p lib/
+    echo "libfile1" > lib/libfile1.txt && git add lib/libfile1.txt && git commit -m "libfile1"
+    git -c protocol.file.allow=always submodule add "$BATS_TMPDIR/$test_remote_repo2" lib/sub
+    git commit -m "add submodule"
+
+    # by default, the gitlink and .gitmodules are filtered like any other file
+    run $PROGRAM_PATH split-out untouched.rf --output-branch untouchedbranch
+    echo "$output"
+    [[ $status == "0" ]]
+    git checkout master
+    [[ "$(git ls-tree untouchedbranch sub)" == "160000 commit "* ]]
+    [[ -z "$(git ls-tree untouchedbranch .gitmodules)" ]]
+
+    run $PROGRAM_PATH split-out keep.rf --output-branch keepbranch
+    echo "$output"
+    [[ $status == "0" ]]
+    git checkout master
+    # the gitlink is moved out of lib/, and .gitmodules has its new path
+    [[ "$(git ls-tree keepbranch sub)" == "160000 commit "* ]]
+    [[ "$(git show keepbranch:.gitmodules)" == *"path = sub"* ]]
+
+    # .gitmodules is only kept if the rules keep it
+    run $PROGRAM_PATH split-out keep_excluded.rf --output-branch keepexcludedbranch
+    echo "$output"
+    [[ $status == "0" ]]
+    git checkout master
+    [[ "$(git ls-tree keepexcludedbranch sub)" == "160000 commit "* ]]
+    [[ -z "$(git ls-tree keepexcludedbranch .gitmodules)" ]]
+
+    run $PROGRAM_PATH split-out drop.rf --output-branch dropbranch
+    echo "$output"
+    [[ $status == "0" ]]
+    git checkout master
+    [[ "$(git log --format=%s dropbranch)" == "libfile1" ]]
+    [[ -z "$(git ls-tree dropbranch sub)" ]]
+    [[ -z "$(git ls-tree dropbranch .gitmodules)" ]]
+
+    run $PROGRAM_PATH split-out merge.rf --output-branch mergebranch
+    echo "$output"
+    [[ $status == "0" ]]
+    git checkout master
+    git log --oneline --graph mergebranch
+    [[ "$(git show mergebranch:sub/test_remote_repo2.txt)" == "name of repo: test_remote_repo2" ]]
+    [[ "$(git log --format=%s mergebranch)" == *"initial commit for test_remote_repo2"* ]]
+    [[ -z "$(git ls-tree mergebranch .gitmodules)" ]]
+}
+
+@test 'version 2 repo files apply their rules in order' {
+    repo_file_contents="
+    version = 2
+
+    [[rule]]
+    rename = \"lib/\"
+    to = \"\"
+    # the secret folder is excluded, except for public.txt
+    [[rule]]
+    exclude = \"lib/secret/\"
+    [[rule]]
+    include = \"lib/secret/public.txt\"
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    # the same rules, but the include comes before the exclude
+    reversed_contents="
+    version = 2
+    [[rule]]
+    rename = \"lib/\"
+    to = \"\"
+    [[rule]]
+    include = \"lib/secret/public.txt\"
+    [[rule]]
+    exclude = \"lib/secret/\"
+    "
+    echo "$reversed_contents" > reversed.rf
+
+    mkdir -p lib/secret
+    echo "libfile1" > lib/libfile1.txt
+    echo "private" > lib/secret/private.txt
+    echo "public" > lib/secret/public.txt
+    git add lib/ && git commit -m "lib files"
+
+    run $PROGRAM_PATH split-out repo_file.rf --output-branch ordered
+    echo "$output"
+    [[ $status == "0" ]]
+    git checkout master
+    [[ "$(git ls-tree -r --name-only ordered)" == *"libfile1.txt"* ]]
+    [[ "$(git ls-tree -r --name-only ordered)" == *"secret/public.txt"* ]]
+    [[ "$(git ls-tree -r --name-only ordered)" != *"secret/private.txt"* ]]
+
+    run $PROGRAM_PATH split-out reversed.rf --output-branch reversed
+    echo "$output"
+    [[ $status == "0" ]]
+    git checkout master
+    [[ "$(git ls-tree -r --name-only reversed)" == *"libfile1.txt"* ]]
+    [[ "$(git ls-tree -r --name-only reversed)" != *"secret/"* ]]
+}
+
+@test 'repo files can be standard toml, and errors show where they are' {
+    repo_file_contents="
+    [repo]
+    name = \"mylib\"
+    include = \"lib/\"
+    exclude = \"lib/secret/\"
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    echo "[repo]" > invalid.rf
+    echo "include [\"lib/\"]" >> invalid.rf
+
+    mkdir -p lib/secret
+    echo "libfile1" > lib/libfile1.txt
+    echo "private" > lib/secret/private.txt
+    git add lib/ && git commit -m "lib files"
+
+    run $PROGRAM_PATH split-out repo_file.rf
+    echo "$output"
+    [[ $status == "0" ]]
+    git checkout master
+    [[ "$(git ls-tree -r --name-only mylib)" == "lib/libfile1.txt" ]]
+
+    run $PROGRAM_PATH split-out invalid.rf
+    echo "$output"
+    [[ $status != "0" ]]
+    [[ "$output" == *"invalid.rf:2:9: error: Invalid toml"* ]]
+}
+
+@test 'problems with a repo file are reported before doing any work' {
+    echo "[repo]" > repo_file.rf
+    echo "nmae = \"mylib\"" >> repo_file.rf
+    echo "include = [\"lib/\", \"lib/a/\"]" >> repo_file.rf
+    echo "include = \"lib/\"" > invalid.rf
+    echo "[include_as]" >> invalid.rf
+    echo "\"a.txt\" = \"b.txt\"" >> invalid.rf
+    echo "\"c.txt\" = \"b.txt\"" >> invalid.rf
+
+    mkdir -p lib/a
+    echo "libfile1" > lib/a/libfile1.txt
+    git add lib/ && git commit -m "lib files"
+
+    run $PROGRAM_PATH verify-rf repo_file.rf
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$output" == *"repo_file.rf:2: warning: Unknown key 'nmae' in [repo]"* ]]
+    [[ "$output" == *"repo_file.rf:3: warning: include 'lib/a/' is already included by 'lib/'"* ]]
+    [[ "$output" == *"lib/a/libfile1.txt"* ]]
+
+    run $PROGRAM_PATH split-out invalid.rf --output-branch newbranch
+    echo "$output"
+    [[ $status != "0" ]]
+    [[ "$output" == *"invalid.rf:4: error: 'a.txt' and 'c.txt' are both kept at 'b.txt'"* ]]
+    [[ -z "$(git branch --list newbranch)" ]]
+}
+
+@test 'split out pushes to every remote that can be pushed to' {
+    git init --bare ../mirror_a.git
+    git init --bare ../mirror_b.git
+    repo_file_contents="
+    [repo]
+    name = \"mylib\"
+    include = \"lib/\"
+
+    [remotes.a]
+    url = \"..${SEP}mirror_a.git\"
+    branch = \"main\"
+
+    [remotes.b]
+    url = \"..${SEP}mirror_b.git\"
+    push = false
+    "
+    echo "$repo_file_contents" > repo_file.rf
+
+    mkdir -p lib
+    echo "libfile1" > lib/libfile1.txt
+    git add lib/ && git commit -m "lib files"
+
+    run $PROGRAM_PATH split-out repo_file.rf
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$output" == *"Pushing mylib to a"* ]]
+    [[ "$(git --git-dir=../mirror_a.git ls-tree -r --name-only main)" == "lib/libfile1.txt" ]]
+    [[ -z "$(git --git-dir=../mirror_b.git branch)" ]]
+
+    git checkout master
+    run $PROGRAM_PATH split-out repo_file.rf --output-branch other --no-push
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$output" != *"Pushing"* ]]
+}
+
+@test 'repo files can extend other repo files' {
+    mkdir -p repo_files
+    echo "exclude = [\"lib/secret/\", \"lib/.internal/\"]" > repo_files/common.rf
+    repo_file_contents="
+    extends = \"common.rf\"
+    [repo]
+    name = \"mylib\"
+    include = \"lib/\"
+    exclude = \"lib/ci.yml\"
+    "
+    echo "$repo_file_contents" > repo_files/mylib.rf
+    echo "extends = \"b.rf\"" > repo_files/a.rf
+    echo "extends = \"a.rf\"" > repo_files/b.rf
+
+    mkdir -p lib/secret lib/.internal
+    echo "libfile1" > lib/libfile1.txt
+    echo "private" > lib/secret/private.txt
+    echo "internal" > lib/.internal/internal.txt
+    echo "ci" > lib/ci.yml
+    git add lib/ repo_files/ && git commit -m "lib files"
+
+    run $PROGRAM_PATH verify-rf repo_files/mylib.rf
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$output" == *"repo_files/mylib.rf extends repo_files/common.rf"* ]]
+    [[ "$output" == *"exclude = [\"lib/secret/\", \"lib/.internal/\", \"lib/ci.yml\"]"* ]]
+
+    run $PROGRAM_PATH split-out repo_files/mylib.rf
+    echo "$output"
+    [[ $status == "0" ]]
+    git checkout master
+    [[ "$(git ls-tree -r --name-only mylib)" == "lib/libfile1.txt" ]]
+
+    run $PROGRAM_PATH verify-rf repo_files/a.rf
+    echo "$output"
+    [[ $status != "0" ]]
+    [[ "$output" == *"extends itself: repo_files/a.rf -> repo_files/b.rf -> repo_files/a.rf"* ]]
+}
+
+@test 'a version 2 repo file cannot include what the repo file it extends excludes' {
+    mkdir -p repo_files
+    echo "version = 2" > repo_files/common.rf
+    echo "[[rule]]" >> repo_files/common.rf
+    echo "exclude = \"lib/secret/\"" >> repo_files/common.rf
+    repo_file_contents="
+    version = 2
+    extends = \"common.rf\"
+    [repo]
+    name = \"mylib\"
+    [[rule]]
+    include = \"lib/\"
+    "
+    echo "$repo_file_contents" > repo_files/mylib.rf
+
+    mkdir -p lib/secret
+    echo "libfile1" > lib/libfile1.txt
+    echo "private" > lib/secret/s
+    git add lib/ repo_files/ && git commit -m "lib files"
+
+    run $PROGRAM_PATH split-out repo_files/mylib.rf
+    echo "$output"
+    [[ $status == "0" ]]
+    git checkout master
+    [[ "$(git ls-tree -r --name-only mylib)" == "lib/libfile1.txt" ]]
+}
+
+@test 'repo files can use variables from vars, mgt.toml, and the environment' {
+    git init --bare ../mirror.git
+    echo "[vars]" > mgt.toml
+    echo "mirrors = \"..\"" >> mgt.toml
+    repo_file_contents="
+    [vars]
+    lib_dir = \"lib\"
+
+    [repo]
+    name = \"\${MGT_TEST_NAME}\"
+    include = \"\${lib_dir}/\"
+
+    [remotes.mirror]
+    url = \"\${mirrors}${SEP}mirror.git\"
+    "
+    echo "$repo_file_contents" > repo_file.rf
+
+    mkdir -p lib
+    echo "libfile1" > lib/libfile1.txt
+    git add lib/ mgt.toml && git commit -m "lib files"
+
+    run $PROGRAM_PATH split-out repo_file.rf
+    echo "$output"
+    [[ $status != "0" ]]
+    [[ "$output" == *"Unknown variable \${MGT_TEST_NAME} in repo.name"* ]]
+
+    MGT_TEST_NAME="mylib" run $PROGRAM_PATH split-out repo_file.rf
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$(git --git-dir=../mirror.git ls-tree -r --name-only mylib)" == "lib/libfile1.txt" ]]
+}
+
+@test 'variables are expanded in patterns and in the paths of include_as' {
+    repo_file_contents="
+    exclude = \"regex:^\${lib_dir}/ci/\"
+
+    [vars]
+    lib_dir = \"lib\"
+
+    [repo]
+    name = \"mylib\"
+
+    [include_as]
+    \"\${lib_dir}/\" = \" \"
+    "
+    echo "$repo_file_contents" > repo_file.rf
+
+    mkdir -p lib/ci
+    echo "libfile1" > lib/libfile1.txt
+    echo "ci" > lib/ci/ci.txt
+    git add lib/ && git commit -m "lib files"
+
+    run $PROGRAM_PATH split-out repo_file.rf
+    echo "$output"
+    [[ $status == "0" ]]
+    git checkout master
+    [[ "$(git ls-tree -r --name-only mylib)" == "libfile1.txt" ]]
+}
+
+@test 'verify-rf --migrate rewrites a version 1 repo file as version 2' {
+    repo_file_contents="
+    [repo]
+    name = \"mylib\"
+    include = [\"lib/secret/public.txt\", \"README.md\"]
+    exclude = \"lib/secret/\"
+
+    [include_as]
+    \"lib/\" = \"\"
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    echo "readme" > README.md
+    mkdir -p lib/secret
+    echo "libfile1" > lib/libfile1.txt
+    echo "private" > lib/secret/private.txt
+    echo "public" > lib/secret/public.txt
+    git add README.md lib/ && git commit -m "lib files"
+
+    run $PROGRAM_PATH split-out repo_file.rf --output-branch version1
+    echo "$output"
+    [[ $status == "0" ]]
+    git checkout master
+
+    run $PROGRAM_PATH verify-rf --migrate repo_file.rf
+    echo "$output"
+    cat repo_file.rf
+    [[ $status == "0" ]]
+    [[ "$output" == *"Rewrote repo_file.rf as a version 2 repo file"* ]]
+    [[ "$(head -n 1 repo_file.rf)" == "version = 2" ]]
+    [[ "$(cat repo_file.rf)" == *"[[rule]]"* ]]
+    [[ "$(cat repo_file.rf)" != *"include_as"* ]]
+
+    run $PROGRAM_PATH split-out repo_file.rf --output-branch version2
+    echo "$output"
+    [[ $status == "0" ]]
+    git checkout master
+    [[ "$(git ls-tree -r --name-only version2)" == "$(git ls-tree -r --name-only version1)" ]]
+
+    run $PROGRAM_PATH verify-rf --migrate repo_file.rf
+    echo "$output"
+    [[ $status != "0" ]]
+    [[ "$output" == *"Only version 1 repo files can be migrated"* ]]
+}
@@ -464,3 +464,41 @@ function setup() {
 #     xyz_actual_contents="$(cat xyz.txt)"
 #     [[ "$xyz_expected_contents" == "$xyz_actual_contents" ]]
 # }
+
+@test '--summary-only --format json outputs the sync type of each repo file' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    # fork point:
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    echo "xyz" > xyz.txt && git add xyz.txt && git commit -m "xyz"
+    commit_to_pull="$(git rev-parse HEAD)"
+    cd "$curr_dir"
+
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    
+
+    include=[\"abc.txt\", \"xyz.txt\"]
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    git_branches_before="$(git branch)"
+
+    run $PROGRAM_PATH sync repo_file.rf --summary-only --format json < /dev/null
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ $output == "["* ]]
+    [[ $output != *"You can pull"* ]]
+    [[ $output == *"\"sync_type\": \"remote_ahead\""* ]]
+    [[ $output == *"\"remote_repo\": \"..$SEP$test_remote_repo2\""* ]]
+    [[ $output == *"\"oid\": \"$commit_to_pull\""* ]]
+    [[ $output == *"\"top_commits\": []"* ]]
+    [[ "$git_branches_before" == "$(git branch)" ]]
+
+    # json is only a summary, so it cannot be used to pull/push
+    run $PROGRAM_PATH sync repo_file.rf --format json < /dev/null
+    echo "$output"
+    [[ $status != "0" ]]
+    [[ $output == *"--summary-only"* ]]
+}
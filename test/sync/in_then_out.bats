@@ -0,0 +1,218 @@
+function make_temp_repo() {
+    cd $BATS_TMPDIR
+    mkdir -p $1
+    cd $1
+    if [[ ! -d .git ]]; then
+        git init
+        git config --local user.email "temp"
+        git config --local user.name "temp"
+        echo "name of repo: $1" > $1.txt
+        git add $1.txt
+        git commit -m "initial commit for $1"
+    fi
+}
+
+function set_seperator() {
+    # I wanna use these tests for both windows (git bash)
+    # and linux, so I need to change the separator
+    if [[ -d /c/ ]]; then
+        SEP="\\\\"
+    else
+        SEP="/"
+    fi
+}
+
+function teardown() {
+    cd $BATS_TMPDIR
+    if [[ -d test_remote_repo ]]; then
+        rm -rf test_remote_repo
+    fi
+    if [[ -d test_remote_repo2 ]]; then
+        rm -rf test_remote_repo2
+    fi
+    cd ..
+    if [[ -d check ]]; then
+        rm -rf check/
+    fi
+}
+
+function setup() {
+    test_folder="$BATS_TMPDIR/syncinthenout"
+    mkdir -p "$test_folder"
+    BATS_TMPDIR="$test_folder"
+    cd $test_folder
+    set_seperator
+    make_temp_repo test_remote_repo
+    test_remote_repo="test_remote_repo"
+    make_temp_repo test_remote_repo2
+    test_remote_repo2="test_remote_repo2"
+    cd $BATS_TMPDIR/test_remote_repo
+}
+
+
+@test 'sync pull --rebase then push can push to remote and pull into local' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    # fork point:
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    # this is the remote commit we have that can be pulled into local
+    echo "remote" > remote.txt && git add remote.txt && git commit -m "remote"
+    # remote should not have its current branch checked out
+    # otherwise we wouldnt be able to push to it
+    git checkout -b tmp
+    echo "REMOTE:"
+    echo "$(git log --oneline)"
+    cd "$curr_dir"
+
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    branch = \"master\"
+
+
+    [include_as]
+    \"lib/\" = \" \"
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    mkdir -p lib
+    echo "abc" > lib/abc.txt && git add lib/abc.txt && git commit -m "abc"
+    # this is the local commit we have that can be pushed up to remote
+    echo "local" > lib/local.txt && git add lib/local.txt && git commit -m "local"
+    echo "LOCAL:"
+    echo "$(git log --oneline)"
+    git_branches_before="$(git branch)"
+
+    # mgt sync is an interactive command, we put a list of our inputs into
+    # a text file and feed that to its stdin.
+    # this series of inputs should be:
+    # 1. select pull --rebase, then push
+    # 1. merge the pulled commits into our branch
+    # master. name of branch for remote to use
+    interact="1\n1\nmaster\n"
+    echo -e "$interact" > interact.txt
+
+    run $PROGRAM_PATH sync repo_file.rf --max-interactive-attempts 1 < interact.txt
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ $output == *"You can push"* ]]
+    [[ $output == *"You can pull"* ]]
+    [[ $output == *"pull --rebase, then push"* ]]
+
+    # no lingering branches, and we should still be on our original branch
+    echo "Git branches before:"
+    echo "$git_branches_before"
+    git_branches_after="$(git branch)"
+    echo "Git branches after:"
+    echo "$git_branches_after"
+    [[ "$git_branches_before" == "$git_branches_after" ]]
+
+    # local should now have the remote's commit on top of ours:
+    echo "LOCAL AFTER:"
+    echo "$(git log --oneline)"
+    [[ -f lib/remote.txt ]]
+    [[ "$(git log -1 --format=%s)" == "remote" ]]
+
+    # remote should have our local commit on top of its own commit
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    echo "REMOTE AFTER:"
+    echo "$(git log master --oneline)"
+    [[ "$(git log master -1 --format=%s)" == "local" ]]
+    [[ "$(git log master~1 -1 --format=%s)" == "remote" ]]
+    [[ "$(git show master:local.txt)" == "local" ]]
+}
+
+@test 'sync pull --rebase then push recovers from a failed push' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    # fork point:
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    echo "remote" > remote.txt && git add remote.txt && git commit -m "remote"
+    # this branch has diverged from what we will fetch, so
+    # pushing to it will get rejected
+    git checkout -b newbranch
+    git reset --hard HEAD~1
+    echo "diverge" > diverge.txt && git add diverge.txt && git commit -m "diverge"
+    git checkout -
+    echo "REMOTE:"
+    echo "$(git log --all --decorate --oneline --graph)"
+    cd "$curr_dir"
+
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+
+
+    [include_as]
+    \"lib/\" = \" \"
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    mkdir -p lib
+    echo "abc" > lib/abc.txt && git add lib/abc.txt && git commit -m "abc"
+    echo "local" > lib/local.txt && git add lib/local.txt && git commit -m "local"
+    echo "LOCAL:"
+    echo "$(git log --oneline)"
+    git_branches_before="$(git branch)"
+    git_log_before="$(git log --oneline)"
+
+    interact="1\n1\nnewbranch\n"
+    echo -e "$interact" > interact.txt
+
+    run $PROGRAM_PATH sync repo_file.rf --max-interactive-attempts 1 < interact.txt
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ $output == *"rejected"* ]] # git should reject the push
+
+    # no lingering branches, and we are back on our original branch
+    echo "Git branches before:"
+    echo "$git_branches_before"
+    git_branches_after="$(git branch)"
+    echo "Git branches after:"
+    echo "$git_branches_after"
+    [[ "$git_branches_before" == "$git_branches_after" ]]
+
+    # the pulled commit was already merged, and the error
+    # tells us how to undo that
+    [[ "$(git log -1 --format=%s)" == "remote" ]]
+    [[ $output == *"were already merged into"* ]]
+    undo_cmd="$(echo "$output" | grep "^git reset --keep ")"
+    $undo_cmd
+    [[ "$git_log_before" == "$(git log --oneline)" ]]
+}
+
+@test 'sync pull --rebase then push leaves both sides up to date' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    # fork point:
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    echo "remote" > remote.txt && git add remote.txt && git commit -m "remote"
+    git checkout -b tmp
+    cd "$curr_dir"
+
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    branch = \"master\"
+
+
+    [include_as]
+    \"lib/\" = \" \"
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    mkdir -p lib
+    echo "abc" > lib/abc.txt && git add lib/abc.txt && git commit -m "abc"
+    echo "local" > lib/local.txt && git add lib/local.txt && git commit -m "local"
+
+    interact="1\n1\nmaster\n"
+    echo -e "$interact" > interact.txt
+    run $PROGRAM_PATH sync repo_file.rf --max-interactive-attempts 1 < interact.txt
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ $output == *"Successfully git pushed"* ]]
+
+    # the local and remote commits are in a different order on each
+    # side, but the next sync should know that they are the same
+    run $PROGRAM_PATH sync repo_file.rf --max-interactive-attempts 1 < /dev/null
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ $output == *"Up to date"* ]]
+}
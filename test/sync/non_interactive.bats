@@ -0,0 +1,461 @@
+function make_temp_repo() {
+    cd $BATS_TMPDIR
+    mkdir -p $1
+    cd $1
+    if [[ ! -d .git ]]; then
+        git init
+        git config --local user.email "temp"
+        git config --local user.name "temp"
+        echo "name of repo: $1" > $1.txt
+        git add $1.txt
+        git commit -m "initial commit for $1"
+    fi
+}
+
+function set_seperator() {
+    # I wanna use these tests for both windows (git bash)
+    # and linux, so I need to change the separator
+    if [[ -d /c/ ]]; then
+        SEP="\\\\"
+    else
+        SEP="/"
+    fi
+}
+
+function teardown() {
+    cd $BATS_TMPDIR
+    if [[ -d test_remote_repo ]]; then
+        rm -rf test_remote_repo
+    fi
+    if [[ -d test_remote_repo2 ]]; then
+        rm -rf test_remote_repo2
+    fi
+    cd ..
+    if [[ -d check ]]; then
+        rm -rf check/
+    fi
+}
+
+function setup() {
+    test_folder="$BATS_TMPDIR/syncnoninteractive"
+    mkdir -p "$test_folder"
+    BATS_TMPDIR="$test_folder"
+    cd $test_folder
+    set_seperator
+    make_temp_repo test_remote_repo
+    test_remote_repo="test_remote_repo"
+    make_temp_repo test_remote_repo2
+    test_remote_repo2="test_remote_repo2"
+    cd $BATS_TMPDIR/test_remote_repo
+}
+
+
+
+
+@test '--non-interactive exits with a code for each sync type' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    # fork point:
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    cd "$curr_dir"
+
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    
+
+    include=[\"abc.txt\", \"xyz.txt\"]
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+
+    # there is nobody to answer questions, so stdin is empty
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive < /dev/null
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ $output == *"Up to date"* ]]
+
+    echo "xyz" > xyz.txt && git add xyz.txt && git commit -m "xyz"
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive < /dev/null
+    echo "$output"
+    [[ $status == "10" ]]
+
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    echo "remote" > abc.txt && git add abc.txt && git commit -m "remote"
+    cd "$curr_dir"
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive < /dev/null
+    echo "$output"
+    [[ $status == "12" ]]
+    [[ $output == *"You can pull"* ]]
+    [[ $output == *"You can push"* ]]
+
+    # summary only should give the same exit code
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive --summary-only < /dev/null
+    echo "$output"
+    [[ $status == "12" ]]
+}
+
+@test '--non-interactive can pull and merge with a policy' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    # fork point:
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    echo "xyz" > xyz.txt && git add xyz.txt && git commit -m "xyz"
+    cd "$curr_dir"
+
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    
+
+    include=[\"abc.txt\", \"xyz.txt\"]
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    git_branches_before="$(git branch)"
+
+    # the default policy is to skip
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive < /dev/null
+    echo "$output"
+    [[ $status == "11" ]]
+    [[ ! -f xyz.txt ]]
+
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive --on-remote-ahead=pull --merge=always < /dev/null
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ -f xyz.txt ]]
+    [[ "$(git log --oneline -n 1)" == *"xyz"* ]]
+
+    git_branches_after="$(git branch)"
+    echo "$git_branches_after"
+    [[ "$git_branches_before" == "$git_branches_after" ]]
+}
+
+@test 'sync records which commits it pulled in the commit map' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    # fork point:
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    echo "xyz" > xyz.txt && git add xyz.txt && git commit -m "xyz"
+    remote_xyz="$(git rev-parse HEAD)"
+    cd "$curr_dir"
+
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    
+
+    include=[\"abc.txt\", \"xyz.txt\"]
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive --on-remote-ahead=pull --merge=always < /dev/null
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$(git log --oneline -n 1)" == *"xyz"* ]]
+
+    # the remote commit was filtered, and then the filtered commit
+    # was rebased into our local xyz commit:
+    local_xyz="$(git rev-parse HEAD)"
+    cat .git/mgt/commit-map
+    filtered_xyz="$(grep "^$remote_xyz " .git/mgt/commit-map | cut -d' ' -f2)"
+    [[ -n "$filtered_xyz" ]]
+    grep -q "^$filtered_xyz $local_xyz$" .git/mgt/commit-map
+
+    # and the next sync finds the fork point from the commit map
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive < /dev/null
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ $output == *"Up to date"* ]]
+}
+
+@test '--non-interactive without merging goes back to the starting branch' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    # fork point:
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    echo "xyz" > xyz.txt && git add xyz.txt && git commit -m "xyz"
+    cd "$curr_dir"
+
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    
+
+    include=[\"abc.txt\", \"xyz.txt\"]
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    git_branch_before="$(git branch --show-current)"
+
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive --on-remote-ahead pull < /dev/null
+    echo "$output"
+    # we pulled, but did not merge, so we are still behind
+    [[ $status == "11" ]]
+    [[ "$(git branch --show-current)" == "$git_branch_before" ]]
+    [[ ! -f xyz.txt ]]
+    [[ "$(git branch | wc -l)" == "2" ]]
+}
+
+@test '--non-interactive can push with a policy' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    # fork point:
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    cd "$curr_dir"
+
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    
+
+    include=[\"abc.txt\", \"xyz.txt\"]
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    echo "xyz" > xyz.txt && git add xyz.txt && git commit -m "xyz"
+    git_branches_before="$(git branch)"
+
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive --on-local-ahead=push < /dev/null
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$git_branches_before" == "$(git branch)" ]]
+
+    # it should have been pushed to an auto generated branch name
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    echo "$(git branch)"
+    [[ "$(git branch)" == *"mgt-tmp-branch"* ]]
+}
+
+@test 'sync goes over every remote, and only pushes where it is allowed' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    # fork point:
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    cd "$curr_dir"
+
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    include = [\"abc.txt\", \"xyz.txt\"]
+
+    [remotes.readonly]
+    url = \"..$SEP$test_remote_repo2\"
+    push = false
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    echo "xyz" > xyz.txt && git add xyz.txt && git commit -m "xyz"
+
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive --on-local-ahead=push < /dev/null
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ $output == *"Fetching origin"* ]]
+    [[ $output == *"Fetching readonly"* ]]
+    [[ $output == *"Not pushing to readonly because it has push = false"* ]]
+
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    [[ "$(git branch)" == *"mgt-tmp-branch"* ]]
+}
+
+@test 'sync can go over a group of the subrepos in mgt.toml with their own policies' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    # fork point:
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    cd "$curr_dir"
+
+    mkdir -p rfs
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    include = [\"abc.txt\", \"xyz.txt\"]
+    "
+    echo "$repo_file_contents" > rfs/a.rf
+    echo "$repo_file_contents" > rfs/b.rf
+    workspace_contents="
+    [[subrepo]]
+    repo_file = \"rfs/a.rf\"
+    groups = [\"libs\"]
+    sync = { on_local_ahead = \"push\" }
+
+    [[subrepo]]
+    repo_file = \"rfs/b.rf\"
+    groups = [\"apps\"]
+    "
+    echo "$workspace_contents" > mgt.toml
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    echo "xyz" > xyz.txt && git add xyz.txt && git commit -m "xyz"
+
+    run $PROGRAM_PATH sync --group tools --non-interactive < /dev/null
+    echo "$output"
+    [[ $status != "0" ]]
+    [[ $output == *"The groups are: apps, libs"* ]]
+
+    run $PROGRAM_PATH sync rfs --group libs --non-interactive < /dev/null
+    echo "$output"
+    [[ $status != "0" ]]
+    [[ $output == *"Cannot use --group or --all-subrepos with a repo file path"* ]]
+
+    # only a.rf is in libs, and it is pushed because of its policy
+    run $PROGRAM_PATH sync --group libs --non-interactive < /dev/null
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ "$(echo "$output" | grep -c "Fetching")" == "1" ]]
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    [[ "$(git branch)" == *"mgt-tmp-branch"* ]]
+}
+
+@test 'sync only pushes the commits that are left after the commit rules' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    # fork point:
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    cd "$curr_dir"
+
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    [commits]
+    drop = \"message:skip-export\"
+    
+
+    include=[\"abc.txt\", \"xyz.txt\", \"secret.txt\"]
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    echo "xyz" > xyz.txt && git add xyz.txt && git commit -m "xyz"
+    echo "secret" > secret.txt && git add secret.txt && git commit -m "secret skip-export"
+
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive --on-local-ahead=push < /dev/null
+    echo "$output"
+    [[ $status == "0" ]]
+
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    pushed_branch="$(git branch --format='%(refname:short)' | grep mgt-tmp-branch)"
+    git log --oneline "$pushed_branch"
+    [[ "$(git log --format=%s -n 2 "$pushed_branch")" == "xyz
+abc" ]]
+    [[ -z "$(git ls-tree "$pushed_branch" secret.txt)" ]]
+}
+
+@test 'sync rewrites identities of the commits it pushes with the mailmap' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    # fork point:
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    cd "$curr_dir"
+
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    [mailmap]
+    \"<jane@corp.internal>\" = \"Jane Doe <jane@example.com>\"
+    
+
+    include=[\"abc.txt\", \"xyz.txt\"]
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    echo "xyz" > xyz.txt && git add xyz.txt && git commit -m "xyz" --author "jane <jane@corp.internal>"
+
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive --on-local-ahead=push < /dev/null
+    echo "$output"
+    [[ $status == "0" ]]
+
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    pushed_branch="$(git branch --format='%(refname:short)' | grep mgt-tmp-branch)"
+    git log --format="%an <%ae> %s" "$pushed_branch"
+    [[ "$(git log --format="%an <%ae>" -n 1 "$pushed_branch")" == "Jane Doe <jane@example.com>" ]]
+}
+
+@test 'sync recognizes origin trailers of commits that were split out' {
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    [message]
+    trailer = true
+    
+
+    include=[\"abc.txt\", \"xyz.txt\"]
+    "
+    echo "$repo_file_contents" > repo_file.rf
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    local_abc="$(git rev-parse HEAD)"
+    echo "xyz" > xyz.txt && git add xyz.txt && git commit -m "xyz"
+    local_xyz="$(git rev-parse HEAD)"
+    curr_dir="$PWD"
+
+    # as if these commits were split out into the remote:
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc
+
+Mgt-Origin: $local_abc"
+    echo "xyz" > xyz.txt && git add xyz.txt && git commit -m "xyz
+
+Mgt-Origin: $local_xyz"
+    remote_xyz="$(git rev-parse HEAD)"
+    cd "$curr_dir"
+
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive < /dev/null
+    echo "$output"
+    [[ $status == "0" ]]
+    [[ $output == *"Up to date"* ]]
+    cat .git/mgt/commit-map
+    grep -q "^$local_xyz $remote_xyz$" .git/mgt/commit-map
+
+    # the trailers are only recorded once
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive < /dev/null
+    [[ $status == "0" ]]
+    [[ "$(wc -l < .git/mgt/commit-map)" == "2" ]]
+}
+
+@test '--non-interactive fail policy stops syncing the rest of the repo files' {
+    curr_dir="$PWD"
+    cd "$BATS_TMPDIR/test_remote_repo2"
+    # fork point:
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    echo "remote" > remote.txt && git add remote.txt && git commit -m "remote"
+    cd "$curr_dir"
+
+    mkdir -p rfs
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    
+
+    include=[\"abc.txt\", \"local.txt\", \"remote.txt\"]
+    "
+    echo "$repo_file_contents" > rfs/a.rf
+    echo "$repo_file_contents" > rfs/b.rf
+    echo "abc" > abc.txt && git add abc.txt && git commit -m "abc"
+    echo "local" > local.txt && git add local.txt && git commit -m "local"
+
+    run $PROGRAM_PATH sync rfs --non-interactive --on-diverged=fail < /dev/null
+    echo "$output"
+    [[ $status == "12" ]]
+    [[ $output == *"Stopping because"* ]]
+    # only one of the repo files should have been fetched
+    [[ "$(echo "$output" | grep -c "Fetching")" == "1" ]]
+}
+
+@test '--non-interactive rejects policies that do not apply' {
+    repo_file_contents="
+    [repo]
+    remote = \"..$SEP$test_remote_repo2\"
+    
+
+    include=[\"abc.txt\"]
+    "
+    echo "$repo_file_contents" > repo_file.rf
+
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive --on-local-ahead=pull < /dev/null
+    echo "$output"
+    [[ $status != "0" ]]
+    [[ $output == *"not a valid policy for --on-local-ahead"* ]]
+
+    run $PROGRAM_PATH sync repo_file.rf --non-interactive --merge=sometimes < /dev/null
+    echo "$output"
+    [[ $status != "0" ]]
+}